@@ -1,25 +1,60 @@
 use chrono::prelude::*;
 use chrono::Duration;
 use clap::Parser;
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use kollider_api::kollider::api::*;
 use kollider_api::kollider::client::*;
 use kollider_api::kollider::websocket::*;
 use kollider_api::kollider::websocket::oneshot::*;
+use config::{Config, Credentials, Settings};
+use kollider_api::kollider::client::keystore::Keystore;
+use kollider_api::kollider::journal::{EventSource, Journal, JournalQuery};
+use kollider_api::kollider::export::{
+    export_fills, export_history, export_orders, ExportFormat, Exporter, TradeRecord,
+    UnknownExportFormat,
+};
+use output::OutputFormat;
 use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
 use uuid::Uuid;
+use zeroize::Zeroizing;
+
+mod config;
+mod output;
 
 #[derive(Parser, Debug)]
 #[clap(about, version, author)]
 struct Args {
-    #[clap(short, long)]
+    /// Use testnet environment
+    #[clap(short, long, global = true)]
     testnet: bool,
+    /// Output format: table, json, jsonl or csv. Defaults to the profile format or table.
+    #[clap(short, long, global = true)]
+    output: Option<OutputFormat>,
+    /// Name of profile from the config file
+    #[clap(short, long, env = "KOLLIDER_PROFILE", global = true)]
+    profile: Option<String>,
+    /// Path to the config file with profiles. Default is ~/.config/kollider/config.toml
+    #[clap(long, env = "KOLLIDER_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// SQLite database to record orders, fills and balances to
+    #[clap(long, env = "KOLLIDER_JOURNAL", global = true)]
+    journal: Option<PathBuf>,
+    #[clap(flatten)]
+    credentials: Credentials,
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
 
 #[derive(Parser, Debug)]
 enum SubCommand {
+    /// Encrypt credentials with passphrase and save them to the keystore of the profile
+    Login(LoginCmd),
+    /// Delete keystore of the profile
+    Logout,
+    /// Measure skew between local and server clocks
+    Time(TimeCmd),
     /// Print available tickers
     Products,
     /// Get info from public orderbook
@@ -29,11 +64,11 @@ enum SubCommand {
     /// Get historical data about prices (not operational)
     History(HistoryCmd),
     /// Get information about an account. Requires authentification.
-    Account(AccountCmd),
+    Account,
     /// Get information about balances via sync websocket request.
-    Balances(BalancesCmd),
+    Balances,
     /// Get information about positions via sync 
-    Positions(PositionsCmd),
+    Positions,
     /// Deposit money to an account. Requires authentification.
     #[clap(subcommand)]
     Deposit(DepositSub),
@@ -46,92 +81,75 @@ enum SubCommand {
     /// Launch a websocket connection and enter iteractive shell.
     #[clap(subcommand)]
     Websocket(WebsocketSub),
+    /// Export history to CSV or Parquet file. Existing file is continued from its last record.
+    #[clap(subcommand)]
+    Export(ExportSub),
+    /// Inspect the journal given with `--journal` or in the profile
+    #[clap(subcommand)]
+    Journal(JournalSub),
+}
+
+#[derive(Parser, Debug)]
+struct LoginCmd {
+    /// Overwrite existing keystore
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Parser, Debug)]
+struct TimeCmd {
+    /// Number of requests to measure the skew
+    #[clap(short, long, default_value = "3")]
+    requests: usize,
 }
 
 #[derive(Parser, Debug)]
 struct OrderbookCmd {
     #[clap(short, long, default_value = "2")]
     level: u64,
-    #[clap(short, long, default_value = "BTCUSD.PERP")]
-    symbol: String,
+    /// Defaults to the symbol of the profile
+    #[clap(short, long)]
+    symbol: Option<Symbol>,
 }
 
 #[derive(Parser, Debug)]
 struct TickerCmd {
-    #[clap(short, long, default_value = "BTCUSD.PERP")]
-    symbol: String,
+    /// Defaults to the symbol of the profile
+    #[clap(short, long)]
+    symbol: Option<Symbol>,
 }
 
 #[derive(Parser, Debug)]
 struct HistoryCmd {
+    /// Maximum number of intervals, ignored with `--all`
     #[clap(short, long, default_value = "100")]
     limit: usize,
-    #[clap(short, long, default_value = "BTCUSD.PERP")]
-    symbol: String,
+    /// Defaults to the symbol of the profile
+    #[clap(short, long)]
+    symbol: Option<Symbol>,
     #[clap(long)]
     start: Option<DateTime<Local>>,
     #[clap(long)]
     end: Option<DateTime<Local>>,
+    /// Server provides 5m, 15m, 1h and 1d, other timeframes like 4h or 1w are resampled
+    /// from the whole range of the coarsest fitting interval
     #[clap(short, long, default_value = "5m")]
-    interval: IntervalSize,
-}
-
-#[derive(Parser, Debug)]
-struct AccountCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
-}
-
-#[derive(Parser, Debug)]
-struct BalancesCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
-}
-
-#[derive(Parser, Debug)]
-struct PositionsCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
+    interval: Timeframe,
+    /// Fetch all intervals in the time range page by page
+    #[clap(long)]
+    all: bool,
 }
 
 #[derive(Parser, Debug)]
 enum DepositSub {
     /// Deposit Bitcoins onchain. Non operational endpoint for now
-    Btc(DepositBtc),
+    Btc,
     /// Deposit Bitcoins using Lightning Network. The method returns an invoice that you should pay with LN wallet as usual.
     Ln(DepositLn),
 }
 
-#[derive(Parser, Debug)]
-struct DepositBtc {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
-}
-
 #[derive(Parser, Debug)]
 struct DepositLn {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
     #[clap(long, help = "Amount of deposit in sats")]
     amount: u64,
 }
@@ -146,12 +164,6 @@ enum WithdrawalSub {
 
 #[derive(Parser, Debug)]
 struct WithdrawalBtc {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
     /// BTC receiving address
     address: String,
     #[clap(long, help = "Amount of withdrawal in sats")]
@@ -160,12 +172,6 @@ struct WithdrawalBtc {
 
 #[derive(Parser, Debug)]
 struct WithdrawalLn {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
     /// Payment request
     invoice: String,
     #[clap(long, help = "Amount of withdrawal in sats")]
@@ -181,25 +187,20 @@ enum OrderSub {
     /// List historic info about orders of the account
     List(OrderListCmd),
     /// List opened orders of the account
-    Opened(OrderOpenedCmd),
+    Opened,
     /// List fill info about user orders
     Fills(OrderFillsCmd),
     /// This will return all positions currently held by user.
-    Positions(OrderPositionsCmd),
+    Positions,
     /// Cancel order with the best effort.
     Cancel(OrderCancelCmd),
 }
 
 #[derive(Parser, Debug)]
 struct OrderCreateCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
-    #[clap(long, default_value = "BTCUSD.PERP")]
-    symbol: String,
+    /// Defaults to the symbol of the profile
+    #[clap(long)]
+    symbol: Option<Symbol>,
     #[clap(long)]
     quantity: u64,
     #[clap(long)]
@@ -218,70 +219,43 @@ struct OrderCreateCmd {
 
 #[derive(Parser, Debug)]
 struct OrderListCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
-    #[clap(long, default_value = "BTCUSD.PERP")]
-    symbol: String,
+    /// Defaults to the symbol of the profile
+    #[clap(long)]
+    symbol: Option<Symbol>,
     #[clap(long)]
     start: Option<DateTime<Local>>,
     #[clap(long)]
     end: Option<DateTime<Local>>,
+    /// Maximum number of orders, or page size with `--all`
     #[clap(long, default_value = "100")]
     limit: usize,
-}
-
-#[derive(Parser, Debug)]
-struct OrderOpenedCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
+    /// Fetch all orders in the time range page by page
+    #[clap(long)]
+    all: bool,
 }
 
 #[derive(Parser, Debug)]
 struct OrderFillsCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
-    #[clap(long, default_value = "BTCUSD.PERP")]
-    symbol: String,
+    /// Defaults to the symbol of the profile
+    #[clap(long)]
+    symbol: Option<Symbol>,
     #[clap(long)]
     start: Option<DateTime<Local>>,
     #[clap(long)]
     end: Option<DateTime<Local>>,
+    /// Maximum number of fills, or page size with `--all`
     #[clap(long, default_value = "100")]
     limit: usize,
-}
-
-#[derive(Parser, Debug)]
-struct OrderPositionsCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
+    /// Fetch all fills in the time range page by page
+    #[clap(long)]
+    all: bool,
 }
 
 #[derive(Parser, Debug)]
 struct OrderCancelCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
-    #[clap(long, default_value = "BTCUSD.PERP")]
-    symbol: String,
+    /// Defaults to the symbol of the profile
+    #[clap(long)]
+    symbol: Option<Symbol>,
     /// ID of order to cancel
     order_id: u64,
 }
@@ -292,22 +266,57 @@ enum WebsocketSub {
     Private(WebsocketPrivateCmd),
     /// Launch websocket without authentification
     Public(WebsocketPublicCmd),
+    /// Launch websocket and enter interactive shell. Type 'help' to list commands.
+    Shell(WebsocketShellCmd),
+    /// Print messages of a recorded session as if they came from the server
+    Replay(WebsocketReplayCmd),
+}
+
+#[derive(Parser, Debug)]
+struct WebsocketReplayCmd {
+    /// JSONL file written with '--record'
+    file: PathBuf,
+    /// How many times faster than the original session to replay
+    #[clap(long, default_value = "1", value_parser = parse_speed)]
+    speed: f64,
+    /// Don't wait between messages
+    #[clap(long, conflicts_with = "speed")]
+    instant: bool,
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if speed.is_finite() && speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err("speed must be a positive number, use --instant to replay without delays".to_owned())
+    }
+}
+
+#[derive(Parser, Debug)]
+struct WebsocketShellCmd {
+    /// Authentificate the session right after connection
+    #[clap(long)]
+    login: bool,
+    /// File with history of commands
+    #[clap(long, env = "KOLLIDER_HISTORY")]
+    history: Option<PathBuf>,
+    /// Append every received frame to the JSONL file for later replay
+    #[clap(long)]
+    record: Option<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
 struct WebsocketPrivateCmd {
-    #[clap(long, env = "KOLLIDER_API_KEY", hide_env_values = true)]
-    api_key: String,
-    #[clap(long, env = "KOLLIDER_API_SECRET", hide_env_values = true)]
-    api_secret: String,
-    #[clap(long, env = "KOLLIDER_API_PASSWORD", hide_env_values = true)]
-    password: String,
     /// Which symbol to filter from channels. E.x. '.BTCUSD' or '.BTCEUR'
     #[clap(long)]
     symbols: Vec<Symbol>,
     /// Which channels to listen
     #[clap(long)]
     channels: Vec<ChannelName>,
+    /// Append every received frame to the JSONL file for later replay
+    #[clap(long)]
+    record: Option<PathBuf>,
     #[clap(subcommand)]
     action: Option<WebsocketAction>,
 }
@@ -319,8 +328,9 @@ enum WebsocketAction {
         price: u64,
         #[clap(long)]
         quantity: u64,
-        #[clap(long, default_value = "BTCUSD.PERP")]
-        symbol: Symbol,
+        /// Defaults to the symbol of the profile
+        #[clap(long)]
+        symbol: Option<Symbol>,
         #[clap(long, default_value = "100")]
         leverage: u64,
         #[clap(long)]
@@ -334,8 +344,9 @@ enum WebsocketAction {
     },
     CancelOrder {
         order_id: u64,
-        #[clap(long, default_value = "BTCUSD.PERP")]
-        symbol: String,
+        /// Defaults to the symbol of the profile
+        #[clap(long)]
+        symbol: Option<Symbol>,
         #[clap(long, default_value = "Delayed")]
         settlement_type: SettlementType,
     },
@@ -343,14 +354,15 @@ enum WebsocketAction {
     FetchPositions,
     FetchBalances,
     GetTicker {
-        #[clap(long, default_value = "BTCUSD.PERP")]
-        symbol: String,
+        /// Defaults to the symbol of the profile
+        #[clap(long)]
+        symbol: Option<Symbol>,
     },
     TradableProducts,
 }
 
 impl WebsocketAction {
-    fn to_message(self) -> KolliderMsg {
+    fn into_message(self, settings: &Settings) -> KolliderMsg {
         match self {
             WebsocketAction::Order {
                 price,
@@ -365,7 +377,7 @@ impl WebsocketAction {
                 _type: OrderTag::Tag,
                 price,
                 quantity,
-                symbol,
+                symbol: settings.symbol(symbol),
                 leverage,
                 side,
                 margin_type,
@@ -383,7 +395,7 @@ impl WebsocketAction {
             } => KolliderMsg::CancelOrder {
                 _type: CancelOrderTag::Tag,
                 order_id,
-                symbol,
+                symbol: settings.symbol(symbol),
                 settlement_type,
             },
             WebsocketAction::FetchOpenOrders => KolliderMsg::FetchOpenOrders {
@@ -397,7 +409,7 @@ impl WebsocketAction {
             },
             WebsocketAction::GetTicker { symbol } => KolliderMsg::GetTicker {
                 _type: GetTickerTag::Tag,
-                symbol,
+                symbol: settings.symbol(symbol),
             },
             WebsocketAction::TradableProducts => KolliderMsg::TradableProducts {
                 _type: TradableProductsTag::Tag,
@@ -414,6 +426,121 @@ struct WebsocketPublicCmd {
     /// Which channels to listen
     #[clap(default_value = "index_values")]
     channels: Vec<ChannelName>,
+    /// Append every received frame to the JSONL file for later replay
+    #[clap(long)]
+    record: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+enum JournalSub {
+    /// Print recorded events in the order they happened
+    Query(JournalQueryCmd),
+}
+
+#[derive(Parser, Debug)]
+struct JournalQueryCmd {
+    /// Where the event came from: ws_out, ws_in or rest
+    #[clap(long)]
+    source: Option<EventSource>,
+    /// Message type, e.x. fill, order_rejection or create_order
+    #[clap(long)]
+    kind: Option<String>,
+    #[clap(short, long)]
+    symbol: Option<Symbol>,
+    #[clap(long)]
+    order_id: Option<u64>,
+    #[clap(long)]
+    since: Option<DateTime<Local>>,
+    #[clap(long)]
+    until: Option<DateTime<Local>>,
+    /// Print only the latest events
+    #[clap(short, long, default_value = "100")]
+    limit: usize,
+}
+
+#[derive(Parser, Debug)]
+enum ExportSub {
+    /// Index price history of any timeframe
+    History(ExportHistoryCmd),
+    /// Orders of the account. Requires authentification.
+    Orders(ExportAccountCmd),
+    /// Fills of the account. Requires authentification.
+    Fills(ExportAccountCmd),
+    /// Record public trades from websocket for a while
+    Trades(ExportTradesCmd),
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportFile {
+    /// File to write, format is taken from the extension: .csv or .parquet
+    file: PathBuf,
+    /// Overrides format of the file extension: csv or parquet
+    #[clap(long)]
+    format: Option<ExportFormat>,
+    /// Defaults to the symbol of the profile
+    #[clap(short, long)]
+    symbol: Option<Symbol>,
+}
+
+impl ExportFile {
+    fn exporter(&self) -> Result<Exporter, UnknownExportFormat> {
+        match self.format {
+            Some(format) => Ok(Exporter::new(&self.file, format)),
+            None => Exporter::from_path(&self.file),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+struct ExportHistoryCmd {
+    #[clap(flatten)]
+    target: ExportFile,
+    /// Defaults to one day ago or the last record of the file
+    #[clap(long)]
+    start: Option<DateTime<Local>>,
+    #[clap(long)]
+    end: Option<DateTime<Local>>,
+    /// Server provides 5m, 15m, 1h and 1d, other timeframes like 4h or 1w are resampled
+    #[clap(short, long, default_value = "1h")]
+    interval: Timeframe,
+}
+
+#[derive(Parser, Debug)]
+struct ExportAccountCmd {
+    #[clap(flatten)]
+    target: ExportFile,
+    /// Defaults to one day ago or the last record of the file
+    #[clap(long)]
+    start: Option<DateTime<Local>>,
+    #[clap(long)]
+    end: Option<DateTime<Local>>,
+    /// Number of records per request
+    #[clap(long, default_value = "100")]
+    page_size: usize,
+}
+
+#[derive(Parser, Debug)]
+struct ExportTradesCmd {
+    #[clap(flatten)]
+    target: ExportFile,
+    /// How long to record trades in seconds, Ctrl-C stops earlier
+    #[clap(long, default_value = "60")]
+    duration: u64,
+}
+
+impl OrderCreateCmd {
+    fn into_body(self, settings: &Settings) -> OrderBody {
+        OrderBody {
+            symbol: settings.symbol(self.symbol),
+            quantity: self.quantity,
+            price: self.price,
+            leverage: self.leverage,
+            side: self.side,
+            margin_type: self.margin_type,
+            order_type: self.order_type,
+            settlement_type: self.settlement_type,
+        }
+    }
 }
 
 #[tokio::main]
@@ -422,26 +549,98 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     env_logger::init();
 
-    let mut client = if args.testnet {
+    let config_path = args.config.or_else(Config::default_path);
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let profile = config.profile(args.profile.as_deref())?;
+    let profile_name = config.profile_name(args.profile.as_deref());
+    let keystore_path = config_path
+        .as_ref()
+        .map(|path| Config::keystore_path(path, &profile_name));
+    let settings = Settings::new(
+        profile,
+        args.credentials,
+        args.testnet,
+        args.output,
+        keystore_path,
+        args.journal,
+    );
+    let out = settings.output;
+    let journal = settings.journal.as_deref().map(Journal::open).transpose()?;
+
+    let mut client = if settings.testnet {
         KolliderClient::testnet()
     } else {
         KolliderClient::mainnet()
     };
+    client.journal = journal.clone();
+    let mut ws_options = WebsocketOptions {
+        journal: journal.clone(),
+        ..if settings.testnet {
+            WebsocketOptions::testnet()
+        } else {
+            WebsocketOptions::default()
+        }
+    };
 
     match args.subcmd {
+        SubCommand::Login(LoginCmd { force }) => {
+            let path = settings.keystore.as_ref().ok_or(NoKeystorePath)?;
+            if path.exists() && !force {
+                return Err(KeystoreExists(path.clone()).into());
+            }
+            let auth = prompt_credentials(&settings)?;
+            let passphrase = Zeroizing::new(rpassword::prompt_password("New passphrase: ")?);
+            let repeated = Zeroizing::new(rpassword::prompt_password("Repeat passphrase: ")?);
+            if passphrase != repeated {
+                return Err(PassphraseMismatch.into());
+            }
+            Keystore::encrypt(&auth, &passphrase)?.save(path)?;
+            println!(
+                "Credentials of profile '{}' are saved to {}",
+                profile_name,
+                path.display()
+            );
+        }
+        SubCommand::Logout => {
+            let path = settings.keystore.as_ref().ok_or(NoKeystorePath)?;
+            if Keystore::remove(path)? {
+                println!("Removed {}", path.display());
+            } else {
+                println!("No keystore for profile '{}'", profile_name);
+            }
+        }
+        SubCommand::Time(TimeCmd { requests }) => {
+            for _ in 0..requests.max(1) {
+                client.sync_time().await?;
+            }
+            let offset = client.clock_skew().unwrap_or_else(Duration::zero);
+            out.print(&serde_json::json!({
+                "local_time": Utc::now().to_rfc3339(),
+                "server_time": client.clock.now().to_rfc3339(),
+                "offset_ms": offset.num_milliseconds(),
+                "samples": client.clock.samples(),
+            }))?;
+        }
         SubCommand::Products => {
             let resp = client.market_products().await?;
-            println!("Response /market/products: {:?}", resp);
+            let mut rows: Vec<&Product> = resp.values().collect();
+            rows.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+            out.print_with_rows(&resp, &rows)?;
         }
         SubCommand::Orderbook(OrderbookCmd { level, symbol }) => {
             let book_level =
                 OrderBookLevel::from_int(level).expect("Order book level is either 2 or 3");
-            let resp = client.market_orderbook(book_level, &symbol).await?;
-            println!("Response /market/orderbook: {:?}", resp);
+            let resp = client
+                .market_orderbook(book_level, &settings.symbol(symbol))
+                .await?;
+            out.print_with_rows(&resp, &orderbook_rows(&resp))?;
         }
         SubCommand::Ticker(TickerCmd { symbol }) => {
-            let resp = client.market_ticker(&symbol).await?;
-            println!("Response /market/ticker: {:?}", resp);
+            let resp = client.market_ticker(&settings.symbol(symbol)).await?;
+            out.print(&resp)?;
         }
         SubCommand::History(HistoryCmd {
             limit,
@@ -449,241 +648,232 @@ async fn main() -> Result<(), Box<dyn Error>> {
             start,
             end,
             interval,
+            all,
         }) => {
+            let symbol = settings.symbol(symbol);
             let start_time = start.unwrap_or_else(|| Local::now() - Duration::days(1));
-            let end_time = end.unwrap_or_else(|| Local::now());
-            let resp = client
-                .market_historic_index_prices(limit, &symbol, start_time, end_time, interval)
-                .await?;
-            println!("Response /market/historic_index_prices: {:?}", resp);
+            let end_time = end.unwrap_or_else(Local::now);
+            let resp = match interval.native() {
+                Some(native) if !all => {
+                    client
+                        .market_historic_index_prices(limit, &symbol, start_time, end_time, native)
+                        .await?
+                }
+                _ => {
+                    client
+                        .historic_index_prices_resampled(&symbol, start_time, end_time, interval)
+                        .await?
+                }
+            };
+            let rows = serde_json::to_value(&resp)?["data"].take();
+            out.print_with_rows(&resp, &rows)?;
         }
-        SubCommand::Account(AccountCmd {
-            api_key,
-            api_secret,
-            password,
-        }) => {
-            let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-            client.auth = Some(auth);
+        SubCommand::Account => {
+            client.auth = Some(settings.signer()?);
             let resp = client.user_account().await?;
-            println!("Response /user/account: {:?}", resp);
+            out.print(&resp)?;
         }
-        SubCommand::Balances(BalancesCmd {
-            api_key,
-            api_secret,
-            password,
-        }) => {
-            let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-            let resp = fetch_balances(&auth).await?;
-            println!("Response WS fetch_balances: {:?}", resp);
+        SubCommand::Balances => {
+            let resp = fetch_balances_with(&ws_options, settings.signer()?.as_ref()).await?;
+            out.print(&resp)?;
         }
-        SubCommand::Positions(PositionsCmd {
-            api_key,
-            api_secret,
-            password,
-        }) => {
-            let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-            let resp = fetch_positions(&auth).await?;
-            println!("Response WS fetch_positions: {:?}", resp);
+        SubCommand::Positions => {
+            let resp = fetch_positions_with(&ws_options, settings.signer()?.as_ref()).await?;
+            out.print_with_rows(&resp, &sorted_values(&resp))?;
         }
-        SubCommand::Deposit(ref deposit_sub) => match deposit_sub {
-            DepositSub::Btc(DepositBtc {
-                api_key,
-                api_secret,
-                password,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client.wallet_deposit(&DepositBody::Bitcoin).await?;
-                println!("Response /wallet/deposit: {:?}", resp);
-            }
-            DepositSub::Ln(DepositLn {
-                api_key,
-                api_secret,
-                password,
-                amount,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client
-                    .wallet_deposit(&DepositBody::Lighting(*amount))
-                    .await?;
-                println!("Response /wallet/deposit: {:?}", resp);
+        SubCommand::Deposit(deposit_sub) => {
+            client.auth = Some(settings.signer()?);
+            let body = match deposit_sub {
+                DepositSub::Btc => DepositBody::Bitcoin,
+                DepositSub::Ln(DepositLn { amount }) => DepositBody::Lighting(amount),
+            };
+            let resp = client.wallet_deposit(&body).await?;
+            out.print(&resp)?;
+        }
+        SubCommand::Withdrawal(withdrawal_sub) => {
+            client.auth = Some(settings.signer()?);
+            let body = match withdrawal_sub {
+                WithdrawalSub::Btc(WithdrawalBtc { address, amount }) => WithdrawalBody::Bitcoin {
+                    _type: BtcTag::BTC,
+                    receive_address: address,
+                    amount,
+                },
+                WithdrawalSub::Ln(WithdrawalLn { invoice, amount }) => WithdrawalBody::Lighting {
+                    _type: LnTag::Ln,
+                    payment_request: invoice,
+                    amount,
+                },
+            };
+            let resp = client.wallet_withdrawal(&body).await?;
+            out.print(&resp)?;
+        }
+        SubCommand::Order(order_sub) => {
+            client.auth = Some(settings.signer()?);
+            match order_sub {
+                OrderSub::Create(cmd) => {
+                    let resp = client.create_order(&cmd.into_body(&settings)).await?;
+                    out.print(&resp)?;
+                }
+                OrderSub::Prediction(cmd) => {
+                    let resp = client.order_prediction(&cmd.into_body(&settings)).await?;
+                    out.print(&resp)?;
+                }
+                OrderSub::List(OrderListCmd {
+                    symbol,
+                    start,
+                    end,
+                    limit,
+                    all,
+                }) => {
+                    let symbol = settings.symbol(symbol);
+                    let start_time = start.unwrap_or_else(|| Local::now() - Duration::days(1));
+                    let end_time = end.unwrap_or_else(Local::now);
+                    let resp: Vec<_> = if all {
+                        client
+                            .orders_stream(&symbol, start_time, end_time, limit)
+                            .try_collect()
+                            .await?
+                    } else {
+                        client.orders(&symbol, start_time, end_time, limit).await?
+                    };
+                    out.print(&resp)?;
+                }
+                OrderSub::Opened => {
+                    let resp = client.open_orders().await?;
+                    let rows: Vec<&OrderDetails> =
+                        sorted_values(&resp).into_iter().flatten().collect();
+                    out.print_with_rows(&resp, &rows)?;
+                }
+                OrderSub::Fills(OrderFillsCmd {
+                    symbol,
+                    start,
+                    end,
+                    limit,
+                    all,
+                }) => {
+                    let symbol = settings.symbol(symbol);
+                    let start_time = start.unwrap_or_else(|| Local::now() - Duration::days(1));
+                    let end_time = end.unwrap_or_else(Local::now);
+                    let resp: Vec<_> = if all {
+                        client
+                            .fills_stream(&symbol, start_time, end_time, limit)
+                            .try_collect()
+                            .await?
+                    } else {
+                        client.fills(&symbol, start_time, end_time, limit).await?
+                    };
+                    out.print(&resp)?;
+                }
+                OrderSub::Positions => {
+                    let resp = client.positions().await?;
+                    out.print_with_rows(&resp, &sorted_values(&resp))?;
+                }
+                OrderSub::Cancel(OrderCancelCmd { symbol, order_id }) => {
+                    let symbol = settings.symbol(symbol);
+                    client.cancel_order(&symbol, order_id).await?;
+                    out.print(&serde_json::json!({
+                        "order_id": order_id,
+                        "symbol": symbol,
+                        "status": "cancelled",
+                    }))?;
+                }
             }
-        },
-        SubCommand::Withdrawal(ref withdrawal_sub) => match withdrawal_sub {
-            WithdrawalSub::Btc(WithdrawalBtc {
-                api_key,
-                api_secret,
-                password,
-                address,
-                amount,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client
-                    .wallet_withdrawal(&WithdrawalBody::Bitcoin {
-                        _type: BtcTag::BTC,
-                        receive_address: address.clone(),
-                        amount: *amount,
-                    })
+        }
+        SubCommand::Export(export_sub) => {
+            let (target, written) = match export_sub {
+                ExportSub::History(cmd) => {
+                    let exporter = cmd.target.exporter()?;
+                    let start = cmd
+                        .start
+                        .unwrap_or_else(|| Local::now() - Duration::days(1));
+                    let written = export_history(
+                        &client,
+                        &exporter,
+                        &settings.symbol(cmd.target.symbol.clone()),
+                        start,
+                        cmd.end.unwrap_or_else(Local::now),
+                        cmd.interval,
+                    )
                     .await?;
-                println!("Response /wallet/withwallet_withdrawal: {:?}", resp);
-            }
-            WithdrawalSub::Ln(WithdrawalLn {
-                api_key,
-                api_secret,
-                password,
-                invoice,
-                amount,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client
-                    .wallet_withdrawal(&WithdrawalBody::Lighting {
-                        _type: LnTag::Ln,
-                        payment_request: invoice.clone(),
-                        amount: *amount,
-                    })
+                    (cmd.target, written)
+                }
+                ExportSub::Orders(cmd) => {
+                    client.auth = Some(settings.signer()?);
+                    let exporter = cmd.target.exporter()?;
+                    let start = cmd
+                        .start
+                        .unwrap_or_else(|| Local::now() - Duration::days(1));
+                    let written = export_orders(
+                        &client,
+                        &exporter,
+                        &settings.symbol(cmd.target.symbol.clone()),
+                        start,
+                        cmd.end.unwrap_or_else(Local::now),
+                        cmd.page_size,
+                    )
                     .await?;
-                println!("Response /wallet/withwallet_withdrawal: {:?}", resp);
-            }
-        },
-        SubCommand::Order(order_sub) => match order_sub {
-            OrderSub::Create(OrderCreateCmd {
-                api_key,
-                api_secret,
-                password,
-                symbol,
-                quantity,
-                price,
-                leverage,
-                side,
-                margin_type,
-                order_type,
-                settlement_type,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client
-                    .create_order(&OrderBody {
-                        symbol,
-                        quantity,
-                        price,
-                        leverage,
-                        side,
-                        margin_type,
-                        order_type,
-                        settlement_type,
-                    })
+                    (cmd.target, written)
+                }
+                ExportSub::Fills(cmd) => {
+                    client.auth = Some(settings.signer()?);
+                    let exporter = cmd.target.exporter()?;
+                    let start = cmd
+                        .start
+                        .unwrap_or_else(|| Local::now() - Duration::days(1));
+                    let written = export_fills(
+                        &client,
+                        &exporter,
+                        &settings.symbol(cmd.target.symbol.clone()),
+                        start,
+                        cmd.end.unwrap_or_else(Local::now),
+                        cmd.page_size,
+                    )
                     .await?;
-                println!("Response /orders: {:?}", resp);
-            }
-            OrderSub::Prediction(OrderCreateCmd {
-                api_key,
-                api_secret,
-                password,
-                symbol,
-                quantity,
-                price,
-                leverage,
-                side,
-                margin_type,
-                order_type,
-                settlement_type,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client
-                    .order_prediction(&OrderBody {
-                        symbol,
-                        quantity,
-                        price,
-                        leverage,
-                        side,
-                        margin_type,
-                        order_type,
-                        settlement_type,
-                    })
+                    (cmd.target, written)
+                }
+                ExportSub::Trades(cmd) => {
+                    let exporter = cmd.target.exporter()?;
+                    let trades = record_trades(
+                        ws_options,
+                        settings.symbol(cmd.target.symbol.clone()),
+                        std::time::Duration::from_secs(cmd.duration),
+                    )
                     .await?;
-                println!("Response /orders/prediction: {:?}", resp);
-            }
-            OrderSub::List(OrderListCmd {
-                api_key,
-                api_secret,
-                password,
-                symbol,
-                start,
-                end,
-                limit,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let start_time = start.unwrap_or_else(|| Local::now() - Duration::days(1));
-                let end_time = end.unwrap_or_else(|| Local::now());
-                let resp = client.orders(&symbol, start_time, end_time, limit).await?;
-                println!("Response /orders: {:?}", resp);
-            }
-            OrderSub::Opened(OrderOpenedCmd {
-                api_key,
-                api_secret,
-                password,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client.open_orders().await?;
-                println!("Response /orders/opened: {:?}", resp);
-            }
-            OrderSub::Fills(OrderFillsCmd {
-                api_key,
-                api_secret,
-                password,
-                symbol,
-                start,
-                end,
-                limit,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let start_time = start.unwrap_or_else(|| Local::now() - Duration::days(1));
-                let end_time = end.unwrap_or_else(|| Local::now());
-                let resp = client.fills(&symbol, start_time, end_time, limit).await?;
-                println!("Response /user/fills: {:?}", resp);
-            }
-            OrderSub::Positions(OrderPositionsCmd {
-                api_key,
-                api_secret,
-                password,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client.positions().await?;
-                println!("Response /positions: {:?}", resp);
-            }
-            OrderSub::Cancel(OrderCancelCmd {
-                api_key,
-                api_secret,
-                password,
-                symbol,
-                order_id,
-            }) => {
-                let auth = KolliderAuth::new(&api_key, &api_secret, &password)?;
-                client.auth = Some(auth);
-                let resp = client.cancel_order(&symbol, order_id).await?;
-                println!("Response /orders: {:?}", resp);
-            }
-        },
+                    let written = exporter.append(&trades)?;
+                    (cmd.target, written)
+                }
+            };
+            out.print(&serde_json::json!({
+                "file": target.file,
+                "records": written,
+            }))?;
+        }
+        SubCommand::Journal(JournalSub::Query(cmd)) => {
+            let journal = journal.ok_or(NoJournalPath)?;
+            let events = journal.query(&JournalQuery {
+                source: cmd.source,
+                kind: cmd.kind,
+                symbol: cmd.symbol,
+                order_id: cmd.order_id,
+                since: cmd.since.map(|t| t.with_timezone(&Utc)),
+                until: cmd.until.map(|t| t.with_timezone(&Utc)),
+                limit: Some(cmd.limit),
+            })?;
+            out.print(&events)?;
+        }
         SubCommand::Websocket(ws_sub) => match ws_sub {
             WebsocketSub::Private(WebsocketPrivateCmd {
-                api_key,
-                api_secret,
-                password,
                 symbols,
                 channels,
+                record,
                 action,
             }) => {
+                ws_options.recorder = record.as_deref().map(Recorder::create).transpose()?;
+                let signer = settings.signer()?;
                 let (stdin_tx, stdin_rx) = futures_channel::mpsc::unbounded();
                 let (msg_sender, msg_receiver) = futures_channel::mpsc::unbounded();
-                let auth_msg = make_user_auth(&api_secret, &api_key, &password)?;
+                let stream = kollider_connect(&ws_options).await?;
+                let auth_msg = make_signed_auth_async(signer.as_ref()).await?;
                 stdin_tx.unbounded_send(auth_msg)?;
                 stdin_tx.unbounded_send(KolliderMsg::Subscribe {
                     _type: SubscribeTag::Tag,
@@ -691,18 +881,68 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     symbols,
                 })?;
                 if let Some(a) = action {
-                    stdin_tx.unbounded_send(a.to_message())?;
+                    stdin_tx.unbounded_send(a.into_message(&settings))?;
                 }
-                // tokio::spawn(websocket_stdin_controller(stdin_tx));
-                tokio::spawn(kollider_websocket(stdin_rx, msg_sender));
+                tokio::spawn(kollider_websocket_over(
+                    ws_options,
+                    stream,
+                    stdin_rx,
+                    msg_sender,
+                ));
 
                 msg_receiver
                     .for_each(|message| async move {
-                        println!("Received message: {:?}", message);
+                        if let Err(e) = out.print_stream_item(&message, format_message) {
+                            eprintln!("Failed to print message: {}", e);
+                        }
                     })
                     .await
             }
-            WebsocketSub::Public(WebsocketPublicCmd { symbols, channels }) => {
+            WebsocketSub::Shell(WebsocketShellCmd {
+                login,
+                history,
+                record,
+            }) => {
+                ws_options.recorder = record.as_deref().map(Recorder::create).transpose()?;
+                let auth = settings.optional_signer()?;
+                let symbols = match client.market_products().await {
+                    Ok(products) => products.into_keys().collect(),
+                    Err(e) => {
+                        eprintln!("Failed to fetch products for completion: {}", e);
+                        vec![]
+                    }
+                };
+                let history_file = history.or_else(|| {
+                    std::env::var_os("HOME")
+                        .map(|home| std::path::Path::new(&home).join(".kollider_history"))
+                });
+                let (stdin_tx, stdin_rx) = futures_channel::mpsc::unbounded();
+                let (msg_sender, msg_receiver) = futures_channel::mpsc::unbounded();
+                let stream = kollider_connect(&ws_options).await?;
+                if let (true, Some(auth)) = (login, &auth) {
+                    stdin_tx.unbounded_send(make_signed_auth_async(auth.as_ref()).await?)?;
+                }
+                tokio::spawn(kollider_websocket_over(
+                    ws_options,
+                    stream,
+                    stdin_rx,
+                    msg_sender,
+                ));
+                let options = ShellOptions {
+                    symbols,
+                    default_symbol: settings.symbol.clone(),
+                    auth,
+                    history_file,
+                    ..ShellOptions::default()
+                };
+                websocket_stdin_controller(options, stdin_tx, msg_receiver).await?;
+            }
+            WebsocketSub::Public(WebsocketPublicCmd {
+                symbols,
+                channels,
+                record,
+            }) => {
+                ws_options.recorder = record.as_deref().map(Recorder::create).transpose()?;
                 let (stdin_tx, stdin_rx) = futures_channel::mpsc::unbounded();
                 let (msg_sender, msg_receiver) = futures_channel::mpsc::unbounded();
                 stdin_tx.unbounded_send(KolliderMsg::Subscribe {
@@ -710,17 +950,194 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     channels,
                     symbols,
                 })?;
-                // tokio::spawn(websocket_stdin_controller(stdin_tx));
-                tokio::spawn(kollider_websocket(stdin_rx, msg_sender));
+                tokio::spawn(kollider_websocket_with(ws_options, stdin_rx, msg_sender));
 
                 msg_receiver
                     .for_each(|message| async move {
-                        println!("Received message: {:?}", message);
+                        if let Err(e) = out.print_stream_item(&message, format_message) {
+                            eprintln!("Failed to print message: {}", e);
+                        }
                     })
                     .await
             }
+            WebsocketSub::Replay(WebsocketReplayCmd {
+                file,
+                speed,
+                instant,
+            }) => {
+                let speed = if instant {
+                    ReplaySpeed::Instant
+                } else {
+                    ReplaySpeed::Accelerated(speed)
+                };
+                // Replayed messages already happened, don't journal them twice
+                ws_options.journal = None;
+                let (msg_sender, msg_receiver) = futures_channel::mpsc::unbounded();
+                let replay = replay_websocket(&file, speed, &ws_options, msg_sender);
+                let print = msg_receiver.for_each(|message| async move {
+                    if let Err(e) = out.print_stream_item(&message, format_message) {
+                        eprintln!("Failed to print message: {}", e);
+                    }
+                });
+                let (res, _) = futures::join!(replay, print);
+                res?;
+            }
         },
     }
 
     Ok(())
 }
+
+/// Collect trades of the `matches` channel until the duration passes or Ctrl-C is pressed
+async fn record_trades(
+    options: WebsocketOptions,
+    symbol: Symbol,
+    duration: std::time::Duration,
+) -> Result<Vec<TradeRecord>, Box<dyn Error>> {
+    let (stdin_tx, stdin_rx) = futures_channel::mpsc::unbounded();
+    let (msg_sender, mut msg_receiver) = futures_channel::mpsc::unbounded();
+    stdin_tx.unbounded_send(KolliderMsg::Subscribe {
+        _type: SubscribeTag::Tag,
+        channels: vec![ChannelName::Matches],
+        symbols: vec![symbol.clone()],
+    })?;
+    tokio::spawn(kollider_websocket_with(options, stdin_rx, msg_sender));
+
+    let mut trades = vec![];
+    let deadline = tokio::time::sleep(duration);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = tokio::signal::ctrl_c() => break,
+            message = msg_receiver.next() => match message {
+                Some(KolliderMsg::Tagged(KolliderTaggedMsg::Matches(trade))) if trade.symbol == symbol => {
+                    trades.push(TradeRecord::from(&trade));
+                }
+                Some(_) => (),
+                None => break,
+            },
+        }
+    }
+    Ok(trades)
+}
+
+#[derive(Debug)]
+struct NoJournalPath;
+
+impl Error for NoJournalPath {}
+
+impl fmt::Display for NoJournalPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Journal is not configured, pass --journal or set it in the profile")
+    }
+}
+
+#[derive(Debug)]
+struct NoKeystorePath;
+
+impl Error for NoKeystorePath {}
+
+impl fmt::Display for NoKeystorePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot find keystore location, set HOME or pass --config")
+    }
+}
+
+#[derive(Debug)]
+struct KeystoreExists(PathBuf);
+
+impl Error for KeystoreExists {}
+
+impl fmt::Display for KeystoreExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Keystore {} already exists, use --force to overwrite it",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct PassphraseMismatch;
+
+impl Error for PassphraseMismatch {}
+
+impl fmt::Display for PassphraseMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Passphrases do not match")
+    }
+}
+
+/// Take credentials from flags and the profile, ask for the missing ones without echo
+fn prompt_credentials(settings: &Settings) -> Result<KolliderAuth, Box<dyn Error>> {
+    let prompt = |value: &Option<String>, name: &str| -> std::io::Result<Zeroizing<String>> {
+        match value {
+            Some(v) => Ok(Zeroizing::new(v.clone())),
+            None => Ok(Zeroizing::new(rpassword::prompt_password(format!(
+                "{}: ",
+                name
+            ))?)),
+        }
+    };
+    let api_key = prompt(&settings.api_key, "API key")?;
+    let api_secret = prompt(&settings.api_secret, "API secret")?;
+    let password = prompt(&settings.password, "API passphrase")?;
+    Ok(KolliderAuth::new(&api_key, &api_secret, &password)?)
+}
+
+/// Values of map sorted by key to get stable output
+fn sorted_values<V>(map: &std::collections::HashMap<Symbol, V>) -> Vec<&V> {
+    let mut items: Vec<(&Symbol, &V)> = map.iter().collect();
+    items.sort_by(|a, b| a.0.cmp(b.0));
+    items.into_iter().map(|(_, v)| v).collect()
+}
+
+/// Flatten order book into rows of price levels: asks from the worst to the best price, then bids
+fn orderbook_rows(resp: &OrderBookResp) -> Vec<serde_json::Value> {
+    let level_row = |side: &str, price: u64, quantity: u64, order_id: Option<u64>| {
+        let mut row = serde_json::json!({
+            "side": side,
+            "price": price,
+            "quantity": quantity,
+        });
+        if let Some(id) = order_id {
+            row["order_id"] = id.into();
+        }
+        row
+    };
+    let mut rows = vec![];
+    match &resp.book {
+        OrderBook::Level2(book) => {
+            let parse = |levels: &std::collections::HashMap<String, u64>| {
+                let mut res: Vec<(u64, u64)> = levels
+                    .iter()
+                    .filter_map(|(p, q)| Some((p.parse().ok()?, *q)))
+                    .collect();
+                res.sort_unstable();
+                res
+            };
+            for (p, q) in parse(&book.asks).into_iter().rev() {
+                rows.push(level_row("ask", p, q, None));
+            }
+            for (p, q) in parse(&book.bids).into_iter().rev() {
+                rows.push(level_row("bid", p, q, None));
+            }
+        }
+        OrderBook::Level3(book) => {
+            let mut asks = book.asks.clone();
+            asks.sort_by_key(|l| std::cmp::Reverse(l.0));
+            let mut bids = book.bids.clone();
+            bids.sort_by_key(|l| std::cmp::Reverse(l.0));
+            for (side, levels) in [("ask", asks), ("bid", bids)] {
+                for (price, orders) in levels {
+                    for o in orders {
+                        rows.push(level_row(side, price, o.quantity, Some(o.order_id)));
+                    }
+                }
+            }
+        }
+    }
+    rows
+}
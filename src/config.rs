@@ -0,0 +1,330 @@
+use crate::output::OutputFormat;
+use kollider_api::kollider::api::Symbol;
+use kollider_api::kollider::client::keystore::Keystore;
+use kollider_api::kollider::client::{KolliderAuth, Signer, UnixSocketSigner};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+pub const DEFAULT_SYMBOL: &str = "BTCUSD.PERP";
+
+/// Name of profile when neither `--profile` nor `default_profile` is set
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Environment variable with passphrase of keystore for non interactive usage
+pub const KEYSTORE_PASSPHRASE_ENV: &str = "KOLLIDER_KEYSTORE_PASSPHRASE";
+
+/// Contents of the CLI config file. Example:
+///
+/// ```toml
+/// default_profile = "main"
+///
+/// [profiles.main]
+/// api_key = "..."
+/// api_secret = "..."
+/// password = "..."
+///
+/// [profiles.trading-bot]
+/// testnet = true
+/// symbol = "ETHUSD.PERP"
+/// output = "jsonl"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Config {
+    /// Profile that is used when `--profile` is not specified
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Named set of credentials and defaults
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub api_key: Option<String>,
+    pub api_secret: Option<String>,
+    pub password: Option<String>,
+    /// Use testnet environment
+    #[serde(default)]
+    pub testnet: bool,
+    /// Default symbol for commands that accept `--symbol`
+    pub symbol: Option<Symbol>,
+    /// Default output format
+    pub output: Option<OutputFormat>,
+    /// Path to the encrypted keystore created by `login`
+    pub keystore: Option<PathBuf>,
+    /// Unix socket of external signer. API secret is not needed then.
+    pub signer_socket: Option<PathBuf>,
+    /// SQLite journal that records orders, fills and balances
+    pub journal: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownProfile(String);
+
+impl Error for UnknownProfile {}
+
+impl fmt::Display for UnknownProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Profile '{}' is not found in the config file", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MissingCredential(&'static str);
+
+impl Error for MissingCredential {}
+
+impl fmt::Display for MissingCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Command requires authentification, but {} is not set. Pass it with --{} or add it to the profile",
+            self.0.replace('-', " "),
+            self.0
+        )
+    }
+}
+
+impl Config {
+    /// Default location of the config file: `$XDG_CONFIG_HOME/kollider/config.toml`
+    /// or `$HOME/.config/kollider/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("kollider").join("config.toml"))
+    }
+
+    /// Read config from the file. Missing file is treated as empty config.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Name of the profile that is used for the given `--profile` argument
+    pub fn profile_name(&self, name: Option<&str>) -> String {
+        name.or(self.default_profile.as_deref())
+            .unwrap_or(DEFAULT_PROFILE)
+            .to_owned()
+    }
+
+    /// Default location of keystore of profile: `keystore/<profile>.json` next to the config file
+    pub fn keystore_path(config_path: &Path, profile: &str) -> PathBuf {
+        config_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("keystore")
+            .join(format!("{}.json", profile))
+    }
+
+    /// Find profile by name. Without name returns the default profile if any.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, UnknownProfile> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| UnknownProfile(name.to_owned())),
+            None => Ok(Profile::default()),
+        }
+    }
+}
+
+/// Settings of the CLI after merging command line arguments and the selected profile.
+/// Explicit arguments take precedence over the profile.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub api_key: Option<String>,
+    pub api_secret: Option<String>,
+    pub password: Option<String>,
+    pub testnet: bool,
+    pub symbol: Symbol,
+    pub output: OutputFormat,
+    /// Keystore that is unlocked when credentials are not passed explicitly
+    pub keystore: Option<PathBuf>,
+    /// Unix socket of external signer
+    pub signer_socket: Option<PathBuf>,
+    /// SQLite journal of orders, fills and balances
+    pub journal: Option<PathBuf>,
+}
+
+impl Settings {
+    pub fn new(
+        profile: Profile,
+        credentials: Credentials,
+        testnet: bool,
+        output: Option<OutputFormat>,
+        keystore: Option<PathBuf>,
+        journal: Option<PathBuf>,
+    ) -> Self {
+        Settings {
+            api_key: credentials.api_key.or(profile.api_key),
+            api_secret: credentials.api_secret.or(profile.api_secret),
+            password: credentials.password.or(profile.password),
+            testnet: testnet || profile.testnet,
+            symbol: profile.symbol.unwrap_or_else(|| DEFAULT_SYMBOL.to_owned()),
+            output: output.or(profile.output).unwrap_or(OutputFormat::Table),
+            keystore: profile.keystore.or(keystore),
+            signer_socket: credentials.signer_socket.or(profile.signer_socket),
+            journal: journal.or(profile.journal),
+        }
+    }
+
+    /// Credentials for commands that require authentification. If credentials are not
+    /// passed explicitly, they are taken from the keystore of the profile.
+    pub fn auth(&self) -> Result<KolliderAuth, Box<dyn Error>> {
+        match (&self.api_key, &self.api_secret, &self.password) {
+            (Some(api_key), Some(api_secret), Some(password)) => {
+                Ok(KolliderAuth::new(api_key, api_secret, password)?)
+            }
+            _ if self.has_keystore() => self.unlock_keystore(),
+            (None, _, _) => Err(MissingCredential("api-key").into()),
+            (_, None, _) => Err(MissingCredential("api-secret").into()),
+            _ => Err(MissingCredential("password").into()),
+        }
+    }
+
+    /// Signer for commands that require authentification. External signer is used if
+    /// its socket is set, otherwise requests are signed in process with `auth` credentials.
+    pub fn signer(&self) -> Result<Arc<dyn Signer>, Box<dyn Error>> {
+        match &self.signer_socket {
+            Some(path) => {
+                let api_key = self.api_key.as_ref().ok_or(MissingCredential("api-key"))?;
+                let password = self
+                    .password
+                    .as_ref()
+                    .ok_or(MissingCredential("password"))?;
+                Ok(Arc::new(UnixSocketSigner::new(path, api_key, password)))
+            }
+            None => Ok(Arc::new(self.auth()?)),
+        }
+    }
+
+    /// Signer if any of credentials are set or there is a keystore
+    pub fn optional_signer(&self) -> Result<Option<Arc<dyn Signer>>, Box<dyn Error>> {
+        let no_flags =
+            self.api_key.is_none() && self.api_secret.is_none() && self.password.is_none();
+        if no_flags && !self.has_keystore() {
+            Ok(None)
+        } else {
+            self.signer().map(Some)
+        }
+    }
+
+    fn has_keystore(&self) -> bool {
+        self.keystore.as_ref().is_some_and(|path| path.exists())
+    }
+
+    /// Decrypt keystore asking for passphrase unless it is set in the environment
+    fn unlock_keystore(&self) -> Result<KolliderAuth, Box<dyn Error>> {
+        let path = self.keystore.as_ref().ok_or(MissingCredential("api-key"))?;
+        let keystore = Keystore::load(path)?;
+        let passphrase = match std::env::var(KEYSTORE_PASSPHRASE_ENV) {
+            Ok(passphrase) => Zeroizing::new(passphrase),
+            Err(_) => Zeroizing::new(rpassword::prompt_password(format!(
+                "Passphrase for {}: ",
+                path.display()
+            ))?),
+        };
+        Ok(keystore.decrypt(&passphrase)?)
+    }
+
+    /// Symbol from the command line or the profile default
+    pub fn symbol(&self, symbol: Option<Symbol>) -> Symbol {
+        symbol.unwrap_or_else(|| self.symbol.clone())
+    }
+}
+
+/// Credential flags shared by all commands
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct Credentials {
+    #[clap(
+        long,
+        env = "KOLLIDER_API_KEY",
+        hide_env_values = true,
+        global = true,
+        help_heading = "CREDENTIALS"
+    )]
+    pub api_key: Option<String>,
+    #[clap(
+        long,
+        env = "KOLLIDER_API_SECRET",
+        hide_env_values = true,
+        global = true,
+        help_heading = "CREDENTIALS"
+    )]
+    pub api_secret: Option<String>,
+    #[clap(
+        long,
+        env = "KOLLIDER_API_PASSWORD",
+        hide_env_values = true,
+        global = true,
+        help_heading = "CREDENTIALS"
+    )]
+    pub password: Option<String>,
+    /// Unix socket of external signer that holds API secret
+    #[clap(
+        long,
+        env = "KOLLIDER_SIGNER_SOCKET",
+        global = true,
+        help_heading = "CREDENTIALS"
+    )]
+    pub signer_socket: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_profiles() {
+        let data = r#"
+        default_profile = "main"
+
+        [profiles.main]
+        api_key = "key"
+        api_secret = "c2VjcmV0"
+        password = "pass"
+
+        [profiles.trading-bot]
+        testnet = true
+        symbol = "ETHUSD.PERP"
+        output = "jsonl"
+        "#;
+        let config: Config = toml::from_str(data).unwrap();
+        let main = config.profile(None).unwrap();
+        assert_eq!(main.api_key, Some("key".to_owned()));
+        assert!(!main.testnet);
+
+        let bot = config.profile(Some("trading-bot")).unwrap();
+        let settings = Settings::new(
+            bot,
+            Credentials {
+                api_key: Some("other".to_owned()),
+                ..Credentials::default()
+            },
+            false,
+            None,
+            None,
+            None,
+        );
+        assert!(settings.testnet);
+        assert_eq!(settings.symbol, "ETHUSD.PERP");
+        assert_eq!(settings.output, OutputFormat::Jsonl);
+        assert_eq!(settings.api_key, Some("other".to_owned()));
+        assert!(settings.auth().is_err());
+
+        assert_eq!(
+            config.profile(Some("missing")),
+            Err(UnknownProfile("missing".to_owned()))
+        );
+    }
+}
@@ -38,7 +38,7 @@ impl From<DepositBody> for DepositBodyInner {
 }
 
 /// Response item of the /wallet/deposit
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 #[serde(untagged)]
 pub enum DepositResp {
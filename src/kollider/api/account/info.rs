@@ -1,10 +1,10 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "openapi")]
 use rweb::Schema;
 
 /// Response item of the /user/account
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct AccountInfo {
     created_at: AccountCreated,
@@ -15,7 +15,7 @@ pub struct AccountInfo {
     validated_email: bool,
 }
 
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct AccountCreated {
     nanos_since_epoch: u64,
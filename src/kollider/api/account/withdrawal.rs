@@ -50,7 +50,7 @@ pub enum WithdrawalStatus {
 }
 
 /// Response body for the /wallet/withdrawal
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub enum WithdrawalResp {
     WithdrawalSuccess {
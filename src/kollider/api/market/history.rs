@@ -20,6 +20,26 @@ pub enum IntervalSize {
     OneDay,
 }
 
+impl IntervalSize {
+    /// All intervals that the server provides from the finest to the coarsest
+    pub const ALL: [IntervalSize; 4] = [
+        IntervalSize::FiveMin,
+        IntervalSize::FifteenMin,
+        IntervalSize::OneHour,
+        IntervalSize::OneDay,
+    ];
+
+    /// Length of the interval in seconds
+    pub fn seconds(&self) -> u64 {
+        match self {
+            IntervalSize::FiveMin => 5 * 60,
+            IntervalSize::FifteenMin => 15 * 60,
+            IntervalSize::OneHour => 60 * 60,
+            IntervalSize::OneDay => 24 * 60 * 60,
+        }
+    }
+}
+
 impl fmt::Display for IntervalSize {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -61,21 +81,23 @@ impl FromStr for IntervalSize {
 }
 
 /// Response body of the /market/historic_index_prices
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct HistoryResp {
-    data: Vec<HistoryItem>,
-    symbol: Symbol,
+    pub data: Vec<HistoryItem>,
+    pub symbol: Symbol,
 }
 
 /// Response item of the /market/historic_index_prices
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct HistoryItem {
-    max: Option<f64>,
-    min: Option<f64>,
-    mean: Option<f64>,
-    time: u64,
+    /// `None` when there were no index updates in the interval
+    pub max: Option<f64>,
+    pub min: Option<f64>,
+    pub mean: Option<f64>,
+    /// Start of the interval in seconds
+    pub time: u64,
 }
 
 #[cfg(test)]
@@ -1,7 +1,9 @@
 pub mod history;
 pub mod orderbook;
+pub mod resample;
 pub mod ticker;
 
 pub use history::*;
 pub use orderbook::*;
+pub use resample::*;
 pub use ticker::*;
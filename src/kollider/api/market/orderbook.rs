@@ -1,12 +1,12 @@
 use super::super::{order::OrderDetails, products::Symbol};
 use serde::{
     de::{self, Deserializer},
-    Deserialize,
+    Deserialize, Serialize,
 };
 use std::collections::HashMap;
 
 /// Response item of the /market/orderbook
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Debug, PartialEq, Clone)]
 pub struct OrderBookResp {
     pub level: OrderBookLevel,
     pub seq_number: u64,
@@ -64,7 +64,7 @@ impl<'de> Deserialize<'de> for OrderBookResp {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum OrderBookLevel {
     Level2,
     Level3,
@@ -87,19 +87,20 @@ impl OrderBookLevel {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
 pub enum OrderBook {
     Level2(OrderBookLevel2),
     Level3(OrderBookLevel3),
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct OrderBookLevel2 {
     pub asks: HashMap<String, u64>,
     pub bids: HashMap<String, u64>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct OrderBookLevel3 {
     pub asks: Vec<(u64, Vec<OrderDetails>)>,
     pub bids: Vec<(u64, Vec<OrderDetails>)>,
@@ -0,0 +1,274 @@
+use super::history::{HistoryItem, HistoryResp, IntervalSize};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+/// Unix time of the first Monday, weeks start on Monday 00:00 UTC
+const FIRST_MONDAY: i64 = 4 * DAY as i64;
+
+/// Arbitrary length of history buckets, e.x. 4h or 1w. Buckets are aligned to Unix epoch in UTC,
+/// multiples of week are aligned to Monday.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct Timeframe {
+    seconds: u64,
+}
+
+impl Timeframe {
+    pub fn from_seconds(seconds: u64) -> Option<Self> {
+        if seconds == 0 {
+            None
+        } else {
+            Some(Timeframe { seconds })
+        }
+    }
+
+    pub fn seconds(&self) -> u64 {
+        self.seconds
+    }
+
+    /// Interval of the server with the same length
+    pub fn native(&self) -> Option<IntervalSize> {
+        IntervalSize::ALL
+            .into_iter()
+            .find(|interval| interval.seconds() == self.seconds)
+    }
+
+    /// The coarsest server interval that the timeframe can be built from
+    pub fn source(&self) -> Option<IntervalSize> {
+        IntervalSize::ALL
+            .into_iter()
+            .rev()
+            .find(|interval| self.seconds.is_multiple_of(interval.seconds()))
+    }
+
+    /// Start of the bucket that contains the time in seconds
+    pub fn bucket_start(&self, time: u64) -> u64 {
+        let offset = if self.seconds.is_multiple_of(WEEK) {
+            FIRST_MONDAY
+        } else {
+            0
+        };
+        let size = self.seconds as i64;
+        ((time as i64 - offset).div_euclid(size) * size + offset) as u64
+    }
+}
+
+impl From<IntervalSize> for Timeframe {
+    fn from(interval: IntervalSize) -> Self {
+        Timeframe {
+            seconds: interval.seconds(),
+        }
+    }
+}
+
+impl fmt::Display for Timeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (unit, name) in [(WEEK, "w"), (DAY, "d"), (HOUR, "h"), (MINUTE, "m")] {
+            if self.seconds.is_multiple_of(unit) {
+                return write!(f, "{}{}", self.seconds / unit, name);
+            }
+        }
+        write!(f, "{}s", self.seconds)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct UnknownTimeframe(String);
+
+impl std::error::Error for UnknownTimeframe {}
+
+impl fmt::Display for UnknownTimeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Given timeframe '{}' is invalid, expected positive number with unit m, h, d or w, e.x. 4h",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Timeframe {
+    type Err = UnknownTimeframe;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || UnknownTimeframe(s.to_owned());
+        let split = s.len().checked_sub(1).filter(|i| s.is_char_boundary(*i));
+        let (num, unit) = s.split_at(split.ok_or_else(err)?);
+        let unit = match unit {
+            "m" => MINUTE,
+            "h" => HOUR,
+            "d" => DAY,
+            "w" => WEEK,
+            _ => return Err(err()),
+        };
+        let num: u64 = num.parse().map_err(|_| err())?;
+        num.checked_mul(unit)
+            .and_then(Timeframe::from_seconds)
+            .ok_or_else(err)
+    }
+}
+
+impl TryFrom<String> for Timeframe {
+    type Error = UnknownTimeframe;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Timeframe> for String {
+    fn from(timeframe: Timeframe) -> Self {
+        timeframe.to_string()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IncompatibleTimeframe {
+    pub source: IntervalSize,
+    pub target: Timeframe,
+}
+
+impl std::error::Error for IncompatibleTimeframe {}
+
+impl fmt::Display for IncompatibleTimeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cannot build {} intervals from {} intervals, the length must be a multiple",
+            self.target, self.source
+        )
+    }
+}
+
+/// Merge items of `source` interval into coarser buckets of `target` timeframe. Extremes are
+/// taken over the items that have them and mean is the average of defined means, so bucket
+/// fields are `None` only when all its items are empty. Buckets without items are skipped.
+pub fn resample(
+    items: &[HistoryItem],
+    source: IntervalSize,
+    target: Timeframe,
+) -> Result<Vec<HistoryItem>, IncompatibleTimeframe> {
+    if !target.seconds().is_multiple_of(source.seconds()) {
+        return Err(IncompatibleTimeframe { source, target });
+    }
+    let mut buckets: BTreeMap<u64, Vec<&HistoryItem>> = BTreeMap::new();
+    for item in items {
+        buckets
+            .entry(target.bucket_start(item.time))
+            .or_default()
+            .push(item);
+    }
+    Ok(buckets
+        .into_iter()
+        .map(|(time, items)| {
+            let means: Vec<f64> = items.iter().filter_map(|item| item.mean).collect();
+            HistoryItem {
+                max: items.iter().filter_map(|item| item.max).reduce(f64::max),
+                min: items.iter().filter_map(|item| item.min).reduce(f64::min),
+                mean: if means.is_empty() {
+                    None
+                } else {
+                    Some(means.iter().sum::<f64>() / means.len() as f64)
+                },
+                time,
+            }
+        })
+        .collect())
+}
+
+impl HistoryResp {
+    /// Resample data of `source` interval to coarser `target` timeframe, see `resample`
+    pub fn resample(
+        &self,
+        source: IntervalSize,
+        target: Timeframe,
+    ) -> Result<HistoryResp, IncompatibleTimeframe> {
+        Ok(HistoryResp {
+            data: resample(&self.data, source, target)?,
+            symbol: self.symbol.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(time: u64, values: Option<(f64, f64, f64)>) -> HistoryItem {
+        HistoryItem {
+            max: values.map(|v| v.0),
+            min: values.map(|v| v.1),
+            mean: values.map(|v| v.2),
+            time,
+        }
+    }
+
+    #[test]
+    fn test_timeframe_parse() {
+        let tf: Timeframe = "4h".parse().unwrap();
+        assert_eq!(tf.seconds(), 4 * HOUR);
+        assert_eq!(tf.to_string(), "4h");
+        assert_eq!(tf.source(), Some(IntervalSize::OneHour));
+        assert_eq!(tf.native(), None);
+        assert_eq!("60m".parse::<Timeframe>().unwrap().to_string(), "1h");
+        assert_eq!(
+            "1d".parse::<Timeframe>().unwrap().native(),
+            Some(IntervalSize::OneDay)
+        );
+        assert_eq!(
+            "7m".parse::<Timeframe>().unwrap().source(),
+            None,
+            "7m is not a multiple of 5m"
+        );
+        for invalid in ["", "h", "0h", "4x", "-1d", "1.5h"] {
+            assert!(invalid.parse::<Timeframe>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_resample() {
+        // Monday 2021-12-20 00:00 UTC
+        let monday = 1639958400;
+        let items = vec![
+            item(monday, Some((10.0, 5.0, 7.0))),
+            item(monday + HOUR, None),
+            item(monday + 2 * HOUR, Some((12.0, 8.0, 9.0))),
+            item(monday + 4 * HOUR, None),
+            item(monday + 6 * DAY + 23 * HOUR, Some((20.0, 1.0, 11.0))),
+            item(monday + WEEK, Some((3.0, 2.0, 2.5))),
+        ];
+        let four_hours = resample(&items, IntervalSize::OneHour, "4h".parse().unwrap()).unwrap();
+        assert_eq!(
+            four_hours,
+            vec![
+                item(monday, Some((12.0, 5.0, 8.0))),
+                item(monday + 4 * HOUR, None),
+                item(monday + 6 * DAY + 20 * HOUR, Some((20.0, 1.0, 11.0))),
+                item(monday + WEEK, Some((3.0, 2.0, 2.5))),
+            ]
+        );
+
+        let weekly = resample(&items, IntervalSize::OneHour, "1w".parse().unwrap()).unwrap();
+        assert_eq!(
+            weekly,
+            vec![
+                item(monday, Some((20.0, 1.0, 9.0))),
+                item(monday + WEEK, Some((3.0, 2.0, 2.5))),
+            ]
+        );
+
+        assert_eq!(
+            resample(&items, IntervalSize::OneDay, "4h".parse().unwrap()),
+            Err(IncompatibleTimeframe {
+                source: IntervalSize::OneDay,
+                target: "4h".parse().unwrap(),
+            })
+        );
+    }
+}
@@ -148,7 +148,7 @@ impl FromStr for OrderSide {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct OrderDetails {
     // pub advanced_order_type: String, //: null,
@@ -168,7 +168,7 @@ pub struct OrderDetails {
     pub uid: u64, //: 1
 }
 
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct FillDetails {
     #[serde(flatten)]
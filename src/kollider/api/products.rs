@@ -24,6 +24,8 @@ pub struct Product {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub price_dp: f64,
     pub underlying_symbol: Symbol,
+    /// Last traded price in integer units of the API like prices of orders, see
+    /// `Product::human_last_price`
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub last_price: f64,
     #[serde(deserialize_with = "deserialize_number_from_string")]
@@ -32,6 +34,23 @@ pub struct Product {
     pub risk_limit: f64,
 }
 
+impl Product {
+    /// Convert integer price of orders and fills to decimal price
+    pub fn human_price(&self, price: u64) -> f64 {
+        price as f64 / 10f64.powi(self.price_dp as i32)
+    }
+
+    /// Convert decimal price to integer price of orders and fills
+    pub fn api_price(&self, price: f64) -> u64 {
+        (price * 10f64.powi(self.price_dp as i32)).round() as u64
+    }
+
+    /// Decimal last traded price of the product
+    pub fn human_last_price(&self) -> f64 {
+        self.human_price(self.last_price as u64)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
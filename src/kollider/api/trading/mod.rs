@@ -1,3 +1,5 @@
+pub mod model;
 pub mod order;
 
+pub use model::*;
 pub use order::*;
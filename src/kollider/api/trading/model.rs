@@ -0,0 +1,167 @@
+//! Canonical models of positions and orders. REST and WebSocket APIs report them in different
+//! wire formats (`PositionDetails` and `websocket::data::Position`, `OrderDetails` and
+//! `websocket::data::OpenOrder`), both convert into the types of this module.
+use super::order::PositionDetails;
+use crate::kollider::api::{
+    MarginType, OrderDetails, OrderSide, OrderType, SettlementType, Symbol,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Open position of the user in a symbol
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Position {
+    pub uid: u64,
+    pub symbol: Symbol,
+    /// `None` for a closed position
+    pub side: Option<OrderSide>,
+    /// Number of contracts
+    pub quantity: u64,
+    pub entry_price: f64,
+    pub leverage: f64,
+    pub liq_price: f64,
+    pub upnl: f64,
+    pub open_order_ids: Vec<u64>,
+    /// Time of the last update in milliseconds
+    pub timestamp: u64,
+    /// Fields below are reported only by the WebSocket API
+    pub position_id: Option<String>,
+    pub entry_time: Option<u64>,
+    pub entry_value: Option<f64>,
+    pub mark_value: Option<f64>,
+    pub bankruptcy_price: Option<f64>,
+    pub rpnl: Option<f64>,
+    pub funding: Option<f64>,
+    pub real_leverage: Option<f64>,
+    pub adl_score: Option<f64>,
+    pub is_liquidating: Option<bool>,
+}
+
+/// Order id in REST response that is not a number
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct InvalidOrderId(pub String);
+
+impl std::error::Error for InvalidOrderId {}
+
+impl fmt::Display for InvalidOrderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Order id '{}' is not a number", self.0)
+    }
+}
+
+impl TryFrom<PositionDetails> for Position {
+    type Error = InvalidOrderId;
+
+    fn try_from(position: PositionDetails) -> Result<Self, Self::Error> {
+        let open_order_ids = position
+            .open_order_ids
+            .iter()
+            .map(|id| id.parse().map_err(|_| InvalidOrderId(id.clone())))
+            .collect::<Result<_, _>>()?;
+        Ok(Position {
+            uid: position.uid,
+            symbol: position.symbol,
+            side: Some(position.side),
+            quantity: position.quantity.round() as u64,
+            entry_price: position.entry_price,
+            leverage: position.leverage,
+            liq_price: position.liq_price,
+            upnl: position.upnl as f64,
+            open_order_ids,
+            timestamp: position.timestamp,
+            position_id: None,
+            entry_time: None,
+            entry_value: None,
+            mark_value: None,
+            bankruptcy_price: None,
+            rpnl: None,
+            funding: None,
+            real_leverage: None,
+            adl_score: None,
+            is_liquidating: None,
+        })
+    }
+}
+
+/// Order of the user, open or historical
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Order {
+    pub uid: u64,
+    pub order_id: u64,
+    pub ext_order_id: String,
+    pub symbol: Symbol,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub margin_type: MarginType,
+    pub settlement_type: SettlementType,
+    /// Price in integer units of the API, see `Product::human_price`
+    pub price: u64,
+    pub quantity: u64,
+    pub filled: u64,
+    pub leverage: u64,
+    pub timestamp: u64,
+}
+
+impl Order {
+    /// Quantity that is not filled yet
+    pub fn remaining(&self) -> u64 {
+        self.quantity.saturating_sub(self.filled)
+    }
+}
+
+impl From<OrderDetails> for Order {
+    fn from(order: OrderDetails) -> Self {
+        Order {
+            uid: order.uid,
+            order_id: order.order_id,
+            ext_order_id: order.ext_order_id,
+            symbol: order.symbol,
+            side: order.side,
+            order_type: order.order_type,
+            margin_type: order.margin_type,
+            settlement_type: order.settlement_type,
+            price: order.price,
+            quantity: order.quantity,
+            filled: order.filled.round() as u64,
+            leverage: order.leverage,
+            timestamp: order.timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_from_rest() {
+        let details = PositionDetails {
+            uid: 11,
+            timestamp: 1604332066202,
+            symbol: "BTCUSD.PERP".to_owned(),
+            upnl: -6,
+            leverage: 1.0,
+            entry_price: 13534.0,
+            side: OrderSide::Bid,
+            quantity: 1.0,
+            liq_price: 6788.3,
+            open_order_ids: vec!["9951519".to_owned()],
+        };
+        let position = Position::try_from(details.clone()).unwrap();
+        assert_eq!(position.side, Some(OrderSide::Bid));
+        assert_eq!(position.quantity, 1);
+        assert_eq!(position.upnl, -6.0);
+        assert_eq!(position.open_order_ids, vec![9951519]);
+        assert_eq!(position.rpnl, None);
+
+        let invalid = PositionDetails {
+            open_order_ids: vec!["abc".to_owned()],
+            ..details
+        };
+        assert_eq!(
+            Position::try_from(invalid),
+            Err(InvalidOrderId("abc".to_owned()))
+        );
+    }
+}
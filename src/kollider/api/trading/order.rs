@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use serde_aux::field_attributes::deserialize_number_from_string;
 
 /// Body of post /orders
-#[derive(Serialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 pub struct OrderBody {
     pub leverage: u64,
     pub margin_type: MarginType,
@@ -18,7 +18,7 @@ pub struct OrderBody {
     pub symbol: Symbol,
 }
 
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 pub struct OrderPrediction {
     pub uid: u64,
     pub ext_id: String,
@@ -33,7 +33,7 @@ pub struct OrderPrediction {
     pub rejection_reason: Option<String>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 pub struct PositionDetails {
     pub uid: u64,
     pub timestamp: u64,
@@ -52,7 +52,7 @@ pub struct PositionDetails {
     pub open_order_ids: Vec<String>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone)]
 pub struct OrderCreated {
     pub timestamp: u64,
     pub order_id: u64,
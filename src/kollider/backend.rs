@@ -0,0 +1,299 @@
+//! Common interface of trading backends. Strategy code that is written against `TradingApi`
+//! can trade via REST (`KolliderClient`), WebSocket (`WebsocketSession`) or locally
+//! (`PaperTradingClient`, or any of them pointed to the mock server) by changing only the
+//! construction of the backend. `OneshotWebsocketClient` opens a new socket for every request,
+//! it suits occasional requests, not a stream of orders.
+//!
+//! ```no_run
+//! # use kollider_api::kollider::backend::*;
+//! # use kollider_api::kollider::client::{KolliderAuth, KolliderClient};
+//! # use kollider_api::kollider::websocket::{session::WebsocketSession, WebsocketOptions};
+//! async fn make_backend(transport: &str, auth: KolliderAuth) -> Result<Box<dyn TradingApi>> {
+//!     Ok(match transport {
+//!         "ws" => Box::new(WebsocketSession::connect(&WebsocketOptions::default(), &auth).await?),
+//!         "ws-oneshot" => Box::new(OneshotWebsocketClient::new(auth)),
+//!         _ => {
+//!             let mut client = KolliderClient::mainnet();
+//!             client.set_auth(auth);
+//!             Box::new(client)
+//!         }
+//!     })
+//! }
+//! ```
+use crate::kollider::api::{InvalidOrderId, Order, OrderBody, OrderCreated, Position, Symbol};
+use crate::kollider::backtest::BacktestError;
+use crate::kollider::client::env::KolliderClient;
+use crate::kollider::client::error::Error as RestError;
+use crate::kollider::client::signer::Signer;
+use crate::kollider::paper::{PaperError, PaperTradingClient};
+use crate::kollider::websocket::client::WebsocketOptions;
+use crate::kollider::websocket::oneshot::{self, Error as WebsocketError};
+use crate::kollider::websocket::session::WebsocketSession;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Error of any trading backend
+#[derive(Error, Debug)]
+pub enum TradingError {
+    #[error("REST request failed: {0}")]
+    Rest(#[from] RestError),
+    #[error("WebSocket request failed: {0}")]
+    Websocket(#[from] WebsocketError),
+    #[error("Paper trading failed: {0}")]
+    Paper(#[from] PaperError),
+    #[error("Backtest order failed: {0}")]
+    Backtest(#[from] BacktestError),
+    #[error("{0}")]
+    InvalidOrderId(#[from] InvalidOrderId),
+}
+
+/// Alias for a `Result` with the error type `TradingError`.
+pub type Result<T> = std::result::Result<T, TradingError>;
+
+/// Order placement and account state operations that every backend supports
+#[async_trait]
+pub trait TradingApi: Send + Sync {
+    async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated>;
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()>;
+
+    async fn open_orders(&self) -> Result<HashMap<Symbol, Vec<Order>>>;
+
+    async fn positions(&self) -> Result<HashMap<Symbol, Position>>;
+}
+
+#[async_trait]
+impl TradingApi for KolliderClient {
+    async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated> {
+        Ok(KolliderClient::create_order(self, body).await?)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()> {
+        Ok(KolliderClient::cancel_order(self, symbol, order_id).await?)
+    }
+
+    async fn open_orders(&self) -> Result<HashMap<Symbol, Vec<Order>>> {
+        Ok(canonical_orders(KolliderClient::open_orders(self).await?))
+    }
+
+    async fn positions(&self) -> Result<HashMap<Symbol, Position>> {
+        canonical_positions(KolliderClient::positions(self).await?)
+    }
+}
+
+#[async_trait]
+impl TradingApi for PaperTradingClient {
+    async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated> {
+        Ok(PaperTradingClient::create_order(self, body).await?)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()> {
+        Ok(PaperTradingClient::cancel_order(self, symbol, order_id).await?)
+    }
+
+    async fn open_orders(&self) -> Result<HashMap<Symbol, Vec<Order>>> {
+        Ok(canonical_orders(
+            PaperTradingClient::open_orders(self).await?,
+        ))
+    }
+
+    async fn positions(&self) -> Result<HashMap<Symbol, Position>> {
+        canonical_positions(PaperTradingClient::positions(self).await?)
+    }
+}
+
+#[async_trait]
+impl TradingApi for WebsocketSession {
+    async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated> {
+        Ok(WebsocketSession::create_order(self, body).await?)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()> {
+        Ok(WebsocketSession::cancel_order(self, symbol, order_id).await?)
+    }
+
+    async fn open_orders(&self) -> Result<HashMap<Symbol, Vec<Order>>> {
+        Ok(canonical_orders(WebsocketSession::open_orders(self).await?))
+    }
+
+    async fn positions(&self) -> Result<HashMap<Symbol, Position>> {
+        Ok(WebsocketSession::positions(self)
+            .await?
+            .into_iter()
+            .map(|(symbol, position)| (symbol, position.into()))
+            .collect())
+    }
+}
+
+/// Backend that makes every request in a short authentificated WebSocket session,
+/// see `websocket::oneshot`. Each call connects, authentificates and closes the socket, so
+/// it costs a handshake and an auth round trip. Use `WebsocketSession` for low latency
+/// trading.
+#[derive(Clone)]
+pub struct OneshotWebsocketClient {
+    pub signer: Arc<dyn Signer>,
+    pub options: WebsocketOptions,
+}
+
+impl OneshotWebsocketClient {
+    /// Client connected to `KOLLIDER_WEBSOCKET`
+    pub fn new<S: Signer + 'static>(signer: S) -> Self {
+        Self::with_options(signer, WebsocketOptions::default())
+    }
+
+    pub fn with_options<S: Signer + 'static>(signer: S, options: WebsocketOptions) -> Self {
+        OneshotWebsocketClient {
+            signer: Arc::new(signer),
+            options,
+        }
+    }
+}
+
+#[async_trait]
+impl TradingApi for OneshotWebsocketClient {
+    async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated> {
+        Ok(oneshot::open_order_with(&self.options, self.signer.as_ref(), body).await?)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()> {
+        Ok(
+            oneshot::cancel_order_with(&self.options, self.signer.as_ref(), order_id, symbol)
+                .await?,
+        )
+    }
+
+    async fn open_orders(&self) -> Result<HashMap<Symbol, Vec<Order>>> {
+        let orders = oneshot::fetch_open_orders_with(&self.options, self.signer.as_ref()).await?;
+        Ok(canonical_orders(orders))
+    }
+
+    async fn positions(&self) -> Result<HashMap<Symbol, Position>> {
+        let positions = oneshot::fetch_positions_with(&self.options, self.signer.as_ref()).await?;
+        Ok(positions
+            .into_iter()
+            .map(|(symbol, position)| (symbol, position.into()))
+            .collect())
+    }
+}
+
+fn canonical_orders<T: Into<Order>>(
+    orders: HashMap<Symbol, Vec<T>>,
+) -> HashMap<Symbol, Vec<Order>> {
+    orders
+        .into_iter()
+        .map(|(symbol, orders)| (symbol, orders.into_iter().map(Into::into).collect()))
+        .collect()
+}
+
+fn canonical_positions<T>(positions: HashMap<Symbol, T>) -> Result<HashMap<Symbol, Position>>
+where
+    Position: TryFrom<T, Error = InvalidOrderId>,
+{
+    positions
+        .into_iter()
+        .map(|(symbol, position)| Ok((symbol, Position::try_from(position)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::{MarginType, OrderSide, OrderType, Product, SettlementType};
+
+    const SYMBOL: &str = "BTCUSD.PERP";
+
+    fn limit(side: OrderSide, price: u64, quantity: u64) -> OrderBody {
+        OrderBody {
+            leverage: 1,
+            margin_type: MarginType::Isolated,
+            order_type: OrderType::Limit,
+            price,
+            quantity,
+            settlement_type: SettlementType::Delayed,
+            side,
+            symbol: SYMBOL.to_owned(),
+        }
+    }
+
+    /// The same strategy steps for every backend: rest an order, cancel it, open a position
+    async fn run_scenario(api: &dyn TradingApi) {
+        let resting = api
+            .create_order(&limit(OrderSide::Bid, 490000, 3))
+            .await
+            .unwrap();
+        assert_eq!(api.open_orders().await.unwrap()[SYMBOL].len(), 1);
+        api.cancel_order(SYMBOL, resting.order_id).await.unwrap();
+        assert!(api
+            .open_orders()
+            .await
+            .unwrap()
+            .get(SYMBOL)
+            .is_none_or(|orders| orders.is_empty()));
+
+        api.create_order(&limit(OrderSide::Bid, 500000, 5))
+            .await
+            .unwrap();
+        let positions = api.positions().await.unwrap();
+        assert_eq!(positions[SYMBOL].quantity, 5);
+        assert_eq!(positions[SYMBOL].side, Some(OrderSide::Bid));
+    }
+
+    #[tokio::test]
+    async fn test_paper_backend() {
+        use crate::kollider::websocket::data::{
+            KolliderMsg, KolliderTaggedMsg, OrderBookLevel2, UpdateType,
+        };
+
+        let product = Product {
+            symbol: SYMBOL.to_owned(),
+            contract_size: 1.0,
+            max_leverage: 100.0,
+            base_margin: 0.005,
+            maintenance_margin: 0.004,
+            is_inverse_priced: true,
+            price_dp: 1.0,
+            underlying_symbol: ".BTCUSD".to_owned(),
+            last_price: 500000.0,
+            tick_size: 0.5,
+            risk_limit: 100000000.0,
+        };
+        let paper = PaperTradingClient::new(HashMap::from([(SYMBOL.to_owned(), product)]), 100_000);
+        paper.on_message(&KolliderMsg::Tagged(KolliderTaggedMsg::OrderBookLevel2(
+            OrderBookLevel2 {
+                asks: HashMap::from([("500000".to_owned(), 100)]),
+                bids: HashMap::new(),
+                seq_number: 1,
+                symbol: SYMBOL.to_owned(),
+                update_type: UpdateType::Snapshot,
+            },
+        )));
+        run_scenario(&paper).await;
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_rest_and_websocket_backends() {
+        use crate::kollider::mock::MockServer;
+
+        let server = MockServer::start().await.unwrap();
+        server
+            .add_liquidity(SYMBOL, OrderSide::Ask, 500000, 100)
+            .unwrap();
+
+        let mut rest = server.client();
+        rest.set_auth(server.add_account(100_000));
+        let session = WebsocketSession::connect(&server.ws_options(), &server.add_account(100_000))
+            .await
+            .unwrap();
+        let oneshot =
+            OneshotWebsocketClient::with_options(server.add_account(100_000), server.ws_options());
+        let backends: Vec<Box<dyn TradingApi>> =
+            vec![Box::new(rest), Box::new(session), Box::new(oneshot)];
+        for backend in backends {
+            run_scenario(backend.as_ref()).await;
+        }
+    }
+}
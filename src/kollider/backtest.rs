@@ -0,0 +1,1326 @@
+//! Backtesting of strategies on historical index prices (`HistoryResp`) or recorded WebSocket
+//! sessions (`websocket::recorder`). The engine replays price bars through a `BacktestStrategy`
+//! and simulates an isolated margin position in a single product with the same model as
+//! `paper::PaperTradingClient`, plus fees and periodic funding.
+//!
+//! Model of the exchange:
+//! - orders placed while handling a bar are executed on the next one, market orders at its
+//!   price moved by `BacktestConfig::slippage`, limit orders at their price when the range of
+//!   the bar reaches it;
+//! - taker fee is charged for market orders and maker fee for limit ones, both as a share of
+//!   the order value;
+//! - funding is paid every `BacktestConfig::funding_interval` by longs to shorts for positive
+//!   rate;
+//! - the position is liquidated at the bankruptcy price when the bar reaches its liquidation
+//!   price, the whole margin is lost.
+//!
+//! Live bots written against `strategy::Strategy` are backtested with `StrategyBacktest`.
+//!
+//! ```
+//! # use kollider_api::kollider::api::{OrderSide, Product};
+//! # use kollider_api::kollider::backtest::*;
+//! struct BuyAndHold;
+//!
+//! impl BacktestStrategy for BuyAndHold {
+//!     fn on_start(&mut self, ctx: &mut Backtest) {
+//!         ctx.market(OrderSide::Bid, 100, 1).unwrap();
+//!     }
+//!
+//!     fn on_bar(&mut self, _ctx: &mut Backtest, _bar: &Bar) {}
+//! }
+//!
+//! # fn run(product: Product, bars: Vec<Bar>) {
+//! let report = Backtest::new(product, BacktestConfig::default()).run(&mut BuyAndHold, &bars);
+//! println!("Sharpe {:.2}", report.summary.sharpe);
+//! # }
+//! ```
+use crate::kollider::api::{
+    HistoryResp, MarginType, Order, OrderBody, OrderCreated, OrderSide, OrderType, Position,
+    Product, SettlementType, Symbol,
+};
+use crate::kollider::backend::{self, TradingApi};
+use crate::kollider::calc::{
+    self, average_entry_price, initial_margin, initial_margin_rate, pnl, position_value, MAKER_FEE,
+    TAKER_FEE,
+};
+use crate::kollider::strategy::{log_hook, Strategy, StrategyContext};
+use crate::kollider::websocket::data::{DecodeMode, IndexValue, KolliderMsg, KolliderTaggedMsg};
+use crate::kollider::websocket::recorder::RecordedFrame;
+use async_trait::async_trait;
+use futures::channel::mpsc::unbounded;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use thiserror::Error;
+
+const YEAR_MS: f64 = 365.0 * 24.0 * 60.0 * 60.0 * 1000.0;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum BacktestError {
+    #[error("Order quantity must be positive")]
+    InvalidQuantity,
+    #[error("Leverage {0} is out of range")]
+    InvalidLeverage(u64),
+    #[error("Price must be positive")]
+    InvalidPrice,
+    #[error("Not enough available balance, required {required}, available {available}")]
+    NotEnoughBalance { required: f64, available: f64 },
+    #[error("Order {0} is not found")]
+    OrderNotFound(u64),
+    #[error("Symbol {0} is not the backtested product")]
+    UnknownSymbol(Symbol),
+}
+
+/// Alias for a `Result` with the error type `BacktestError`.
+pub type Result<T> = std::result::Result<T, BacktestError>;
+
+/// Price of the product over an interval of time
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Bar {
+    /// Time in milliseconds since Unix epoch
+    pub timestamp: u64,
+    /// Price at which market orders are executed and equity is marked
+    pub price: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Bar {
+    /// Bar of a single price observation
+    pub fn point(timestamp: u64, price: f64) -> Self {
+        Bar {
+            timestamp,
+            price,
+            low: price,
+            high: price,
+        }
+    }
+}
+
+/// Bars from mean, min and max of historical index prices. Intervals without index updates
+/// are skipped.
+pub fn bars_from_history(history: &HistoryResp) -> Vec<Bar> {
+    let mut bars: Vec<Bar> = history
+        .data
+        .iter()
+        .filter_map(|item| {
+            let price = item.mean?;
+            Some(Bar {
+                timestamp: item.time * 1000,
+                price,
+                low: item.min.unwrap_or(price).min(price),
+                high: item.max.unwrap_or(price).max(price),
+            })
+        })
+        .collect();
+    bars.sort_by_key(|bar| bar.timestamp);
+    bars
+}
+
+/// Bars from a recorded WebSocket session: index values of the underlying symbol of the
+/// product or, if the session has none, last prices from its ticker.
+pub fn bars_from_recording(frames: &[RecordedFrame], product: &Product) -> Vec<Bar> {
+    let mut index = vec![];
+    let mut ticker = vec![];
+    for frame in frames {
+        let timestamp = frame.timestamp.max(0) as u64;
+        match KolliderMsg::decode(&frame.frame, DecodeMode::Lenient) {
+            Ok(KolliderMsg::Tagged(KolliderTaggedMsg::IndexValues(value)))
+                if value.symbol == product.underlying_symbol =>
+            {
+                index.push(Bar::point(timestamp, value.value))
+            }
+            Ok(KolliderMsg::Tagged(KolliderTaggedMsg::Ticker(value)))
+                if value.symbol == product.symbol =>
+            {
+                ticker.push(Bar::point(timestamp, value.last_price))
+            }
+            Ok(_) => (),
+            Err(e) => warn!("Skipping undecodable frame: {}", e),
+        }
+    }
+    if index.is_empty() {
+        ticker
+    } else {
+        index
+    }
+}
+
+/// Parameters of the simulation
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct BacktestConfig {
+    /// Initial balance in sats for inverse products and in quote currency for linear ones
+    pub balance: f64,
+    /// Share of the value of market orders, negative for rebates
+    pub taker_fee: f64,
+    /// Share of the value of limit orders, negative for rebates
+    pub maker_fee: f64,
+    /// Share of the position value paid by longs to shorts every funding interval
+    pub funding_rate: f64,
+    /// Milliseconds between funding payments, zero disables funding
+    pub funding_interval: u64,
+    /// Share of the price that market orders lose on execution
+    pub slippage: f64,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        BacktestConfig {
+            balance: 1_000_000.0,
+            taker_fee: TAKER_FEE,
+            maker_fee: MAKER_FEE,
+            funding_rate: 0.0,
+            funding_interval: 8 * 60 * 60 * 1000,
+            slippage: 0.0,
+        }
+    }
+}
+
+/// Strategy driven by the backtesting engine
+pub trait BacktestStrategy {
+    /// Called once before the first bar
+    fn on_start(&mut self, _ctx: &mut Backtest) {}
+
+    /// Called for every bar after its fills
+    fn on_bar(&mut self, ctx: &mut Backtest, bar: &Bar);
+
+    /// Called for every execution of own orders and liquidations
+    fn on_fill(&mut self, _ctx: &mut Backtest, _fill: &Fill) {}
+
+    /// Called once after the last bar
+    fn on_stop(&mut self, _ctx: &mut Backtest) {}
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum OrderKind {
+    Market,
+    Limit(f64),
+}
+
+/// Order that waits for execution
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PendingOrder {
+    pub order_id: u64,
+    pub kind: OrderKind,
+    pub side: OrderSide,
+    pub quantity: u64,
+    pub leverage: u64,
+    pub timestamp: u64,
+    /// Limit order that crossed the last price when placed, it is filled as a taker
+    #[serde(default)]
+    pub crossing: bool,
+}
+
+/// Execution of an order or liquidation of the position
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Fill {
+    /// Zero for liquidations
+    pub order_id: u64,
+    pub timestamp: u64,
+    pub side: OrderSide,
+    pub price: f64,
+    pub quantity: u64,
+    pub fee: f64,
+    pub is_maker: bool,
+    pub is_liquidation: bool,
+}
+
+/// Closed part of the position
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Trade {
+    /// Side of the closed position
+    pub side: OrderSide,
+    pub quantity: u64,
+    pub entry_time: u64,
+    pub exit_time: u64,
+    pub entry_price: f64,
+    pub exit_price: f64,
+    /// Realized PnL minus entry and exit fees
+    pub pnl: f64,
+    pub is_liquidation: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct EquityPoint {
+    pub timestamp: u64,
+    /// Wallet balance with realized PnL, fees and funding
+    pub balance: f64,
+    /// Balance plus unrealized PnL
+    pub equity: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Summary {
+    pub initial_balance: f64,
+    pub final_equity: f64,
+    /// Final equity relative to the initial balance, 0.1 is 10% gain
+    pub total_return: f64,
+    /// Annualized Sharpe ratio of bar returns with zero risk-free rate
+    pub sharpe: f64,
+    /// Largest drop of equity from its peak relative to the peak
+    pub max_drawdown: f64,
+    /// Share of trades with positive PnL
+    pub win_rate: f64,
+    pub trades: usize,
+    pub fees: f64,
+    /// Funding received, negative when paid
+    pub funding: f64,
+    /// Orders dropped on execution for lack of balance
+    pub rejected_orders: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct BacktestReport {
+    pub equity: Vec<EquityPoint>,
+    pub fills: Vec<Fill>,
+    pub trades: Vec<Trade>,
+    pub summary: Summary,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct SimPosition {
+    /// Positive for long and negative for short positions
+    quantity: i64,
+    entry_price: f64,
+    entry_time: u64,
+    /// Isolated margin locked in the position
+    margin: f64,
+    /// Entry fees of the open part that are not attributed to trades yet
+    fees: f64,
+}
+
+impl SimPosition {
+    fn side(&self) -> OrderSide {
+        if self.quantity >= 0 {
+            OrderSide::Bid
+        } else {
+            OrderSide::Ask
+        }
+    }
+}
+
+/// State of the simulated account. Strategies place orders and read the account through it.
+#[derive(Debug, Clone)]
+pub struct Backtest {
+    product: Product,
+    config: BacktestConfig,
+    balance: f64,
+    position: SimPosition,
+    orders: Vec<PendingOrder>,
+    next_order_id: u64,
+    last_bar: Option<Bar>,
+    next_funding: Option<u64>,
+    fills: Vec<Fill>,
+    trades: Vec<Trade>,
+    equity: Vec<EquityPoint>,
+    fees: f64,
+    funding: f64,
+    rejected_orders: usize,
+}
+
+impl Backtest {
+    pub fn new(product: Product, config: BacktestConfig) -> Self {
+        Backtest {
+            product,
+            balance: config.balance,
+            config,
+            position: SimPosition::default(),
+            orders: vec![],
+            next_order_id: 0,
+            last_bar: None,
+            next_funding: None,
+            fills: vec![],
+            trades: vec![],
+            equity: vec![],
+            fees: 0.0,
+            funding: 0.0,
+            rejected_orders: 0,
+        }
+    }
+
+    /// Replay the bars through the strategy and collect the results
+    pub fn run<S: BacktestStrategy + ?Sized>(
+        mut self,
+        strategy: &mut S,
+        bars: &[Bar],
+    ) -> BacktestReport {
+        strategy.on_start(&mut self);
+        for bar in bars {
+            self.step(strategy, bar);
+        }
+        strategy.on_stop(&mut self);
+        self.report()
+    }
+
+    pub fn product(&self) -> &Product {
+        &self.product
+    }
+
+    /// Time of the current bar, zero before the first one
+    pub fn timestamp(&self) -> u64 {
+        self.last_bar.map_or(0, |bar| bar.timestamp)
+    }
+
+    /// Price of the current bar
+    pub fn price(&self) -> Option<f64> {
+        self.last_bar.map(|bar| bar.price)
+    }
+
+    /// Wallet balance with realized PnL, fees and funding
+    pub fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    /// Balance plus unrealized PnL at the price of the current bar
+    pub fn equity(&self) -> f64 {
+        self.balance + self.upnl()
+    }
+
+    pub fn upnl(&self) -> f64 {
+        match self.price() {
+            Some(price) if self.position.quantity != 0 => pnl(
+                &self.product,
+                self.position.side(),
+                self.position.entry_price,
+                price,
+                self.position.quantity.unsigned_abs(),
+            ),
+            _ => 0.0,
+        }
+    }
+
+    /// Size of the position, positive for long and negative for short
+    pub fn position(&self) -> i64 {
+        self.position.quantity
+    }
+
+    /// Average entry price, zero without position
+    pub fn entry_price(&self) -> f64 {
+        self.position.entry_price
+    }
+
+    pub fn liquidation_price(&self) -> Option<f64> {
+        self.threshold(calc::liquidation_price)
+    }
+
+    pub fn open_orders(&self) -> &[PendingOrder] {
+        &self.orders
+    }
+
+    /// Balance that is not locked in the position margin
+    pub fn available(&self) -> f64 {
+        self.balance - self.position.margin
+    }
+
+    /// Place a market order executed at the price of the next bar
+    pub fn market(&mut self, side: OrderSide, quantity: u64, leverage: u64) -> Result<u64> {
+        self.submit(OrderKind::Market, side, quantity, leverage)
+    }
+
+    /// Place a limit order executed at `price` when a bar reaches it. The order that crosses
+    /// the last price is executed as a taker at the price of the next bar.
+    pub fn limit(
+        &mut self,
+        side: OrderSide,
+        price: f64,
+        quantity: u64,
+        leverage: u64,
+    ) -> Result<u64> {
+        if price <= 0.0 || !price.is_finite() {
+            return Err(BacktestError::InvalidPrice);
+        }
+        self.submit(OrderKind::Limit(price), side, quantity, leverage)
+    }
+
+    /// Close the whole position with a market order. Returns `None` without position.
+    pub fn close(&mut self) -> Result<Option<u64>> {
+        if self.position.quantity == 0 {
+            return Ok(None);
+        }
+        let side = self.position.side().inverse();
+        let quantity = self.position.quantity.unsigned_abs();
+        self.market(side, quantity, 1).map(Some)
+    }
+
+    pub fn cancel(&mut self, order_id: u64) -> Result<()> {
+        let before = self.orders.len();
+        self.orders.retain(|order| order.order_id != order_id);
+        if self.orders.len() == before {
+            return Err(BacktestError::OrderNotFound(order_id));
+        }
+        Ok(())
+    }
+
+    pub fn cancel_all(&mut self) {
+        self.orders.clear();
+    }
+
+    fn submit(
+        &mut self,
+        kind: OrderKind,
+        side: OrderSide,
+        quantity: u64,
+        leverage: u64,
+    ) -> Result<u64> {
+        if quantity == 0 {
+            return Err(BacktestError::InvalidQuantity);
+        }
+        if leverage == 0 || leverage as f64 > self.product.max_leverage {
+            return Err(BacktestError::InvalidLeverage(leverage));
+        }
+        let price = match kind {
+            OrderKind::Market => self.price(),
+            OrderKind::Limit(price) => Some(price),
+        };
+        if let Some(price) = price {
+            let required = self.required_margin(side, quantity, leverage, price);
+            let available = self.available();
+            if required > available {
+                return Err(BacktestError::NotEnoughBalance {
+                    required,
+                    available,
+                });
+            }
+        }
+        // Such order would match resting orders at once instead of resting itself
+        let crossing = match (kind, self.price()) {
+            (OrderKind::Limit(limit), Some(last)) => match side {
+                OrderSide::Bid => limit >= last,
+                OrderSide::Ask => limit <= last,
+            },
+            _ => false,
+        };
+        self.next_order_id += 1;
+        self.orders.push(PendingOrder {
+            order_id: self.next_order_id,
+            kind,
+            side,
+            quantity,
+            leverage,
+            timestamp: self.timestamp(),
+            crossing,
+        });
+        Ok(self.next_order_id)
+    }
+
+    /// Margin for the part of the order that increases the position
+    fn required_margin(&self, side: OrderSide, quantity: u64, leverage: u64, price: f64) -> f64 {
+        let closing = if self.position.quantity != 0 && self.position.side() != side {
+            self.position.quantity.unsigned_abs()
+        } else {
+            0
+        };
+        let opening = quantity.saturating_sub(closing);
+        initial_margin(&self.product, price, opening, leverage)
+    }
+
+    /// Bankruptcy or liquidation price of the position
+    fn threshold(&self, price: fn(&Product, OrderSide, f64, u64, f64) -> f64) -> Option<f64> {
+        if self.position.quantity == 0 {
+            return None;
+        }
+        Some(price(
+            &self.product,
+            self.position.side(),
+            self.position.entry_price,
+            self.position.quantity.unsigned_abs(),
+            self.position.margin,
+        ))
+    }
+
+    fn step<S: BacktestStrategy + ?Sized>(&mut self, strategy: &mut S, bar: &Bar) {
+        self.last_bar = Some(*bar);
+        self.apply_funding(bar);
+
+        let mut fills = vec![];
+        if let Some(fill) = self.check_liquidation(bar) {
+            fills.push(fill);
+        }
+        let (market, limit): (Vec<_>, Vec<_>) = std::mem::take(&mut self.orders)
+            .into_iter()
+            .partition(|order| order.kind == OrderKind::Market);
+        for order in market.into_iter().chain(limit) {
+            let price = match (order.kind, order.side) {
+                (OrderKind::Market, OrderSide::Bid) => bar.price * (1.0 + self.config.slippage),
+                (OrderKind::Market, OrderSide::Ask) => bar.price * (1.0 - self.config.slippage),
+                (OrderKind::Limit(price), OrderSide::Bid) if order.crossing && bar.low <= price => {
+                    bar.price.min(price)
+                }
+                (OrderKind::Limit(price), OrderSide::Ask)
+                    if order.crossing && bar.high >= price =>
+                {
+                    bar.price.max(price)
+                }
+                (OrderKind::Limit(price), OrderSide::Bid) if bar.low <= price => price,
+                (OrderKind::Limit(price), OrderSide::Ask) if bar.high >= price => price,
+                (OrderKind::Limit(_), _) => {
+                    self.orders.push(order);
+                    continue;
+                }
+            };
+            if self.required_margin(order.side, order.quantity, order.leverage, price)
+                > self.available()
+            {
+                warn!(
+                    "Backtest order {} is dropped for lack of balance",
+                    order.order_id
+                );
+                self.rejected_orders += 1;
+                continue;
+            }
+            fills.push(self.execute(&order, price, bar.timestamp));
+        }
+
+        for fill in &fills {
+            strategy.on_fill(self, fill);
+        }
+        strategy.on_bar(self, bar);
+        self.equity.push(EquityPoint {
+            timestamp: bar.timestamp,
+            balance: self.balance,
+            equity: self.equity(),
+        });
+    }
+
+    fn apply_funding(&mut self, bar: &Bar) {
+        let interval = self.config.funding_interval;
+        if interval == 0 {
+            return;
+        }
+        let mut next = *self
+            .next_funding
+            .get_or_insert((bar.timestamp / interval + 1) * interval);
+        while bar.timestamp >= next {
+            if self.position.quantity != 0 {
+                let value = position_value(
+                    &self.product,
+                    bar.price,
+                    self.position.quantity.unsigned_abs(),
+                );
+                let payment = match self.position.side() {
+                    OrderSide::Bid => -self.config.funding_rate * value,
+                    OrderSide::Ask => self.config.funding_rate * value,
+                };
+                self.balance += payment;
+                self.funding += payment;
+            }
+            next += interval;
+        }
+        self.next_funding = Some(next);
+    }
+
+    /// Close the position at the bankruptcy price if the bar reached the liquidation price
+    fn check_liquidation(&mut self, bar: &Bar) -> Option<Fill> {
+        let liq_price = self.liquidation_price()?;
+        let side = self.position.side();
+        let reached = match side {
+            OrderSide::Bid => bar.low <= liq_price,
+            OrderSide::Ask => bar.high >= liq_price,
+        };
+        if !reached {
+            return None;
+        }
+        let price = self.threshold(calc::bankruptcy_price)?;
+        let quantity = self.position.quantity.unsigned_abs();
+        info!(
+            "Backtest position {:?} {} is liquidated at {}",
+            side, quantity, price
+        );
+        let rpnl = -self.position.margin;
+        self.balance += rpnl;
+        self.trades.push(Trade {
+            side,
+            quantity,
+            entry_time: self.position.entry_time,
+            exit_time: bar.timestamp,
+            entry_price: self.position.entry_price,
+            exit_price: price,
+            pnl: rpnl - self.position.fees,
+            is_liquidation: true,
+        });
+        self.position = SimPosition::default();
+        let fill = Fill {
+            order_id: 0,
+            timestamp: bar.timestamp,
+            side: side.inverse(),
+            price,
+            quantity,
+            fee: 0.0,
+            is_maker: false,
+            is_liquidation: true,
+        };
+        self.fills.push(fill.clone());
+        Some(fill)
+    }
+
+    fn execute(&mut self, order: &PendingOrder, price: f64, timestamp: u64) -> Fill {
+        let product = &self.product;
+        let is_maker = matches!(order.kind, OrderKind::Limit(_)) && !order.crossing;
+        let fee_rate = if is_maker {
+            self.config.maker_fee
+        } else {
+            self.config.taker_fee
+        };
+        let fee = position_value(product, price, order.quantity) * fee_rate;
+        self.balance -= fee;
+        self.fees += fee;
+
+        let signed = match order.side {
+            OrderSide::Bid => order.quantity as i64,
+            OrderSide::Ask => -(order.quantity as i64),
+        };
+        let rate = initial_margin_rate(product, order.leverage);
+        let position = &mut self.position;
+        if position.quantity == 0 || position.quantity.signum() == signed.signum() {
+            if position.quantity == 0 {
+                position.entry_time = timestamp;
+            }
+            position.entry_price = average_entry_price(
+                product,
+                position.entry_price,
+                position.quantity.unsigned_abs(),
+                price,
+                order.quantity,
+            );
+            position.margin += position_value(product, price, order.quantity) * rate;
+            position.fees += fee;
+            position.quantity += signed;
+        } else {
+            let held = position.quantity.unsigned_abs();
+            let closed = held.min(order.quantity);
+            let share = closed as f64 / held as f64;
+            let rpnl = pnl(
+                product,
+                order.side.inverse(),
+                position.entry_price,
+                price,
+                closed,
+            );
+            let fees = position.fees * share + fee * closed as f64 / order.quantity as f64;
+            self.trades.push(Trade {
+                side: order.side.inverse(),
+                quantity: closed,
+                entry_time: position.entry_time,
+                exit_time: timestamp,
+                entry_price: position.entry_price,
+                exit_price: price,
+                pnl: rpnl - fees,
+                is_liquidation: false,
+            });
+            self.balance += rpnl;
+            position.margin -= position.margin * share;
+            position.fees -= position.fees * share;
+            position.quantity += signed;
+            if position.quantity == 0 {
+                *position = SimPosition::default();
+            } else if position.quantity.signum() == signed.signum() {
+                let opened = position.quantity.unsigned_abs();
+                *position = SimPosition {
+                    quantity: position.quantity,
+                    entry_price: price,
+                    entry_time: timestamp,
+                    margin: position_value(product, price, opened) * rate,
+                    fees: fee * opened as f64 / order.quantity as f64,
+                };
+            }
+        }
+
+        let fill = Fill {
+            order_id: order.order_id,
+            timestamp,
+            side: order.side,
+            price,
+            quantity: order.quantity,
+            fee,
+            is_maker,
+            is_liquidation: false,
+        };
+        self.fills.push(fill.clone());
+        fill
+    }
+
+    fn report(self) -> BacktestReport {
+        let summary = summarize(
+            self.config.balance,
+            &self.equity,
+            &self.trades,
+            self.fees,
+            self.funding,
+            self.rejected_orders,
+        );
+        BacktestReport {
+            equity: self.equity,
+            fills: self.fills,
+            trades: self.trades,
+            summary,
+        }
+    }
+}
+
+fn summarize(
+    initial_balance: f64,
+    equity: &[EquityPoint],
+    trades: &[Trade],
+    fees: f64,
+    funding: f64,
+    rejected_orders: usize,
+) -> Summary {
+    let final_equity = equity.last().map_or(initial_balance, |point| point.equity);
+    let total_return = if initial_balance > 0.0 {
+        final_equity / initial_balance - 1.0
+    } else {
+        0.0
+    };
+
+    let mut peak = initial_balance;
+    let mut max_drawdown: f64 = 0.0;
+    for point in equity {
+        peak = peak.max(point.equity);
+        if peak > 0.0 {
+            max_drawdown = max_drawdown.max((peak - point.equity) / peak);
+        }
+    }
+
+    let wins = trades.iter().filter(|trade| trade.pnl > 0.0).count();
+    let win_rate = if trades.is_empty() {
+        0.0
+    } else {
+        wins as f64 / trades.len() as f64
+    };
+
+    Summary {
+        initial_balance,
+        final_equity,
+        total_return,
+        sharpe: sharpe(equity),
+        max_drawdown,
+        win_rate,
+        trades: trades.len(),
+        fees,
+        funding,
+        rejected_orders,
+    }
+}
+
+/// Annualized Sharpe ratio of returns between equity points, the number of periods in a year
+/// is derived from the average distance between points
+fn sharpe(equity: &[EquityPoint]) -> f64 {
+    let returns: Vec<f64> = equity
+        .windows(2)
+        .filter(|pair| pair[0].equity > 0.0)
+        .map(|pair| pair[1].equity / pair[0].equity - 1.0)
+        .collect();
+    if returns.len() < 2 {
+        return 0.0;
+    }
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let span = equity[equity.len() - 1].timestamp - equity[0].timestamp;
+    if variance <= 0.0 || span == 0 {
+        return 0.0;
+    }
+    let periods_per_year = YEAR_MS / (span as f64 / (equity.len() - 1) as f64);
+    mean / variance.sqrt() * periods_per_year.sqrt()
+}
+
+/// Drives a live `Strategy` from the backtest. Bars are passed to `Strategy::on_index` as
+/// values of the underlying index with empty `denom`, fills to `Strategy::on_fill` as `trade`
+/// messages without leverage and realized PnL. Orders placed through
+/// `StrategyContext::trading` go to the simulated account.
+///
+/// The WebSocket session is not simulated: `StrategyContext::send` fails, `on_book`,
+/// `on_order_update` and `on_timer` are never called. Hooks are run to completion on every
+/// bar, so they must not wait for I/O or timers. After `StrategyContext::stop` only
+/// `Strategy::on_stop` is called.
+pub struct StrategyBacktest<'a, S: ?Sized> {
+    strategy: &'a mut S,
+    session: Option<(Arc<BacktestTrading>, StrategyContext)>,
+}
+
+impl<'a, S: Strategy + ?Sized> StrategyBacktest<'a, S> {
+    pub fn new(strategy: &'a mut S) -> Self {
+        StrategyBacktest {
+            strategy,
+            session: None,
+        }
+    }
+
+    /// Run the hook with the account of the backtest behind `StrategyContext::trading`
+    fn call(&mut self, backtest: &mut Backtest, hook: Hook) {
+        let (trading, ctx) = self.session.get_or_insert_with(|| {
+            let trading = Arc::new(BacktestTrading {
+                backtest: Mutex::new(Backtest::new(
+                    backtest.product.clone(),
+                    backtest.config.clone(),
+                )),
+            });
+            // The receiver is dropped, so raw messages fail with `Disconnected`
+            let (outgoing, _) = unbounded();
+            let ctx = StrategyContext::new(trading.clone(), outgoing);
+            (trading, ctx)
+        });
+        if ctx.is_stopping() && !matches!(hook, Hook::Stop) {
+            return;
+        }
+        std::mem::swap(&mut *trading.backtest(), backtest);
+        let (name, res) = futures::executor::block_on(hook.call(&mut *self.strategy, ctx));
+        std::mem::swap(&mut *trading.backtest(), backtest);
+        log_hook(name, res);
+    }
+}
+
+impl<S: Strategy + ?Sized> BacktestStrategy for StrategyBacktest<'_, S> {
+    fn on_start(&mut self, ctx: &mut Backtest) {
+        self.call(ctx, Hook::Start);
+    }
+
+    fn on_bar(&mut self, ctx: &mut Backtest, bar: &Bar) {
+        let index = IndexValue {
+            denom: String::new(),
+            symbol: ctx.product.underlying_symbol.clone(),
+            value: bar.price,
+        };
+        self.call(ctx, Hook::Index(index));
+    }
+
+    fn on_fill(&mut self, ctx: &mut Backtest, fill: &Fill) {
+        let trade = KolliderTaggedMsg::Trade {
+            fees: fill.fee,
+            is_liquidation: fill.is_liquidation,
+            is_maker: fill.is_maker,
+            leverage: 0.0,
+            margin_type: MarginType::Isolated,
+            order_id: fill.order_id,
+            price: fill.price,
+            quantity: fill.quantity,
+            rpnl: 0.0,
+            settlement_type: SettlementType::Delayed,
+            side: fill.side,
+            symbol: ctx.product.symbol.clone(),
+            timestamp: fill.timestamp,
+        };
+        self.call(ctx, Hook::Fill(trade));
+    }
+
+    fn on_stop(&mut self, ctx: &mut Backtest) {
+        self.call(ctx, Hook::Stop);
+    }
+}
+
+/// `Strategy` hook that a backtest event maps to
+enum Hook {
+    Start,
+    Index(IndexValue),
+    Fill(KolliderTaggedMsg),
+    Stop,
+}
+
+impl Hook {
+    async fn call<S: Strategy + ?Sized>(
+        self,
+        strategy: &mut S,
+        ctx: &mut StrategyContext,
+    ) -> (&'static str, backend::Result<()>) {
+        match self {
+            Hook::Start => ("on_start", strategy.on_start(ctx).await),
+            Hook::Index(index) => ("on_index", strategy.on_index(ctx, &index).await),
+            Hook::Fill(trade) => ("on_fill", strategy.on_fill(ctx, &trade).await),
+            Hook::Stop => ("on_stop", strategy.on_stop(ctx).await),
+        }
+    }
+}
+
+/// Trading backend over the account of the running backtest
+struct BacktestTrading {
+    backtest: Mutex<Backtest>,
+}
+
+impl BacktestTrading {
+    fn backtest(&self) -> MutexGuard<'_, Backtest> {
+        self.backtest.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl TradingApi for BacktestTrading {
+    async fn create_order(&self, body: &OrderBody) -> backend::Result<OrderCreated> {
+        let mut backtest = self.backtest();
+        if body.symbol != backtest.product.symbol {
+            return Err(BacktestError::UnknownSymbol(body.symbol.clone()).into());
+        }
+        let order_id = match body.order_type {
+            OrderType::Market => backtest.market(body.side, body.quantity, body.leverage)?,
+            OrderType::Limit => {
+                let price = backtest.product.human_price(body.price);
+                backtest.limit(body.side, price, body.quantity, body.leverage)?
+            }
+        };
+        Ok(OrderCreated {
+            timestamp: backtest.timestamp(),
+            order_id,
+            ext_order_id: String::new(),
+            uid: 0,
+            symbol: body.symbol.clone(),
+            quantity: body.quantity,
+            order_type: body.order_type,
+            price: body.price,
+            leverage: body.leverage,
+        })
+    }
+
+    async fn cancel_order(&self, _symbol: &str, order_id: u64) -> backend::Result<()> {
+        Ok(self.backtest().cancel(order_id)?)
+    }
+
+    async fn open_orders(&self) -> backend::Result<HashMap<Symbol, Vec<Order>>> {
+        let backtest = self.backtest();
+        let product = &backtest.product;
+        let orders: Vec<Order> = backtest
+            .orders
+            .iter()
+            .map(|order| {
+                let (order_type, price) = match order.kind {
+                    OrderKind::Market => (OrderType::Market, 0),
+                    OrderKind::Limit(price) => (OrderType::Limit, product.api_price(price)),
+                };
+                Order {
+                    uid: 0,
+                    order_id: order.order_id,
+                    ext_order_id: String::new(),
+                    symbol: product.symbol.clone(),
+                    side: order.side,
+                    order_type,
+                    margin_type: MarginType::Isolated,
+                    settlement_type: SettlementType::Delayed,
+                    price,
+                    quantity: order.quantity,
+                    filled: 0,
+                    leverage: order.leverage,
+                    timestamp: order.timestamp,
+                }
+            })
+            .collect();
+        if orders.is_empty() {
+            return Ok(HashMap::new());
+        }
+        Ok(HashMap::from([(product.symbol.clone(), orders)]))
+    }
+
+    async fn positions(&self) -> backend::Result<HashMap<Symbol, Position>> {
+        let backtest = self.backtest();
+        let position = &backtest.position;
+        if position.quantity == 0 {
+            return Ok(HashMap::new());
+        }
+        let quantity = position.quantity.unsigned_abs();
+        let value = position_value(&backtest.product, position.entry_price, quantity);
+        let position = Position {
+            uid: 0,
+            symbol: backtest.product.symbol.clone(),
+            side: Some(position.side()),
+            quantity,
+            entry_price: position.entry_price,
+            leverage: value / position.margin,
+            liq_price: backtest.liquidation_price().unwrap_or(0.0),
+            upnl: backtest.upnl(),
+            open_order_ids: backtest.orders.iter().map(|order| order.order_id).collect(),
+            timestamp: backtest.timestamp(),
+            position_id: None,
+            entry_time: Some(position.entry_time),
+            entry_value: Some(value),
+            mark_value: None,
+            bankruptcy_price: backtest.threshold(calc::bankruptcy_price),
+            rpnl: None,
+            funding: None,
+            real_leverage: None,
+            adl_score: None,
+            is_liquidating: None,
+        };
+        Ok(HashMap::from([(position.symbol.clone(), position)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::HistoryItem;
+
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    fn product() -> Product {
+        Product {
+            symbol: "BTCUSD.PERP".to_owned(),
+            contract_size: 1.0,
+            max_leverage: 100.0,
+            base_margin: 0.005,
+            maintenance_margin: 0.004,
+            is_inverse_priced: true,
+            price_dp: 1.0,
+            underlying_symbol: ".BTCUSD".to_owned(),
+            last_price: 50000.0,
+            tick_size: 0.5,
+            risk_limit: 100000000.0,
+        }
+    }
+
+    fn no_costs() -> BacktestConfig {
+        BacktestConfig {
+            taker_fee: 0.0,
+            maker_fee: 0.0,
+            funding_interval: 0,
+            ..BacktestConfig::default()
+        }
+    }
+
+    fn bars(prices: &[f64]) -> Vec<Bar> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, price)| Bar::point(i as u64 * HOUR, *price))
+            .collect()
+    }
+
+    /// Places the planned orders at the given bar indices
+    struct Script {
+        bar: usize,
+        plan: Vec<(usize, OrderSide, u64, u64)>,
+    }
+
+    impl BacktestStrategy for Script {
+        fn on_bar(&mut self, ctx: &mut Backtest, _bar: &Bar) {
+            for (_, side, quantity, leverage) in self.plan.iter().filter(|p| p.0 == self.bar) {
+                ctx.market(*side, *quantity, *leverage).unwrap();
+            }
+            self.bar += 1;
+        }
+    }
+
+    #[test]
+    fn test_inverse_pnl_and_fees() {
+        let mut strategy = Script {
+            bar: 0,
+            plan: vec![(0, OrderSide::Bid, 100, 1), (1, OrderSide::Ask, 100, 1)],
+        };
+        let report = Backtest::new(product(), no_costs())
+            .run(&mut strategy, &bars(&[40000.0, 50000.0, 55000.0]));
+        // 100 contracts of 1 USD: 200000 sats at 50000, 181818.18 sats at 55000
+        let expected = 200000.0 - 100.0 / 55000.0 * 1e8;
+        assert_eq!(report.trades.len(), 1);
+        assert!((report.trades[0].pnl - expected).abs() < 1e-6);
+        assert!((report.summary.final_equity - 1_000_000.0 - expected).abs() < 1e-6);
+        assert_eq!(report.summary.win_rate, 1.0);
+
+        let mut strategy = Script {
+            bar: 0,
+            plan: vec![(0, OrderSide::Bid, 100, 1), (1, OrderSide::Ask, 100, 1)],
+        };
+        let config = BacktestConfig {
+            taker_fee: 0.001,
+            funding_rate: 0.01,
+            funding_interval: 2 * HOUR,
+            ..BacktestConfig::default()
+        };
+        let report = Backtest::new(product(), config)
+            .run(&mut strategy, &bars(&[40000.0, 50000.0, 55000.0]));
+        let fees = 200.0 + 100.0 / 55000.0 * 1e5;
+        let funding = -0.01 * 100.0 / 55000.0 * 1e8;
+        assert!((report.summary.fees - fees).abs() < 1e-6);
+        assert!((report.summary.funding - funding).abs() < 1e-6);
+        assert!((report.trades[0].pnl - (expected - fees)).abs() < 1e-6);
+        assert!(
+            (report.summary.final_equity - (1_000_000.0 + expected - fees + funding)).abs() < 1e-6
+        );
+    }
+
+    /// Buys through the live strategy interface on the first bar and stops on the second one
+    #[derive(Default)]
+    struct LiveBuyer {
+        bars: usize,
+        fills: usize,
+        position: Option<u64>,
+    }
+
+    #[async_trait]
+    impl Strategy for LiveBuyer {
+        async fn on_index(
+            &mut self,
+            ctx: &mut StrategyContext,
+            _index: &IndexValue,
+        ) -> backend::Result<()> {
+            self.bars += 1;
+            if self.bars == 1 {
+                let body = OrderBody {
+                    leverage: 1,
+                    margin_type: MarginType::Isolated,
+                    order_type: OrderType::Market,
+                    price: 0,
+                    quantity: 100,
+                    settlement_type: SettlementType::Delayed,
+                    side: OrderSide::Bid,
+                    symbol: product().symbol,
+                };
+                ctx.trading().create_order(&body).await?;
+            } else {
+                let positions = ctx.trading().positions().await?;
+                self.position = positions.values().next().map(|p| p.quantity);
+                ctx.stop();
+            }
+            Ok(())
+        }
+
+        async fn on_fill(
+            &mut self,
+            _ctx: &mut StrategyContext,
+            _trade: &KolliderTaggedMsg,
+        ) -> backend::Result<()> {
+            self.fills += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_strategy_backtest() {
+        let mut strategy = LiveBuyer::default();
+        let report = Backtest::new(product(), no_costs()).run(
+            &mut StrategyBacktest::new(&mut strategy),
+            &bars(&[50000.0, 50000.0, 55000.0]),
+        );
+        assert_eq!(strategy.bars, 2);
+        assert_eq!(strategy.fills, 1);
+        assert_eq!(strategy.position, Some(100));
+        assert_eq!(report.fills.len(), 1);
+    }
+
+    /// Bids above the price on the first bar and below it on the second one
+    struct LimitBidder {
+        bar: usize,
+    }
+
+    impl BacktestStrategy for LimitBidder {
+        fn on_bar(&mut self, ctx: &mut Backtest, _bar: &Bar) {
+            match self.bar {
+                0 => ctx.limit(OrderSide::Bid, 51000.0, 100, 1).unwrap(),
+                1 => ctx.limit(OrderSide::Bid, 49000.0, 100, 1).unwrap(),
+                _ => 0,
+            };
+            self.bar += 1;
+        }
+    }
+
+    #[test]
+    fn test_crossing_limit_order() {
+        let config = BacktestConfig {
+            taker_fee: 0.001,
+            maker_fee: -0.0005,
+            funding_interval: 0,
+            ..BacktestConfig::default()
+        };
+        let mut prices = bars(&[50000.0, 50000.0, 50000.0]);
+        prices[2].low = 49000.0;
+        let report = Backtest::new(product(), config).run(&mut LimitBidder { bar: 0 }, &prices);
+        assert_eq!(report.fills.len(), 2);
+        // Takes liquidity at the bar price
+        assert_eq!(report.fills[0].price, 50000.0);
+        assert!(!report.fills[0].is_maker);
+        assert!((report.fills[0].fee - 100.0 / 50000.0 * 1e5).abs() < 1e-6);
+        // Rests and is filled at its price
+        assert_eq!(report.fills[1].price, 49000.0);
+        assert!(report.fills[1].is_maker);
+        assert!((report.fills[1].fee + 100.0 / 49000.0 * 0.5e5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_liquidation() {
+        let mut strategy = Script {
+            bar: 0,
+            plan: vec![(0, OrderSide::Bid, 1000, 100)],
+        };
+        let mut prices = bars(&[50000.0, 50000.0, 50000.0]);
+        prices[2].low = 49000.0;
+        let report = Backtest::new(product(), no_costs()).run(&mut strategy, &prices);
+        let margin = 1000.0 / 50000.0 * 1e8 / 100.0;
+        assert_eq!(report.trades.len(), 1);
+        assert!(report.trades[0].is_liquidation);
+        assert!((report.trades[0].pnl + margin).abs() < 1e-6);
+        assert!(report.fills[1].is_liquidation);
+        assert!((report.summary.final_equity - (1_000_000.0 - margin)).abs() < 1e-6);
+        assert_eq!(report.summary.win_rate, 0.0);
+    }
+
+    #[test]
+    fn test_summary() {
+        let equity: Vec<EquityPoint> = [100.0, 120.0, 90.0, 110.0]
+            .iter()
+            .enumerate()
+            .map(|(i, equity)| EquityPoint {
+                timestamp: i as u64 * HOUR,
+                balance: *equity,
+                equity: *equity,
+            })
+            .collect();
+        let summary = summarize(100.0, &equity, &[], 0.0, 0.0, 0);
+        assert!((summary.total_return - 0.1).abs() < 1e-9);
+        assert!((summary.max_drawdown - 0.25).abs() < 1e-9);
+        assert!(summary.sharpe > 0.0);
+        assert_eq!(summary.win_rate, 0.0);
+    }
+
+    #[test]
+    fn test_bars_from_history() {
+        let history = HistoryResp {
+            data: vec![
+                HistoryItem {
+                    max: Some(51000.0),
+                    min: Some(49000.0),
+                    mean: Some(50000.0),
+                    time: 1639603374,
+                },
+                HistoryItem {
+                    max: None,
+                    min: None,
+                    mean: None,
+                    time: 1639603674,
+                },
+            ],
+            symbol: ".BTCUSD".to_owned(),
+        };
+        assert_eq!(
+            bars_from_history(&history),
+            vec![Bar {
+                timestamp: 1639603374000,
+                price: 50000.0,
+                low: 49000.0,
+                high: 51000.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_bars_from_recording() {
+        let frame = |timestamp, frame: &str| RecordedFrame {
+            timestamp,
+            frame: frame.to_owned(),
+        };
+        let frames = vec![
+            frame(
+                1000,
+                r#"{"type":"ticker","data":{"best_ask":"50001.0","best_bid":"49999.0","last_price":"50000.0","last_quantity":1,"last_side":"Bid","symbol":"BTCUSD.PERP"}}"#,
+            ),
+            frame(
+                2000,
+                r#"{"type":"index_values","data":{"denom":"USD","symbol":".BTCUSD","value":"50010.5"}}"#,
+            ),
+            frame(3000, "not json"),
+        ];
+        assert_eq!(
+            bars_from_recording(&frames, &product()),
+            vec![Bar::point(2000, 50010.5)]
+        );
+        assert_eq!(
+            bars_from_recording(&frames[..1], &product()),
+            vec![Bar::point(1000, 50000.0)]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Synchronous facade over the async client for scripts and FFI bindings. Each client owns
+//! a single threaded tokio runtime and blocks on it in every call, so the methods must not
+//! be called from inside another async runtime.
+use crate::kollider::api::*;
+use crate::kollider::client::error::Result;
+use crate::kollider::client::{KolliderClient as AsyncClient, Signer};
+use chrono::prelude::*;
+use chrono::Duration;
+use std::collections::HashMap;
+use tokio::runtime::{Builder, Runtime};
+
+#[cfg(feature = "ws")]
+use crate::kollider::websocket::{oneshot, OpenOrder, Position};
+#[cfg(feature = "ws")]
+use std::sync::Arc;
+
+pub struct KolliderClient {
+    inner: AsyncClient,
+    runtime: Runtime,
+}
+
+impl KolliderClient {
+    /// Client of the mainnet, same as `KolliderClient::mainnet`
+    pub fn new() -> Self {
+        KolliderClient::mainnet()
+    }
+
+    /// Client of the testnet with default HTTP settings.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client or the tokio runtime cannot be built. Use `from_async` with a
+    /// client from `AsyncClient::builder` to handle the errors.
+    pub fn testnet() -> Self {
+        KolliderClient::from_async(AsyncClient::testnet()).expect("Failed to start tokio runtime")
+    }
+
+    /// Client of the mainnet with default HTTP settings.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client or the tokio runtime cannot be built. Use `from_async` with a
+    /// client from `AsyncClient::builder` to handle the errors.
+    pub fn mainnet() -> Self {
+        KolliderClient::from_async(AsyncClient::mainnet()).expect("Failed to start tokio runtime")
+    }
+
+    /// Wrap configured async client
+    pub fn from_async(inner: AsyncClient) -> std::io::Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        Ok(KolliderClient { inner, runtime })
+    }
+
+    /// Underlying async client
+    pub fn inner(&self) -> &AsyncClient {
+        &self.inner
+    }
+
+    /// Set credentials or external signer for private endpoints
+    pub fn set_auth<S: Signer + 'static>(&mut self, signer: S) {
+        self.inner.set_auth(signer);
+    }
+
+    /// Measured skew of server clock relative to the local one
+    pub fn clock_skew(&self) -> Option<Duration> {
+        self.inner.clock_skew()
+    }
+
+    /// Measure clock skew with a request to the server and return the current estimate
+    pub fn sync_time(&self) -> Result<Duration> {
+        self.runtime.block_on(self.inner.sync_time())
+    }
+
+    /// GET endpoint `/user/account`
+    pub fn user_account(&self) -> Result<AccountInfo> {
+        self.runtime.block_on(self.inner.user_account())
+    }
+
+    /// POST endpoint `/wallet/deposit`
+    pub fn wallet_deposit(&self, body: &DepositBody) -> Result<DepositResp> {
+        self.runtime.block_on(self.inner.wallet_deposit(body))
+    }
+
+    /// POST endpoint `/wallet/withdrawal`
+    pub fn wallet_withdrawal(&self, body: &WithdrawalBody) -> Result<WithdrawalResp> {
+        self.runtime.block_on(self.inner.wallet_withdrawal(body))
+    }
+
+    /// GET endpoint `/market/orderbook`
+    pub fn market_orderbook(&self, level: OrderBookLevel, symbol: &str) -> Result<OrderBookResp> {
+        self.runtime
+            .block_on(self.inner.market_orderbook(level, symbol))
+    }
+
+    /// GET endpoint `/market/ticker`
+    pub fn market_ticker(&self, symbol: &str) -> Result<Ticker> {
+        self.runtime.block_on(self.inner.market_ticker(symbol))
+    }
+
+    /// GET endpoint `/market/historic_index_prices`
+    pub fn market_historic_index_prices(
+        &self,
+        limit: usize,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        interval_size: IntervalSize,
+    ) -> Result<HistoryResp> {
+        self.runtime
+            .block_on(self.inner.market_historic_index_prices(
+                limit,
+                symbol,
+                start,
+                end,
+                interval_size,
+            ))
+    }
+
+    /// Index price history of any length, fetched page by page
+    pub fn historic_index_prices_range(
+        &self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        interval_size: IntervalSize,
+    ) -> Result<HistoryResp> {
+        self.runtime.block_on(
+            self.inner
+                .historic_index_prices_range(symbol, start, end, interval_size),
+        )
+    }
+
+    /// GET endpoint `/market/products`
+    pub fn market_products(&self) -> Result<HashMap<Symbol, Product>> {
+        self.runtime.block_on(self.inner.market_products())
+    }
+
+    /// POST endpoint `/orders`
+    pub fn create_order(&self, body: &OrderBody) -> Result<OrderCreated> {
+        self.runtime.block_on(self.inner.create_order(body))
+    }
+
+    /// POST endpoint `/orders/prediction`
+    pub fn order_prediction(&self, body: &OrderBody) -> Result<OrderPrediction> {
+        self.runtime.block_on(self.inner.order_prediction(body))
+    }
+
+    /// GET endpoint `/orders`
+    pub fn orders(
+        &self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        limit: usize,
+    ) -> Result<Vec<OrderDetails>> {
+        self.runtime
+            .block_on(self.inner.orders(symbol, start, end, limit))
+    }
+
+    /// GET endpoint `/orders/open`
+    pub fn open_orders(&self) -> Result<HashMap<Symbol, Vec<OrderDetails>>> {
+        self.runtime.block_on(self.inner.open_orders())
+    }
+
+    /// GET endpoint `/user/fills`
+    pub fn fills(
+        &self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        limit: usize,
+    ) -> Result<Vec<FillDetails>> {
+        self.runtime
+            .block_on(self.inner.fills(symbol, start, end, limit))
+    }
+
+    /// GET endpoint `/positions`
+    pub fn positions(&self) -> Result<HashMap<Symbol, PositionDetails>> {
+        self.runtime.block_on(self.inner.positions())
+    }
+
+    /// DELETE endpoint `/orders`
+    pub fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.cancel_order(symbol, order_id))
+    }
+}
+
+/// Oneshot websocket requests. Credentials of the client are used for private requests.
+#[cfg(feature = "ws")]
+impl KolliderClient {
+    fn signer(&self) -> std::result::Result<Arc<dyn Signer>, oneshot::Error> {
+        self.inner.auth.clone().ok_or(oneshot::Error::AuthRequired)
+    }
+
+    /// Fetch balances via websocket
+    pub fn ws_balances(&self) -> std::result::Result<oneshot::Balances, oneshot::Error> {
+        let signer = self.signer()?;
+        self.runtime
+            .block_on(oneshot::fetch_balances(signer.as_ref()))
+    }
+
+    /// Fetch positions via websocket
+    pub fn ws_positions(&self) -> std::result::Result<HashMap<Symbol, Position>, oneshot::Error> {
+        let signer = self.signer()?;
+        self.runtime
+            .block_on(oneshot::fetch_positions(signer.as_ref()))
+    }
+
+    /// Fetch open orders via websocket
+    pub fn ws_open_orders(
+        &self,
+    ) -> std::result::Result<HashMap<Symbol, Vec<OpenOrder>>, oneshot::Error> {
+        let signer = self.signer()?;
+        self.runtime
+            .block_on(oneshot::fetch_open_orders(signer.as_ref()))
+    }
+
+    /// Place order via websocket and wait until it is accepted
+    pub fn ws_open_order(
+        &self,
+        body: &OrderBody,
+    ) -> std::result::Result<OrderCreated, oneshot::Error> {
+        let signer = self.signer()?;
+        self.runtime
+            .block_on(oneshot::open_order(signer.as_ref(), body))
+    }
+
+    /// Cancel order via websocket
+    pub fn ws_cancel_order(
+        &self,
+        order_id: u64,
+        symbol: &str,
+    ) -> std::result::Result<(), oneshot::Error> {
+        let signer = self.signer()?;
+        self.runtime
+            .block_on(oneshot::cancel_order(signer.as_ref(), order_id, symbol))
+    }
+
+    /// Get ticker via websocket
+    pub fn ws_ticker(&self, symbol: &str) -> std::result::Result<Ticker, oneshot::Error> {
+        self.runtime.block_on(oneshot::get_ticker(symbol))
+    }
+
+    /// Get tradable products via websocket
+    pub fn ws_tradable_products(
+        &self,
+    ) -> std::result::Result<HashMap<Symbol, Product>, oneshot::Error> {
+        self.runtime.block_on(oneshot::tradable_products())
+    }
+}
+
+impl Default for KolliderClient {
+    fn default() -> Self {
+        KolliderClient::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::client::error::Error;
+
+    #[test]
+    fn test_blocking_request_error() {
+        let mut inner = AsyncClient::mainnet();
+        inner.server = "http://127.0.0.1:1".to_owned();
+        let client = KolliderClient::from_async(inner).unwrap();
+        match client.market_products() {
+            Err(Error::ReqwestErr(_)) => (),
+            res => panic!("Expected connection error, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Position math on top of `Product` parameters: value, margin, fees, PnL, bankruptcy and
+//! liquidation prices for inverse and linear contracts. Prices are decimal, see
+//! `Product::human_price`. Values, margins and PnL are in sats for inverse products and in
+//! quote currency for linear ones.
+//!
+//! A position is liquidated when its margin with unrealized PnL drops to
+//! `Product::maintenance_margin` share of its entry value plus `LIQUIDATION_BUFFER`, and at
+//! the bankruptcy price the whole margin is lost.
+use crate::kollider::api::{OrderSide, Product};
+
+pub const SATS_IN_BTC: f64 = 100_000_000.0;
+
+/// Share of the order value that takers pay
+pub const TAKER_FEE: f64 = 0.00075;
+
+/// Share of the order value that makers pay, negative for rebates
+pub const MAKER_FEE: f64 = -0.00025;
+
+/// Share of the entry value that the exchange keeps above the maintenance margin at
+/// liquidation, fitted to its order predictions
+pub const LIQUIDATION_BUFFER: f64 = 0.0025;
+
+/// Value of `quantity` contracts at `price`
+pub fn position_value(product: &Product, price: f64, quantity: u64) -> f64 {
+    let contracts = quantity as f64 * product.contract_size;
+    if !product.is_inverse_priced {
+        contracts * price
+    } else if price > 0.0 {
+        contracts / price * SATS_IN_BTC
+    } else {
+        0.0
+    }
+}
+
+/// Share of the order value that is locked as initial margin: inverse of the leverage, but
+/// not less than `Product::base_margin`
+pub fn initial_margin_rate(product: &Product, leverage: u64) -> f64 {
+    (1.0 / leverage.max(1) as f64).max(product.base_margin)
+}
+
+/// Margin required to open `quantity` contracts at `price` with the leverage
+pub fn initial_margin(product: &Product, price: f64, quantity: u64, leverage: u64) -> f64 {
+    position_value(product, price, quantity) * initial_margin_rate(product, leverage)
+}
+
+/// Margin below which the position opened at `price` is liquidated
+pub fn maintenance_margin(product: &Product, price: f64, quantity: u64) -> f64 {
+    position_value(product, price, quantity) * product.maintenance_margin
+}
+
+/// Fee of the order execution, negative for maker rebates
+pub fn exchange_fee(product: &Product, price: f64, quantity: u64, is_maker: bool) -> f64 {
+    let rate = if is_maker { MAKER_FEE } else { TAKER_FEE };
+    position_value(product, price, quantity) * rate
+}
+
+/// PnL of `quantity` contracts of the position on `side` opened at `entry`. Unrealized PnL
+/// when `exit` is the mark price, realized PnL when it is the price of the closing trade.
+pub fn pnl(product: &Product, side: OrderSide, entry: f64, exit: f64, quantity: u64) -> f64 {
+    let diff = if product.is_inverse_priced {
+        position_value(product, entry, quantity) - position_value(product, exit, quantity)
+    } else {
+        position_value(product, exit, quantity) - position_value(product, entry, quantity)
+    };
+    match side {
+        OrderSide::Bid => diff,
+        OrderSide::Ask => -diff,
+    }
+}
+
+/// Average entry price after adding to a position, harmonic for inverse products
+pub fn average_entry_price(
+    product: &Product,
+    entry: f64,
+    held: u64,
+    price: f64,
+    added: u64,
+) -> f64 {
+    if held == 0 {
+        return price;
+    }
+    let total = (held + added) as f64;
+    if product.is_inverse_priced {
+        total / (held as f64 / entry + added as f64 / price)
+    } else {
+        (held as f64 * entry + added as f64 * price) / total
+    }
+}
+
+/// Price at which the loss of the position equals its margin. Infinite for inverse shorts
+/// which margin covers any rise of the price.
+pub fn bankruptcy_price(
+    product: &Product,
+    side: OrderSide,
+    entry: f64,
+    quantity: u64,
+    margin: f64,
+) -> f64 {
+    if quantity == 0 || entry <= 0.0 {
+        return 0.0;
+    }
+    let price = if product.is_inverse_priced {
+        let value = quantity as f64 * product.contract_size * SATS_IN_BTC;
+        match side {
+            OrderSide::Bid => value / (value / entry + margin),
+            OrderSide::Ask => {
+                let divisor = value / entry - margin;
+                if divisor > 0.0 {
+                    value / divisor
+                } else {
+                    f64::INFINITY
+                }
+            }
+        }
+    } else {
+        let contracts = quantity as f64 * product.contract_size;
+        match side {
+            OrderSide::Bid => entry - margin / contracts,
+            OrderSide::Ask => entry + margin / contracts,
+        }
+    };
+    price.max(0.0)
+}
+
+/// Mark price at which the position is liquidated
+pub fn liquidation_price(
+    product: &Product,
+    side: OrderSide,
+    entry: f64,
+    quantity: u64,
+    margin: f64,
+) -> f64 {
+    liquidation_price_with_buffer(product, side, entry, quantity, margin, LIQUIDATION_BUFFER)
+}
+
+/// Same as `liquidation_price`, but `buffer` share of the entry value is kept above the
+/// maintenance margin instead of `LIQUIDATION_BUFFER`
+pub fn liquidation_price_with_buffer(
+    product: &Product,
+    side: OrderSide,
+    entry: f64,
+    quantity: u64,
+    margin: f64,
+    buffer: f64,
+) -> f64 {
+    // The loss at the liquidation price leaves the kept margin, so it is the bankruptcy price
+    // of the margin above it
+    let kept = maintenance_margin(product, entry, quantity)
+        + position_value(product, entry, quantity) * buffer;
+    bankruptcy_price(product, side, entry, quantity, margin - kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::{OrderPrediction, PositionDetails};
+
+    /// BTCUSD.PERP with the parameters of `api::products` sample
+    fn inverse() -> Product {
+        Product {
+            symbol: "BTCUSD.PERP".to_owned(),
+            contract_size: 1.0,
+            max_leverage: 100.0,
+            base_margin: 0.005,
+            maintenance_margin: 0.004,
+            is_inverse_priced: true,
+            price_dp: 1.0,
+            underlying_symbol: ".BTCUSD".to_owned(),
+            last_price: 480000.0,
+            tick_size: 0.5,
+            risk_limit: 100000000.0,
+        }
+    }
+
+    fn linear() -> Product {
+        Product {
+            symbol: "ETHBTC.PERP".to_owned(),
+            contract_size: 1000.0,
+            is_inverse_priced: false,
+            underlying_symbol: ".ETHBTC".to_owned(),
+            ..inverse()
+        }
+    }
+
+    #[test]
+    fn test_order_prediction_sample() {
+        // Bid for 1 contract at 48000 with leverage 1, see `test_order_prediction` in
+        // `api::trading::order`
+        let sample: OrderPrediction = serde_json::from_str(
+            r#"
+            {
+                "uid": 7051,
+                "ext_id": "8c8b8062-d14b-4b14-a05b-84c92a32f4d3",
+                "margin_required": "2083.3333333333333333333300000",
+                "value": "2083.3333333333333333333300000",
+                "exchange_fee": "-0.5208333333333333333333325000",
+                "estimated_liquidation_price": "240782.54326561324303988018849",
+                "rejection_reason": "InstantLiquidation"
+            }"#,
+        )
+        .unwrap();
+        let product = inverse();
+        let (price, quantity, leverage) = (48000.0, 1, 1);
+
+        let value = position_value(&product, price, quantity);
+        let margin = initial_margin(&product, price, quantity, leverage);
+        let fee = exchange_fee(&product, price, quantity, true);
+        let liquidation = liquidation_price(&product, OrderSide::Bid, price, quantity, margin);
+        assert!((value - sample.value).abs() < 1e-9);
+        assert!((margin - sample.margin_required).abs() < 1e-9);
+        assert!((fee - sample.exchange_fee).abs() < 1e-9);
+        assert!(
+            (bankruptcy_price(&product, OrderSide::Bid, price, quantity, margin) - 24000.0).abs()
+                < 1e-9
+        );
+
+        let expected = sample.estimated_liquidation_price / 10f64.powi(product.price_dp as i32);
+        assert!((liquidation - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_position_sample() {
+        let sample: PositionDetails = serde_json::from_str(
+            r#"
+            {
+                "uid": 11,
+                "timestamp": 1604332066202,
+                "symbol": "BTCUSD.PERP",
+                "upnl": "-6",
+                "leverage": "1.00",
+                "entry_price": "13534.0",
+                "side": "Bid",
+                "quantity": "1",
+                "liq_price": "6788.3",
+                "open_order_ids": []
+            }"#,
+        )
+        .unwrap();
+        let product = inverse();
+        let quantity = sample.quantity as u64;
+        let margin = initial_margin(
+            &product,
+            sample.entry_price,
+            quantity,
+            sample.leverage as u64,
+        );
+        let (side, entry) = (sample.side, sample.entry_price);
+        // The older sample was made with a smaller buffer, the price is rounded to 0.1
+        let liquidation =
+            liquidation_price_with_buffer(&product, side, entry, quantity, margin, 0.00228);
+        assert!((liquidation - sample.liq_price).abs() < 0.05);
+        // The current buffer liquidates the long a bit earlier, on the safe side
+        let liquidation = liquidation_price(&product, side, entry, quantity, margin);
+        assert!(liquidation > sample.liq_price && liquidation - sample.liq_price < 1.0);
+    }
+
+    #[test]
+    fn test_inverse_short() {
+        let product = inverse();
+        let margin = initial_margin(&product, 50000.0, 100, 10);
+        assert!((margin - 20000.0).abs() < 1e-6);
+        let bankruptcy = bankruptcy_price(&product, OrderSide::Ask, 50000.0, 100, margin);
+        assert!((pnl(&product, OrderSide::Ask, 50000.0, bankruptcy, 100) + margin).abs() < 1e-6);
+        let liquidation = liquidation_price(&product, OrderSide::Ask, 50000.0, 100, margin);
+        assert!(liquidation < bankruptcy && liquidation > 50000.0);
+        let left = margin + pnl(&product, OrderSide::Ask, 50000.0, liquidation, 100);
+        let kept = maintenance_margin(&product, 50000.0, 100)
+            + position_value(&product, 50000.0, 100) * LIQUIDATION_BUFFER;
+        assert!((left - kept).abs() < 1e-6);
+
+        let unlevered = initial_margin(&product, 50000.0, 100, 1);
+        assert_eq!(
+            bankruptcy_price(&product, OrderSide::Ask, 50000.0, 100, unlevered),
+            f64::INFINITY
+        );
+        assert!((average_entry_price(&product, 40000.0, 1, 60000.0, 1) - 48000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_linear() {
+        let product = linear();
+        let value = position_value(&product, 0.07, 2);
+        assert!((value - 140.0).abs() < 1e-9);
+        let margin = initial_margin(&product, 0.07, 2, 10);
+        assert!((margin - 14.0).abs() < 1e-9);
+        let bankruptcy = bankruptcy_price(&product, OrderSide::Bid, 0.07, 2, margin);
+        assert!((bankruptcy - 0.063).abs() < 1e-12);
+        let liquidation = liquidation_price(&product, OrderSide::Bid, 0.07, 2, margin);
+        let left = margin + pnl(&product, OrderSide::Bid, 0.07, liquidation, 2);
+        let kept = maintenance_margin(&product, 0.07, 2) + value * LIQUIDATION_BUFFER;
+        assert!((left - kept).abs() < 1e-9);
+        assert!((pnl(&product, OrderSide::Ask, 0.07, 0.06, 2) - 20.0).abs() < 1e-9);
+        assert!((average_entry_price(&product, 0.06, 1, 0.08, 1) - 0.07).abs() < 1e-12);
+    }
+}
@@ -12,7 +12,7 @@ impl KolliderClient {
 
     /// POST endpoint /wallet/deposit
     pub async fn wallet_deposit(&self, body: &DepositBody) -> Result<DepositResp> {
-        let inner: DepositBodyInner = body.clone().into();
+        let inner: DepositBodyInner = (*body).into();
         self.post_request_auth("/wallet/deposit", Some(&inner)).await
     }
 
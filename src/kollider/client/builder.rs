@@ -0,0 +1,220 @@
+use super::clock::{global_clock, ServerClock};
+use super::env::{KolliderClient, KOLLIDER_MAINNET, KOLLIDER_TESTNET};
+use super::error::{Error, Result};
+use super::signer::Signer;
+#[cfg(feature = "journal")]
+use crate::kollider::journal::Journal;
+use log::*;
+use reqwest::header::HeaderMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Configures HTTP settings of `KolliderClient`. HTTP options are applied to a new
+/// `reqwest::Client`, they are ignored when a ready client is injected with `http_client`.
+///
+/// ```no_run
+/// # use kollider_api::kollider::client::*;
+/// let client = KolliderClientBuilder::new()
+///     .testnet()
+///     .timeout(std::time::Duration::from_secs(10))
+///     .user_agent("trading-bot/1.0")
+///     .build()?;
+/// # Ok::<(), kollider_api::kollider::client::error::Error>(())
+/// ```
+pub struct KolliderClientBuilder {
+    server: String,
+    http: reqwest::ClientBuilder,
+    http_configured: bool,
+    client: Option<reqwest::Client>,
+    auth: Option<Arc<dyn Signer>>,
+    clock: Option<ServerClock>,
+    #[cfg(feature = "journal")]
+    journal: Option<Journal>,
+}
+
+impl KolliderClientBuilder {
+    /// Builder for the mainnet with default HTTP settings
+    pub fn new() -> Self {
+        KolliderClientBuilder {
+            server: KOLLIDER_MAINNET.to_owned(),
+            http: reqwest::ClientBuilder::new(),
+            http_configured: false,
+            client: None,
+            auth: None,
+            clock: None,
+            #[cfg(feature = "journal")]
+            journal: None,
+        }
+    }
+
+    /// Base URL of the REST API, e.x. `https://api.kollider.xyz/v1`
+    pub fn server(mut self, url: &str) -> Self {
+        self.server = url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    pub fn mainnet(self) -> Self {
+        self.server(KOLLIDER_MAINNET)
+    }
+
+    pub fn testnet(self) -> Self {
+        self.server(KOLLIDER_TESTNET)
+    }
+
+    /// Timeout of the whole request including reading of the body
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.with_http(|b| b.timeout(timeout))
+    }
+
+    /// Timeout of establishing connection
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        self.with_http(|b| b.connect_timeout(timeout))
+    }
+
+    /// Route requests through the proxy
+    pub fn proxy(self, proxy: reqwest::Proxy) -> Self {
+        self.with_http(|b| b.proxy(proxy))
+    }
+
+    /// Ignore system proxy settings
+    pub fn no_proxy(self) -> Self {
+        self.with_http(|b| b.no_proxy())
+    }
+
+    pub fn user_agent(self, user_agent: &str) -> Self {
+        let user_agent = user_agent.to_owned();
+        self.with_http(|b| b.user_agent(user_agent))
+    }
+
+    /// Maximum number of idle connections kept for the server
+    pub fn pool_max_idle_per_host(self, max: usize) -> Self {
+        self.with_http(|b| b.pool_max_idle_per_host(max))
+    }
+
+    /// How long idle connections are kept in the pool
+    pub fn pool_idle_timeout(self, timeout: Option<Duration>) -> Self {
+        self.with_http(|b| b.pool_idle_timeout(timeout))
+    }
+
+    /// Headers that are added to every request
+    pub fn default_headers(self, headers: HeaderMap) -> Self {
+        self.with_http(|b| b.default_headers(headers))
+    }
+
+    /// Trust additional root certificate, e.x. of a corporate proxy
+    pub fn add_root_certificate(self, cert: reqwest::Certificate) -> Self {
+        self.with_http(|b| b.add_root_certificate(cert))
+    }
+
+    /// Minimum TLS version of connections
+    pub fn min_tls_version(self, version: reqwest::tls::Version) -> Self {
+        self.with_http(|b| b.min_tls_version(version))
+    }
+
+    /// Accept invalid certificates. Use only for local testing.
+    pub fn danger_accept_invalid_certs(self, accept: bool) -> Self {
+        self.with_http(|b| b.danger_accept_invalid_certs(accept))
+    }
+
+    /// Apply any other setting of `reqwest::ClientBuilder`
+    pub fn with_http<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(reqwest::ClientBuilder) -> reqwest::ClientBuilder,
+    {
+        self.http = f(self.http);
+        self.http_configured = true;
+        self
+    }
+
+    /// Use already configured HTTP client, e.x. to share connection pool with other code
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Credentials or external signer for private endpoints
+    pub fn auth<S: Signer + 'static>(mut self, signer: S) -> Self {
+        self.auth = Some(Arc::new(signer));
+        self
+    }
+
+    /// Use separate server clock instead of the global one
+    pub fn clock(mut self, clock: ServerClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Record results of order requests in the journal
+    #[cfg(feature = "journal")]
+    pub fn journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub fn build(self) -> Result<KolliderClient> {
+        url::Url::parse(&self.server)
+            .map_err(|e| Error::InvalidServerUrl(self.server.clone(), e))?;
+        let client = match self.client {
+            Some(client) => {
+                if self.http_configured {
+                    warn!("HTTP settings of KolliderClientBuilder are ignored as the client is injected");
+                }
+                client
+            }
+            None => self.http.build()?,
+        };
+        Ok(KolliderClient {
+            client,
+            server: self.server,
+            auth: self.auth,
+            clock: self.clock.unwrap_or_else(|| global_clock().clone()),
+            #[cfg(feature = "journal")]
+            journal: self.journal,
+        })
+    }
+}
+
+impl Default for KolliderClientBuilder {
+    fn default() -> Self {
+        KolliderClientBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_builder() {
+        let client = KolliderClientBuilder::new()
+            .server("http://localhost:8080/v1/")
+            .timeout(Duration::from_secs(5))
+            .user_agent("test")
+            .pool_max_idle_per_host(2)
+            .build()
+            .unwrap();
+        assert_eq!(client.server, "http://localhost:8080/v1");
+        assert!(client.auth.is_none());
+
+        match KolliderClientBuilder::new().server("not a url").build() {
+            Err(Error::InvalidServerUrl(url, _)) => assert_eq!(url, "not a url"),
+            _ => panic!("Expected invalid URL error"),
+        }
+
+        let clock = ServerClock::default();
+        let client = KolliderClientBuilder::new()
+            .testnet()
+            .http_client(reqwest::Client::new())
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        assert_eq!(client.server, KOLLIDER_TESTNET);
+        clock.observe(
+            Utc::now(),
+            Utc::now(),
+            Utc::now() + chrono::Duration::seconds(10),
+        );
+        assert!(client.clock_skew().is_some());
+    }
+}
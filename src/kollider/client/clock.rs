@@ -0,0 +1,125 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use log::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// How many last measurements are used to estimate the offset
+pub const CLOCK_SAMPLES: usize = 8;
+
+/// Estimates offset between local and server clocks from server responses and applies it
+/// to timestamps of signed requests. HTTP `Date` header has a resolution of one second, so
+/// the estimate is the median of several measurements each assuming the middle of the second.
+///
+/// Clones share the same measurements.
+#[derive(Debug, Clone, Default)]
+pub struct ServerClock {
+    samples: Arc<Mutex<VecDeque<i64>>>,
+}
+
+static GLOBAL_CLOCK: OnceLock<ServerClock> = OnceLock::new();
+
+/// Clock that is shared by `KolliderClient` instances and WebSocket auth messages by default
+pub fn global_clock() -> &'static ServerClock {
+    GLOBAL_CLOCK.get_or_init(ServerClock::default)
+}
+
+impl ServerClock {
+    /// Record server time taken from response to request that was sent at `sent` and
+    /// received at `received` local time.
+    pub fn observe(&self, sent: DateTime<Utc>, received: DateTime<Utc>, server: DateTime<Utc>) {
+        let local_mid = sent + (received - sent) / 2;
+        let server_mid = server + Duration::milliseconds(500);
+        let offset = (server_mid - local_mid).num_milliseconds();
+        trace!("Measured server clock offset {} ms", offset);
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back(offset);
+        while samples.len() > CLOCK_SAMPLES {
+            samples.pop_front();
+        }
+    }
+
+    /// Record server time from HTTP `Date` header. Returns `false` if the header is missing or malformed.
+    pub fn observe_date_header(
+        &self,
+        sent: DateTime<Utc>,
+        received: DateTime<Utc>,
+        headers: &reqwest::header::HeaderMap,
+    ) -> bool {
+        let date = headers
+            .get(reqwest::header::DATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+        match date {
+            Some(date) => {
+                self.observe(sent, received, date.with_timezone(&Utc));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Measured skew of server clock relative to the local one. Positive if the server is ahead.
+    /// `None` until the first measurement.
+    pub fn offset(&self) -> Option<Duration> {
+        let samples = self.samples.lock().unwrap();
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<i64> = samples.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(Duration::milliseconds(sorted[sorted.len() / 2]))
+    }
+
+    /// Number of measurements the offset is estimated from
+    pub fn samples(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    /// Forget all measurements
+    pub fn reset(&self) {
+        self.samples.lock().unwrap().clear();
+    }
+
+    /// Current time of the server according to the estimated offset
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.offset().unwrap_or_else(Duration::zero)
+    }
+
+    /// Timestamp in seconds for `K-TIMESTAMP` header and WebSocket auth
+    pub fn timestamp(&self) -> i64 {
+        self.now().timestamp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_offset() {
+        let clock = ServerClock::default();
+        assert_eq!(clock.offset(), None);
+
+        let sent = Utc.timestamp_opt(1640000000, 0).unwrap();
+        let received = sent + Duration::milliseconds(200);
+        let server = Utc.timestamp_opt(1640000005, 0).unwrap();
+        clock.observe(sent, received, server);
+        assert_eq!(clock.offset(), Some(Duration::milliseconds(5400)));
+
+        // Outlier doesn't move the median
+        clock.observe(sent, received, server);
+        clock.observe(sent, received + Duration::seconds(20), server);
+        assert_eq!(clock.offset(), Some(Duration::milliseconds(5400)));
+        assert_eq!(clock.samples(), 3);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::DATE,
+            "Mon, 20 Dec 2021 11:33:25 GMT".parse().unwrap(),
+        );
+        clock.reset();
+        assert!(clock.observe_date_header(sent, received, &headers));
+        assert_eq!(clock.offset(), Some(Duration::milliseconds(5400)));
+    }
+}
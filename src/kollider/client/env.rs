@@ -1,10 +1,20 @@
+use super::builder::KolliderClientBuilder;
+use super::clock::{global_clock, ServerClock};
 use super::error::{Error, Result};
+use super::signer::{
+    rest_payload, SignError, Signer, HEADER_API_KEY, HEADER_PASSPHRASE, HEADER_SIGNATURE,
+    HEADER_TIMESTAMP,
+};
+#[cfg(feature = "journal")]
+use crate::kollider::journal::Journal;
 use crate::kollider::api::error::{KolliderError, KolliderResult};
 use chrono::prelude::*;
-use hmac::{Hmac, Mac};
+use chrono::Duration;
 use log::*;
-use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
 use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 pub const KOLLIDER_MAINNET: &str = "https://api.kollider.xyz/v1";
 pub const KOLLIDER_TESTNET: &str = "https://test.api.kollider.xyz/v1";
@@ -12,28 +22,95 @@ pub const KOLLIDER_TESTNET: &str = "https://test.api.kollider.xyz/v1";
 pub struct KolliderClient {
     pub client: reqwest::Client,
     pub server: String,
-    pub auth: Option<KolliderAuth>,
+    /// Signer of private requests, `KolliderAuth` signs with HMAC in process
+    pub auth: Option<Arc<dyn Signer>>,
+    /// Offset of server clock that is measured from responses and applied to `K-TIMESTAMP`
+    pub clock: ServerClock,
+    /// Record results of `create_order` and `cancel_order`
+    #[cfg(feature = "journal")]
+    pub journal: Option<Journal>,
 }
 
 impl KolliderClient {
+    /// Client of the mainnet, same as `KolliderClient::mainnet`
     pub fn new() -> Self {
         KolliderClient::mainnet()
     }
 
+    /// Client of the testnet with default HTTP settings.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client cannot be built, e.x. TLS backend fails to initialize. Use
+    /// `KolliderClient::builder().testnet().build()` to handle the error.
     pub fn testnet() -> Self {
-        KolliderClient {
-            client: reqwest::ClientBuilder::new().build().unwrap(),
-            server: KOLLIDER_TESTNET.to_owned(),
-            auth: None,
-        }
+        KolliderClient::builder()
+            .testnet()
+            .build()
+            .expect("Failed to build HTTP client")
     }
 
+    /// Client of the mainnet with default HTTP settings.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client cannot be built, e.x. TLS backend fails to initialize. Use
+    /// `KolliderClient::builder().mainnet().build()` to handle the error.
     pub fn mainnet() -> Self {
-        KolliderClient {
-            client: reqwest::ClientBuilder::new().build().unwrap(),
-            server: KOLLIDER_MAINNET.to_owned(),
-            auth: None,
+        KolliderClient::builder()
+            .mainnet()
+            .build()
+            .expect("Failed to build HTTP client")
+    }
+
+    /// Configure HTTP settings of the client, see `KolliderClientBuilder`
+    pub fn builder() -> KolliderClientBuilder {
+        KolliderClientBuilder::new()
+    }
+
+    /// Set credentials or external signer for private endpoints
+    pub fn set_auth<S: Signer + 'static>(&mut self, signer: S) {
+        self.auth = Some(Arc::new(signer));
+    }
+
+    /// Measured skew of server clock relative to the local one
+    pub fn clock_skew(&self) -> Option<Duration> {
+        self.clock.offset()
+    }
+
+    /// Measure clock skew with a request to the server and return the current estimate
+    pub async fn sync_time(&self) -> Result<Duration> {
+        let request = self
+            .client
+            .get(format!("{}/market/products", self.server))
+            .build()?;
+        self.execute(request).await?;
+        Ok(self.clock.offset().unwrap_or_else(Duration::zero))
+    }
+
+    /// Timestamp for `K-TIMESTAMP`. The server clock is measured first if there are no
+    /// samples yet, so the first signed request from a machine with skewed clock is accepted.
+    async fn auth_timestamp(&self) -> i64 {
+        if self.clock.samples() == 0 {
+            if let Err(e) = self.sync_time().await {
+                warn!("Failed to measure server clock: {}", e);
+            }
+        }
+        self.clock.timestamp()
+    }
+
+    /// Execute request and return body. `Date` header of the response is used to
+    /// measure the server clock.
+    async fn execute(&self, request: reqwest::Request) -> Result<String> {
+        let sent = Utc::now();
+        let response = self.client.execute(request).await?;
+        if !self
+            .clock
+            .observe_date_header(sent, Utc::now(), response.headers())
+        {
+            trace!("Response has no valid Date header");
         }
+        Ok(response.text().await?)
     }
 
     /// Helper to query GET request with authentification headers
@@ -49,16 +126,18 @@ impl KolliderClient {
         let endpoint = format!("{}{}", self.server, path);
         let body: Option<()> = None;
 
-        let request = auth
-            .inject_auth(
-                "GET",
-                path,
-                body,
-                self.client.get(endpoint).query(query_args),
-            )?
-            .build()?;
+        let request = inject_auth_async(
+            auth.as_ref(),
+            self.auth_timestamp().await,
+            "GET",
+            path,
+            body,
+            self.client.get(endpoint).query(query_args),
+        )
+        .await?
+        .build()?;
         debug!("Requesting GET URL {}", request.url());
-        let txt = self.client.execute(request).await?.text().await?;
+        let txt = self.execute(request).await?;
         debug!("Got response body {}", txt);
         let raw_res: KolliderResult<T> = serde_json::from_str(&txt)?;
         let res: std::result::Result<T, KolliderError> = raw_res.into();
@@ -77,11 +156,18 @@ impl KolliderClient {
         let endpoint = format!("{}{}", self.server, path);
         let body: Option<()> = None;
 
-        let request = auth
-            .inject_auth("GET", path, body, self.client.get(endpoint))?
-            .build()?;
+        let request = inject_auth_async(
+            auth.as_ref(),
+            self.auth_timestamp().await,
+            "GET",
+            path,
+            body,
+            self.client.get(endpoint),
+        )
+        .await?
+        .build()?;
         debug!("Requesting GET URL {}", request.url());
-        let txt = self.client.execute(request).await?.text().await?;
+        let txt = self.execute(request).await?;
         debug!("Got response body {}", txt);
         let raw_res: KolliderResult<T> = serde_json::from_str(&txt)?;
         let res: std::result::Result<T, KolliderError> = raw_res.into();
@@ -99,7 +185,7 @@ impl KolliderClient {
         let request = self.client.get(endpoint).query(query_args).build()?;
 
         debug!("Requesting GET URL {}", request.url());
-        let txt = self.client.execute(request).await?.text().await?;
+        let txt = self.execute(request).await?;
         debug!("Got response body {}", txt);
         let raw_res: KolliderResult<T> = serde_json::from_str(&txt)?;
         let res: std::result::Result<T, KolliderError> = raw_res.into();
@@ -116,7 +202,7 @@ impl KolliderClient {
         let request = self.client.get(endpoint).build()?;
 
         debug!("Requesting GET URL {}", request.url());
-        let txt = self.client.execute(request).await?.text().await?;
+        let txt = self.execute(request).await?;
         debug!("Got response body {}", txt);
         let raw_res: KolliderResult<T> = serde_json::from_str(&txt)?;
         let res: std::result::Result<T, KolliderError> = raw_res.into();
@@ -140,12 +226,21 @@ impl KolliderClient {
         } else {
             self.client.post(endpoint)
         };
-        let request = auth.inject_auth("POST", path, body, raw_request)?.build()?;
+        let request = inject_auth_async(
+            auth.as_ref(),
+            self.auth_timestamp().await,
+            "POST",
+            path,
+            body,
+            raw_request,
+        )
+        .await?
+        .build()?;
         if log_enabled!(Level::Debug) {
             let body = std::str::from_utf8(request.body().unwrap().as_bytes().unwrap()).unwrap();
             debug!("Requesting POST URL {} with body {}", request.url(), body);
         }
-        let txt = self.client.execute(request).await?.text().await?;
+        let txt = self.execute(request).await?;
         debug!("Got response body {}", txt);
         let raw_res: KolliderResult<R> = serde_json::from_str(&txt)?;
         let res: std::result::Result<R, KolliderError> = raw_res.into();
@@ -166,13 +261,20 @@ impl KolliderClient {
 
         let body: Option<serde_json::Value> = Some(serde_json::to_value(query_args)?);
         let raw_request = self.client.delete(endpoint).query(query_args);
-        let request = auth
-            .inject_auth("DELETE", path, body, raw_request)?
-            .build()?;
+        let request = inject_auth_async(
+            auth.as_ref(),
+            self.auth_timestamp().await,
+            "DELETE",
+            path,
+            body,
+            raw_request,
+        )
+        .await?
+        .build()?;
         if log_enabled!(Level::Debug) {
             debug!("Requesting DELETE URL {}", request.url());
         }
-        let txt = self.client.execute(request).await?.text().await?;
+        let txt = self.execute(request).await?;
         debug!("Got response body {}", txt);
         let raw_res: KolliderResult<R> = serde_json::from_str(&txt)?;
         let res: std::result::Result<R, KolliderError> = raw_res.into();
@@ -186,21 +288,33 @@ impl Default for KolliderClient {
     }
 }
 
-#[derive(Clone)]
+/// Credentials for private endpoints. Secrets are wiped from memory on drop and
+/// are not printed by `Debug`.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct KolliderAuth {
     pub api_key: String,
     pub api_secret: Vec<u8>,
     pub password: String,
 }
 
-type HmacSha256 = Hmac<Sha256>;
+impl fmt::Debug for KolliderAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KolliderAuth")
+            .field("api_key", &self.api_key)
+            .field("api_secret", &"<redacted>")
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum AuthError {
-    #[error("API secret invalid: {0}")]
-    ApiSecretInvalid(#[from] crypto_common::InvalidLength),
+    #[error("Failed to sign request: {0}")]
+    Sign(#[from] SignError),
     #[error("Cannot serialize body to sign: {0}")]
     BodySerialization(#[from] serde_json::Error),
+    #[error("Value of header {0} is not valid: {1}")]
+    InvalidHeader(&'static str, reqwest::header::InvalidHeaderValue),
 }
 
 impl KolliderAuth {
@@ -216,6 +330,7 @@ impl KolliderAuth {
         })
     }
 
+    /// Add authentification headers signed with HMAC to the request
     pub fn inject_auth<T>(
         &self,
         method: &str,
@@ -226,28 +341,125 @@ impl KolliderAuth {
     where
         T: serde::Serialize,
     {
-        let mut mac = HmacSha256::new_from_slice(&self.api_secret)?;
-        let mut payload = vec![];
-        let timestamp = format!("{}", Utc::now().timestamp());
-        payload.extend(timestamp.as_bytes());
-        payload.extend(method.bytes());
-        payload.extend(route.bytes());
-        if let Some(body) = mbody {
-            let mut body_str = serde_json::to_string(&body)?;
-            body_str.retain(|c| !c.is_whitespace());
-            payload.extend(body_str.bytes());
-        }
-        trace!("HMAC payload: {}", std::str::from_utf8(&payload).unwrap());
-        mac.update(&payload);
-        let signature = base64::encode(mac.finalize().into_bytes());
-        trace!("Signagure {}", signature);
-
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert("K-API-KEY", self.api_key.parse().unwrap());
-        headers.insert("K-SIGNATURE", signature.parse().unwrap());
-        headers.insert("K-TIMESTAMP", timestamp.parse().unwrap());
-        headers.insert("K-PASSPHRASE", self.password.parse().unwrap());
-
-        Ok(request.headers(headers))
+        inject_auth(self, method, route, mbody, request)
+    }
+}
+
+/// Add authentification headers to the request with signature made by the signer.
+/// Timestamp is taken from the global server clock.
+pub fn inject_auth<T>(
+    signer: &dyn Signer,
+    method: &str,
+    route: &str,
+    mbody: Option<T>,
+    request: reqwest::RequestBuilder,
+) -> std::result::Result<reqwest::RequestBuilder, AuthError>
+where
+    T: serde::Serialize,
+{
+    let timestamp = global_clock().timestamp();
+    inject_auth_at(signer, timestamp, method, route, mbody, request)
+}
+
+/// Add authentification headers to the request signed for the given timestamp in seconds
+pub fn inject_auth_at<T>(
+    signer: &dyn Signer,
+    timestamp: i64,
+    method: &str,
+    route: &str,
+    mbody: Option<T>,
+    request: reqwest::RequestBuilder,
+) -> std::result::Result<reqwest::RequestBuilder, AuthError>
+where
+    T: serde::Serialize,
+{
+    let timestamp = format!("{}", timestamp);
+    let payload = signed_payload(&timestamp, method, route, mbody)?;
+    let signature = signer.sign(&payload)?;
+    with_auth_headers(signer, &timestamp, &signature, request)
+}
+
+/// Same as `inject_auth_at`, but the signature is made with `Signer::sign_async`
+pub async fn inject_auth_async<T>(
+    signer: &dyn Signer,
+    timestamp: i64,
+    method: &str,
+    route: &str,
+    mbody: Option<T>,
+    request: reqwest::RequestBuilder,
+) -> std::result::Result<reqwest::RequestBuilder, AuthError>
+where
+    T: serde::Serialize,
+{
+    let timestamp = format!("{}", timestamp);
+    let payload = signed_payload(&timestamp, method, route, mbody)?;
+    let signature = signer.sign_async(&payload).await?;
+    with_auth_headers(signer, &timestamp, &signature, request)
+}
+
+fn signed_payload<T: serde::Serialize>(
+    timestamp: &str,
+    method: &str,
+    route: &str,
+    mbody: Option<T>,
+) -> std::result::Result<Vec<u8>, AuthError> {
+    let body_str = match mbody {
+        Some(body) => Some(serde_json::to_string(&body)?),
+        None => None,
+    };
+    Ok(rest_payload(timestamp, method, route, body_str.as_deref()))
+}
+
+/// Credentials come from config files and external signers, so any of them can contain
+/// bytes that are not allowed in headers, e.x. a trailing newline
+fn with_auth_headers(
+    signer: &dyn Signer,
+    timestamp: &str,
+    signature: &str,
+    request: reqwest::RequestBuilder,
+) -> std::result::Result<reqwest::RequestBuilder, AuthError> {
+    trace!("Signagure {}", signature);
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in [
+        (HEADER_API_KEY, signer.api_key()),
+        (HEADER_SIGNATURE, signature),
+        (HEADER_TIMESTAMP, timestamp),
+        (HEADER_PASSPHRASE, signer.passphrase()),
+    ] {
+        let value = reqwest::header::HeaderValue::from_str(value)
+            .map_err(|e| AuthError::InvalidHeader(name, e))?;
+        headers.insert(name, value);
+    }
+    Ok(request.headers(headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_debug_redacted() {
+        let auth = KolliderAuth::new("my-key", "c2VjcmV0", "hunter2").unwrap();
+        let debug = format!("{:?}", auth);
+        assert!(debug.contains("my-key"));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("115, 101, 99"));
+    }
+
+    #[test]
+    fn test_invalid_header_value() {
+        let auth = KolliderAuth::new("my-key", "c2VjcmV0", "hunter2\n").unwrap();
+        let res = inject_auth_at(
+            &auth,
+            1640000000,
+            "GET",
+            "/user/account",
+            None::<()>,
+            reqwest::Client::new().get("http://localhost/user/account"),
+        );
+        assert!(matches!(
+            res,
+            Err(AuthError::InvalidHeader(HEADER_PASSPHRASE, _))
+        ));
     }
 }
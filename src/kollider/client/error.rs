@@ -1,4 +1,5 @@
 use crate::kollider::api::error::KolliderError;
+use crate::kollider::api::market::IncompatibleTimeframe;
 use crate::kollider::env::AuthError;
 use thiserror::Error;
 
@@ -16,6 +17,10 @@ pub enum Error {
     AuthError(#[from] AuthError),
     #[error("Cannot cancel order {0} for ticker {1} due reason: {2}")]
     CancelOrder(u64, String, String),
+    #[error("Server URL {0} is invalid: {1}")]
+    InvalidServerUrl(String, url::ParseError),
+    #[error("{0}")]
+    Resample(#[from] IncompatibleTimeframe),
 }
 
 /// Alias for a `Result` with the error type `self::Error`.
@@ -0,0 +1,249 @@
+use super::env::KolliderAuth;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Current version of keystore file format
+pub const KEYSTORE_VERSION: u32 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("Failed to read or write keystore file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Keystore file is malformed: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("Keystore field is not valid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Keystore version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("Failed to derive key from passphrase: {0}")]
+    Kdf(argon2::Error),
+    #[error("Failed to decrypt keystore, passphrase is wrong or the file is corrupted")]
+    Decryption,
+    #[error("Failed to encrypt credentials")]
+    Encryption,
+}
+
+/// Alias for a `Result` with the error type `KeystoreError`.
+pub type Result<T> = std::result::Result<T, KeystoreError>;
+
+/// Parameters of Argon2id key derivation
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct KdfParams {
+    /// Memory size in KiB
+    pub m_cost: u32,
+    /// Number of iterations
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Encrypted credentials as they are stored on disk. The key is derived from passphrase
+/// with Argon2id and the credentials are encrypted with ChaCha20-Poly1305.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Keystore {
+    pub version: u32,
+    pub kdf: KdfParams,
+    /// Base64 encoded salt of KDF
+    pub salt: String,
+    /// Base64 encoded nonce of cipher
+    pub nonce: String,
+    /// Base64 encoded encrypted credentials
+    pub ciphertext: String,
+}
+
+/// Plain text content of keystore
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct StoredAuth {
+    api_key: String,
+    /// Base64 encoded secret as it is given by Kollider
+    api_secret: String,
+    password: String,
+}
+
+impl Keystore {
+    /// Encrypt credentials with key derived from the passphrase
+    pub fn encrypt(auth: &KolliderAuth, passphrase: &str) -> Result<Self> {
+        Keystore::encrypt_with(auth, passphrase, KdfParams::default())
+    }
+
+    /// Encrypt credentials with custom KDF parameters
+    pub fn encrypt_with(auth: &KolliderAuth, passphrase: &str, kdf: KdfParams) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let stored = StoredAuth {
+            api_key: auth.api_key.clone(),
+            api_secret: base64::encode(&auth.api_secret),
+            password: auth.password.clone(),
+        };
+        let plaintext = Zeroizing::new(serde_json::to_vec(&stored)?);
+        let key = derive_key(passphrase, &salt, kdf)?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| KeystoreError::Encryption)?;
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            kdf,
+            salt: base64::encode(salt),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        })
+    }
+
+    /// Decrypt credentials with the passphrase
+    pub fn decrypt(&self, passphrase: &str) -> Result<KolliderAuth> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::UnsupportedVersion(self.version));
+        }
+        let salt = base64::decode(&self.salt)?;
+        let nonce = base64::decode(&self.nonce)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(KeystoreError::Decryption);
+        }
+        let ciphertext = base64::decode(&self.ciphertext)?;
+        let key = derive_key(passphrase, &salt, self.kdf)?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()));
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+                .map_err(|_| KeystoreError::Decryption)?,
+        );
+        let stored: StoredAuth = serde_json::from_slice(&plaintext)?;
+        let api_secret = base64::decode(&stored.api_secret)?;
+        Ok(KolliderAuth {
+            api_key: stored.api_key.clone(),
+            api_secret,
+            password: stored.password.clone(),
+        })
+    }
+
+    /// Read keystore from the file
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Write keystore to the file creating parent directories. On Unix the file
+    /// is readable only by the owner, also when it replaces an existing file. The
+    /// keystore is written to a temporary file next to the target and renamed over it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp_path)?;
+        // Mode of `OpenOptions` is ignored for a leftover temporary file
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        std::io::Write::write_all(&mut file, content.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Delete keystore file. Returns `false` if there was no file.
+    pub fn remove(path: &Path) -> Result<bool> {
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], kdf: KdfParams) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+        .map_err(KeystoreError::Kdf)?;
+    let argon = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    argon
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(KeystoreError::Kdf)?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_params() -> KdfParams {
+        KdfParams {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        }
+    }
+
+    #[test]
+    fn test_keystore_roundtrip() {
+        let auth = KolliderAuth::new("key", "c2VjcmV0", "pass").unwrap();
+        let keystore = Keystore::encrypt_with(&auth, "passphrase", test_params()).unwrap();
+        assert!(!keystore.ciphertext.contains("key"));
+
+        let json = serde_json::to_string(&keystore).unwrap();
+        let loaded: Keystore = serde_json::from_str(&json).unwrap();
+        let decrypted = loaded.decrypt("passphrase").unwrap();
+        assert_eq!(decrypted.api_key, "key");
+        assert_eq!(decrypted.api_secret, b"secret");
+        assert_eq!(decrypted.password, "pass");
+
+        match loaded.decrypt("wrong") {
+            Err(KeystoreError::Decryption) => (),
+            other => panic!("Expected decryption error, got {:?}", other),
+        }
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_save_restricts_existing_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let path =
+            std::env::temp_dir().join(format!("kollider-keystore-{}.json", std::process::id()));
+        std::fs::write(&path, "{}").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let auth = KolliderAuth::new("key", "c2VjcmV0", "pass").unwrap();
+        let keystore = Keystore::encrypt_with(&auth, "passphrase", test_params()).unwrap();
+        keystore.save(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let loaded = Keystore::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(loaded.decrypt("passphrase").unwrap().api_key, "key");
+    }
+}
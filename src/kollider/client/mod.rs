@@ -1,12 +1,18 @@
 pub mod account;
+pub mod builder;
+pub mod clock;
 pub mod env;
 pub mod error;
+#[cfg(feature = "keystore")]
+pub mod keystore;
 pub mod market;
+pub mod pagination;
 pub mod products;
+pub mod signer;
 pub mod trading;
 
-pub use account::*;
+pub use builder::*;
+pub use clock::*;
 pub use env::*;
-pub use market::*;
-pub use products::*;
-pub use trading::*;
+pub use pagination::*;
+pub use signer::*;
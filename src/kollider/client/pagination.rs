@@ -0,0 +1,339 @@
+use super::env::KolliderClient;
+use super::error::Result;
+use crate::kollider::api::{
+    FillDetails, HistoryItem, HistoryResp, IncompatibleTimeframe, IntervalSize, OrderDetails,
+    Timeframe,
+};
+use chrono::prelude::*;
+use futures::future::Future;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use log::*;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// Number of intervals requested per page by `historic_index_prices_range`
+pub const HISTORY_PAGE_SIZE: usize = 1000;
+
+/// Item of history endpoints that can be fetched page by page
+pub trait PageItem {
+    type Key: Hash + Eq;
+
+    /// Time of the item in milliseconds
+    fn timestamp(&self) -> u64;
+
+    /// Identity of the item to drop duplicates that are returned twice at page boundaries
+    fn page_key(&self) -> Self::Key;
+}
+
+impl PageItem for OrderDetails {
+    type Key = (u64, u64);
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn page_key(&self) -> Self::Key {
+        (self.order_id, self.timestamp)
+    }
+}
+
+impl PageItem for FillDetails {
+    type Key = (u64, u64, u64);
+
+    fn timestamp(&self) -> u64 {
+        self.order.timestamp
+    }
+
+    fn page_key(&self) -> Self::Key {
+        (self.order.order_id, self.order.timestamp, self.remaining)
+    }
+}
+
+impl PageItem for HistoryItem {
+    type Key = u64;
+
+    fn timestamp(&self) -> u64 {
+        self.time * 1000
+    }
+
+    fn page_key(&self) -> Self::Key {
+        self.time
+    }
+}
+
+struct PageState<F, K> {
+    fetch: F,
+    /// Window in seconds as the endpoints accept it
+    start: i64,
+    end: i64,
+    limit: usize,
+    /// Keys of items at the boundary second of the previous page, the next page starts there
+    seen: HashSet<K>,
+    done: bool,
+}
+
+/// Walk time range from `start` to `end` in pages of `limit` items. Page that is shorter than
+/// `limit` ends the range. Otherwise the window is narrowed to the part the page hasn't covered:
+/// after the oldest item if the server returned items in ascending order, before the newest
+/// one if in descending order. Items are yielded in the order of the server. The first error
+/// is yielded and ends the stream.
+pub fn paginate<T, F, Fut>(
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    limit: usize,
+    fetch: F,
+) -> impl Stream<Item = Result<T>>
+where
+    T: PageItem,
+    F: FnMut(DateTime<Local>, DateTime<Local>) -> Fut,
+    Fut: Future<Output = Result<Vec<T>>>,
+{
+    let state = PageState {
+        fetch,
+        start: start.timestamp(),
+        end: end.timestamp(),
+        limit: limit.max(1),
+        seen: HashSet::new(),
+        done: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+        let window_start = Local.timestamp_opt(state.start, 0).unwrap();
+        let window_end = Local.timestamp_opt(state.end, 0).unwrap();
+        let page = match (state.fetch)(window_start, window_end).await {
+            Ok(page) => page,
+            Err(e) => {
+                state.done = true;
+                return Some((vec![Err(e)], state));
+            }
+        };
+        trace!(
+            "Fetched page of {} items between {} and {}",
+            page.len(),
+            state.start,
+            state.end
+        );
+        let items = next_window(&mut state, page);
+        Some((items.into_iter().map(Ok).collect::<Vec<_>>(), state))
+    })
+    .flat_map(stream::iter)
+}
+
+/// Narrow the window after the page and return items that weren't yielded yet
+fn next_window<F, T: PageItem>(state: &mut PageState<F, T::Key>, page: Vec<T>) -> Vec<T> {
+    let full = page.len() >= state.limit;
+    let descending = match (page.first(), page.last()) {
+        (Some(first), Some(last)) => first.timestamp() > last.timestamp(),
+        _ => false,
+    };
+    let boundary = if descending {
+        page.iter().map(|item| item.timestamp()).min()
+    } else {
+        page.iter().map(|item| item.timestamp()).max()
+    };
+    let boundary_second = boundary.map(|boundary| (boundary / 1000) as i64);
+    let boundary_keys: HashSet<T::Key> = page
+        .iter()
+        .filter(|item| Some((item.timestamp() / 1000) as i64) == boundary_second)
+        .map(|item| item.page_key())
+        .collect();
+    let items: Vec<T> = page
+        .into_iter()
+        .filter(|item| !state.seen.contains(&item.page_key()))
+        .collect();
+
+    match boundary_second {
+        Some(second) if full => {
+            let (start, end) = if descending {
+                (state.start, second)
+            } else {
+                (second, state.end)
+            };
+            if (start, end) == (state.start, state.end) && items.is_empty() {
+                // The whole page is within one second, the window cannot be narrowed by time
+                warn!(
+                    "More than {} items at second {}, some of them are skipped",
+                    state.limit, second
+                );
+                if descending {
+                    state.end = second - 1;
+                } else {
+                    state.start = second + 1;
+                }
+                state.seen.clear();
+            } else {
+                state.start = start;
+                state.end = end;
+                state.seen = boundary_keys;
+            }
+            state.done = state.start > state.end;
+        }
+        _ => state.done = true,
+    }
+    items
+}
+
+impl KolliderClient {
+    /// Stream all orders between `start` and `end` fetching `page_size` orders per request
+    pub fn orders_stream<'a>(
+        &'a self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<OrderDetails>> + 'a {
+        let symbol = symbol.to_owned();
+        paginate(start, end, page_size, move |start, end| {
+            let symbol = symbol.clone();
+            async move { self.orders(&symbol, start, end, page_size).await }
+        })
+    }
+
+    /// Stream all fills between `start` and `end` fetching `page_size` fills per request
+    pub fn fills_stream<'a>(
+        &'a self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<FillDetails>> + 'a {
+        let symbol = symbol.to_owned();
+        paginate(start, end, page_size, move |start, end| {
+            let symbol = symbol.clone();
+            async move { self.fills(&symbol, start, end, page_size).await }
+        })
+    }
+
+    /// Stream index price history between `start` and `end` fetching `page_size` intervals per request
+    pub fn historic_index_prices_stream<'a>(
+        &'a self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        interval_size: IntervalSize,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<HistoryItem>> + 'a {
+        let symbol = symbol.to_owned();
+        paginate(start, end, page_size, move |start, end| {
+            let symbol = symbol.clone();
+            async move {
+                let resp = self
+                    .market_historic_index_prices(page_size, &symbol, start, end, interval_size)
+                    .await?;
+                Ok(resp.data)
+            }
+        })
+    }
+
+    /// Fetch index price history of any length between `start` and `end` sorted by time
+    pub async fn historic_index_prices_range(
+        &self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        interval_size: IntervalSize,
+    ) -> Result<HistoryResp> {
+        let mut data: Vec<HistoryItem> = self
+            .historic_index_prices_stream(symbol, start, end, interval_size, HISTORY_PAGE_SIZE)
+            .try_collect()
+            .await?;
+        data.sort_by_key(|item| item.time);
+        Ok(HistoryResp {
+            data,
+            symbol: symbol.to_owned(),
+        })
+    }
+
+    /// Fetch index price history of any timeframe. Timeframes that the server doesn't provide
+    /// are resampled from the coarsest fitting interval, see `resample`.
+    pub async fn historic_index_prices_resampled(
+        &self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        timeframe: Timeframe,
+    ) -> Result<HistoryResp> {
+        if let Some(native) = timeframe.native() {
+            return self
+                .historic_index_prices_range(symbol, start, end, native)
+                .await;
+        }
+        let source = timeframe.source().ok_or(IncompatibleTimeframe {
+            source: IntervalSize::FiveMin,
+            target: timeframe,
+        })?;
+        let resp = self
+            .historic_index_prices_range(symbol, start, end, source)
+            .await?;
+        Ok(resp.resample(source, timeframe)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct Item(u64, u64);
+
+    impl PageItem for Item {
+        type Key = (u64, u64);
+
+        fn timestamp(&self) -> u64 {
+            self.1
+        }
+
+        fn page_key(&self) -> Self::Key {
+            (self.0, self.1)
+        }
+    }
+
+    fn history() -> Vec<Item> {
+        // Several items share the same second to cross page boundaries
+        let stamps = [
+            1_000, 1_500, 2_000, 2_100, 2_200, 2_900, 3_000, 5_000, 5_001,
+        ];
+        stamps
+            .iter()
+            .enumerate()
+            .map(|(i, ts)| Item(i as u64, 1_640_000_000_000 + ts))
+            .collect()
+    }
+
+    fn collect(limit: usize, descending: bool) -> Vec<Item> {
+        let all = history();
+        let start = Local.timestamp_opt(1_640_000_000, 0).unwrap();
+        let end = Local.timestamp_opt(1_640_000_010, 0).unwrap();
+        let stream = paginate(start, end, limit, |start, end| {
+            let mut page: Vec<Item> = all
+                .iter()
+                .filter(|item| {
+                    let second = (item.1 / 1000) as i64;
+                    second >= start.timestamp() && second <= end.timestamp()
+                })
+                .cloned()
+                .collect();
+            if descending {
+                page.reverse();
+            }
+            page.truncate(limit);
+            async move { Ok(page) }
+        });
+        block_on(stream.try_collect()).unwrap()
+    }
+
+    #[test]
+    fn test_paginate() {
+        let all = history();
+        for limit in 5..10 {
+            assert_eq!(collect(limit, false), all, "ascending, limit {}", limit);
+            let mut items = collect(limit, true);
+            items.reverse();
+            assert_eq!(items, all, "descending, limit {}", limit);
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use super::env::KolliderAuth;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use log::*;
+use sha2::Sha256;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Error, Debug)]
+pub enum SignError {
+    #[error("API secret invalid: {0}")]
+    InvalidKey(#[from] crypto_common::InvalidLength),
+    #[error("Failed to communicate with external signer: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to decode reply of external signer: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("External signer refused to sign: {0}")]
+    Remote(String),
+}
+
+/// Source of signatures for authentificated REST requests and WebSocket sessions.
+/// Implementations don't have to hold API secret in the process, e.x. `UnixSocketSigner`
+/// asks an external process for signature.
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// API key that is sent as `K-API-KEY` header or `token` of WS auth message
+    fn api_key(&self) -> &str;
+
+    /// Passphrase of the API key
+    fn passphrase(&self) -> &str;
+
+    /// Sign the payload and return base64 encoded signature
+    fn sign(&self, payload: &[u8]) -> Result<String, SignError>;
+
+    /// Same as `sign` for async code. Signers that wait for I/O override it to not block
+    /// the runtime, the default calls `sign`.
+    async fn sign_async(&self, payload: &[u8]) -> Result<String, SignError> {
+        self.sign(payload)
+    }
+}
+
+/// Default signer that signs with HMAC-SHA256 in process
+impl Signer for KolliderAuth {
+    fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    fn passphrase(&self) -> &str {
+        &self.password
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<String, SignError> {
+        let mut mac = HmacSha256::new_from_slice(&self.api_secret)?;
+        mac.update(payload);
+        Ok(base64::encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// Payload that is signed for REST request: timestamp, method, route and body without whitespaces
+pub fn rest_payload(timestamp: &str, method: &str, route: &str, body: Option<&str>) -> Vec<u8> {
+    let mut payload = vec![];
+    payload.extend(timestamp.as_bytes());
+    payload.extend(method.bytes());
+    payload.extend(route.bytes());
+    if let Some(body) = body {
+        let mut body = body.to_owned();
+        body.retain(|c| !c.is_whitespace());
+        payload.extend(body.bytes());
+    }
+    trace!("Signing payload: {}", String::from_utf8_lossy(&payload));
+    payload
+}
+
+/// Payload that is signed for WebSocket authentification
+pub fn ws_auth_payload(timestamp: &str) -> Vec<u8> {
+    let mut payload = vec![];
+    payload.extend(timestamp.as_bytes());
+    payload.extend("authentication".bytes());
+    trace!("Signing payload: {}", String::from_utf8_lossy(&payload));
+    payload
+}
+
+/// Header with API key of authentificated REST request
+pub const HEADER_API_KEY: &str = "K-API-KEY";
+/// Header with signature of authentificated REST request
+pub const HEADER_SIGNATURE: &str = "K-SIGNATURE";
+/// Header with timestamp in seconds of authentificated REST request
+pub const HEADER_TIMESTAMP: &str = "K-TIMESTAMP";
+/// Header with passphrase of authentificated REST request
+pub const HEADER_PASSPHRASE: &str = "K-PASSPHRASE";
+
+/// Reason to refuse a signed request. Names of variants match `AuthError` of the server.
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("Missing header {0}")]
+    MissingHeader(&'static str),
+    #[error("InvalidKey")]
+    InvalidKey,
+    #[error("InvalidPassphrase")]
+    InvalidPassphrase,
+    #[error("InvalidTimestamp")]
+    InvalidTimestamp,
+    #[error("InvalidSignature")]
+    InvalidSignature,
+    #[error("Failed to compute expected signature: {0}")]
+    Sign(#[from] SignError),
+}
+
+/// Credentials and the signed payload reconstructed from an incoming request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedRequest {
+    pub api_key: String,
+    pub passphrase: String,
+    pub timestamp: String,
+    pub signature: String,
+    pub payload: Vec<u8>,
+}
+
+impl SignedRequest {
+    /// Parse REST request with `K-*` headers. The `route` is without API prefix, e.x.
+    /// `/orders`. DELETE requests carry arguments in the query, but clients sign them as
+    /// JSON object, so the object is rebuilt from `query` when the body is empty.
+    pub fn rest(
+        method: &str,
+        route: &str,
+        query: &[(String, String)],
+        headers: &reqwest::header::HeaderMap,
+        body: &str,
+    ) -> Result<Self, VerifyError> {
+        let header = |name: &'static str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_owned())
+                .ok_or(VerifyError::MissingHeader(name))
+        };
+        let timestamp = header(HEADER_TIMESTAMP)?;
+        let signed_body = if !body.is_empty() {
+            Some(body.to_owned())
+        } else if method.eq_ignore_ascii_case("DELETE") && !query.is_empty() {
+            Some(query_json(query))
+        } else {
+            None
+        };
+        Ok(SignedRequest {
+            api_key: header(HEADER_API_KEY)?,
+            passphrase: header(HEADER_PASSPHRASE)?,
+            signature: header(HEADER_SIGNATURE)?,
+            payload: rest_payload(&timestamp, method, route, signed_body.as_deref()),
+            timestamp,
+        })
+    }
+
+    /// Fields of WebSocket `authenticate` message, `token` is the API key
+    pub fn ws_auth(token: &str, passphrase: &str, timestamp: &str, signature: &str) -> Self {
+        SignedRequest {
+            api_key: token.to_owned(),
+            passphrase: passphrase.to_owned(),
+            timestamp: timestamp.to_owned(),
+            signature: signature.to_owned(),
+            payload: ws_auth_payload(timestamp),
+        }
+    }
+}
+
+/// JSON object of query arguments with sorted keys, integers are not quoted
+fn query_json(query: &[(String, String)]) -> String {
+    let object: serde_json::Map<String, serde_json::Value> = query
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .parse::<i64>()
+                .map_or_else(|_| value.as_str().into(), serde_json::Value::from);
+            (key.clone(), value)
+        })
+        .collect();
+    serde_json::Value::Object(object).to_string()
+}
+
+/// Check that the request is signed by the signer and its timestamp differs from `now` (in
+/// seconds) by no more than `recv_window` seconds.
+pub fn verify_signature(
+    signer: &dyn Signer,
+    request: &SignedRequest,
+    now: i64,
+    recv_window: i64,
+) -> Result<(), VerifyError> {
+    if request.api_key != signer.api_key() {
+        return Err(VerifyError::InvalidKey);
+    }
+    if request.passphrase != signer.passphrase() {
+        return Err(VerifyError::InvalidPassphrase);
+    }
+    let timestamp: i64 = request
+        .timestamp
+        .parse()
+        .map_err(|_| VerifyError::InvalidTimestamp)?;
+    if (now - timestamp).abs() > recv_window {
+        return Err(VerifyError::InvalidTimestamp);
+    }
+    let expected = signer.sign(&request.payload)?;
+    if constant_time_eq(expected.as_bytes(), request.signature.as_bytes()) {
+        Ok(())
+    } else {
+        Err(VerifyError::InvalidSignature)
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(unix)]
+pub use self::unix::*;
+
+#[cfg(unix)]
+mod unix {
+    use super::{SignError, Signer};
+    use async_trait::async_trait;
+    use log::*;
+    use serde::{Deserialize, Serialize};
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    /// Default limit of `UnixSocketSigner` on the whole exchange with the external signer
+    pub const SIGNER_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Request of `UnixSocketSigner`, sent as single JSON line
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+    pub struct SignRequest {
+        pub api_key: String,
+        /// Base64 encoded payload
+        pub payload: String,
+    }
+
+    /// Reply of external signer, single JSON line
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+    #[serde(rename_all = "lowercase")]
+    pub enum SignReply {
+        Signature(String),
+        Error(String),
+    }
+
+    /// Signer that delegates signing to external process listening on Unix socket. The process
+    /// gets `SignRequest` and replies with `SignReply`, one JSON object per line. Connection is
+    /// opened for each signature. `sign` blocks until the reply, `sign_async` waits on the
+    /// tokio reactor when the `tokio` dependency is enabled. Both give up after `timeout`.
+    #[derive(Debug, Clone)]
+    pub struct UnixSocketSigner {
+        pub path: PathBuf,
+        pub api_key: String,
+        pub passphrase: String,
+        pub timeout: Duration,
+    }
+
+    impl UnixSocketSigner {
+        pub fn new(path: &Path, api_key: &str, passphrase: &str) -> Self {
+            UnixSocketSigner {
+                path: path.to_owned(),
+                api_key: api_key.to_owned(),
+                passphrase: passphrase.to_owned(),
+                timeout: SIGNER_TIMEOUT,
+            }
+        }
+
+        fn request_line(&self, payload: &[u8]) -> Result<String, SignError> {
+            let request = SignRequest {
+                api_key: self.api_key.clone(),
+                payload: base64::encode(payload),
+            };
+            let mut line = serde_json::to_string(&request)?;
+            line.push('\n');
+            Ok(line)
+        }
+
+        #[cfg(feature = "tokio")]
+        async fn exchange(&self, payload: &[u8]) -> Result<String, SignError> {
+            use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+            let mut stream = tokio::net::UnixStream::connect(&self.path).await?;
+            stream
+                .write_all(self.request_line(payload)?.as_bytes())
+                .await?;
+            let mut reply = String::new();
+            tokio::io::BufReader::new(stream)
+                .read_line(&mut reply)
+                .await?;
+            parse_reply(&reply)
+        }
+    }
+
+    fn parse_reply(reply: &str) -> Result<String, SignError> {
+        match serde_json::from_str(reply)? {
+            SignReply::Signature(signature) => Ok(signature),
+            SignReply::Error(msg) => Err(SignError::Remote(msg)),
+        }
+    }
+
+    #[async_trait]
+    impl Signer for UnixSocketSigner {
+        fn api_key(&self) -> &str {
+            &self.api_key
+        }
+
+        fn passphrase(&self) -> &str {
+            &self.passphrase
+        }
+
+        fn sign(&self, payload: &[u8]) -> Result<String, SignError> {
+            let mut stream = UnixStream::connect(&self.path)?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.write_all(self.request_line(payload)?.as_bytes())?;
+            let mut reply = String::new();
+            BufReader::new(stream).read_line(&mut reply)?;
+            parse_reply(&reply)
+        }
+
+        #[cfg(feature = "tokio")]
+        async fn sign_async(&self, payload: &[u8]) -> Result<String, SignError> {
+            tokio::time::timeout(self.timeout, self.exchange(payload))
+                .await
+                .map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("no reply in {:?}", self.timeout),
+                    )
+                })?
+        }
+    }
+
+    /// Serve signing requests with the given signer. Building block for external signer process.
+    /// Requests for other API keys are refused. Blocks forever.
+    pub fn serve_unix_signer<S: Signer>(listener: UnixListener, signer: &S) -> std::io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            if let Err(e) = serve_connection(&stream, signer) {
+                warn!("Failed to serve signing request: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn serve_connection<S: Signer>(stream: &UnixStream, signer: &S) -> Result<(), SignError> {
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        let request: SignRequest = serde_json::from_str(&line)?;
+        let reply = if request.api_key != signer.api_key() {
+            SignReply::Error(format!("Unknown API key {}", request.api_key))
+        } else {
+            match base64::decode(&request.payload) {
+                Ok(payload) => match signer.sign(&payload) {
+                    Ok(signature) => SignReply::Signature(signature),
+                    Err(e) => SignReply::Error(e.to_string()),
+                },
+                Err(e) => SignReply::Error(format!("Payload is not base64: {}", e)),
+            }
+        };
+        let mut out = serde_json::to_string(&reply)?;
+        out.push('\n');
+        let mut writer = stream;
+        writer.write_all(out.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::client::env::inject_auth_at;
+
+    #[derive(serde::Serialize)]
+    struct CancelQuery {
+        order_id: u64,
+        symbol: String,
+    }
+
+    #[test]
+    fn test_hmac_signer() {
+        let auth = KolliderAuth::new("key", "c2VjcmV0", "pass").unwrap();
+        let payload = rest_payload("1640000000", "GET", "/user/account", None);
+        assert_eq!(payload, b"1640000000GET/user/account");
+        assert_eq!(
+            auth.sign(&payload).unwrap(),
+            "EnE38cKNSvmOscJ1FcCFM2P6TbDQF5cBG7PZAVx1ATc="
+        );
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let auth = KolliderAuth::new("key", "c2VjcmV0", "pass").unwrap();
+        let request = reqwest::Client::new().delete("http://localhost/orders");
+        let query = CancelQuery {
+            order_id: 42,
+            symbol: "BTCUSD.PERP".to_owned(),
+        };
+        let request = inject_auth_at(
+            &auth,
+            1640000000,
+            "DELETE",
+            "/orders",
+            Some(&query),
+            request,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        let query = vec![
+            ("symbol".to_owned(), "BTCUSD.PERP".to_owned()),
+            ("order_id".to_owned(), "42".to_owned()),
+        ];
+        let signed =
+            SignedRequest::rest("DELETE", "/orders", &query, request.headers(), "").unwrap();
+        assert!(verify_signature(&auth, &signed, 1640000010, 30).is_ok());
+        assert!(matches!(
+            verify_signature(&auth, &signed, 1640000100, 30),
+            Err(VerifyError::InvalidTimestamp)
+        ));
+
+        let tampered = SignedRequest {
+            payload: rest_payload(&signed.timestamp, "DELETE", "/orders", None),
+            ..signed
+        };
+        assert!(matches!(
+            verify_signature(&auth, &tampered, 1640000000, 30),
+            Err(VerifyError::InvalidSignature)
+        ));
+
+        let other = KolliderAuth::new("other", "c2VjcmV0", "pass").unwrap();
+        let signature = auth.sign(&ws_auth_payload("1640000000")).unwrap();
+        let ws = SignedRequest::ws_auth("key", "pass", "1640000000", &signature);
+        assert!(verify_signature(&auth, &ws, 1640000000, 30).is_ok());
+        assert!(matches!(
+            verify_signature(&other, &ws, 1640000000, 30),
+            Err(VerifyError::InvalidKey)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_signer() {
+        let auth = KolliderAuth::new("key", "c2VjcmV0", "pass").unwrap();
+        let path =
+            std::env::temp_dir().join(format!("kollider-signer-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let server_auth = auth.clone();
+        std::thread::spawn(move || serve_unix_signer(listener, &server_auth));
+
+        let payload = ws_auth_payload("1640000000");
+        let signer = UnixSocketSigner::new(&path, "key", "pass");
+        assert_eq!(signer.sign(&payload).unwrap(), auth.sign(&payload).unwrap());
+
+        let other = UnixSocketSigner::new(&path, "other", "pass");
+        match other.sign(&payload) {
+            Err(SignError::Remote(_)) => (),
+            res => panic!("Expected remote error, got {:?}", res),
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn test_unix_socket_signer_timeout() {
+        let path =
+            std::env::temp_dir().join(format!("kollider-signer-hung-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // Accepts connections, but never replies
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let signer = UnixSocketSigner {
+            timeout: std::time::Duration::from_millis(100),
+            ..UnixSocketSigner::new(&path, "key", "pass")
+        };
+        let payload = ws_auth_payload("1640000000");
+        match signer.sign_async(&payload).await {
+            Err(SignError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+            res => panic!("Expected timeout, got {:?}", res),
+        }
+        assert!(matches!(signer.sign(&payload), Err(SignError::Io(_))));
+        drop(listener);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -4,6 +4,8 @@ use crate::kollider::api::{
     FillDetails, OrderBody, OrderCreated, OrderDetails, OrderPrediction, PositionDetails, Symbol,
 };
 use chrono::prelude::*;
+#[cfg(feature = "journal")]
+use log::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -20,7 +22,14 @@ struct CancelResult {
 
 impl KolliderClient {
     pub async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated> {
-        self.post_request_auth("/orders", Some(body)).await
+        let result = self.post_request_auth("/orders", Some(body)).await;
+        #[cfg(feature = "journal")]
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.record_create_order(body, &result) {
+                warn!("Failed to journal created order: {}", e);
+            }
+        }
+        result
     }
 
     pub async fn order_prediction(&self, body: &OrderBody) -> Result<OrderPrediction> {
@@ -75,11 +84,22 @@ impl KolliderClient {
     }
 
     pub async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()> {
+        let result = self.cancel_order_request(symbol, order_id).await;
+        #[cfg(feature = "journal")]
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.record_cancel_order(symbol, order_id, &result) {
+                warn!("Failed to journal cancelled order: {}", e);
+            }
+        }
+        result
+    }
+
+    async fn cancel_order_request(&self, symbol: &str, order_id: u64) -> Result<()> {
         let inner_result: CancelResult = self
             .delete_request_auth(
                 "/orders",
                 &CancelOrder {
-                    order_id: order_id,
+                    order_id,
                     symbol: symbol.to_owned(),
                 },
             )
@@ -0,0 +1,102 @@
+use super::{Column, ColumnType, ExportError, Result, Value};
+use chrono::prelude::*;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+fn check_header(path: &Path, columns: &[Column], header: &csv::StringRecord) -> Result<()> {
+    if !header.iter().eq(columns.iter().map(|c| c.name)) {
+        let expected: Vec<&str> = columns.iter().map(|c| c.name).collect();
+        return Err(ExportError::ColumnMismatch(
+            path.to_owned(),
+            expected.join(", "),
+        ));
+    }
+    Ok(())
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(v) => v.to_string(),
+        Value::Int(v) => v.to_string(),
+        Value::Float(v) => v.to_string(),
+        Value::Text(v) => v.clone(),
+        Value::Timestamp(ms) => Utc
+            .timestamp_millis_opt(*ms)
+            .single()
+            .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true))
+            .unwrap_or_else(|| ms.to_string()),
+    }
+}
+
+fn parse_value(column: &Column, cell: &str) -> Result<Value> {
+    if cell.is_empty() {
+        return Ok(Value::Null);
+    }
+    let malformed = || ExportError::MalformedValue(column.name.to_owned(), cell.to_owned());
+    Ok(match column.kind {
+        ColumnType::Bool => Value::Bool(cell.parse().map_err(|_| malformed())?),
+        ColumnType::Int => Value::Int(cell.parse().map_err(|_| malformed())?),
+        ColumnType::Float => Value::Float(cell.parse().map_err(|_| malformed())?),
+        ColumnType::Text => Value::Text(cell.to_owned()),
+        ColumnType::Timestamp => Value::Timestamp(
+            DateTime::parse_from_rfc3339(cell)
+                .map_err(|_| malformed())?
+                .timestamp_millis(),
+        ),
+    })
+}
+
+/// Read all rows of the file
+pub fn read(path: &Path, columns: &[Column]) -> Result<Vec<Vec<Value>>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    check_header(path, columns, reader.headers()?)?;
+    reader
+        .records()
+        .map(|record| {
+            let record = record?;
+            columns
+                .iter()
+                .zip(record.iter())
+                .map(|(column, cell)| parse_value(column, cell))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn last_timestamp(path: &Path, columns: &[Column]) -> Result<Option<i64>> {
+    let rows = read(path, columns)?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| match row.first() {
+            Some(Value::Timestamp(ms)) => Some(*ms),
+            _ => None,
+        })
+        .max())
+}
+
+/// Append rows to the file, header is written when the file is created
+pub fn append(path: &Path, columns: &[Column], rows: &[Vec<Value>]) -> Result<()> {
+    let exists = path.exists() && std::fs::metadata(path)?.len() > 0;
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = csv::Writer::from_writer(file);
+    if !exists {
+        writer.write_record(columns.iter().map(|c| c.name))?;
+    }
+    for row in rows {
+        writer.write_record(row.iter().map(format_value))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Replace content of the file with the rows
+pub fn rewrite(path: &Path, columns: &[Column], rows: &[Vec<Value>]) -> Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = Path::new(&tmp_name);
+    let _ = std::fs::remove_file(tmp_path);
+    append(tmp_path, columns, rows)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
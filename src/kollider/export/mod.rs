@@ -0,0 +1,526 @@
+//! Export of index price history, orders, fills and trades to CSV and Parquet files. Columns are
+//! typed, prices are converted to decimals with `price_dp` of the product. When the file already
+//! exists, export is resumed from the time of the last exported record and records that are
+//! already in the file are skipped by `ExportRecord::key_columns`.
+mod csv_format;
+mod parquet_format;
+pub mod records;
+
+pub use records::*;
+
+use crate::kollider::api::{Product, Symbol, Timeframe};
+use crate::kollider::client::{error::Error as ClientError, KolliderClient};
+use chrono::prelude::*;
+use futures::TryStreamExt;
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Name of the column that every record starts with and export is resumed from
+pub const TIMESTAMP_COLUMN: &str = "timestamp";
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Failed to access export file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("Request failed: {0}")]
+    Client(#[from] ClientError),
+    #[error("Columns of existing file {0} don't match the export, expected: {1}")]
+    ColumnMismatch(PathBuf, String),
+    #[error("Malformed value '{1}' in column {0}")]
+    MalformedValue(String, String),
+    #[error("Unknown product {0}")]
+    UnknownProduct(Symbol),
+}
+
+/// Alias for a `Result` with the error type `ExportError`.
+pub type Result<T> = std::result::Result<T, ExportError>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownExportFormat(String);
+
+impl std::error::Error for UnknownExportFormat {}
+
+impl fmt::Display for UnknownExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Given export format '{}' is unknown, valid are: csv, parquet",
+            self.0
+        )
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = UnknownExportFormat;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(UnknownExportFormat(s.to_owned())),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// Guess format from extension of the file
+    pub fn from_path(path: &Path) -> std::result::Result<Self, UnknownExportFormat> {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        ext.parse()
+    }
+}
+
+/// Type of column in exported files
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ColumnType {
+    Bool,
+    Int,
+    Float,
+    Text,
+    /// Milliseconds since Unix epoch, RFC 3339 in CSV
+    Timestamp,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnType,
+}
+
+/// Value of exported cell
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Timestamp(i64),
+}
+
+impl From<Option<f64>> for Value {
+    fn from(v: Option<f64>) -> Self {
+        v.map_or(Value::Null, Value::Float)
+    }
+}
+
+/// Row of exported file
+pub trait ExportRecord {
+    /// Columns of the file, the first one is `TIMESTAMP_COLUMN`
+    fn columns() -> &'static [Column];
+
+    /// Values in the order of columns
+    fn values(&self) -> Vec<Value>;
+
+    /// Time of the record in milliseconds
+    fn timestamp(&self) -> i64;
+
+    /// Columns that identify the record among records with the same timestamp
+    fn key_columns() -> &'static [&'static str];
+}
+
+/// Identity of the row, the timestamp and values of `ExportRecord::key_columns`
+fn row_key<R: ExportRecord>(row: &[Value]) -> String {
+    let columns = R::columns();
+    let values: Vec<&Value> = columns
+        .iter()
+        .zip(row)
+        .filter(|(column, _)| {
+            column.name == TIMESTAMP_COLUMN || R::key_columns().contains(&column.name)
+        })
+        .map(|(_, value)| value)
+        .collect();
+    format!("{:?}", values)
+}
+
+/// Values of `ExportRecord::key_columns`, rows with the same ones are a series
+fn series_key<R: ExportRecord>(row: &[Value]) -> String {
+    let values: Vec<&Value> = R::columns()
+        .iter()
+        .zip(row)
+        .filter(|(column, _)| R::key_columns().contains(&column.name))
+        .map(|(_, value)| value)
+        .collect();
+    format!("{:?}", values)
+}
+
+fn row_timestamp(row: &[Value]) -> Option<i64> {
+    match row.first() {
+        Some(Value::Timestamp(ms)) => Some(*ms),
+        _ => None,
+    }
+}
+
+/// File that records are exported to
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+}
+
+impl Exporter {
+    pub fn new(path: &Path, format: ExportFormat) -> Self {
+        Exporter {
+            path: path.to_owned(),
+            format,
+        }
+    }
+
+    /// Exporter with format guessed from extension of the file
+    pub fn from_path(path: &Path) -> std::result::Result<Self, UnknownExportFormat> {
+        Ok(Exporter::new(path, ExportFormat::from_path(path)?))
+    }
+
+    /// Time of the last exported record, `None` if the file doesn't exist or is empty
+    pub fn last_timestamp<R: ExportRecord>(&self) -> Result<Option<DateTime<Utc>>> {
+        if !self.path.exists() || std::fs::metadata(&self.path)?.len() == 0 {
+            return Ok(None);
+        }
+        let last = match self.format {
+            ExportFormat::Csv => csv_format::last_timestamp(&self.path, R::columns())?,
+            ExportFormat::Parquet => parquet_format::last_timestamp(&self.path, R::columns())?,
+        };
+        Ok(last.and_then(|ms| Utc.timestamp_millis_opt(ms).single()))
+    }
+
+    /// Start of the range to fetch: the time of the last exported record if there is one. The
+    /// range includes the last record, so records with the same timestamp are not lost.
+    pub fn resume_from<R: ExportRecord>(&self, start: DateTime<Local>) -> Result<DateTime<Local>> {
+        Ok(match self.last_timestamp::<R>()? {
+            Some(last) if last > start => {
+                info!("Resuming export to {} from {}", self.path.display(), last);
+                last.with_timezone(&Local)
+            }
+            _ => start,
+        })
+    }
+
+    /// Rows of the file, empty if the file doesn't exist
+    fn read<R: ExportRecord>(&self) -> Result<Vec<Vec<Value>>> {
+        if !self.path.exists() || std::fs::metadata(&self.path)?.len() == 0 {
+            return Ok(vec![]);
+        }
+        match self.format {
+            ExportFormat::Csv => csv_format::read(&self.path, R::columns()),
+            ExportFormat::Parquet => parquet_format::read(&self.path, R::columns()),
+        }
+    }
+
+    /// Append records that are not in the file yet: newer than the last exported one or
+    /// with the same timestamp, but other key. Returns number of written records.
+    pub fn append<R: ExportRecord>(&self, records: &[R]) -> Result<usize> {
+        let existing = self.read::<R>()?;
+        let last = existing.iter().filter_map(|row| row_timestamp(row)).max();
+        let mut known: HashSet<String> = existing
+            .iter()
+            .filter(|row| row_timestamp(row) == last)
+            .map(|row| row_key::<R>(row))
+            .collect();
+        let mut rows: Vec<(i64, Vec<Value>)> = records
+            .iter()
+            .filter(|record| last.is_none_or(|last| record.timestamp() >= last))
+            .map(|record| (record.timestamp(), record.values()))
+            .filter(|(_, row)| known.insert(row_key::<R>(row)))
+            .collect();
+        rows.sort_by_key(|(timestamp, _)| *timestamp);
+        let rows: Vec<Vec<Value>> = rows.into_iter().map(|(_, row)| row).collect();
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        match self.format {
+            ExportFormat::Csv => csv_format::append(&self.path, R::columns(), &rows)?,
+            ExportFormat::Parquet => parquet_format::append(&self.path, R::columns(), &rows)?,
+        }
+        Ok(rows.len())
+    }
+
+    /// Time of the last exported record of the series of `record`, that is with the same values
+    /// of `ExportRecord::key_columns`
+    pub fn last_series_timestamp<R: ExportRecord>(
+        &self,
+        record: &R,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let series = series_key::<R>(&record.values());
+        let last = self
+            .read::<R>()?
+            .iter()
+            .filter(|row| series_key::<R>(row) == series)
+            .filter_map(|row| row_timestamp(row))
+            .max();
+        Ok(last.and_then(|ms| Utc.timestamp_millis_opt(ms).single()))
+    }
+
+    /// Replace the last exported interval of every series in `records` and append the newer
+    /// ones. Rows of other series are kept. Returns number of written records minus replaced
+    /// ones.
+    pub fn replace_last<R: ExportRecord>(&self, records: &[R]) -> Result<usize> {
+        let existing = self.read::<R>()?;
+        let mut last: HashMap<String, i64> = HashMap::new();
+        for row in &existing {
+            if let Some(timestamp) = row_timestamp(row) {
+                let entry = last.entry(series_key::<R>(row)).or_insert(timestamp);
+                *entry = (*entry).max(timestamp);
+            }
+        }
+        let series: HashSet<String> = records
+            .iter()
+            .map(|record| series_key::<R>(&record.values()))
+            .collect();
+        let replaced = |row: &Vec<Value>| {
+            let key = series_key::<R>(row);
+            series.contains(&key) && row_timestamp(row) == last.get(&key).copied()
+        };
+        let (dropped, mut rows): (Vec<_>, Vec<_>) = existing.into_iter().partition(replaced);
+        let mut known: HashSet<String> = rows.iter().map(|row| row_key::<R>(row)).collect();
+        let mut new_rows: Vec<(i64, Vec<Value>)> = records
+            .iter()
+            .map(|record| (record.timestamp(), record.values()))
+            .filter(|(timestamp, row)| {
+                last.get(&series_key::<R>(row))
+                    .is_none_or(|last| timestamp >= last)
+            })
+            .filter(|(_, row)| known.insert(row_key::<R>(row)))
+            .collect();
+        new_rows.sort_by_key(|(timestamp, _)| *timestamp);
+        let written = new_rows.len();
+        if dropped.is_empty() {
+            let new_rows: Vec<Vec<Value>> = new_rows.into_iter().map(|(_, row)| row).collect();
+            if !new_rows.is_empty() {
+                match self.format {
+                    ExportFormat::Csv => csv_format::append(&self.path, R::columns(), &new_rows)?,
+                    ExportFormat::Parquet => {
+                        parquet_format::append(&self.path, R::columns(), &new_rows)?
+                    }
+                }
+            }
+        } else {
+            rows.extend(new_rows.into_iter().map(|(_, row)| row));
+            rows.sort_by_key(|row| row_timestamp(row));
+            match self.format {
+                ExportFormat::Csv => csv_format::rewrite(&self.path, R::columns(), &rows)?,
+                ExportFormat::Parquet => parquet_format::rewrite(&self.path, R::columns(), &rows)?,
+            }
+        }
+        Ok(written.saturating_sub(dropped.len()))
+    }
+}
+
+async fn product(client: &KolliderClient, symbol: &str) -> Result<Product> {
+    client
+        .market_products()
+        .await?
+        .remove(symbol)
+        .ok_or_else(|| ExportError::UnknownProduct(symbol.to_owned()))
+}
+
+/// Export index price history of any timeframe, returns number of written records. The last
+/// exported interval is replaced, as it could be exported before it was over.
+pub async fn export_history(
+    client: &KolliderClient,
+    exporter: &Exporter,
+    symbol: &str,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    timeframe: Timeframe,
+) -> Result<usize> {
+    let series = HistoryRecord {
+        timestamp: 0,
+        symbol: symbol.to_owned(),
+        interval: timeframe,
+        min: None,
+        max: None,
+        mean: None,
+    };
+    let start = match exporter.last_series_timestamp(&series)? {
+        Some(last) if last > start => {
+            info!(
+                "Resuming export to {} from {}",
+                exporter.path.display(),
+                last
+            );
+            last.with_timezone(&Local)
+        }
+        _ => start,
+    };
+    let resp = client
+        .historic_index_prices_resampled(symbol, start, end, timeframe)
+        .await?;
+    let records: Vec<HistoryRecord> = resp
+        .data
+        .iter()
+        .map(|item| HistoryRecord::new(&resp.symbol, timeframe, item))
+        .collect();
+    exporter.replace_last(&records)
+}
+
+/// Export orders of the account, returns number of written records
+pub async fn export_orders(
+    client: &KolliderClient,
+    exporter: &Exporter,
+    symbol: &str,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    page_size: usize,
+) -> Result<usize> {
+    let product = product(client, symbol).await?;
+    let start = exporter.resume_from::<OrderRecord>(start)?;
+    let orders: Vec<_> = client
+        .orders_stream(symbol, start, end, page_size)
+        .try_collect()
+        .await?;
+    let records: Vec<OrderRecord> = orders
+        .iter()
+        .map(|order| OrderRecord::new(&product, order))
+        .collect();
+    exporter.append(&records)
+}
+
+/// Export fills of the account, returns number of written records
+pub async fn export_fills(
+    client: &KolliderClient,
+    exporter: &Exporter,
+    symbol: &str,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    page_size: usize,
+) -> Result<usize> {
+    let product = product(client, symbol).await?;
+    let start = exporter.resume_from::<FillRecord>(start)?;
+    let fills: Vec<_> = client
+        .fills_stream(symbol, start, end, page_size)
+        .try_collect()
+        .await?;
+    let records: Vec<FillRecord> = fills
+        .iter()
+        .map(|fill| FillRecord::new(&product, fill))
+        .collect();
+    exporter.append(&records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::HistoryItem;
+
+    fn history(time: u64, mean: Option<f64>) -> HistoryRecord {
+        HistoryRecord::new(
+            "BTCUSD.PERP",
+            "1h".parse().unwrap(),
+            &HistoryItem {
+                max: mean,
+                min: mean,
+                mean,
+                time,
+            },
+        )
+    }
+
+    #[test]
+    fn test_export_resume() {
+        for format in [ExportFormat::Csv, ExportFormat::Parquet] {
+            let ext = if format == ExportFormat::Csv {
+                "csv"
+            } else {
+                "parquet"
+            };
+            let path = std::env::temp_dir().join(format!(
+                "kollider-export-{}.{}",
+                std::process::id(),
+                ext
+            ));
+            let _ = std::fs::remove_file(&path);
+            let exporter = Exporter::from_path(&path).unwrap();
+            assert_eq!(exporter.format, format);
+            assert_eq!(exporter.last_timestamp::<HistoryRecord>().unwrap(), None);
+
+            let first = vec![history(1640000000, Some(1.5)), history(1640003600, None)];
+            assert_eq!(exporter.append(&first).unwrap(), 2);
+            assert_eq!(
+                exporter.last_timestamp::<HistoryRecord>().unwrap(),
+                Utc.timestamp_opt(1640003600, 0).single()
+            );
+
+            // The overlapping record is skipped, other one with the same timestamp is not
+            let second = vec![
+                history(1640003600, None),
+                history(1640007200, Some(2.0)),
+                history(1640007200, Some(2.0)),
+            ];
+            assert_eq!(exporter.append(&second).unwrap(), 1);
+            let other = HistoryRecord {
+                symbol: ".ETHUSD".to_owned(),
+                ..history(1640007200, Some(0.07))
+            };
+            assert_eq!(exporter.append(std::slice::from_ref(&other)).unwrap(), 1);
+            assert_eq!(
+                exporter.last_timestamp::<HistoryRecord>().unwrap(),
+                Utc.timestamp_opt(1640007200, 0).single()
+            );
+            let rows = match format {
+                ExportFormat::Csv => csv_format::read(&path, HistoryRecord::columns()).unwrap(),
+                ExportFormat::Parquet => {
+                    parquet_format::read(&path, HistoryRecord::columns()).unwrap()
+                }
+            };
+            let expected: Vec<Vec<Value>> = vec![
+                first[0].values(),
+                first[1].values(),
+                second[1].values(),
+                other.values(),
+            ];
+            assert_eq!(rows, expected, "{:?}", format);
+
+            // The last interval of the series is replaced, rows of other series are kept
+            let updated = history(1640007200, Some(2.5));
+            assert_eq!(
+                exporter.last_series_timestamp(&updated).unwrap(),
+                Utc.timestamp_opt(1640007200, 0).single()
+            );
+            let later = history(1640010800, Some(3.0));
+            assert_eq!(
+                exporter
+                    .replace_last(&[updated.clone(), later.clone()])
+                    .unwrap(),
+                1
+            );
+            let rows = match format {
+                ExportFormat::Csv => csv_format::read(&path, HistoryRecord::columns()).unwrap(),
+                ExportFormat::Parquet => {
+                    parquet_format::read(&path, HistoryRecord::columns()).unwrap()
+                }
+            };
+            let expected: Vec<Vec<Value>> = vec![
+                first[0].values(),
+                first[1].values(),
+                other.values(),
+                updated.values(),
+                later.values(),
+            ];
+            assert_eq!(rows, expected, "{:?}", format);
+
+            // Records of other type don't fit into the file
+            match exporter.last_timestamp::<OrderRecord>() {
+                Err(ExportError::ColumnMismatch(..)) => (),
+                res => panic!("Expected column mismatch, got {:?}", res),
+            }
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
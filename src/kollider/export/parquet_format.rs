@@ -0,0 +1,176 @@
+use super::{Column, ColumnType, ExportError, Result, Value};
+use parquet::basic::Compression;
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::Field;
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+fn message_type(columns: &[Column]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            let kind = match column.kind {
+                ColumnType::Bool => "BOOLEAN",
+                ColumnType::Int => "INT64",
+                ColumnType::Float => "DOUBLE",
+                ColumnType::Text => "BYTE_ARRAY",
+                ColumnType::Timestamp => "INT64",
+            };
+            let logical = match column.kind {
+                ColumnType::Text => " (UTF8)",
+                ColumnType::Timestamp => " (TIMESTAMP(MILLIS,true))",
+                _ => "",
+            };
+            format!("OPTIONAL {} {}{};", kind, column.name, logical)
+        })
+        .collect();
+    format!("message kollider {{ {} }}", fields.join(" "))
+}
+
+fn field_value(column: &Column, field: &Field) -> Result<Value> {
+    Ok(match (column.kind, field) {
+        (_, Field::Null) => Value::Null,
+        (ColumnType::Bool, Field::Bool(v)) => Value::Bool(*v),
+        (ColumnType::Int, Field::Long(v)) => Value::Int(*v),
+        (ColumnType::Float, Field::Double(v)) => Value::Float(*v),
+        (ColumnType::Text, Field::Str(v)) => Value::Text(v.clone()),
+        (ColumnType::Timestamp, Field::TimestampMillis(v)) => Value::Timestamp(*v),
+        (_, field) => {
+            return Err(ExportError::MalformedValue(
+                column.name.to_owned(),
+                field.to_string(),
+            ))
+        }
+    })
+}
+
+/// Read all rows of the file
+pub fn read(path: &Path, columns: &[Column]) -> Result<Vec<Vec<Value>>> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let schema = reader.metadata().file_metadata().schema_descr();
+    if !schema
+        .columns()
+        .iter()
+        .map(|c| c.name())
+        .eq(columns.iter().map(|c| c.name))
+    {
+        let expected: Vec<&str> = columns.iter().map(|c| c.name).collect();
+        return Err(ExportError::ColumnMismatch(
+            path.to_owned(),
+            expected.join(", "),
+        ));
+    }
+    let mut rows = vec![];
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let values = columns
+            .iter()
+            .zip(row.get_column_iter())
+            .map(|(column, (_, field))| field_value(column, field))
+            .collect::<Result<Vec<Value>>>()?;
+        rows.push(values);
+    }
+    Ok(rows)
+}
+
+pub fn last_timestamp(path: &Path, columns: &[Column]) -> Result<Option<i64>> {
+    let rows = read(path, columns)?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| match row.first() {
+            Some(Value::Timestamp(ms)) => Some(*ms),
+            _ => None,
+        })
+        .max())
+}
+
+/// Parquet files cannot be appended in place, so existing rows are read and written together
+/// with new ones to a temporary file that replaces the original.
+pub fn append(path: &Path, columns: &[Column], rows: &[Vec<Value>]) -> Result<()> {
+    let mut all = if path.exists() {
+        read(path, columns)?
+    } else {
+        vec![]
+    };
+    all.extend_from_slice(rows);
+    rewrite(path, columns, &all)
+}
+
+/// Replace content of the file with the rows
+pub fn rewrite(path: &Path, columns: &[Column], rows: &[Vec<Value>]) -> Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = Path::new(&tmp_name);
+    write(tmp_path, columns, rows)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+fn write(path: &Path, columns: &[Column], rows: &[Vec<Value>]) -> Result<()> {
+    let schema = Arc::new(parse_message_type(&message_type(columns))?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        let cells = rows.iter().map(|row| &row[index]);
+        let levels: Vec<i16> = cells
+            .clone()
+            .map(|v| if *v == Value::Null { 0 } else { 1 })
+            .collect();
+        match column.untyped() {
+            ColumnWriter::BoolColumnWriter(w) => {
+                let values: Vec<bool> = cells
+                    .filter_map(|v| match v {
+                        Value::Bool(v) => Some(*v),
+                        _ => None,
+                    })
+                    .collect();
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnWriter::Int64ColumnWriter(w) => {
+                let values: Vec<i64> = cells
+                    .filter_map(|v| match v {
+                        Value::Int(v) | Value::Timestamp(v) => Some(*v),
+                        _ => None,
+                    })
+                    .collect();
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnWriter::DoubleColumnWriter(w) => {
+                let values: Vec<f64> = cells
+                    .filter_map(|v| match v {
+                        Value::Float(v) => Some(*v),
+                        _ => None,
+                    })
+                    .collect();
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnWriter::ByteArrayColumnWriter(w) => {
+                let values: Vec<ByteArray> = cells
+                    .filter_map(|v| match v {
+                        Value::Text(v) => Some(ByteArray::from(v.as_str())),
+                        _ => None,
+                    })
+                    .collect();
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            _ => unreachable!("Column types are limited by message_type"),
+        }
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
@@ -0,0 +1,272 @@
+use super::{Column, ColumnType, ExportRecord, Value, TIMESTAMP_COLUMN};
+use crate::kollider::api::{FillDetails, HistoryItem, OrderDetails, Product, Symbol, Timeframe};
+
+const fn column(name: &'static str, kind: ColumnType) -> Column {
+    Column { name, kind }
+}
+
+/// Interval of index price history
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoryRecord {
+    /// Start of the interval in milliseconds
+    pub timestamp: i64,
+    pub symbol: Symbol,
+    pub interval: Timeframe,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+}
+
+impl HistoryRecord {
+    pub fn new(symbol: &str, interval: Timeframe, item: &HistoryItem) -> Self {
+        HistoryRecord {
+            timestamp: item.time as i64 * 1000,
+            symbol: symbol.to_owned(),
+            interval,
+            min: item.min,
+            max: item.max,
+            mean: item.mean,
+        }
+    }
+}
+
+const HISTORY_COLUMNS: &[Column] = &[
+    column(TIMESTAMP_COLUMN, ColumnType::Timestamp),
+    column("symbol", ColumnType::Text),
+    column("interval", ColumnType::Text),
+    column("min", ColumnType::Float),
+    column("max", ColumnType::Float),
+    column("mean", ColumnType::Float),
+];
+
+impl ExportRecord for HistoryRecord {
+    fn columns() -> &'static [Column] {
+        HISTORY_COLUMNS
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            Value::Timestamp(self.timestamp),
+            Value::Text(self.symbol.clone()),
+            Value::Text(self.interval.to_string()),
+            self.min.into(),
+            self.max.into(),
+            self.mean.into(),
+        ]
+    }
+
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    fn key_columns() -> &'static [&'static str] {
+        &["symbol", "interval"]
+    }
+}
+
+/// Order of the account with decimal price
+#[derive(Debug, PartialEq, Clone)]
+pub struct OrderRecord {
+    pub timestamp: i64,
+    pub order_id: u64,
+    pub ext_order_id: String,
+    pub symbol: Symbol,
+    pub side: String,
+    pub order_type: String,
+    pub margin_type: String,
+    pub settlement_type: String,
+    pub leverage: u64,
+    pub price: f64,
+    pub quantity: u64,
+    pub filled: f64,
+}
+
+impl OrderRecord {
+    pub fn new(product: &Product, order: &OrderDetails) -> Self {
+        OrderRecord {
+            timestamp: order.timestamp as i64,
+            order_id: order.order_id,
+            ext_order_id: order.ext_order_id.clone(),
+            symbol: order.symbol.clone(),
+            side: format!("{:?}", order.side),
+            order_type: format!("{:?}", order.order_type),
+            margin_type: format!("{:?}", order.margin_type),
+            settlement_type: format!("{:?}", order.settlement_type),
+            leverage: order.leverage,
+            price: product.human_price(order.price),
+            quantity: order.quantity,
+            filled: order.filled,
+        }
+    }
+}
+
+const ORDER_COLUMNS: &[Column] = &[
+    column(TIMESTAMP_COLUMN, ColumnType::Timestamp),
+    column("order_id", ColumnType::Int),
+    column("ext_order_id", ColumnType::Text),
+    column("symbol", ColumnType::Text),
+    column("side", ColumnType::Text),
+    column("order_type", ColumnType::Text),
+    column("margin_type", ColumnType::Text),
+    column("settlement_type", ColumnType::Text),
+    column("leverage", ColumnType::Int),
+    column("price", ColumnType::Float),
+    column("quantity", ColumnType::Int),
+    column("filled", ColumnType::Float),
+];
+
+impl ExportRecord for OrderRecord {
+    fn columns() -> &'static [Column] {
+        ORDER_COLUMNS
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            Value::Timestamp(self.timestamp),
+            Value::Int(self.order_id as i64),
+            Value::Text(self.ext_order_id.clone()),
+            Value::Text(self.symbol.clone()),
+            Value::Text(self.side.clone()),
+            Value::Text(self.order_type.clone()),
+            Value::Text(self.margin_type.clone()),
+            Value::Text(self.settlement_type.clone()),
+            Value::Int(self.leverage as i64),
+            Value::Float(self.price),
+            Value::Int(self.quantity as i64),
+            Value::Float(self.filled),
+        ]
+    }
+
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    fn key_columns() -> &'static [&'static str] {
+        &["order_id"]
+    }
+}
+
+/// Fill of the account order with decimal price
+#[derive(Debug, PartialEq, Clone)]
+pub struct FillRecord {
+    pub order: OrderRecord,
+    pub remaining: u64,
+    pub partial: bool,
+    pub is_maker: bool,
+    pub is_liquidation: bool,
+    pub is_selftrade: bool,
+}
+
+impl FillRecord {
+    pub fn new(product: &Product, fill: &FillDetails) -> Self {
+        FillRecord {
+            order: OrderRecord::new(product, &fill.order),
+            remaining: fill.remaining,
+            partial: fill.partial,
+            is_maker: fill.is_maker,
+            is_liquidation: fill.is_liquidation,
+            is_selftrade: fill.is_selftrade,
+        }
+    }
+}
+
+const FILL_COLUMNS: &[Column] = &[
+    column(TIMESTAMP_COLUMN, ColumnType::Timestamp),
+    column("order_id", ColumnType::Int),
+    column("ext_order_id", ColumnType::Text),
+    column("symbol", ColumnType::Text),
+    column("side", ColumnType::Text),
+    column("order_type", ColumnType::Text),
+    column("margin_type", ColumnType::Text),
+    column("settlement_type", ColumnType::Text),
+    column("leverage", ColumnType::Int),
+    column("price", ColumnType::Float),
+    column("quantity", ColumnType::Int),
+    column("filled", ColumnType::Float),
+    column("remaining", ColumnType::Int),
+    column("partial", ColumnType::Bool),
+    column("is_maker", ColumnType::Bool),
+    column("is_liquidation", ColumnType::Bool),
+    column("is_selftrade", ColumnType::Bool),
+];
+
+impl ExportRecord for FillRecord {
+    fn columns() -> &'static [Column] {
+        FILL_COLUMNS
+    }
+
+    fn values(&self) -> Vec<Value> {
+        let mut values = self.order.values();
+        values.extend([
+            Value::Int(self.remaining as i64),
+            Value::Bool(self.partial),
+            Value::Bool(self.is_maker),
+            Value::Bool(self.is_liquidation),
+            Value::Bool(self.is_selftrade),
+        ]);
+        values
+    }
+
+    fn timestamp(&self) -> i64 {
+        self.order.timestamp
+    }
+
+    fn key_columns() -> &'static [&'static str] {
+        &["order_id", "remaining"]
+    }
+}
+
+/// Public trade from the `matches` channel
+#[derive(Debug, PartialEq, Clone)]
+pub struct TradeRecord {
+    pub timestamp: i64,
+    pub symbol: Symbol,
+    pub side: String,
+    pub price: f64,
+    pub quantity: u64,
+}
+
+#[cfg(feature = "ws")]
+impl From<&crate::kollider::websocket::TradeMatch> for TradeRecord {
+    fn from(trade: &crate::kollider::websocket::TradeMatch) -> Self {
+        TradeRecord {
+            timestamp: trade.timestamp as i64,
+            symbol: trade.symbol.clone(),
+            side: format!("{:?}", trade.side),
+            price: trade.price,
+            quantity: trade.quantity,
+        }
+    }
+}
+
+const TRADE_COLUMNS: &[Column] = &[
+    column(TIMESTAMP_COLUMN, ColumnType::Timestamp),
+    column("symbol", ColumnType::Text),
+    column("side", ColumnType::Text),
+    column("price", ColumnType::Float),
+    column("quantity", ColumnType::Int),
+];
+
+impl ExportRecord for TradeRecord {
+    fn columns() -> &'static [Column] {
+        TRADE_COLUMNS
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            Value::Timestamp(self.timestamp),
+            Value::Text(self.symbol.clone()),
+            Value::Text(self.side.clone()),
+            Value::Float(self.price),
+            Value::Int(self.quantity as i64),
+        ]
+    }
+
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    fn key_columns() -> &'static [&'static str] {
+        &["symbol", "side", "price", "quantity"]
+    }
+}
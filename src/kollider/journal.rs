@@ -0,0 +1,572 @@
+//! Durable journal of trading activity in SQLite. Outgoing `Order` and `CancelOrder` WebSocket
+//! messages, incoming order lifecycle and balance messages and results of REST order requests
+//! are stored as rows of the `events` table. Besides the indexed columns every row keeps the
+//! whole message as JSON, so the database can be queried with any SQLite tool:
+//!
+//! ```sql
+//! SELECT recorded_at, kind, price, quantity FROM events WHERE order_id = 9317213;
+//! ```
+use crate::kollider::api::{OrderBody, OrderCreated, Symbol};
+use chrono::prelude::*;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[cfg(feature = "ws")]
+use crate::kollider::websocket::{KolliderMsg, KolliderTaggedMsg};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recorded_at INTEGER NOT NULL,
+    source TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    symbol TEXT,
+    order_id INTEGER,
+    ext_order_id TEXT,
+    side TEXT,
+    price REAL,
+    quantity INTEGER,
+    error TEXT,
+    payload TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_order_id ON events(order_id);
+CREATE INDEX IF NOT EXISTS events_symbol_time ON events(symbol, recorded_at);
+CREATE INDEX IF NOT EXISTS events_kind_time ON events(kind, recorded_at);
+";
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("Journal database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Failed to encode journal payload: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Alias for a `Result` with the error type `JournalError`.
+pub type Result<T> = std::result::Result<T, JournalError>;
+
+/// Where the journaled message came from
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSource {
+    /// Message sent to WebSocket
+    WsOut,
+    /// Message received from WebSocket
+    WsIn,
+    /// Request to REST API with its result
+    Rest,
+}
+
+impl EventSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventSource::WsOut => "ws_out",
+            EventSource::WsIn => "ws_in",
+            EventSource::Rest => "rest",
+        }
+    }
+}
+
+impl fmt::Display for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownEventSource(String);
+
+impl std::error::Error for UnknownEventSource {}
+
+impl fmt::Display for UnknownEventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Given event source '{}' is unknown, valid are: ws_out, ws_in, rest",
+            self.0
+        )
+    }
+}
+
+impl FromStr for EventSource {
+    type Err = UnknownEventSource;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ws_out" => Ok(EventSource::WsOut),
+            "ws_in" => Ok(EventSource::WsIn),
+            "rest" => Ok(EventSource::Rest),
+            _ => Err(UnknownEventSource(s.to_owned())),
+        }
+    }
+}
+
+/// Row of the journal
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct JournalEvent {
+    pub id: i64,
+    /// Local time when the event was recorded
+    pub recorded_at: DateTime<Utc>,
+    pub source: EventSource,
+    /// Type tag of WebSocket message, `order` or `cancel_order` for outgoing messages and
+    /// `create_order` or `cancel_order` for REST requests
+    pub kind: String,
+    pub symbol: Option<Symbol>,
+    pub order_id: Option<u64>,
+    pub ext_order_id: Option<String>,
+    pub side: Option<String>,
+    /// Price as in the message, integer prices of orders are not converted
+    pub price: Option<f64>,
+    pub quantity: Option<u64>,
+    /// Reason of rejection or failed request
+    pub error: Option<String>,
+    pub payload: serde_json::Value,
+}
+
+/// Indexed columns of the new event
+#[derive(Debug, Default)]
+struct EventFields {
+    symbol: Option<Symbol>,
+    order_id: Option<u64>,
+    ext_order_id: Option<String>,
+    side: Option<String>,
+    price: Option<f64>,
+    quantity: Option<u64>,
+    error: Option<String>,
+}
+
+/// Filter of `Journal::query`. Empty filter matches all events.
+#[derive(Debug, Default, Clone)]
+pub struct JournalQuery {
+    pub source: Option<EventSource>,
+    pub kind: Option<String>,
+    pub symbol: Option<Symbol>,
+    pub order_id: Option<u64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Return only the latest events
+    pub limit: Option<usize>,
+}
+
+/// Handle to the journal database. Clones share the same connection.
+#[derive(Clone)]
+pub struct Journal {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let conn = self.conn.lock().unwrap();
+        f.debug_struct("Journal")
+            .field("path", &conn.path())
+            .finish()
+    }
+}
+
+/// Handles are equal when they share the same connection
+impl PartialEq for Journal {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.conn, &other.conn)
+    }
+}
+
+impl Journal {
+    /// Open or create the database file
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        // Let `journal query` read while a bot is writing
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        Journal::init(conn)
+    }
+
+    /// Journal that lives in memory, for tests and dry runs
+    pub fn open_in_memory() -> Result<Self> {
+        Journal::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Journal {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn insert<T: Serialize + ?Sized>(
+        &self,
+        source: EventSource,
+        kind: &str,
+        fields: EventFields,
+        payload: &T,
+    ) -> Result<()> {
+        let payload = serde_json::to_string(payload)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO events (recorded_at, source, kind, symbol, order_id, ext_order_id, side, price, quantity, error, payload)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                Utc::now().timestamp_millis(),
+                source.as_str(),
+                kind,
+                fields.symbol,
+                fields.order_id.map(|v| v as i64),
+                fields.ext_order_id,
+                fields.side,
+                fields.price,
+                fields.quantity.map(|v| v as i64),
+                fields.error,
+                payload,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Record result of REST `create_order`
+    pub fn record_create_order<E: fmt::Display>(
+        &self,
+        body: &OrderBody,
+        result: &std::result::Result<OrderCreated, E>,
+    ) -> Result<()> {
+        let fields = EventFields {
+            symbol: Some(body.symbol.clone()),
+            order_id: result.as_ref().ok().map(|created| created.order_id),
+            ext_order_id: result.as_ref().ok().map(|c| c.ext_order_id.clone()),
+            side: Some(format!("{:?}", body.side)),
+            price: Some(body.price as f64),
+            quantity: Some(body.quantity),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        let payload = serde_json::json!({
+            "request": body,
+            "response": result.as_ref().ok(),
+        });
+        self.insert(EventSource::Rest, "create_order", fields, &payload)
+    }
+
+    /// Record result of REST `cancel_order`
+    pub fn record_cancel_order<E: fmt::Display>(
+        &self,
+        symbol: &str,
+        order_id: u64,
+        result: &std::result::Result<(), E>,
+    ) -> Result<()> {
+        let fields = EventFields {
+            symbol: Some(symbol.to_owned()),
+            order_id: Some(order_id),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            ..EventFields::default()
+        };
+        let payload = serde_json::json!({
+            "symbol": symbol,
+            "order_id": order_id,
+        });
+        self.insert(EventSource::Rest, "cancel_order", fields, &payload)
+    }
+
+    /// Record outgoing `Order` and `CancelOrder` messages, other messages are skipped
+    #[cfg(feature = "ws")]
+    pub fn record_outgoing(&self, msg: &KolliderMsg) -> Result<()> {
+        let (kind, fields) = match msg {
+            KolliderMsg::Order {
+                price,
+                quantity,
+                symbol,
+                side,
+                ext_order_id,
+                ..
+            } => (
+                "order",
+                EventFields {
+                    symbol: Some(symbol.clone()),
+                    ext_order_id: Some(ext_order_id.clone()),
+                    side: Some(format!("{:?}", side)),
+                    price: Some(*price as f64),
+                    quantity: Some(*quantity),
+                    ..EventFields::default()
+                },
+            ),
+            KolliderMsg::CancelOrder {
+                order_id, symbol, ..
+            } => (
+                "cancel_order",
+                EventFields {
+                    symbol: Some(symbol.clone()),
+                    order_id: Some(*order_id),
+                    ..EventFields::default()
+                },
+            ),
+            _ => return Ok(()),
+        };
+        self.insert(EventSource::WsOut, kind, fields, msg)
+    }
+
+    /// Record incoming order lifecycle, trade and balance messages, other messages are skipped
+    #[cfg(feature = "ws")]
+    pub fn record_incoming(&self, msg: &KolliderMsg) -> Result<()> {
+        let tagged = match msg {
+            KolliderMsg::Tagged(tagged) => tagged,
+            _ => return Ok(()),
+        };
+        let fields = match tagged {
+            KolliderTaggedMsg::Received {
+                order_id,
+                price,
+                quantity,
+                symbol,
+                ext_order_id,
+                ..
+            } => EventFields {
+                symbol: Some(symbol.clone()),
+                order_id: Some(*order_id),
+                ext_order_id: Some(ext_order_id.clone()),
+                price: Some(*price as f64),
+                quantity: Some(*quantity),
+                ..EventFields::default()
+            },
+            KolliderTaggedMsg::Open {
+                order_id,
+                price,
+                quantity,
+                symbol,
+                side,
+                ext_order_id,
+                ..
+            }
+            | KolliderTaggedMsg::Fill {
+                order_id,
+                price,
+                quantity,
+                symbol,
+                side,
+                ext_order_id,
+                ..
+            } => EventFields {
+                symbol: Some(symbol.clone()),
+                order_id: Some(*order_id),
+                ext_order_id: Some(ext_order_id.clone()),
+                side: Some(format!("{:?}", side)),
+                price: Some(*price as f64),
+                quantity: Some(*quantity),
+                ..EventFields::default()
+            },
+            KolliderTaggedMsg::Trade {
+                order_id,
+                price,
+                quantity,
+                symbol,
+                side,
+                ..
+            } => EventFields {
+                symbol: Some(symbol.clone()),
+                order_id: Some(*order_id),
+                side: Some(format!("{:?}", side)),
+                price: Some(*price),
+                quantity: Some(*quantity),
+                ..EventFields::default()
+            },
+            KolliderTaggedMsg::Done {
+                order_id,
+                symbol,
+                reason,
+                ..
+            } => EventFields {
+                symbol: Some(symbol.clone()),
+                order_id: Some(*order_id),
+                error: Some(reason.clone()),
+                ..EventFields::default()
+            },
+            KolliderTaggedMsg::OrderRejection {
+                order_id,
+                ext_order_id,
+                reason,
+            } => EventFields {
+                order_id: Some(*order_id),
+                ext_order_id: Some(ext_order_id.clone()),
+                error: Some(format!("{:?}", reason)),
+                ..EventFields::default()
+            },
+            KolliderTaggedMsg::Balances { .. } => EventFields::default(),
+            _ => return Ok(()),
+        };
+        self.insert(EventSource::WsIn, tagged.type_tag(), fields, msg)
+    }
+
+    /// Find events in the order they were recorded
+    pub fn query(&self, query: &JournalQuery) -> Result<Vec<JournalEvent>> {
+        let mut conditions = vec![];
+        let mut values: Vec<SqlValue> = vec![];
+        let mut condition = |sql: &str, value: SqlValue| {
+            conditions.push(format!("{} ?{}", sql, values.len() + 1));
+            values.push(value);
+        };
+        if let Some(source) = query.source {
+            condition("source =", SqlValue::Text(source.as_str().to_owned()));
+        }
+        if let Some(kind) = &query.kind {
+            condition("kind =", SqlValue::Text(kind.clone()));
+        }
+        if let Some(symbol) = &query.symbol {
+            condition("symbol =", SqlValue::Text(symbol.clone()));
+        }
+        if let Some(order_id) = query.order_id {
+            condition("order_id =", SqlValue::Integer(order_id as i64));
+        }
+        if let Some(since) = query.since {
+            condition(
+                "recorded_at >=",
+                SqlValue::Integer(since.timestamp_millis()),
+            );
+        }
+        if let Some(until) = query.until {
+            condition("recorded_at <", SqlValue::Integer(until.timestamp_millis()));
+        }
+        let mut sql = "SELECT id, recorded_at, source, kind, symbol, order_id, ext_order_id, side, price, quantity, error, payload FROM events".to_owned();
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY id DESC");
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql)?;
+        let mut events = stmt
+            .query_map(params_from_iter(values), event_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        events.reverse();
+        Ok(events)
+    }
+}
+
+fn event_from_row(row: &Row) -> rusqlite::Result<JournalEvent> {
+    let source: String = row.get(2)?;
+    let payload: String = row.get(11)?;
+    Ok(JournalEvent {
+        id: row.get(0)?,
+        recorded_at: Utc
+            .timestamp_millis_opt(row.get(1)?)
+            .single()
+            .unwrap_or_default(),
+        source: source.parse().unwrap_or(EventSource::WsIn),
+        kind: row.get(3)?,
+        symbol: row.get(4)?,
+        order_id: row.get::<_, Option<i64>>(5)?.map(|v| v as u64),
+        ext_order_id: row.get(6)?,
+        side: row.get(7)?,
+        price: row.get(8)?,
+        quantity: row.get::<_, Option<i64>>(9)?.map(|v| v as u64),
+        error: row.get(10)?,
+        payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::{MarginType, OrderSide, OrderType, SettlementType};
+
+    fn order_body() -> OrderBody {
+        OrderBody {
+            leverage: 100,
+            margin_type: MarginType::Isolated,
+            order_type: OrderType::Limit,
+            price: 486950,
+            quantity: 10,
+            settlement_type: SettlementType::Delayed,
+            side: OrderSide::Bid,
+            symbol: "BTCUSD.PERP".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_journal_rest() {
+        let journal = Journal::open_in_memory().unwrap();
+        let created = OrderCreated {
+            timestamp: 1640100776001,
+            order_id: 42,
+            ext_order_id: "ext".to_owned(),
+            uid: 1,
+            symbol: "BTCUSD.PERP".to_owned(),
+            quantity: 10,
+            order_type: OrderType::Limit,
+            price: 486950,
+            leverage: 100,
+        };
+        journal
+            .record_create_order::<String>(&order_body(), &Ok(created))
+            .unwrap();
+        journal
+            .record_create_order(&order_body(), &Err("Insufficient margin"))
+            .unwrap();
+        journal
+            .record_cancel_order::<String>("BTCUSD.PERP", 42, &Ok(()))
+            .unwrap();
+
+        let all = journal.query(&JournalQuery::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].kind, "create_order");
+        assert_eq!(all[0].order_id, Some(42));
+        assert_eq!(all[0].payload["response"]["ext_order_id"], "ext");
+        assert_eq!(all[1].error.as_deref(), Some("Insufficient margin"));
+
+        let by_order = journal
+            .query(&JournalQuery {
+                order_id: Some(42),
+                ..JournalQuery::default()
+            })
+            .unwrap();
+        assert_eq!(by_order.len(), 2);
+        assert_eq!(by_order[1].kind, "cancel_order");
+
+        let latest = journal
+            .query(&JournalQuery {
+                source: Some(EventSource::Rest),
+                kind: Some("create_order".to_owned()),
+                limit: Some(1),
+                ..JournalQuery::default()
+            })
+            .unwrap();
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].id, all[1].id);
+    }
+
+    #[cfg(feature = "ws")]
+    #[test]
+    fn test_journal_ws() {
+        let journal = Journal::open_in_memory().unwrap();
+        let done = r#"{"data":{"orde_type":"Limit","order_id":42,"reason":"Cancel","symbol":"BTCUSD.PERP","timestamp":1642633795546},"seq":1,"type":"done"}"#;
+        let ticker = r#"{"data":{"best_ask":"1","best_bid":"1","last_price":"1","last_quantity":1,"last_side":"Bid","mid":"1","symbol":"BTCUSD.PERP"},"seq":1,"type":"ticker"}"#;
+        for data in [done, ticker] {
+            let msg: KolliderMsg = serde_json::from_str(data).unwrap();
+            journal.record_incoming(&msg).unwrap();
+        }
+        journal
+            .record_outgoing(&KolliderMsg::CancelOrder {
+                _type: crate::kollider::websocket::CancelOrderTag::Tag,
+                order_id: 42,
+                symbol: "BTCUSD.PERP".to_owned(),
+                settlement_type: SettlementType::Delayed,
+            })
+            .unwrap();
+
+        let events = journal.query(&JournalQuery::default()).unwrap();
+        let kinds: Vec<(EventSource, &str)> =
+            events.iter().map(|e| (e.source, e.kind.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (EventSource::WsIn, "done"),
+                (EventSource::WsOut, "cancel_order")
+            ]
+        );
+        assert_eq!(events[0].error.as_deref(), Some("Cancel"));
+    }
+}
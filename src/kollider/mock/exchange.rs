@@ -0,0 +1,1203 @@
+//! State of the mock exchange: products, accounts, order books, orders, fills and positions.
+//! Orders are matched by price and time priority, trades happen at the price of the resting
+//! order. Fees are zero, margins and liquidation prices are computed with `calc`.
+use crate::kollider::api::{
+    self, FillDetails, HistoryItem, HistoryResp, MarginType, OrderBody, OrderBook, OrderBookLevel,
+    OrderBookResp, OrderDetails, OrderPrediction, OrderSide, OrderType, PositionDetails, Product,
+    SettlementType, Symbol, Ticker,
+};
+use crate::kollider::calc::{self, average_entry_price, initial_margin, pnl, position_value};
+use crate::kollider::client::env::KolliderAuth;
+use crate::kollider::websocket::data::{
+    BalancesCash, ChannelName, IndexValue, KolliderTaggedMsg, OpenOrder, OrderBookLevel1,
+    OrderBookLevel2, OrderReject, Position, TradeMatch, UpdateType, WrappedPrice,
+};
+use chrono::Utc;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Account that owns liquidity added with `MockServer::add_liquidity`. Its margin is not checked.
+pub const MARKET_MAKER_UID: u64 = 0;
+
+/// Receiver of a message produced by the exchange
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Recipient {
+    /// Sessions authentificated as the user
+    User(u64),
+    /// Subscribers of the channel for the symbol
+    Channel(ChannelName, Symbol),
+}
+
+pub type Event = (Recipient, KolliderTaggedMsg);
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Rejection {
+    #[error("Unknown symbol {0}")]
+    UnknownSymbol(Symbol),
+    #[error("Order quantity must be positive")]
+    InvalidQuantity,
+    #[error("Leverage {0} is out of range")]
+    InvalidLeverage(u64),
+    #[error("Order is rejected: {0:?}")]
+    Rejected(OrderReject),
+    #[error("Order {0} for {1} is not found")]
+    OrderNotFound(u64, Symbol),
+    #[error("Not enough available balance to withdraw {0} sats")]
+    InsufficientFunds(u64),
+}
+
+impl Rejection {
+    /// Value of the `error` field in REST responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejection::UnknownSymbol(_) => "InvalidSymbol",
+            Rejection::InvalidQuantity => "InvalidQuantity",
+            Rejection::InvalidLeverage(_) => "InvalidLeverage",
+            Rejection::Rejected(_) => "NotEnoughAvailableBalance",
+            Rejection::OrderNotFound(..) => "OrderNotFound",
+            Rejection::InsufficientFunds(_) => "InsufficientFunds",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockAccount {
+    pub uid: u64,
+    pub auth: KolliderAuth,
+    pub username: String,
+    /// Wallet balance in sats including realized PnL
+    pub cash: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct PositionState {
+    /// Positive for long and negative for short positions
+    quantity: i64,
+    entry_price: f64,
+    entry_time: Option<u64>,
+    leverage: u64,
+    rpnl: f64,
+    timestamp: u64,
+}
+
+/// Resting orders by price level, each level is a queue of order ids
+#[derive(Debug, Default)]
+struct Book {
+    asks: BTreeMap<u64, VecDeque<u64>>,
+    bids: BTreeMap<u64, VecDeque<u64>>,
+    seq_number: u64,
+}
+
+impl Book {
+    fn side_mut(&mut self, side: OrderSide) -> &mut BTreeMap<u64, VecDeque<u64>> {
+        match side {
+            OrderSide::Ask => &mut self.asks,
+            OrderSide::Bid => &mut self.bids,
+        }
+    }
+
+    fn side(&self, side: OrderSide) -> &BTreeMap<u64, VecDeque<u64>> {
+        match side {
+            OrderSide::Ask => &self.asks,
+            OrderSide::Bid => &self.bids,
+        }
+    }
+
+    /// Best price on the side: lowest ask or highest bid
+    fn best(&self, side: OrderSide) -> Option<u64> {
+        match side {
+            OrderSide::Ask => self.asks.keys().next().copied(),
+            OrderSide::Bid => self.bids.keys().next_back().copied(),
+        }
+    }
+}
+
+/// Product that the mock exchange lists by default
+pub fn default_product() -> Product {
+    Product {
+        symbol: "BTCUSD.PERP".to_owned(),
+        contract_size: 1.0,
+        max_leverage: 100.0,
+        base_margin: 0.005,
+        maintenance_margin: 0.004,
+        is_inverse_priced: true,
+        price_dp: 1.0,
+        underlying_symbol: ".BTCUSD".to_owned(),
+        last_price: 500000.0,
+        tick_size: 0.5,
+        risk_limit: 100000000.0,
+    }
+}
+
+fn now_ms() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
+
+fn remaining(order: &OrderDetails) -> u64 {
+    order.quantity - order.filled as u64
+}
+
+/// Matching engine and account state of the mock server
+#[derive(Debug)]
+pub struct Exchange {
+    products: HashMap<Symbol, Product>,
+    accounts: HashMap<u64, MockAccount>,
+    books: HashMap<Symbol, Book>,
+    orders: BTreeMap<u64, OrderDetails>,
+    open_orders: BTreeSet<u64>,
+    fills: Vec<FillDetails>,
+    positions: HashMap<(u64, Symbol), PositionState>,
+    index_prices: HashMap<Symbol, f64>,
+    history: HashMap<Symbol, Vec<HistoryItem>>,
+    last_trades: HashMap<Symbol, TradeMatch>,
+    next_uid: u64,
+    next_order_id: u64,
+}
+
+impl Default for Exchange {
+    fn default() -> Self {
+        Exchange::new()
+    }
+}
+
+impl Exchange {
+    /// Exchange that lists `default_product` and has only the market maker account
+    pub fn new() -> Self {
+        let mut exchange = Exchange {
+            products: HashMap::new(),
+            accounts: HashMap::new(),
+            books: HashMap::new(),
+            orders: BTreeMap::new(),
+            open_orders: BTreeSet::new(),
+            fills: vec![],
+            positions: HashMap::new(),
+            index_prices: HashMap::new(),
+            history: HashMap::new(),
+            last_trades: HashMap::new(),
+            next_uid: MARKET_MAKER_UID,
+            next_order_id: 0,
+        };
+        exchange.add_product(default_product());
+        exchange.add_account(0);
+        exchange
+    }
+
+    pub fn add_product(&mut self, product: Product) {
+        self.books.entry(product.symbol.clone()).or_default();
+        self.products.insert(product.symbol.clone(), product);
+    }
+
+    pub fn products(&self) -> &HashMap<Symbol, Product> {
+        &self.products
+    }
+
+    /// Create account with random credentials and the given balance in sats
+    pub fn add_account(&mut self, balance: u64) -> KolliderAuth {
+        let uid = self.next_uid;
+        self.next_uid += 1;
+        let mut secret = Uuid::new_v4().as_bytes().to_vec();
+        secret.extend(Uuid::new_v4().as_bytes());
+        let auth = KolliderAuth {
+            api_key: Uuid::new_v4().to_string(),
+            api_secret: secret,
+            password: format!("mock-{}", uid),
+        };
+        self.accounts.insert(
+            uid,
+            MockAccount {
+                uid,
+                auth: auth.clone(),
+                username: format!("user{}", uid),
+                cash: balance as f64,
+            },
+        );
+        auth
+    }
+
+    pub fn account(&self, uid: u64) -> Option<&MockAccount> {
+        self.accounts.get(&uid)
+    }
+
+    pub fn account_by_key(&self, api_key: &str) -> Option<&MockAccount> {
+        self.accounts
+            .values()
+            .find(|account| account.auth.api_key == api_key)
+    }
+
+    pub fn deposit(&mut self, uid: u64, amount: u64) {
+        if let Some(account) = self.accounts.get_mut(&uid) {
+            account.cash += amount as f64;
+        }
+    }
+
+    pub fn withdraw(&mut self, uid: u64, amount: u64) -> Result<(), Rejection> {
+        if amount as f64 > self.available(uid) {
+            return Err(Rejection::InsufficientFunds(amount));
+        }
+        if let Some(account) = self.accounts.get_mut(&uid) {
+            account.cash -= amount as f64;
+        }
+        Ok(())
+    }
+
+    /// Balance that is not locked in positions and open orders
+    pub fn available(&self, uid: u64) -> f64 {
+        let cash = self.accounts.get(&uid).map_or(0.0, |account| account.cash);
+        let locked: f64 = self.position_margins(uid).values().sum::<f64>()
+            + self.order_margins(uid).values().sum::<f64>();
+        cash - locked
+    }
+
+    fn position_margins(&self, uid: u64) -> HashMap<Symbol, f64> {
+        self.positions
+            .iter()
+            .filter(|((owner, _), position)| *owner == uid && position.quantity != 0)
+            .filter_map(|((_, symbol), position)| {
+                let product = self.products.get(symbol)?;
+                let margin = initial_margin(
+                    product,
+                    position.entry_price,
+                    position.quantity.unsigned_abs(),
+                    position.leverage,
+                );
+                Some((symbol.clone(), margin))
+            })
+            .collect()
+    }
+
+    fn order_margins(&self, uid: u64) -> HashMap<Symbol, f64> {
+        let mut margins = HashMap::new();
+        for order in self.user_open_orders(uid) {
+            if let Some(product) = self.products.get(&order.symbol) {
+                let price = product.human_price(order.price);
+                *margins.entry(order.symbol.clone()).or_insert(0.0) +=
+                    initial_margin(product, price, remaining(order), order.leverage);
+            }
+        }
+        margins
+    }
+
+    fn user_open_orders(&self, uid: u64) -> impl Iterator<Item = &OrderDetails> {
+        self.open_orders
+            .iter()
+            .filter_map(move |id| self.orders.get(id))
+            .filter(move |order| order.uid == uid)
+    }
+
+    fn product(&self, symbol: &str) -> Result<Product, Rejection> {
+        self.products
+            .get(symbol)
+            .cloned()
+            .ok_or_else(|| Rejection::UnknownSymbol(symbol.to_owned()))
+    }
+
+    fn validate(&self, body: &OrderBody) -> Result<Product, Rejection> {
+        let product = self.product(&body.symbol)?;
+        if body.quantity == 0 {
+            return Err(Rejection::InvalidQuantity);
+        }
+        if body.leverage == 0 || body.leverage as f64 > product.max_leverage {
+            return Err(Rejection::InvalidLeverage(body.leverage));
+        }
+        Ok(product)
+    }
+
+    /// Price that a new order is expected to trade at
+    fn expected_price(&self, product: &Product, body: &OrderBody) -> f64 {
+        if body.order_type == OrderType::Limit {
+            return product.human_price(body.price);
+        }
+        let book = self.books.get(&body.symbol);
+        match book.and_then(|book| book.best(body.side.inverse())) {
+            Some(price) => product.human_price(price),
+            None => self
+                .last_trades
+                .get(&body.symbol)
+                .map_or(product.human_last_price(), |trade| trade.price),
+        }
+    }
+
+    /// Price that positions are valued at: index price, last trade or entry price
+    fn mark_price(&self, product: &Product) -> Option<f64> {
+        self.index_prices
+            .get(&product.underlying_symbol)
+            .copied()
+            .or_else(|| self.last_trades.get(&product.symbol).map(|t| t.price))
+    }
+
+    /// Margin, value and liquidation price of the order, `None` reason if it can be placed
+    pub fn prediction(&self, uid: u64, body: &OrderBody) -> OrderPrediction {
+        let mut prediction = OrderPrediction {
+            uid,
+            ext_id: Uuid::new_v4().to_string(),
+            margin_required: 0.0,
+            value: 0.0,
+            exchange_fee: 0.0,
+            estimated_liquidation_price: 0.0,
+            rejection_reason: None,
+        };
+        match self.validate(body) {
+            Ok(product) => {
+                let price = self.expected_price(&product, body);
+                prediction.value = position_value(&product, price, body.quantity);
+                prediction.margin_required =
+                    initial_margin(&product, price, body.quantity, body.leverage);
+                prediction.estimated_liquidation_price = calc::liquidation_price(
+                    &product,
+                    body.side,
+                    price,
+                    body.quantity,
+                    prediction.margin_required,
+                );
+                if uid != MARKET_MAKER_UID && prediction.margin_required > self.available(uid) {
+                    prediction.rejection_reason =
+                        Some(format!("{:?}", OrderReject::NotEnoughAvailableBalance));
+                }
+            }
+            Err(e) => prediction.rejection_reason = Some(e.code().to_owned()),
+        }
+        prediction
+    }
+
+    /// Place order and match it against the book
+    pub fn submit(
+        &mut self,
+        uid: u64,
+        body: &OrderBody,
+        ext_order_id: &str,
+    ) -> Result<(OrderDetails, Vec<Event>), Rejection> {
+        let product = self.validate(body)?;
+        if uid != MARKET_MAKER_UID {
+            let price = self.expected_price(&product, body);
+            let margin = initial_margin(&product, price, body.quantity, body.leverage);
+            if margin > self.available(uid) {
+                return Err(Rejection::Rejected(OrderReject::NotEnoughAvailableBalance));
+            }
+        }
+
+        let now = now_ms();
+        self.next_order_id += 1;
+        let mut order = OrderDetails {
+            ext_order_id: ext_order_id.to_owned(),
+            filled: 0.0,
+            leverage: body.leverage,
+            margin_type: body.margin_type,
+            order_id: self.next_order_id,
+            order_type: body.order_type,
+            price: body.price,
+            quantity: body.quantity,
+            settlement_type: body.settlement_type,
+            side: body.side,
+            symbol: body.symbol.clone(),
+            timestamp: now,
+            uid,
+        };
+        let user = Recipient::User(uid);
+        let mut events = vec![
+            (
+                user.clone(),
+                KolliderTaggedMsg::Received {
+                    uid,
+                    order_id: order.order_id,
+                    price: order.price,
+                    quantity: order.quantity,
+                    symbol: order.symbol.clone(),
+                    leverage: order.leverage,
+                    order_type: order.order_type,
+                    ext_order_id: order.ext_order_id.clone(),
+                    timestamp: now,
+                },
+            ),
+            (
+                user.clone(),
+                KolliderTaggedMsg::Open {
+                    order_id: order.order_id,
+                    price: order.price,
+                    quantity: order.quantity,
+                    symbol: order.symbol.clone(),
+                    leverage: order.leverage,
+                    side: order.side,
+                    margin_type: order.margin_type,
+                    order_type: order.order_type,
+                    settlement_type: order.settlement_type,
+                    ext_order_id: order.ext_order_id.clone(),
+                    timestamp: now,
+                    filled: 0,
+                },
+            ),
+        ];
+
+        let maker_side = order.side.inverse();
+        let mut touched = BTreeSet::new();
+        let mut traders = BTreeSet::new();
+        while remaining(&order) > 0 {
+            let book = self.books.entry(order.symbol.clone()).or_default();
+            let level_price = match book.best(maker_side) {
+                Some(price) => price,
+                None => break,
+            };
+            let crosses = order.order_type == OrderType::Market
+                || match order.side {
+                    OrderSide::Bid => level_price <= order.price,
+                    OrderSide::Ask => level_price >= order.price,
+                };
+            if !crosses {
+                break;
+            }
+            let maker_id = book.side(maker_side)[&level_price][0];
+            let maker = self.orders[&maker_id].clone();
+            let quantity = remaining(&order).min(remaining(&maker));
+            touched.insert((maker_side, level_price));
+            traders.insert(uid);
+            traders.insert(maker.uid);
+
+            order.filled += quantity as f64;
+            let maker_filled = maker.filled + quantity as f64;
+            if let Some(resting) = self.orders.get_mut(&maker_id) {
+                resting.filled = maker_filled;
+            }
+            let is_selftrade = maker.uid == uid;
+            events.extend(self.fill(&product, &order, level_price, quantity, false, is_selftrade));
+            let maker = self.orders[&maker_id].clone();
+            events.extend(self.fill(&product, &maker, level_price, quantity, true, is_selftrade));
+
+            let trade = TradeMatch {
+                price: product.human_price(level_price),
+                quantity,
+                side: order.side,
+                symbol: order.symbol.clone(),
+                timestamp: now,
+            };
+            self.last_trades.insert(order.symbol.clone(), trade.clone());
+            events.push((
+                Recipient::Channel(ChannelName::Matches, order.symbol.clone()),
+                KolliderTaggedMsg::Matches(trade),
+            ));
+
+            if remaining(&maker) == 0 {
+                let book = self.books.entry(order.symbol.clone()).or_default();
+                let levels = book.side_mut(maker_side);
+                if let Some(queue) = levels.get_mut(&level_price) {
+                    queue.pop_front();
+                    if queue.is_empty() {
+                        levels.remove(&level_price);
+                    }
+                }
+                self.open_orders.remove(&maker_id);
+                events.push(done(&maker, "Fill", now));
+            }
+        }
+
+        let left = remaining(&order);
+        if left > 0 && order.order_type == OrderType::Limit {
+            let book = self.books.entry(order.symbol.clone()).or_default();
+            book.side_mut(order.side)
+                .entry(order.price)
+                .or_default()
+                .push_back(order.order_id);
+            self.open_orders.insert(order.order_id);
+            touched.insert((order.side, order.price));
+        } else if left > 0 {
+            events.push(done(&order, "Cancel", now));
+        } else {
+            events.push(done(&order, "Fill", now));
+        }
+        self.orders.insert(order.order_id, order.clone());
+
+        events.extend(self.book_updates(&order.symbol, &touched));
+        if !traders.is_empty() {
+            if let Some(ticker) = self.ticker(&order.symbol) {
+                events.push((
+                    Recipient::Channel(ChannelName::Ticker, order.symbol.clone()),
+                    KolliderTaggedMsg::Ticker(ticker),
+                ));
+            }
+        }
+        traders.insert(uid);
+        for trader in traders {
+            events.push((Recipient::User(trader), self.positions_msg(trader)));
+            events.push((Recipient::User(trader), self.balances_msg(trader)));
+        }
+        Ok((order, events))
+    }
+
+    /// Record fill of the order, update position of the owner and return private messages
+    fn fill(
+        &mut self,
+        product: &Product,
+        order: &OrderDetails,
+        price: u64,
+        quantity: u64,
+        is_maker: bool,
+        is_selftrade: bool,
+    ) -> Vec<Event> {
+        let now = now_ms();
+        let rpnl = self.apply_fill(
+            order.uid,
+            product,
+            order.side,
+            price,
+            quantity,
+            order.leverage,
+        );
+        let left = remaining(order);
+        self.fills.push(FillDetails {
+            order: OrderDetails {
+                price,
+                quantity,
+                timestamp: now,
+                ..order.clone()
+            },
+            remaining: left,
+            partial: left > 0,
+            is_maker,
+            is_liquidation: false,
+            is_selftrade,
+        });
+        let user = Recipient::User(order.uid);
+        vec![
+            (
+                user.clone(),
+                KolliderTaggedMsg::Fill {
+                    ext_order_id: order.ext_order_id.clone(),
+                    is_maker,
+                    is_selftrade,
+                    leverage: order.leverage,
+                    margin_type: order.margin_type,
+                    order_id: order.order_id,
+                    partial: left > 0,
+                    price,
+                    quantity,
+                    side: order.side,
+                    symbol: order.symbol.clone(),
+                    user_id: order.uid,
+                },
+            ),
+            (
+                user,
+                KolliderTaggedMsg::Trade {
+                    fees: 0.0,
+                    is_liquidation: false,
+                    is_maker,
+                    leverage: order.leverage as f64,
+                    margin_type: order.margin_type,
+                    order_id: order.order_id,
+                    price: product.human_price(price),
+                    quantity,
+                    rpnl,
+                    settlement_type: order.settlement_type,
+                    side: order.side,
+                    symbol: order.symbol.clone(),
+                    timestamp: now,
+                },
+            ),
+        ]
+    }
+
+    /// Update position after a trade and return realized PnL
+    fn apply_fill(
+        &mut self,
+        uid: u64,
+        product: &Product,
+        side: OrderSide,
+        price: u64,
+        quantity: u64,
+        leverage: u64,
+    ) -> f64 {
+        let now = now_ms();
+        let price = product.human_price(price);
+        let signed = match side {
+            OrderSide::Bid => quantity as i64,
+            OrderSide::Ask => -(quantity as i64),
+        };
+        let position = self
+            .positions
+            .entry((uid, product.symbol.clone()))
+            .or_default();
+        let mut rpnl = 0.0;
+        if position.quantity == 0 || position.quantity.signum() == signed.signum() {
+            if position.quantity == 0 {
+                position.entry_time = Some(now);
+                position.leverage = leverage;
+            }
+            position.entry_price = average_entry_price(
+                product,
+                position.entry_price,
+                position.quantity.unsigned_abs(),
+                price,
+                quantity,
+            );
+            position.quantity += signed;
+        } else {
+            let held_side = side.inverse();
+            let closed = position.quantity.unsigned_abs().min(quantity);
+            rpnl = pnl(product, held_side, position.entry_price, price, closed);
+            position.quantity += signed;
+            if position.quantity == 0 {
+                position.entry_price = 0.0;
+                position.entry_time = None;
+            } else if position.quantity.signum() == signed.signum() {
+                position.entry_price = price;
+                position.entry_time = Some(now);
+                position.leverage = leverage;
+            }
+        }
+        position.rpnl += rpnl;
+        position.timestamp = now;
+        if let Some(account) = self.accounts.get_mut(&uid) {
+            account.cash += rpnl;
+        }
+        rpnl
+    }
+
+    /// Cancel open order of the user
+    pub fn cancel(
+        &mut self,
+        uid: u64,
+        symbol: &str,
+        order_id: u64,
+    ) -> Result<Vec<Event>, Rejection> {
+        let order = self
+            .orders
+            .get(&order_id)
+            .filter(|order| {
+                order.uid == uid && order.symbol == symbol && self.open_orders.contains(&order_id)
+            })
+            .cloned()
+            .ok_or_else(|| Rejection::OrderNotFound(order_id, symbol.to_owned()))?;
+        let book = self.books.entry(order.symbol.clone()).or_default();
+        let levels = book.side_mut(order.side);
+        if let Some(queue) = levels.get_mut(&order.price) {
+            queue.retain(|id| *id != order_id);
+            if queue.is_empty() {
+                levels.remove(&order.price);
+            }
+        }
+        self.open_orders.remove(&order_id);
+
+        let mut events = vec![done(&order, "Cancel", now_ms())];
+        let touched = BTreeSet::from([(order.side, order.price)]);
+        events.extend(self.book_updates(symbol, &touched));
+        events.push((Recipient::User(uid), self.balances_msg(uid)));
+        Ok(events)
+    }
+
+    /// Change leverage of the position and future orders of the symbol
+    pub fn change_leverage(
+        &mut self,
+        uid: u64,
+        symbol: &str,
+        leverage: u64,
+    ) -> Result<Vec<Event>, Rejection> {
+        let product = self.product(symbol)?;
+        if leverage == 0 || leverage as f64 > product.max_leverage {
+            return Err(Rejection::InvalidLeverage(leverage));
+        }
+        if let Some(position) = self.positions.get_mut(&(uid, symbol.to_owned())) {
+            position.leverage = leverage;
+        }
+        Ok(vec![
+            (
+                Recipient::User(uid),
+                KolliderTaggedMsg::ChangeLeverageSuccess {
+                    symbol: symbol.to_owned(),
+                },
+            ),
+            (Recipient::User(uid), self.balances_msg(uid)),
+        ])
+    }
+
+    /// Level 2 deltas for changed price levels and a fresh top of the book
+    fn book_updates(&mut self, symbol: &str, touched: &BTreeSet<(OrderSide, u64)>) -> Vec<Event> {
+        if touched.is_empty() {
+            return vec![];
+        }
+        let quantities: Vec<(OrderSide, u64, u64)> = touched
+            .iter()
+            .map(|(side, price)| (*side, *price, self.level_quantity(symbol, *side, *price)))
+            .collect();
+        let book = self.books.entry(symbol.to_owned()).or_default();
+        book.seq_number += 1;
+        let mut update = OrderBookLevel2 {
+            asks: HashMap::new(),
+            bids: HashMap::new(),
+            seq_number: book.seq_number,
+            symbol: symbol.to_owned(),
+            update_type: UpdateType::Delta,
+        };
+        for (side, price, quantity) in quantities {
+            let levels = match side {
+                OrderSide::Ask => &mut update.asks,
+                OrderSide::Bid => &mut update.bids,
+            };
+            levels.insert(price.to_string(), quantity);
+        }
+        let mut events = vec![(
+            Recipient::Channel(ChannelName::OrderBookLevel2, symbol.to_owned()),
+            KolliderTaggedMsg::OrderBookLevel2(update),
+        )];
+        if let Some(top) = self.level1_snapshot(symbol) {
+            events.push((
+                Recipient::Channel(ChannelName::OrderBookLevel1, symbol.to_owned()),
+                KolliderTaggedMsg::OrderBookLevel1(top),
+            ));
+        }
+        events
+    }
+
+    fn level_quantity(&self, symbol: &str, side: OrderSide, price: u64) -> u64 {
+        self.books
+            .get(symbol)
+            .and_then(|book| book.side(side).get(&price))
+            .map_or(0, |queue| {
+                queue
+                    .iter()
+                    .filter_map(|id| self.orders.get(id))
+                    .map(remaining)
+                    .sum()
+            })
+    }
+
+    fn levels(&self, symbol: &str, side: OrderSide) -> HashMap<String, u64> {
+        self.books.get(symbol).map_or_else(HashMap::new, |book| {
+            book.side(side)
+                .keys()
+                .map(|price| (price.to_string(), self.level_quantity(symbol, side, *price)))
+                .collect()
+        })
+    }
+
+    pub fn level2_snapshot(&self, symbol: &str) -> Option<OrderBookLevel2> {
+        let book = self.books.get(symbol)?;
+        Some(OrderBookLevel2 {
+            asks: self.levels(symbol, OrderSide::Ask),
+            bids: self.levels(symbol, OrderSide::Bid),
+            seq_number: book.seq_number,
+            symbol: symbol.to_owned(),
+            update_type: UpdateType::Snapshot,
+        })
+    }
+
+    pub fn level1_snapshot(&self, symbol: &str) -> Option<OrderBookLevel1> {
+        let book = self.books.get(symbol)?;
+        let top = |side| -> HashMap<String, u64> {
+            book.best(side)
+                .map(|price| (price.to_string(), self.level_quantity(symbol, side, price)))
+                .into_iter()
+                .collect()
+        };
+        Some(OrderBookLevel1 {
+            asks: top(OrderSide::Ask),
+            bids: top(OrderSide::Bid),
+            seq_number: book.seq_number,
+            symbol: symbol.to_owned(),
+            update_type: UpdateType::Snapshot,
+        })
+    }
+
+    /// Response of the REST order book endpoint
+    pub fn orderbook(&self, level: OrderBookLevel, symbol: &str) -> Option<OrderBookResp> {
+        let book = self.books.get(symbol)?;
+        let orders = |side: OrderSide| -> Vec<(u64, Vec<OrderDetails>)> {
+            let mut levels: Vec<(u64, Vec<OrderDetails>)> = book
+                .side(side)
+                .iter()
+                .map(|(price, queue)| {
+                    let orders = queue
+                        .iter()
+                        .filter_map(|id| self.orders.get(id))
+                        .cloned()
+                        .collect();
+                    (*price, orders)
+                })
+                .collect();
+            if side == OrderSide::Bid {
+                levels.reverse();
+            }
+            levels
+        };
+        let book_data = match level {
+            OrderBookLevel::Level2 => OrderBook::Level2(api::OrderBookLevel2 {
+                asks: self.levels(symbol, OrderSide::Ask),
+                bids: self.levels(symbol, OrderSide::Bid),
+            }),
+            OrderBookLevel::Level3 => OrderBook::Level3(api::OrderBookLevel3 {
+                asks: orders(OrderSide::Ask),
+                bids: orders(OrderSide::Bid),
+            }),
+        };
+        Some(OrderBookResp {
+            level,
+            seq_number: book.seq_number,
+            symbol: symbol.to_owned(),
+            book: book_data,
+        })
+    }
+
+    pub fn ticker(&self, symbol: &str) -> Option<Ticker> {
+        let product = self.products.get(symbol)?;
+        let book = self.books.get(symbol)?;
+        let best = |side| book.best(side).map_or(0.0, |p| product.human_price(p));
+        let last = self.last_trades.get(symbol);
+        Some(Ticker {
+            best_ask: best(OrderSide::Ask),
+            best_bid: best(OrderSide::Bid),
+            last_price: last.map_or(product.human_last_price(), |t| t.price),
+            last_quantity: last.map_or(0, |t| t.quantity),
+            last_side: last.map_or(OrderSide::Bid, |t| t.side),
+            symbol: symbol.to_owned(),
+        })
+    }
+
+    /// Set value of the index, e.x. `.BTCUSD`, and return the `index_values` message
+    pub fn set_index_price(&mut self, symbol: &str, value: f64) -> Vec<Event> {
+        self.index_prices.insert(symbol.to_owned(), value);
+        let channel = Recipient::Channel(ChannelName::IndexValues, symbol.to_owned());
+        self.index_value_msg(symbol)
+            .map(|msg| (channel, msg))
+            .into_iter()
+            .collect()
+    }
+
+    pub fn index_price(&self, symbol: &str) -> Option<f64> {
+        self.index_prices.get(symbol).copied()
+    }
+
+    /// Current value of the index as `index_values` message
+    pub fn index_value_msg(&self, symbol: &str) -> Option<KolliderTaggedMsg> {
+        let value = self.index_price(symbol)?;
+        Some(KolliderTaggedMsg::IndexValues(IndexValue {
+            denom: "USD".to_owned(),
+            symbol: symbol.to_owned(),
+            value,
+        }))
+    }
+
+    /// Replace index price history that is served by `/market/historic_index_prices`
+    pub fn set_history(&mut self, symbol: &str, mut items: Vec<HistoryItem>) {
+        items.sort_by_key(|item| item.time);
+        self.history.insert(symbol.to_owned(), items);
+    }
+
+    /// History items of the symbol between `start` and `end` seconds
+    pub fn history(&self, symbol: &str, start: u64, end: u64, limit: usize) -> HistoryResp {
+        let data = self
+            .history
+            .get(symbol)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter(|item| item.time >= start && item.time <= end)
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        HistoryResp {
+            data,
+            symbol: symbol.to_owned(),
+        }
+    }
+
+    /// Orders of the user placed between `start` and `end` seconds
+    pub fn orders(
+        &self,
+        uid: u64,
+        symbol: &str,
+        start: u64,
+        end: u64,
+        limit: usize,
+    ) -> Vec<OrderDetails> {
+        self.orders
+            .values()
+            .filter(|order| order.uid == uid && order.symbol == symbol)
+            .filter(|order| (start..=end).contains(&(order.timestamp / 1000)))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Fills of the user between `start` and `end` seconds
+    pub fn fills(
+        &self,
+        uid: u64,
+        symbol: &str,
+        start: u64,
+        end: u64,
+        limit: usize,
+    ) -> Vec<FillDetails> {
+        self.fills
+            .iter()
+            .filter(|fill| fill.order.uid == uid && fill.order.symbol == symbol)
+            .filter(|fill| (start..=end).contains(&(fill.order.timestamp / 1000)))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn open_orders(&self, uid: u64) -> HashMap<Symbol, Vec<OrderDetails>> {
+        let mut orders: HashMap<Symbol, Vec<OrderDetails>> = HashMap::new();
+        for order in self.user_open_orders(uid) {
+            orders
+                .entry(order.symbol.clone())
+                .or_default()
+                .push(order.clone());
+        }
+        orders
+    }
+
+    pub fn open_orders_msg(&self, uid: u64) -> KolliderTaggedMsg {
+        let open_orders = self
+            .open_orders(uid)
+            .into_iter()
+            .map(|(symbol, orders)| {
+                let orders = orders
+                    .into_iter()
+                    .map(|order| OpenOrder {
+                        ext_order_id: order.ext_order_id,
+                        filled: order.filled as u64,
+                        leverage: order.leverage,
+                        margin_type: order.margin_type,
+                        order_id: order.order_id,
+                        order_type: order.order_type,
+                        price: order.price,
+                        quantity: order.quantity,
+                        settlement_type: order.settlement_type,
+                        side: order.side,
+                        symbol: order.symbol,
+                        timestamp: order.timestamp,
+                        uid: order.uid,
+                    })
+                    .collect();
+                (symbol, orders)
+            })
+            .collect();
+        KolliderTaggedMsg::OpenOrders { open_orders }
+    }
+
+    fn open_order_ids(&self, uid: u64, symbol: &str) -> Vec<u64> {
+        self.user_open_orders(uid)
+            .filter(|order| order.symbol == symbol)
+            .map(|order| order.order_id)
+            .collect()
+    }
+
+    /// Open positions of the user in the format of the REST API
+    pub fn positions(&self, uid: u64) -> HashMap<Symbol, PositionDetails> {
+        self.ws_positions(uid)
+            .into_iter()
+            .map(|(symbol, position)| {
+                let details = PositionDetails {
+                    uid,
+                    timestamp: position.timestamp,
+                    symbol: symbol.clone(),
+                    upnl: position.upnl.round() as i64,
+                    leverage: position.leverage,
+                    entry_price: position.entry_price,
+                    side: position.side.unwrap_or(OrderSide::Bid),
+                    quantity: position.quantity,
+                    liq_price: position.liq_price,
+                    open_order_ids: position
+                        .open_order_ids
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect(),
+                };
+                (symbol, details)
+            })
+            .collect()
+    }
+
+    /// Open positions of the user in the format of the WebSocket API
+    pub fn ws_positions(&self, uid: u64) -> HashMap<Symbol, Position> {
+        self.positions
+            .iter()
+            .filter(|((owner, _), position)| *owner == uid && position.quantity != 0)
+            .filter_map(|((_, symbol), state)| {
+                let product = self.products.get(symbol)?;
+                let side = if state.quantity > 0 {
+                    OrderSide::Bid
+                } else {
+                    OrderSide::Ask
+                };
+                let quantity = state.quantity.unsigned_abs();
+                let mark = self.mark_price(product).unwrap_or(state.entry_price);
+                let margin = initial_margin(product, state.entry_price, quantity, state.leverage);
+                let position = Position {
+                    adl_score: 0.0,
+                    bankruptcy_price: calc::bankruptcy_price(
+                        product,
+                        side,
+                        state.entry_price,
+                        quantity,
+                        margin,
+                    ),
+                    entry_price: state.entry_price,
+                    entry_time: state.entry_time,
+                    entry_value: position_value(product, state.entry_price, quantity),
+                    funding: 0.0,
+                    is_liquidating: false,
+                    leverage: state.leverage as f64,
+                    liq_price: calc::liquidation_price(
+                        product,
+                        side,
+                        state.entry_price,
+                        quantity,
+                        margin,
+                    ),
+                    mark_value: position_value(product, mark, quantity),
+                    open_order_ids: self.open_order_ids(uid, symbol),
+                    position_id: format!("{}-{}", uid, symbol),
+                    quantity: quantity as f64,
+                    real_leverage: state.leverage as f64,
+                    rpnl: state.rpnl,
+                    side: Some(side),
+                    symbol: symbol.clone(),
+                    timestamp: state.timestamp,
+                    uid,
+                    upnl: pnl(product, side, state.entry_price, mark, quantity),
+                };
+                Some((symbol.clone(), position))
+            })
+            .collect()
+    }
+
+    pub fn positions_msg(&self, uid: u64) -> KolliderTaggedMsg {
+        KolliderTaggedMsg::Positions {
+            positions: self.ws_positions(uid),
+        }
+    }
+
+    pub fn balances_msg(&self, uid: u64) -> KolliderTaggedMsg {
+        let wrap = |margins: HashMap<Symbol, f64>| {
+            margins
+                .into_iter()
+                .map(|(symbol, margin)| (symbol, WrappedPrice(margin)))
+                .collect()
+        };
+        KolliderTaggedMsg::Balances {
+            cash: BalancesCash {
+                kkp: 0.0,
+                sat: self.available(uid),
+            },
+            cross_margin: 0.0,
+            isolated_margin: wrap(self.position_margins(uid)),
+            order_margin: wrap(self.order_margins(uid)),
+        }
+    }
+}
+
+fn done(order: &OrderDetails, reason: &str, timestamp: u64) -> Event {
+    (
+        Recipient::User(order.uid),
+        KolliderTaggedMsg::Done {
+            orde_type: order.order_type,
+            order_id: order.order_id,
+            reason: reason.to_owned(),
+            symbol: order.symbol.clone(),
+            timestamp,
+        },
+    )
+}
+
+/// Body of a limit order with isolated margin and delayed settlement
+pub fn limit_order(
+    symbol: &str,
+    side: OrderSide,
+    price: u64,
+    quantity: u64,
+    leverage: u64,
+) -> OrderBody {
+    OrderBody {
+        leverage,
+        margin_type: MarginType::Isolated,
+        order_type: OrderType::Limit,
+        price,
+        quantity,
+        settlement_type: SettlementType::Delayed,
+        side,
+        symbol: symbol.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has_msg(events: &[Event], uid: u64, tag: &str) -> bool {
+        events
+            .iter()
+            .any(|(to, msg)| *to == Recipient::User(uid) && msg.type_tag() == tag)
+    }
+
+    #[test]
+    fn test_matching_and_positions() {
+        let mut exchange = Exchange::new();
+        let auth = exchange.add_account(1_000_000);
+        let uid = exchange.account_by_key(&auth.api_key).unwrap().uid;
+        let symbol = "BTCUSD.PERP";
+
+        let ask = limit_order(symbol, OrderSide::Ask, 500000, 50, 1);
+        exchange.submit(MARKET_MAKER_UID, &ask, "mm-1").unwrap();
+        let ask = limit_order(symbol, OrderSide::Ask, 500010, 50, 1);
+        exchange.submit(MARKET_MAKER_UID, &ask, "mm-2").unwrap();
+
+        // Bid crosses the first level fully and the second one partially, the rest rests
+        let bid = limit_order(symbol, OrderSide::Bid, 500010, 120, 10);
+        let (order, events) = exchange.submit(uid, &bid, "bid").unwrap();
+        assert_eq!(order.filled, 100.0);
+        assert!(has_msg(&events, uid, "fill"));
+        assert!(has_msg(&events, MARKET_MAKER_UID, "done"));
+        assert!(!has_msg(&events, uid, "done"));
+        assert_eq!(exchange.open_orders(uid)[symbol].len(), 1);
+        assert_eq!(exchange.level1_snapshot(symbol).unwrap().bids["500010"], 20);
+
+        let positions = exchange.positions(uid);
+        let position = &positions[symbol];
+        assert_eq!(position.side, OrderSide::Bid);
+        assert_eq!(position.quantity, 100.0);
+        assert!(position.entry_price > 50000.0 && position.entry_price < 50001.0);
+        assert!(position.liq_price < position.entry_price);
+        assert_eq!(position.open_order_ids, vec![order.order_id.to_string()]);
+
+        // Closing at a higher price realizes profit
+        exchange.cancel(uid, symbol, order.order_id).unwrap();
+        let bid = limit_order(symbol, OrderSide::Bid, 510000, 100, 1);
+        exchange.submit(MARKET_MAKER_UID, &bid, "mm-3").unwrap();
+        let cash_before = exchange.account(uid).unwrap().cash;
+        let ask = OrderBody {
+            order_type: OrderType::Market,
+            ..limit_order(symbol, OrderSide::Ask, 0, 100, 10)
+        };
+        let (_, events) = exchange.submit(uid, &ask, "close").unwrap();
+        assert!(has_msg(&events, uid, "done"));
+        assert!(exchange.positions(uid).is_empty());
+        assert!(exchange.account(uid).unwrap().cash > cash_before);
+        assert_eq!(
+            exchange.cancel(uid, symbol, order.order_id),
+            Err(Rejection::OrderNotFound(order.order_id, symbol.to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_margin_check() {
+        let mut exchange = Exchange::new();
+        let auth = exchange.add_account(1000);
+        let uid = exchange.account_by_key(&auth.api_key).unwrap().uid;
+        // 100 contracts at 50000 USD cost 200000 sats with leverage 1
+        let bid = limit_order("BTCUSD.PERP", OrderSide::Bid, 500000, 100, 1);
+        assert_eq!(
+            exchange.submit(uid, &bid, "bid").map(|_| ()),
+            Err(Rejection::Rejected(OrderReject::NotEnoughAvailableBalance))
+        );
+        assert!(exchange.prediction(uid, &bid).rejection_reason.is_some());
+        exchange.deposit(uid, 200000);
+        assert!(exchange.submit(uid, &bid, "bid").is_ok());
+        assert_eq!(exchange.available(uid).round(), 1000.0);
+        assert_eq!(
+            exchange.withdraw(uid, 2000),
+            Err(Rejection::InsufficientFunds(2000))
+        );
+    }
+}
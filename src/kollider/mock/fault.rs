@@ -0,0 +1,165 @@
+//! Scriptable faults of the mock server. Rules are checked in the order they were injected, the
+//! first matching one is applied and removed once its count is exhausted.
+use crate::kollider::websocket::data::OrderReject;
+use std::time::Duration;
+
+/// What the server does instead of (or before) handling a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Wait before handling the request as usual
+    Delay(Duration),
+    /// Reply with the HTTP status and empty body. WebSocket gets an `error` message.
+    Status(u16),
+    /// Reply with Kollider error, e.x. `Error { error: "InvalidKey", msg: "..." }`.
+    /// WebSocket gets an `error` message with `msg`.
+    Error { error: String, msg: String },
+    /// Reply with a body that is not JSON
+    Malformed,
+    /// Close connection without a reply
+    Disconnect,
+    /// Reject order with the reason, applies to order placement only
+    RejectOrder(OrderReject),
+}
+
+/// Requests that a rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultTarget {
+    /// REST request to the route, e.x. `/orders`. Any method matches if `method` is `None`.
+    Http {
+        method: Option<String>,
+        route: String,
+    },
+    /// WebSocket message with the `type` field, e.x. `order`. Any message matches if `None`.
+    Ws { type_tag: Option<String> },
+}
+
+impl FaultTarget {
+    fn matches_http(&self, request_method: &str, request_route: &str) -> bool {
+        match self {
+            FaultTarget::Http { method, route } => {
+                route == request_route
+                    && method
+                        .as_deref()
+                        .is_none_or(|m| m.eq_ignore_ascii_case(request_method))
+            }
+            FaultTarget::Ws { .. } => false,
+        }
+    }
+
+    fn matches_ws(&self, message_tag: Option<&str>) -> bool {
+        match self {
+            FaultTarget::Ws { type_tag } => {
+                type_tag.is_none() || type_tag.as_deref() == message_tag
+            }
+            FaultTarget::Http { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultRule {
+    pub target: FaultTarget,
+    pub fault: Fault,
+    /// How many requests the rule applies to, `None` is forever
+    pub times: Option<usize>,
+}
+
+impl FaultRule {
+    /// Rule for REST requests, `method` is e.x. `POST` or `*` for any method
+    pub fn http(method: &str, route: &str, fault: Fault) -> Self {
+        FaultRule {
+            target: FaultTarget::Http {
+                method: if method == "*" {
+                    None
+                } else {
+                    Some(method.to_owned())
+                },
+                route: route.to_owned(),
+            },
+            fault,
+            times: None,
+        }
+    }
+
+    /// Rule for WebSocket messages of the type, `*` matches any message
+    pub fn ws(type_tag: &str, fault: Fault) -> Self {
+        FaultRule {
+            target: FaultTarget::Ws {
+                type_tag: if type_tag == "*" {
+                    None
+                } else {
+                    Some(type_tag.to_owned())
+                },
+            },
+            fault,
+            times: None,
+        }
+    }
+
+    /// Apply the rule only to the next `n` matching requests
+    pub fn times(mut self, n: usize) -> Self {
+        self.times = Some(n);
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Faults {
+    rules: Vec<FaultRule>,
+}
+
+impl Faults {
+    pub fn push(&mut self, rule: FaultRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    pub fn take_http(&mut self, method: &str, route: &str) -> Option<Fault> {
+        self.take(|target| target.matches_http(method, route))
+    }
+
+    pub fn take_ws(&mut self, type_tag: Option<&str>) -> Option<Fault> {
+        self.take(|target| target.matches_ws(type_tag))
+    }
+
+    fn take<F: Fn(&FaultTarget) -> bool>(&mut self, matches: F) -> Option<Fault> {
+        let index = self.rules.iter().position(|rule| matches(&rule.target))?;
+        let rule = &mut self.rules[index];
+        let fault = rule.fault.clone();
+        if let Some(times) = &mut rule.times {
+            *times = times.saturating_sub(1);
+            if *times == 0 {
+                self.rules.remove(index);
+            }
+        }
+        Some(fault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_rules() {
+        let mut faults = Faults::default();
+        faults.push(FaultRule::http("POST", "/orders", Fault::Status(500)).times(2));
+        faults.push(FaultRule::ws("*", Fault::Disconnect).times(1));
+
+        assert_eq!(faults.take_http("GET", "/orders"), None);
+        assert_eq!(
+            faults.take_http("post", "/orders"),
+            Some(Fault::Status(500))
+        );
+        assert_eq!(
+            faults.take_http("POST", "/orders"),
+            Some(Fault::Status(500))
+        );
+        assert_eq!(faults.take_http("POST", "/orders"), None);
+        assert_eq!(faults.take_ws(Some("order")), Some(Fault::Disconnect));
+        assert_eq!(faults.take_ws(Some("order")), None);
+    }
+}
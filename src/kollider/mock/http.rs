@@ -0,0 +1,340 @@
+// Handlers return error responses in `Err` to use `?` for early replies
+#![allow(clippy::result_large_err)]
+use super::exchange::Rejection;
+use super::fault::Fault;
+use super::{Shared, API_PREFIX};
+use crate::kollider::api::{
+    OrderBody, OrderBookLevel, OrderCreated, WithdrawalNetwork, WithdrawalResp, WithdrawalStatus,
+};
+use crate::kollider::client::signer::{SignedRequest, VerifyError};
+use crate::kollider::websocket::data::OrderReject;
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Parsed incoming request
+struct MockRequest {
+    method: Method,
+    /// Path without `API_PREFIX`, the same that clients sign
+    route: String,
+    query: Vec<(String, String)>,
+    headers: HeaderMap,
+    body: String,
+    /// Rejection of order placement injected by `Fault::RejectOrder`
+    forced_rejection: Option<OrderReject>,
+}
+
+type Reply = std::result::Result<Response<Body>, Response<Body>>;
+
+/// Serve a request. Error closes the connection without reply.
+pub(crate) async fn handle(
+    shared: Arc<Shared>,
+    request: Request<Body>,
+) -> Result<Response<Body>, std::io::Error> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let route = path.strip_prefix(API_PREFIX).unwrap_or(&path).to_owned();
+    let query = request
+        .uri()
+        .query()
+        .map(|q| {
+            url::form_urlencoded::parse(q.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    let headers = request.headers().clone();
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(std::io::Error::other)?;
+    debug!("Mock server got {} {}", method, path);
+
+    let fault = shared
+        .faults
+        .lock()
+        .unwrap()
+        .take_http(method.as_str(), &route);
+    let mut forced_rejection = None;
+    match fault {
+        Some(Fault::Delay(duration)) => tokio::time::sleep(duration).await,
+        Some(Fault::Status(code)) => {
+            let status = StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = status;
+            return Ok(response);
+        }
+        Some(Fault::Error { error, msg }) => {
+            return Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({ "error": error, "msg": msg }),
+            ))
+        }
+        Some(Fault::Malformed) => {
+            return Ok(Response::new(Body::from("<html>502 Bad Gateway</html>")));
+        }
+        Some(Fault::Disconnect) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "Injected disconnect",
+            ));
+        }
+        Some(Fault::RejectOrder(reason)) => forced_rejection = Some(reason),
+        None => (),
+    }
+
+    let request = MockRequest {
+        method,
+        route,
+        query,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+        forced_rejection,
+    };
+    Ok(route_request(&shared, &request).unwrap_or_else(|response| response))
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let body = serde_json::to_string(value).expect("Mock responses are serializable");
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+fn ok<T: Serialize>(value: &T) -> Reply {
+    Ok(json_response(StatusCode::OK, value))
+}
+
+fn error(status: StatusCode, error: Value, msg: &str) -> Response<Body> {
+    json_response(status, &json!({ "error": error, "msg": msg }))
+}
+
+fn bad_request(msg: &str) -> Response<Body> {
+    error(StatusCode::BAD_REQUEST, json!("BadRequest"), msg)
+}
+
+fn rejected(rejection: &Rejection) -> Response<Body> {
+    error(
+        StatusCode::BAD_REQUEST,
+        json!(rejection.code()),
+        &rejection.to_string(),
+    )
+}
+
+impl MockRequest {
+    fn param(&self, name: &str) -> std::result::Result<&str, Response<Body>> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .ok_or_else(|| bad_request(&format!("Missing query parameter {}", name)))
+    }
+
+    fn parse_param<T: std::str::FromStr>(
+        &self,
+        name: &str,
+    ) -> std::result::Result<T, Response<Body>> {
+        self.param(name)?
+            .parse()
+            .map_err(|_| bad_request(&format!("Invalid query parameter {}", name)))
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(&self) -> std::result::Result<T, Response<Body>> {
+        serde_json::from_str(&self.body)
+            .map_err(|e| bad_request(&format!("Failed to parse body: {}", e)))
+    }
+
+    /// Check `K-*` headers and return uid of the account
+    fn authenticate(&self, shared: &Shared) -> std::result::Result<u64, Response<Body>> {
+        SignedRequest::rest(
+            self.method.as_str(),
+            &self.route,
+            &self.query,
+            &self.headers,
+            &self.body,
+        )
+        .and_then(|request| shared.authenticate(&request))
+        .map_err(|e| match e {
+            VerifyError::MissingHeader(_) => error(
+                StatusCode::UNAUTHORIZED,
+                json!({ "GeneralError": "Unauthorized" }),
+                "A general error has occured.",
+            ),
+            e => error(
+                StatusCode::UNAUTHORIZED,
+                json!({ "AuthError": e.to_string() }),
+                "An auth error has occured.",
+            ),
+        })
+    }
+}
+
+fn route_request(shared: &Shared, request: &MockRequest) -> Reply {
+    match (request.method.clone(), request.route.as_str()) {
+        (Method::GET, "/market/products") => ok(shared.exchange().products()),
+        (Method::GET, "/market/ticker") => {
+            let symbol = request.param("symbol")?;
+            let ticker = shared.exchange().ticker(symbol);
+            ticker.map_or_else(
+                || Err(rejected(&Rejection::UnknownSymbol(symbol.to_owned()))),
+                |ticker| ok(&ticker),
+            )
+        }
+        (Method::GET, "/market/orderbook") => {
+            let symbol = request.param("symbol")?;
+            let level: OrderBookLevel = serde_json::from_value(json!(request.param("level")?))
+                .map_err(|_| bad_request("Invalid query parameter level"))?;
+            let book = shared.exchange().orderbook(level, symbol);
+            book.map_or_else(
+                || Err(rejected(&Rejection::UnknownSymbol(symbol.to_owned()))),
+                |book| ok(&book),
+            )
+        }
+        (Method::GET, "/market/historic_index_prices") => {
+            let history = shared.exchange().history(
+                request.param("symbol")?,
+                request.parse_param("start")?,
+                request.parse_param("end")?,
+                request.parse_param("limit")?,
+            );
+            ok(&history)
+        }
+        (Method::POST, "/orders") => {
+            let uid = request.authenticate(shared)?;
+            let body: OrderBody = request.json()?;
+            if let Some(reason) = request.forced_rejection {
+                return Err(rejected(&Rejection::Rejected(reason)));
+            }
+            let ext_order_id = Uuid::new_v4().to_string();
+            let result = shared.exchange().submit(uid, &body, &ext_order_id);
+            let (order, events) = result.map_err(|e| rejected(&e))?;
+            shared.publish(events);
+            ok(&OrderCreated {
+                timestamp: order.timestamp,
+                order_id: order.order_id,
+                ext_order_id: order.ext_order_id,
+                uid: order.uid,
+                symbol: order.symbol,
+                quantity: order.quantity,
+                order_type: order.order_type,
+                price: order.price,
+                leverage: order.leverage,
+            })
+        }
+        (Method::DELETE, "/orders") => {
+            let uid = request.authenticate(shared)?;
+            let order_id = request.parse_param("order_id")?;
+            let symbol = request.param("symbol")?;
+            let result = shared.exchange().cancel(uid, symbol, order_id);
+            match result {
+                Ok(events) => {
+                    shared.publish(events);
+                    ok(&json!({ "reason": "Cancel" }))
+                }
+                Err(e) => ok(&json!({ "reason": e.code() })),
+            }
+        }
+        (Method::GET, "/orders") => {
+            let uid = request.authenticate(shared)?;
+            let orders = shared.exchange().orders(
+                uid,
+                request.param("symbol")?,
+                request.parse_param("start")?,
+                request.parse_param("end")?,
+                request.parse_param("limit")?,
+            );
+            ok(&orders)
+        }
+        (Method::GET, "/orders/open") => {
+            let uid = request.authenticate(shared)?;
+            ok(&shared.exchange().open_orders(uid))
+        }
+        (Method::POST, "/orders/prediction") => {
+            let uid = request.authenticate(shared)?;
+            let body: OrderBody = request.json()?;
+            ok(&shared.exchange().prediction(uid, &body))
+        }
+        (Method::GET, "/positions") => {
+            let uid = request.authenticate(shared)?;
+            ok(&shared.exchange().positions(uid))
+        }
+        (Method::GET, "/user/account") => {
+            let uid = request.authenticate(shared)?;
+            let exchange = shared.exchange();
+            let account = exchange
+                .account(uid)
+                .expect("Authentificated account exists");
+            ok(&json!({
+                "created_at": { "nanos_since_epoch": 0, "secs_since_epoch": 1640000000 },
+                "email": format!("{}@example.com", account.username),
+                "lnauth_enabled": false,
+                "user_type": "Pro",
+                "username": account.username,
+                "validated_email": true,
+            }))
+        }
+        (Method::GET, "/user/fills") => {
+            let uid = request.authenticate(shared)?;
+            let fills = shared.exchange().fills(
+                uid,
+                request.param("symbol")?,
+                request.parse_param("start")?,
+                request.parse_param("end")?,
+                request.parse_param("limit")?,
+            );
+            ok(&fills)
+        }
+        (Method::POST, "/wallet/deposit") => {
+            let uid = request.authenticate(shared)?;
+            let body: Value = request.json()?;
+            match body.get("type").and_then(Value::as_str) {
+                // Invoices of the mock server are paid instantly
+                Some("Ln") => {
+                    let amount = body.get("amount").and_then(Value::as_u64).unwrap_or(0);
+                    shared.exchange().deposit(uid, amount);
+                    ok(&json!({ "payment_request": format!("lnbcrt{}n1mock{}", amount, uid) }))
+                }
+                Some("BTC") => ok(&json!({ "receive_address": format!("bcrt1qmock{}", uid) })),
+                _ => Err(bad_request("Unknown deposit type")),
+            }
+        }
+        (Method::POST, "/wallet/withdrawal") => {
+            let uid = request.authenticate(shared)?;
+            let body: Value = request.json()?;
+            let amount = body.get("amount").and_then(Value::as_u64).unwrap_or(0);
+            let network = match body.get("type").and_then(Value::as_str) {
+                Some("Ln") => WithdrawalNetwork::Lightning,
+                Some("BTC") => WithdrawalNetwork::Bitcoin,
+                _ => return Err(bad_request("Unknown withdrawal type")),
+            };
+            let result = shared.exchange().withdraw(uid, amount);
+            let resp = match result {
+                Ok(()) => WithdrawalResp::WithdrawalSuccess {
+                    uid,
+                    receipt: Uuid::new_v4().to_string(),
+                    amount,
+                    network,
+                    status: WithdrawalStatus::Complete,
+                    txid: Uuid::new_v4().to_simple().to_string(),
+                },
+                Err(e) => WithdrawalResp::WithdrawalRejection {
+                    uid,
+                    reason: e.code().to_owned(),
+                    amount,
+                },
+            };
+            ok(&resp)
+        }
+        (method, route) => Err(error(
+            StatusCode::NOT_FOUND,
+            json!("NotFound"),
+            &format!("No route {} {}", method, route),
+        )),
+    }
+}
@@ -0,0 +1,462 @@
+//! In-process Kollider server for integration tests of bots without network. It serves the REST
+//! API over HTTP and the WebSocket API on a separate port, checks HMAC signatures of private
+//! requests, matches orders in a simple engine and can be scripted to fail with `FaultRule`.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use kollider_api::kollider::api::OrderSide;
+//! use kollider_api::kollider::mock::*;
+//!
+//! let server = MockServer::start().await?;
+//! let auth = server.add_account(1_000_000);
+//! server.add_liquidity("BTCUSD.PERP", OrderSide::Ask, 500000, 100)?;
+//! server.inject(FaultRule::http("POST", "/orders", Fault::Status(503)).times(1));
+//!
+//! let mut client = server.client();
+//! client.set_auth(auth);
+//! # Ok(())
+//! # }
+//! ```
+pub mod exchange;
+pub mod fault;
+mod http;
+mod ws;
+
+pub use exchange::*;
+pub use fault::*;
+
+use crate::kollider::api::{HistoryItem, OrderSide, Product};
+use crate::kollider::client::env::{KolliderAuth, KolliderClient};
+use crate::kollider::client::signer::{verify_signature, SignedRequest, VerifyError};
+use crate::kollider::websocket::client::WebsocketOptions;
+use crate::kollider::websocket::data::KolliderTaggedMsg;
+use chrono::Utc;
+use futures::channel::oneshot;
+use hyper::service::{make_service_fn, service_fn};
+use log::*;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex, MutexGuard};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Allowed difference in seconds between timestamp of a signed request and the server clock
+pub const RECV_WINDOW_SECS: i64 = 30;
+
+/// Prefix of REST routes, the same as in `KOLLIDER_MAINNET`
+const API_PREFIX: &str = "/v1";
+
+#[derive(Error, Debug)]
+pub enum MockError {
+    #[error("Failed to bind mock server: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("HTTP server error: {0}")]
+    Http(#[from] hyper::Error),
+}
+
+/// Alias for a `Result` with the error type `MockError`.
+pub type Result<T> = std::result::Result<T, MockError>;
+
+/// State shared by HTTP and WebSocket handlers
+pub(crate) struct Shared {
+    exchange: Mutex<Exchange>,
+    faults: Mutex<Faults>,
+    sessions: Mutex<HashMap<u64, ws::Session>>,
+    next_session: AtomicU64,
+}
+
+impl Shared {
+    fn exchange(&self) -> MutexGuard<'_, Exchange> {
+        self.exchange.lock().unwrap()
+    }
+
+    /// Check credentials of a signed request and return uid of the account
+    fn authenticate(&self, request: &SignedRequest) -> std::result::Result<u64, VerifyError> {
+        let exchange = self.exchange();
+        let account = exchange
+            .account_by_key(&request.api_key)
+            .ok_or(VerifyError::InvalidKey)?;
+        verify_signature(
+            &account.auth,
+            request,
+            Utc::now().timestamp(),
+            RECV_WINDOW_SECS,
+        )?;
+        Ok(account.uid)
+    }
+
+    /// Deliver messages to WebSocket sessions
+    fn publish(&self, events: Vec<Event>) {
+        ws::publish(self, events)
+    }
+}
+
+/// Running mock server. It stops when dropped.
+pub struct MockServer {
+    shared: Arc<Shared>,
+    http_addr: SocketAddr,
+    ws_addr: SocketAddr,
+    shutdown: Vec<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Start HTTP and WebSocket servers on random local ports. The exchange lists
+    /// `default_product` and has no user accounts.
+    pub async fn start() -> Result<Self> {
+        let shared = Arc::new(Shared {
+            exchange: Mutex::new(Exchange::new()),
+            faults: Mutex::new(Faults::default()),
+            sessions: Mutex::new(HashMap::new()),
+            next_session: AtomicU64::new(0),
+        });
+
+        let service_shared = shared.clone();
+        let make_service = make_service_fn(move |_| {
+            let shared = service_shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    http::handle(shared.clone(), request)
+                }))
+            }
+        });
+        let server =
+            hyper::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make_service);
+        let http_addr = server.local_addr();
+        let (http_tx, http_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let graceful = server.with_graceful_shutdown(async {
+                let _ = http_rx.await;
+            });
+            if let Err(e) = graceful.await {
+                warn!("Mock HTTP server failed: {}", e);
+            }
+        });
+
+        let listener = tokio::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let ws_addr = listener.local_addr()?;
+        let (ws_tx, ws_rx) = oneshot::channel();
+        tokio::spawn(ws::serve(shared.clone(), listener, ws_rx));
+
+        debug!(
+            "Mock server is listening on {} (HTTP) and {} (WebSocket)",
+            http_addr, ws_addr
+        );
+        Ok(MockServer {
+            shared,
+            http_addr,
+            ws_addr,
+            shutdown: vec![http_tx, ws_tx],
+        })
+    }
+
+    /// Base URL of the REST API to use instead of `KOLLIDER_MAINNET`
+    pub fn http_url(&self) -> String {
+        format!("http://{}{}", self.http_addr, API_PREFIX)
+    }
+
+    /// URL of the WebSocket API to use instead of `KOLLIDER_WEBSOCKET`
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}{}/ws/", self.ws_addr, API_PREFIX)
+    }
+
+    /// REST client without credentials pointed to the server
+    pub fn client(&self) -> KolliderClient {
+        KolliderClient::builder()
+            .server(&self.http_url())
+            .build()
+            .expect("Failed to build HTTP client")
+    }
+
+    /// Options of the websocket worker pointed to the server
+    pub fn ws_options(&self) -> WebsocketOptions {
+        WebsocketOptions {
+            url: self.ws_url(),
+            ..WebsocketOptions::default()
+        }
+    }
+
+    /// Create account with random credentials and the balance in sats
+    pub fn add_account(&self, balance: u64) -> KolliderAuth {
+        self.shared.exchange().add_account(balance)
+    }
+
+    pub fn add_product(&self, product: Product) {
+        self.shared.exchange().add_product(product)
+    }
+
+    /// Place limit order of the market maker account, returns order id
+    pub fn add_liquidity(
+        &self,
+        symbol: &str,
+        side: OrderSide,
+        price: u64,
+        quantity: u64,
+    ) -> std::result::Result<u64, Rejection> {
+        let body = limit_order(symbol, side, price, quantity, 1);
+        let ext_order_id = Uuid::new_v4().to_string();
+        let (order, events) =
+            self.shared
+                .exchange()
+                .submit(MARKET_MAKER_UID, &body, &ext_order_id)?;
+        self.shared.publish(events);
+        Ok(order.order_id)
+    }
+
+    /// Cancel order of the market maker account
+    pub fn remove_liquidity(
+        &self,
+        symbol: &str,
+        order_id: u64,
+    ) -> std::result::Result<(), Rejection> {
+        let events = self
+            .shared
+            .exchange()
+            .cancel(MARKET_MAKER_UID, symbol, order_id)?;
+        self.shared.publish(events);
+        Ok(())
+    }
+
+    /// Set value of the index, e.x. `.BTCUSD`, and send it to subscribers of `index_values`
+    pub fn set_index_price(&self, symbol: &str, value: f64) {
+        let events = self.shared.exchange().set_index_price(symbol, value);
+        self.shared.publish(events);
+    }
+
+    /// Set index price history of the symbol that is served by `/market/historic_index_prices`
+    pub fn set_history(&self, symbol: &str, items: Vec<HistoryItem>) {
+        self.shared.exchange().set_history(symbol, items)
+    }
+
+    /// Send the message to every connected WebSocket session
+    pub fn broadcast(&self, msg: KolliderTaggedMsg) {
+        ws::broadcast(&self.shared, &msg)
+    }
+
+    /// Add rule that makes matching requests fail
+    pub fn inject(&self, rule: FaultRule) {
+        self.shared.faults.lock().unwrap().push(rule)
+    }
+
+    pub fn clear_faults(&self) {
+        self.shared.faults.lock().unwrap().clear()
+    }
+
+    /// Number of connected WebSocket sessions
+    pub fn session_count(&self) -> usize {
+        self.shared.sessions.lock().unwrap().len()
+    }
+
+    /// Direct access to the exchange state. Changes made through it are not sent to sessions.
+    pub fn exchange(&self) -> MutexGuard<'_, Exchange> {
+        self.shared.exchange()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        for tx in self.shutdown.drain(..) {
+            let _ = tx.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::{OrderBook, OrderBookLevel};
+    use crate::kollider::client::clock::ServerClock;
+    use crate::kollider::client::error::Error;
+    use crate::kollider::websocket::client::kollider_websocket_with;
+    use crate::kollider::websocket::data::{
+        make_signed_auth, ChannelName, KolliderMsg, OrderTag, SubscribeTag,
+    };
+    use crate::kollider::websocket::error::Error as WebsocketError;
+    use futures::channel::mpsc::UnboundedReceiver;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    const SYMBOL: &str = "BTCUSD.PERP";
+
+    #[tokio::test]
+    async fn test_rest_trading() {
+        let server = MockServer::start().await.unwrap();
+        let auth = server.add_account(1_000_000);
+        let ask_id = server
+            .add_liquidity(SYMBOL, OrderSide::Ask, 500000, 100)
+            .unwrap();
+
+        let mut client = server.client();
+        client.set_auth(auth);
+        let products = client.market_products().await.unwrap();
+        assert!(products.contains_key(SYMBOL));
+
+        let order = limit_order(SYMBOL, OrderSide::Bid, 500000, 10, 1);
+        let created = client.create_order(&order).await.unwrap();
+        assert_eq!(created.quantity, 10);
+        let positions = client.positions().await.unwrap();
+        assert_eq!(positions[SYMBOL].quantity, 10.0);
+        assert_eq!(positions[SYMBOL].side, OrderSide::Bid);
+
+        let resting = limit_order(SYMBOL, OrderSide::Bid, 490000, 5, 1);
+        let created = client.create_order(&resting).await.unwrap();
+        let open = client.open_orders().await.unwrap();
+        assert_eq!(open[SYMBOL].len(), 1);
+        client.cancel_order(SYMBOL, created.order_id).await.unwrap();
+        assert!(client
+            .open_orders()
+            .await
+            .unwrap()
+            .get(SYMBOL)
+            .is_none_or(|orders| orders.is_empty()));
+
+        server.remove_liquidity(SYMBOL, ask_id).unwrap();
+        let book = client
+            .market_orderbook(OrderBookLevel::Level2, SYMBOL)
+            .await
+            .unwrap();
+        match book.book {
+            OrderBook::Level2(book) => assert!(book.asks.is_empty() && book.bids.is_empty()),
+            other => panic!("Expected level 2 book, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rest_clock_synced_before_signing() {
+        let server = MockServer::start().await.unwrap();
+        let clock = ServerClock::default();
+        let mut client = KolliderClient::builder()
+            .server(&server.http_url())
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        client.set_auth(server.add_account(1_000_000));
+        client.positions().await.unwrap();
+        // Measured by the sync before signing and by the request itself
+        assert_eq!(clock.samples(), 2);
+        client.positions().await.unwrap();
+        assert_eq!(clock.samples(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rest_auth_and_faults() {
+        let server = MockServer::start().await.unwrap();
+        server.add_account(1_000_000);
+
+        let mut client = server.client();
+        client.set_auth(KolliderAuth::new("unknown", "c2VjcmV0", "secret").unwrap());
+        match client.positions().await {
+            Err(Error::ServerErr(e)) => assert!(e.to_string().contains("InvalidKey")),
+            other => panic!("Expected auth error, got {:?}", other),
+        }
+
+        client.set_auth(server.add_account(1_000_000));
+        server.inject(FaultRule::http("POST", "/orders", Fault::Status(503)).times(1));
+        let order = limit_order(SYMBOL, OrderSide::Bid, 490000, 1, 1);
+        assert!(client.create_order(&order).await.is_err());
+        assert!(client.create_order(&order).await.is_ok());
+    }
+
+    async fn next_matching<F>(rx: &mut UnboundedReceiver<KolliderMsg>, matches: F) -> KolliderMsg
+    where
+        F: Fn(&KolliderMsg) -> bool,
+    {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let msg = rx.next().await.expect("Websocket worker is stopped");
+                if matches(&msg) {
+                    return msg;
+                }
+            }
+        })
+        .await
+        .expect("Timeout waiting for message")
+    }
+
+    #[tokio::test]
+    async fn test_websocket_trading() {
+        let server = MockServer::start().await.unwrap();
+        let auth = server.add_account(1_000_000);
+        server
+            .add_liquidity(SYMBOL, OrderSide::Ask, 500000, 100)
+            .unwrap();
+
+        let (out_tx, out_rx) = futures::channel::mpsc::unbounded();
+        let (in_tx, mut in_rx) = futures::channel::mpsc::unbounded();
+        let worker = tokio::spawn(kollider_websocket_with(server.ws_options(), out_rx, in_tx));
+
+        out_tx
+            .unbounded_send(make_signed_auth(&auth).unwrap())
+            .unwrap();
+        next_matching(&mut in_rx, |msg| {
+            matches!(msg, KolliderMsg::Tagged(KolliderTaggedMsg::Authenticate { message }) if message == "success")
+        })
+        .await;
+
+        out_tx
+            .unbounded_send(KolliderMsg::Subscribe {
+                _type: SubscribeTag::Tag,
+                symbols: vec![SYMBOL.to_owned()],
+                channels: vec![ChannelName::OrderBookLevel2],
+            })
+            .unwrap();
+        next_matching(&mut in_rx, |msg| {
+            matches!(
+                msg,
+                KolliderMsg::Tagged(KolliderTaggedMsg::OrderBookLevel2(_))
+            )
+        })
+        .await;
+        assert_eq!(server.session_count(), 1);
+
+        let body = limit_order(SYMBOL, OrderSide::Bid, 500000, 7, 1);
+        out_tx
+            .unbounded_send(KolliderMsg::Order {
+                _type: OrderTag::Tag,
+                price: body.price,
+                quantity: body.quantity,
+                symbol: body.symbol,
+                leverage: body.leverage,
+                side: body.side,
+                margin_type: body.margin_type,
+                order_type: body.order_type,
+                settlement_type: body.settlement_type,
+                ext_order_id: "ws-order".to_owned(),
+            })
+            .unwrap();
+        let fill = next_matching(&mut in_rx, |msg| {
+            matches!(
+                msg,
+                KolliderMsg::Tagged(KolliderTaggedMsg::Fill {
+                    is_maker: false,
+                    ..
+                })
+            )
+        })
+        .await;
+        match fill {
+            KolliderMsg::Tagged(KolliderTaggedMsg::Fill {
+                ext_order_id,
+                quantity,
+                ..
+            }) => {
+                assert_eq!(ext_order_id, "ws-order");
+                assert_eq!(quantity, 7);
+            }
+            _ => unreachable!(),
+        }
+
+        // Unknown messages have no wire format, the worker refuses to send them
+        out_tx
+            .unbounded_send(KolliderMsg::Unknown {
+                type_tag: Some("unknown".to_owned()),
+                raw: serde_json::json!({}),
+            })
+            .unwrap();
+        assert!(matches!(
+            worker.await.unwrap(),
+            Err(WebsocketError::UnknownOutgoing(Some(_)))
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+use super::exchange::{Event, Recipient, Rejection};
+use super::fault::Fault;
+use super::Shared;
+use crate::kollider::api::{OrderBody, Symbol};
+use crate::kollider::client::signer::SignedRequest;
+use crate::kollider::websocket::data::{ChannelName, KolliderMsg, KolliderTaggedMsg, OrderReject};
+use futures::channel::mpsc::UnboundedSender;
+use futures::channel::oneshot;
+use futures::{SinkExt, StreamExt};
+use log::*;
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+/// Connected WebSocket client
+pub(crate) struct Session {
+    uid: Option<u64>,
+    subscriptions: HashSet<(ChannelName, Symbol)>,
+    tx: UnboundedSender<Message>,
+}
+
+impl Session {
+    fn receives(&self, recipient: &Recipient) -> bool {
+        match recipient {
+            Recipient::User(uid) => self.uid == Some(*uid),
+            Recipient::Channel(channel, symbol) => {
+                self.subscriptions.contains(&(*channel, symbol.clone()))
+            }
+        }
+    }
+}
+
+fn encode(msg: &KolliderTaggedMsg) -> Message {
+    Message::text(serde_json::to_string(msg).expect("Mock messages are serializable"))
+}
+
+pub(crate) fn publish(shared: &Shared, events: Vec<Event>) {
+    let sessions = shared.sessions.lock().unwrap();
+    for (recipient, msg) in events {
+        let frame = encode(&msg);
+        for session in sessions.values().filter(|s| s.receives(&recipient)) {
+            let _ = session.tx.unbounded_send(frame.clone());
+        }
+    }
+}
+
+pub(crate) fn broadcast(shared: &Shared, msg: &KolliderTaggedMsg) {
+    let frame = encode(msg);
+    for session in shared.sessions.lock().unwrap().values() {
+        let _ = session.tx.unbounded_send(frame.clone());
+    }
+}
+
+/// Accept connections until shutdown, then close all sessions
+pub(crate) async fn serve(
+    shared: Arc<Shared>,
+    listener: TcpListener,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(session(shared.clone(), stream));
+                }
+                Err(e) => warn!("Mock WebSocket server failed to accept connection: {}", e),
+            },
+            _ = &mut shutdown => break,
+        }
+    }
+    for (_, session) in shared.sessions.lock().unwrap().drain() {
+        let _ = session.tx.unbounded_send(Message::Close(None));
+    }
+}
+
+async fn session(shared: Arc<Shared>, stream: TcpStream) {
+    let ws_stream = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            warn!("Mock WebSocket handshake failed: {}", e);
+            return;
+        }
+    };
+    let (mut write, mut read) = ws_stream.split();
+    let (tx, mut rx) = futures::channel::mpsc::unbounded::<Message>();
+    let id = shared.next_session.fetch_add(1, Ordering::SeqCst);
+    shared.sessions.lock().unwrap().insert(
+        id,
+        Session {
+            uid: None,
+            subscriptions: HashSet::new(),
+            tx: tx.clone(),
+        },
+    );
+
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.next().await {
+            let close = message.is_close();
+            if write.send(message).await.is_err() || close {
+                break;
+            }
+        }
+        let _ = write.close().await;
+    });
+
+    while let Some(Ok(message)) = read.next().await {
+        match message {
+            Message::Text(text) if !handle_frame(&shared, id, &tx, &text).await => break,
+            Message::Close(_) => break,
+            _ => (),
+        }
+    }
+    shared.sessions.lock().unwrap().remove(&id);
+    drop(tx);
+    let _ = writer.await;
+    debug!("Mock WebSocket session {} is closed", id);
+}
+
+/// Apply injected faults and handle the message. Returns `false` when the session should close.
+async fn handle_frame(shared: &Shared, id: u64, tx: &UnboundedSender<Message>, text: &str) -> bool {
+    let reply = |msg: KolliderTaggedMsg| {
+        let _ = tx.unbounded_send(encode(&msg));
+    };
+    let type_tag = serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .and_then(|v| v.get("type")?.as_str().map(|t| t.to_owned()));
+    let fault = shared.faults.lock().unwrap().take_ws(type_tag.as_deref());
+    let mut forced_rejection = None;
+    match fault {
+        Some(Fault::Delay(duration)) => tokio::time::sleep(duration).await,
+        Some(Fault::Status(code)) => {
+            reply(KolliderTaggedMsg::Error(format!("Status {}", code)));
+            return true;
+        }
+        Some(Fault::Error { msg, .. }) => {
+            reply(KolliderTaggedMsg::Error(msg));
+            return true;
+        }
+        Some(Fault::Malformed) => {
+            let _ = tx.unbounded_send(Message::text("{\"type\": "));
+            return true;
+        }
+        Some(Fault::Disconnect) => {
+            let _ = tx.unbounded_send(Message::Close(None));
+            return false;
+        }
+        Some(Fault::RejectOrder(reason)) => forced_rejection = Some(reason),
+        None => (),
+    }
+
+    let msg: KolliderMsg = match serde_json::from_str(text) {
+        Ok(msg) => msg,
+        Err(e) => {
+            reply(KolliderTaggedMsg::Error(format!(
+                "Failed to parse message: {}",
+                e
+            )));
+            return true;
+        }
+    };
+    let (replies, events) = respond(shared, id, msg, forced_rejection);
+    for msg in replies {
+        reply(msg);
+    }
+    shared.publish(events);
+    true
+}
+
+/// Messages to the session itself and to other recipients
+type Response = (Vec<KolliderTaggedMsg>, Vec<Event>);
+
+fn respond(
+    shared: &Shared,
+    id: u64,
+    msg: KolliderMsg,
+    forced_rejection: Option<OrderReject>,
+) -> Response {
+    let uid = shared.sessions.lock().unwrap().get(&id).and_then(|s| s.uid);
+    let error = |msg: String| (vec![KolliderTaggedMsg::Error(msg)], vec![]);
+    match msg {
+        KolliderMsg::Subscribe {
+            symbols, channels, ..
+        } => {
+            let mut replies = vec![];
+            let exchange = shared.exchange();
+            let mut sessions = shared.sessions.lock().unwrap();
+            let session = match sessions.get_mut(&id) {
+                Some(session) => session,
+                None => return (vec![], vec![]),
+            };
+            for channel in &channels {
+                for symbol in &symbols {
+                    session.subscriptions.insert((*channel, symbol.clone()));
+                    let snapshot = match channel {
+                        ChannelName::OrderBookLevel1 => exchange
+                            .level1_snapshot(symbol)
+                            .map(KolliderTaggedMsg::OrderBookLevel1),
+                        ChannelName::OrderBookLevel2 => exchange
+                            .level2_snapshot(symbol)
+                            .map(KolliderTaggedMsg::OrderBookLevel2),
+                        ChannelName::Ticker => {
+                            exchange.ticker(symbol).map(KolliderTaggedMsg::Ticker)
+                        }
+                        ChannelName::IndexValues => exchange.index_value_msg(symbol),
+                        ChannelName::OrderBookLevel3 | ChannelName::Matches => None,
+                    };
+                    replies.extend(snapshot);
+                }
+            }
+            (replies, vec![])
+        }
+        KolliderMsg::Unsubscribe {
+            symbols, channels, ..
+        } => {
+            if let Some(session) = shared.sessions.lock().unwrap().get_mut(&id) {
+                for channel in &channels {
+                    for symbol in &symbols {
+                        session.subscriptions.remove(&(*channel, symbol.clone()));
+                    }
+                }
+            }
+            (vec![], vec![])
+        }
+        KolliderMsg::UserAuth {
+            token,
+            passphrase,
+            signature,
+            timestamp,
+            ..
+        } => {
+            let request = SignedRequest::ws_auth(&token, &passphrase, &timestamp, &signature);
+            match shared.authenticate(&request) {
+                Ok(uid) => {
+                    if let Some(session) = shared.sessions.lock().unwrap().get_mut(&id) {
+                        session.uid = Some(uid);
+                    }
+                    let success = KolliderTaggedMsg::Authenticate {
+                        message: "success".to_owned(),
+                    };
+                    (vec![success], vec![])
+                }
+                Err(e) => error(format!("Authentication failed: {}", e)),
+            }
+        }
+        KolliderMsg::GetTicker { symbol, .. } => match shared.exchange().ticker(&symbol) {
+            Some(ticker) => (vec![KolliderTaggedMsg::Ticker(ticker)], vec![]),
+            None => error(Rejection::UnknownSymbol(symbol).to_string()),
+        },
+        KolliderMsg::TradableProducts { .. } => {
+            let symbols = shared.exchange().products().clone();
+            (
+                vec![KolliderTaggedMsg::TradableProducts { symbols }],
+                vec![],
+            )
+        }
+        msg => match uid {
+            Some(uid) => respond_private(shared, uid, msg, forced_rejection),
+            None => error("Not authenticated".to_owned()),
+        },
+    }
+}
+
+fn respond_private(
+    shared: &Shared,
+    uid: u64,
+    msg: KolliderMsg,
+    forced_rejection: Option<OrderReject>,
+) -> Response {
+    let mut exchange = shared.exchange();
+    match msg {
+        KolliderMsg::Order {
+            price,
+            quantity,
+            symbol,
+            leverage,
+            side,
+            margin_type,
+            order_type,
+            settlement_type,
+            ext_order_id,
+            ..
+        } => {
+            let body = OrderBody {
+                leverage,
+                margin_type,
+                order_type,
+                price,
+                quantity,
+                settlement_type,
+                side,
+                symbol,
+            };
+            let result = match forced_rejection {
+                Some(reason) => Err(Rejection::Rejected(reason)),
+                None => exchange.submit(uid, &body, &ext_order_id),
+            };
+            match result {
+                Ok((_, events)) => (vec![], events),
+                Err(Rejection::Rejected(reason)) => (
+                    vec![KolliderTaggedMsg::OrderRejection {
+                        ext_order_id,
+                        order_id: 0,
+                        reason,
+                    }],
+                    vec![],
+                ),
+                Err(e) => (vec![KolliderTaggedMsg::Error(e.to_string())], vec![]),
+            }
+        }
+        KolliderMsg::CancelOrder {
+            order_id, symbol, ..
+        } => match exchange.cancel(uid, &symbol, order_id) {
+            Ok(events) => (vec![], events),
+            Err(_) => (
+                vec![KolliderTaggedMsg::OrderNotFound { order_id, symbol }],
+                vec![],
+            ),
+        },
+        KolliderMsg::ChangeLeverage {
+            symbol, leverage, ..
+        } => match exchange.change_leverage(uid, &symbol, leverage) {
+            Ok(events) => (vec![], events),
+            Err(e) => (vec![KolliderTaggedMsg::Error(e.to_string())], vec![]),
+        },
+        KolliderMsg::FetchOpenOrders { .. } => (vec![exchange.open_orders_msg(uid)], vec![]),
+        KolliderMsg::FetchPositions { .. } => (vec![exchange.positions_msg(uid)], vec![]),
+        KolliderMsg::FetchBalances { .. } => (vec![exchange.balances_msg(uid)], vec![]),
+        _ => (
+            vec![KolliderTaggedMsg::Error("Unsupported message".to_owned())],
+            vec![],
+        ),
+    }
+}
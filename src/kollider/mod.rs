@@ -1,9 +1,29 @@
 pub mod api;
+#[cfg(feature = "ws")]
+pub mod backend;
+#[cfg(feature = "ws")]
+pub mod backtest;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod calc;
 pub mod client;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "journal")]
+pub mod journal;
+#[cfg(feature = "mock-server")]
+pub mod mock;
+#[cfg(feature = "ws")]
+pub mod paper;
+#[cfg(feature = "ws")]
+pub mod strategy;
 #[cfg(feature = "ws")]
 pub mod websocket;
 
 #[cfg(feature = "ws")]
+#[allow(ambiguous_glob_reexports)]
 pub use self::websocket::*;
+#[allow(ambiguous_glob_reexports)]
 pub use api::*;
+#[allow(ambiguous_glob_reexports)]
 pub use client::*;
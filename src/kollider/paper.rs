@@ -0,0 +1,1180 @@
+//! Paper trading: orders are filled locally against the live order books and tickers, so
+//! strategies can run on real market data without risking funds. Feed the client with messages
+//! of the WebSocket worker and read the same private messages (`fill`, `trade`, `positions`,
+//! `balances`, ...) from `PaperTradingClient::events`.
+//!
+//! Model of the exchange:
+//! - market orders and crossing limit orders take liquidity from the local copy of the book;
+//! - resting orders are filled at their price when the opposite side of the book or the last
+//!   trade of the ticker goes through it;
+//! - initial margin is the value of the order divided by leverage, but not less than
+//!   `Product::base_margin` share of it, positions are liquidated at the bankruptcy price when
+//!   the mark price reaches the liquidation price, see `calc`;
+//! - mark price is the index of the underlying symbol or the last price of the ticker;
+//! - fees are zero, funding is applied with `PaperTradingClient::apply_funding`.
+use crate::kollider::api::{
+    FillDetails, MarginType, OrderBody, OrderCreated, OrderDetails, OrderPrediction, OrderSide,
+    OrderType, PositionDetails, Product, SettlementType, Symbol, Ticker,
+};
+use crate::kollider::calc::{
+    average_entry_price, bankruptcy_price, initial_margin, initial_margin_rate, liquidation_price,
+    pnl, position_value,
+};
+use crate::kollider::websocket::book::LocalOrderBook;
+use crate::kollider::websocket::data::{
+    BalancesCash, KolliderMsg, KolliderTaggedMsg, OrderReject, Position, WrappedPrice,
+};
+use crate::kollider::websocket::oneshot::Balances;
+use chrono::prelude::*;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use log::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// User id of the paper account in orders, fills and positions
+pub const PAPER_UID: u64 = 0;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PaperError {
+    #[error("Unknown symbol {0}")]
+    UnknownSymbol(Symbol),
+    #[error("Order quantity must be positive")]
+    InvalidQuantity,
+    #[error("Leverage {0} is out of range")]
+    InvalidLeverage(u64),
+    #[error("Order is rejected: {0:?}")]
+    Rejected(OrderReject),
+    #[error("Order {0} for {1} is not found")]
+    OrderNotFound(u64, Symbol),
+}
+
+/// Alias for a `Result` with the error type `PaperError`.
+pub type Result<T> = std::result::Result<T, PaperError>;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct PaperPosition {
+    /// Positive for long and negative for short positions
+    quantity: i64,
+    entry_price: f64,
+    entry_time: Option<u64>,
+    leverage: u64,
+    /// Isolated margin locked in the position
+    margin: f64,
+    rpnl: f64,
+    funding: f64,
+    timestamp: u64,
+}
+
+impl PaperPosition {
+    fn side(&self) -> OrderSide {
+        if self.quantity >= 0 {
+            OrderSide::Bid
+        } else {
+            OrderSide::Ask
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
+
+fn remaining(order: &OrderDetails) -> u64 {
+    order.quantity - order.filled as u64
+}
+
+/// Whether both tickers report the same last trade
+fn same_last_trade(a: &Ticker, b: &Ticker) -> bool {
+    a.last_price == b.last_price && a.last_quantity == b.last_quantity && a.last_side == b.last_side
+}
+
+/// Levels of the side that an order on the other side at `limit` price crosses, best first
+fn crossing_levels(book: &LocalOrderBook, taker: OrderSide, limit: Option<u64>) -> Vec<(u64, u64)> {
+    book.levels(taker.inverse())
+        .into_iter()
+        .take_while(|(price, _)| match (taker, limit) {
+            (_, None) => true,
+            (OrderSide::Bid, Some(limit)) => *price <= limit,
+            (OrderSide::Ask, Some(limit)) => *price >= limit,
+        })
+        .collect()
+}
+
+/// Remove traded quantity from the local copy of the book until the next update
+fn consume_level(book: &mut LocalOrderBook, side: OrderSide, price: u64, quantity: u64) {
+    let levels = match side {
+        OrderSide::Ask => &mut book.asks,
+        OrderSide::Bid => &mut book.bids,
+    };
+    if let Some(level) = levels.get_mut(&price) {
+        *level = level.saturating_sub(quantity);
+        if *level == 0 {
+            levels.remove(&price);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PaperState {
+    products: HashMap<Symbol, Product>,
+    books: HashMap<Symbol, LocalOrderBook>,
+    tickers: HashMap<Symbol, Ticker>,
+    index_prices: HashMap<Symbol, f64>,
+    /// Wallet balance including realized PnL and funding
+    cash: f64,
+    orders: BTreeMap<u64, OrderDetails>,
+    open_orders: BTreeSet<u64>,
+    fills: Vec<FillDetails>,
+    positions: HashMap<Symbol, PaperPosition>,
+    next_order_id: u64,
+    events: Option<UnboundedSender<KolliderMsg>>,
+}
+
+/// Client that trades against local simulation of the exchange fed by live market data.
+/// Clones share the same account.
+#[derive(Debug, Clone)]
+pub struct PaperTradingClient {
+    state: Arc<Mutex<PaperState>>,
+}
+
+impl PaperTradingClient {
+    /// Account with `balance` in sats that can trade the products
+    pub fn new(products: HashMap<Symbol, Product>, balance: u64) -> Self {
+        PaperTradingClient {
+            state: Arc::new(Mutex::new(PaperState {
+                products,
+                books: HashMap::new(),
+                tickers: HashMap::new(),
+                index_prices: HashMap::new(),
+                cash: balance as f64,
+                orders: BTreeMap::new(),
+                open_orders: BTreeSet::new(),
+                fills: vec![],
+                positions: HashMap::new(),
+                next_order_id: 0,
+                events: None,
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, PaperState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Stream of private messages in the same format as the WebSocket worker produces.
+    /// Only the last returned receiver gets messages.
+    pub fn events(&self) -> UnboundedReceiver<KolliderMsg> {
+        let (tx, rx) = unbounded();
+        self.state().events = Some(tx);
+        rx
+    }
+
+    /// Update market data from the message of the WebSocket worker and fill orders that it
+    /// crosses. Order book, ticker and index value messages are used, others are ignored.
+    pub fn on_message(&self, msg: &KolliderMsg) {
+        let mut state = self.state();
+        match msg {
+            KolliderMsg::Tagged(KolliderTaggedMsg::OrderBookLevel2(update)) => {
+                let book = state.book_mut(&update.symbol);
+                if let Err(e) = book.apply_level2(update) {
+                    warn!("Paper trading book is not updated: {}", e);
+                }
+                state.match_resting(&update.symbol);
+            }
+            KolliderMsg::Tagged(KolliderTaggedMsg::OrderBookLevel1(update)) => {
+                let book = state.book_mut(&update.symbol);
+                if let Err(e) = book.apply_level1(update) {
+                    warn!("Paper trading book is not updated: {}", e);
+                }
+                state.match_resting(&update.symbol);
+            }
+            KolliderMsg::Tagged(KolliderTaggedMsg::Ticker(ticker)) => {
+                let previous = state.tickers.insert(ticker.symbol.clone(), ticker.clone());
+                // Tickers are repeated without new trades, match only a trade we haven't seen
+                if !previous.is_some_and(|previous| same_last_trade(&previous, ticker)) {
+                    state.match_last_trade(ticker);
+                }
+                state.check_liquidations();
+            }
+            KolliderMsg::Tagged(KolliderTaggedMsg::IndexValues(index)) => {
+                state.index_prices.insert(index.symbol.clone(), index.value);
+                state.check_liquidations();
+            }
+            _ => (),
+        }
+    }
+
+    pub async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated> {
+        let order = self.state().submit(body)?;
+        Ok(OrderCreated {
+            timestamp: order.timestamp,
+            order_id: order.order_id,
+            ext_order_id: order.ext_order_id,
+            uid: order.uid,
+            symbol: order.symbol,
+            quantity: order.quantity,
+            order_type: order.order_type,
+            price: order.price,
+            leverage: order.leverage,
+        })
+    }
+
+    pub async fn order_prediction(&self, body: &OrderBody) -> Result<OrderPrediction> {
+        let state = self.state();
+        let product = state.validate(body)?;
+        let price = state.expected_price(&product, body);
+        let value = position_value(&product, price, body.quantity);
+        let margin = state.required_margin(&product, body, price);
+        let rejection_reason = if margin > state.available() {
+            Some(format!("{:?}", OrderReject::NotEnoughAvailableBalance))
+        } else {
+            None
+        };
+        Ok(OrderPrediction {
+            uid: PAPER_UID,
+            ext_id: Uuid::new_v4().to_string(),
+            margin_required: margin,
+            value,
+            exchange_fee: 0.0,
+            estimated_liquidation_price: liquidation_price(
+                &product,
+                body.side,
+                price,
+                body.quantity,
+                initial_margin(&product, price, body.quantity, body.leverage),
+            ),
+            rejection_reason,
+        })
+    }
+
+    pub async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<()> {
+        self.state().cancel(symbol, order_id)
+    }
+
+    pub async fn orders(
+        &self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        limit: usize,
+    ) -> Result<Vec<OrderDetails>> {
+        let range = start.timestamp_millis() as u64..=end.timestamp_millis() as u64;
+        Ok(self
+            .state()
+            .orders
+            .values()
+            .filter(|order| order.symbol == symbol && range.contains(&order.timestamp))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    pub async fn open_orders(&self) -> Result<HashMap<Symbol, Vec<OrderDetails>>> {
+        let state = self.state();
+        let mut orders: HashMap<Symbol, Vec<OrderDetails>> = HashMap::new();
+        for order in state.resting_orders() {
+            orders
+                .entry(order.symbol.clone())
+                .or_default()
+                .push(order.clone());
+        }
+        Ok(orders)
+    }
+
+    pub async fn fills(
+        &self,
+        symbol: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        limit: usize,
+    ) -> Result<Vec<FillDetails>> {
+        let range = start.timestamp_millis() as u64..=end.timestamp_millis() as u64;
+        Ok(self
+            .state()
+            .fills
+            .iter()
+            .filter(|fill| fill.order.symbol == symbol && range.contains(&fill.order.timestamp))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    pub async fn positions(&self) -> Result<HashMap<Symbol, PositionDetails>> {
+        Ok(self
+            .state()
+            .ws_positions()
+            .into_iter()
+            .map(|(symbol, position)| {
+                let details = PositionDetails {
+                    uid: PAPER_UID,
+                    timestamp: position.timestamp,
+                    symbol: symbol.clone(),
+                    upnl: position.upnl.round() as i64,
+                    leverage: position.leverage,
+                    entry_price: position.entry_price,
+                    side: position.side.unwrap_or(OrderSide::Bid),
+                    quantity: position.quantity,
+                    liq_price: position.liq_price,
+                    open_order_ids: position
+                        .open_order_ids
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect(),
+                };
+                (symbol, details)
+            })
+            .collect())
+    }
+
+    pub async fn balances(&self) -> Result<Balances> {
+        let state = self.state();
+        Ok(Balances {
+            cash: BalancesCash {
+                kkp: 0.0,
+                sat: state.available(),
+            },
+            cross_margin: 0.0,
+            isolated_margin: state.position_margins(),
+            order_margin: state.order_margins(),
+        })
+    }
+
+    /// Pay funding of the position in the symbol. Positive rate means that longs pay shorts
+    /// `rate` share of the mark value of the position.
+    pub fn apply_funding(&self, symbol: &str, rate: f64) {
+        self.state().apply_funding(symbol, rate)
+    }
+}
+
+impl PaperState {
+    fn emit(&mut self, msg: KolliderTaggedMsg) {
+        if let Some(tx) = &self.events {
+            if tx.unbounded_send(KolliderMsg::Tagged(msg)).is_err() {
+                self.events = None;
+            }
+        }
+    }
+
+    fn emit_account(&mut self) {
+        let positions = self.ws_positions();
+        self.emit(KolliderTaggedMsg::Positions { positions });
+        let wrap = |margins: HashMap<Symbol, f64>| {
+            margins
+                .into_iter()
+                .map(|(symbol, margin)| (symbol, WrappedPrice(margin)))
+                .collect()
+        };
+        let balances = KolliderTaggedMsg::Balances {
+            cash: BalancesCash {
+                kkp: 0.0,
+                sat: self.available(),
+            },
+            cross_margin: 0.0,
+            isolated_margin: wrap(self.position_margins()),
+            order_margin: wrap(self.order_margins()),
+        };
+        self.emit(balances);
+    }
+
+    fn book_mut(&mut self, symbol: &str) -> &mut LocalOrderBook {
+        self.books
+            .entry(symbol.to_owned())
+            .or_insert_with(|| LocalOrderBook::new(symbol))
+    }
+
+    fn product(&self, symbol: &str) -> Result<Product> {
+        self.products
+            .get(symbol)
+            .cloned()
+            .ok_or_else(|| PaperError::UnknownSymbol(symbol.to_owned()))
+    }
+
+    fn validate(&self, body: &OrderBody) -> Result<Product> {
+        let product = self.product(&body.symbol)?;
+        if body.quantity == 0 {
+            return Err(PaperError::InvalidQuantity);
+        }
+        if body.leverage == 0 || body.leverage as f64 > product.max_leverage {
+            return Err(PaperError::InvalidLeverage(body.leverage));
+        }
+        Ok(product)
+    }
+
+    fn resting_orders(&self) -> impl Iterator<Item = &OrderDetails> {
+        self.open_orders
+            .iter()
+            .filter_map(move |id| self.orders.get(id))
+    }
+
+    /// Price that a new order is expected to trade at
+    fn expected_price(&self, product: &Product, body: &OrderBody) -> f64 {
+        if body.order_type == OrderType::Limit {
+            return product.human_price(body.price);
+        }
+        let best = self
+            .books
+            .get(&body.symbol)
+            .and_then(|book| match body.side {
+                OrderSide::Bid => book.best_ask(),
+                OrderSide::Ask => book.best_bid(),
+            });
+        match best {
+            Some((price, _)) => product.human_price(price),
+            None => self
+                .tickers
+                .get(&body.symbol)
+                .map_or(product.human_last_price(), |ticker| ticker.last_price),
+        }
+    }
+
+    /// Price that positions are valued at: index of the underlying or the last traded price
+    fn mark_price(&self, product: &Product) -> Option<f64> {
+        self.index_prices
+            .get(&product.underlying_symbol)
+            .or_else(|| self.tickers.get(&product.symbol).map(|t| &t.last_price))
+            .copied()
+    }
+
+    /// Margin for the part of the order that increases the position
+    fn required_margin(&self, product: &Product, body: &OrderBody, price: f64) -> f64 {
+        let closing = self
+            .positions
+            .get(&body.symbol)
+            .filter(|position| position.quantity != 0 && position.side() != body.side)
+            .map_or(0, |position| position.quantity.unsigned_abs());
+        let opening = body.quantity.saturating_sub(closing);
+        initial_margin(product, price, opening, body.leverage)
+    }
+
+    fn position_margins(&self) -> HashMap<Symbol, f64> {
+        self.positions
+            .iter()
+            .filter(|(_, position)| position.quantity != 0)
+            .map(|(symbol, position)| (symbol.clone(), position.margin))
+            .collect()
+    }
+
+    fn order_margins(&self) -> HashMap<Symbol, f64> {
+        let mut margins = HashMap::new();
+        for order in self.resting_orders() {
+            if let Some(product) = self.products.get(&order.symbol) {
+                let price = product.human_price(order.price);
+                *margins.entry(order.symbol.clone()).or_insert(0.0) +=
+                    initial_margin(product, price, remaining(order), order.leverage);
+            }
+        }
+        margins
+    }
+
+    /// Balance that is not locked in positions and open orders
+    fn available(&self) -> f64 {
+        self.cash
+            - self.position_margins().values().sum::<f64>()
+            - self.order_margins().values().sum::<f64>()
+    }
+
+    fn submit(&mut self, body: &OrderBody) -> Result<OrderDetails> {
+        let product = self.validate(body)?;
+        let price = self.expected_price(&product, body);
+        if self.required_margin(&product, body, price) > self.available() {
+            return Err(PaperError::Rejected(OrderReject::NotEnoughAvailableBalance));
+        }
+
+        let now = now_ms();
+        self.next_order_id += 1;
+        let mut order = OrderDetails {
+            ext_order_id: Uuid::new_v4().to_string(),
+            filled: 0.0,
+            leverage: body.leverage,
+            margin_type: body.margin_type,
+            order_id: self.next_order_id,
+            order_type: body.order_type,
+            price: body.price,
+            quantity: body.quantity,
+            settlement_type: body.settlement_type,
+            side: body.side,
+            symbol: body.symbol.clone(),
+            timestamp: now,
+            uid: PAPER_UID,
+        };
+        self.emit(KolliderTaggedMsg::Received {
+            uid: PAPER_UID,
+            order_id: order.order_id,
+            price: order.price,
+            quantity: order.quantity,
+            symbol: order.symbol.clone(),
+            leverage: order.leverage,
+            order_type: order.order_type,
+            ext_order_id: order.ext_order_id.clone(),
+            timestamp: now,
+        });
+        self.emit(KolliderTaggedMsg::Open {
+            order_id: order.order_id,
+            price: order.price,
+            quantity: order.quantity,
+            symbol: order.symbol.clone(),
+            leverage: order.leverage,
+            side: order.side,
+            margin_type: order.margin_type,
+            order_type: order.order_type,
+            settlement_type: order.settlement_type,
+            ext_order_id: order.ext_order_id.clone(),
+            timestamp: now,
+            filled: 0,
+        });
+
+        let limit = match order.order_type {
+            OrderType::Limit => Some(order.price),
+            _ => None,
+        };
+        let levels = self
+            .books
+            .get(&order.symbol)
+            .map(|book| crossing_levels(book, order.side, limit))
+            .unwrap_or_default();
+        for (level_price, level_quantity) in levels {
+            let quantity = remaining(&order).min(level_quantity);
+            if quantity == 0 {
+                break;
+            }
+            consume_level(
+                self.book_mut(&body.symbol),
+                order.side.inverse(),
+                level_price,
+                quantity,
+            );
+            order.filled += quantity as f64;
+            self.fill(&product, &order, level_price, quantity, false);
+        }
+
+        let left = remaining(&order);
+        if left > 0 && order.order_type == OrderType::Limit {
+            self.open_orders.insert(order.order_id);
+        } else {
+            let reason = if left > 0 { "Cancel" } else { "Fill" };
+            self.emit(done(&order, reason, now));
+        }
+        self.orders.insert(order.order_id, order.clone());
+        self.emit_account();
+        Ok(order)
+    }
+
+    /// Fill resting orders that the opposite side of the book crosses
+    fn match_resting(&mut self, symbol: &str) {
+        let resting: Vec<u64> = self
+            .resting_orders()
+            .filter(|order| order.symbol == symbol)
+            .map(|order| order.order_id)
+            .collect();
+        let mut filled = false;
+        for order_id in resting {
+            let order = self.orders[&order_id].clone();
+            let levels = self
+                .books
+                .get(symbol)
+                .map(|book| crossing_levels(book, order.side, Some(order.price)))
+                .unwrap_or_default();
+            for (level_price, level_quantity) in levels {
+                let quantity = remaining(&self.orders[&order_id]).min(level_quantity);
+                if quantity == 0 {
+                    break;
+                }
+                consume_level(
+                    self.book_mut(symbol),
+                    order.side.inverse(),
+                    level_price,
+                    quantity,
+                );
+                self.fill_resting(order_id, quantity);
+                filled = true;
+            }
+        }
+        if filled {
+            self.emit_account();
+        }
+    }
+
+    /// Fill resting orders that the last trade went through
+    fn match_last_trade(&mut self, ticker: &Ticker) {
+        let product = match self.products.get(&ticker.symbol) {
+            Some(product) => product.clone(),
+            None => return,
+        };
+        let resting: Vec<(u64, u64)> = self
+            .resting_orders()
+            .filter(|order| order.symbol == ticker.symbol)
+            .filter(|order| {
+                let price = product.human_price(order.price);
+                match order.side {
+                    OrderSide::Bid => ticker.last_price < price,
+                    OrderSide::Ask => ticker.last_price > price,
+                }
+            })
+            .map(|order| (order.order_id, remaining(order)))
+            .collect();
+        let mut left = ticker.last_quantity;
+        for (order_id, order_remaining) in resting {
+            let quantity = order_remaining.min(left);
+            if quantity == 0 {
+                break;
+            }
+            self.fill_resting(order_id, quantity);
+            left -= quantity;
+        }
+        if left < ticker.last_quantity {
+            self.emit_account();
+        }
+    }
+
+    /// Fill resting order at its price as a maker
+    fn fill_resting(&mut self, order_id: u64, quantity: u64) {
+        let order = match self.orders.get_mut(&order_id) {
+            Some(order) => {
+                order.filled += quantity as f64;
+                order.clone()
+            }
+            None => return,
+        };
+        let product = match self.products.get(&order.symbol) {
+            Some(product) => product.clone(),
+            None => return,
+        };
+        self.fill(&product, &order, order.price, quantity, true);
+        if remaining(&order) == 0 {
+            self.open_orders.remove(&order_id);
+            self.emit(done(&order, "Fill", now_ms()));
+        }
+    }
+
+    /// Record fill of the order and update the position
+    fn fill(
+        &mut self,
+        product: &Product,
+        order: &OrderDetails,
+        price: u64,
+        quantity: u64,
+        is_maker: bool,
+    ) {
+        let now = now_ms();
+        let rpnl = self.apply_fill(product, order.side, price, quantity, order.leverage);
+        let left = remaining(order);
+        self.fills.push(FillDetails {
+            order: OrderDetails {
+                price,
+                quantity,
+                timestamp: now,
+                ..order.clone()
+            },
+            remaining: left,
+            partial: left > 0,
+            is_maker,
+            is_liquidation: false,
+            is_selftrade: false,
+        });
+        self.emit(KolliderTaggedMsg::Fill {
+            ext_order_id: order.ext_order_id.clone(),
+            is_maker,
+            is_selftrade: false,
+            leverage: order.leverage,
+            margin_type: order.margin_type,
+            order_id: order.order_id,
+            partial: left > 0,
+            price,
+            quantity,
+            side: order.side,
+            symbol: order.symbol.clone(),
+            user_id: PAPER_UID,
+        });
+        self.emit(KolliderTaggedMsg::Trade {
+            fees: 0.0,
+            is_liquidation: false,
+            is_maker,
+            leverage: order.leverage as f64,
+            margin_type: order.margin_type,
+            order_id: order.order_id,
+            price: product.human_price(price),
+            quantity,
+            rpnl,
+            settlement_type: order.settlement_type,
+            side: order.side,
+            symbol: order.symbol.clone(),
+            timestamp: now,
+        });
+    }
+
+    /// Update position after a trade and return realized PnL
+    fn apply_fill(
+        &mut self,
+        product: &Product,
+        side: OrderSide,
+        price: u64,
+        quantity: u64,
+        leverage: u64,
+    ) -> f64 {
+        let now = now_ms();
+        let price = product.human_price(price);
+        let signed = match side {
+            OrderSide::Bid => quantity as i64,
+            OrderSide::Ask => -(quantity as i64),
+        };
+        let rate = initial_margin_rate(product, leverage);
+        let position = self.positions.entry(product.symbol.clone()).or_default();
+        let mut rpnl = 0.0;
+        if position.quantity == 0 || position.quantity.signum() == signed.signum() {
+            if position.quantity == 0 {
+                position.entry_time = Some(now);
+                position.leverage = leverage;
+            }
+            position.entry_price = average_entry_price(
+                product,
+                position.entry_price,
+                position.quantity.unsigned_abs(),
+                price,
+                quantity,
+            );
+            position.margin += position_value(product, price, quantity) * rate;
+            position.quantity += signed;
+        } else {
+            let held = position.quantity.unsigned_abs();
+            let closed = held.min(quantity);
+            rpnl = pnl(product, side.inverse(), position.entry_price, price, closed);
+            position.margin -= position.margin * closed as f64 / held as f64;
+            position.quantity += signed;
+            if position.quantity == 0 {
+                position.entry_price = 0.0;
+                position.entry_time = None;
+                position.margin = 0.0;
+            } else if position.quantity.signum() == signed.signum() {
+                let opened = position.quantity.unsigned_abs();
+                position.entry_price = price;
+                position.entry_time = Some(now);
+                position.leverage = leverage;
+                position.margin = position_value(product, price, opened) * rate;
+            }
+        }
+        position.rpnl += rpnl;
+        position.timestamp = now;
+        self.cash += rpnl;
+        rpnl
+    }
+
+    fn cancel(&mut self, symbol: &str, order_id: u64) -> Result<()> {
+        let order = self
+            .orders
+            .get(&order_id)
+            .filter(|order| order.symbol == symbol && self.open_orders.contains(&order_id))
+            .cloned()
+            .ok_or_else(|| PaperError::OrderNotFound(order_id, symbol.to_owned()))?;
+        self.open_orders.remove(&order_id);
+        self.emit(done(&order, "Cancel", now_ms()));
+        self.emit_account();
+        Ok(())
+    }
+
+    fn apply_funding(&mut self, symbol: &str, rate: f64) {
+        let product = match self.products.get(symbol) {
+            Some(product) => product.clone(),
+            None => return,
+        };
+        let mark = self.mark_price(&product);
+        let position = match self.positions.get_mut(symbol) {
+            Some(position) if position.quantity != 0 => position,
+            _ => return,
+        };
+        let mark = mark.unwrap_or(position.entry_price);
+        let value = position_value(&product, mark, position.quantity.unsigned_abs());
+        let payment = match position.side() {
+            OrderSide::Bid => -rate * value,
+            OrderSide::Ask => rate * value,
+        };
+        position.funding += payment;
+        position.timestamp = now_ms();
+        self.cash += payment;
+        self.emit_account();
+    }
+
+    /// Close positions which mark price reached the liquidation price
+    fn check_liquidations(&mut self) {
+        let mut liquidated = false;
+        let symbols: Vec<Symbol> = self.positions.keys().cloned().collect();
+        for symbol in symbols {
+            let product = match self.products.get(&symbol) {
+                Some(product) => product.clone(),
+                None => continue,
+            };
+            let mark = match self.mark_price(&product) {
+                Some(mark) => mark,
+                None => continue,
+            };
+            let position = self.positions[&symbol].clone();
+            if position.quantity == 0 {
+                continue;
+            }
+            let side = position.side();
+            let quantity = position.quantity.unsigned_abs();
+            let liq_price = liquidation_price(
+                &product,
+                side,
+                position.entry_price,
+                quantity,
+                position.margin,
+            );
+            let reached = match side {
+                OrderSide::Bid => mark <= liq_price,
+                OrderSide::Ask => mark >= liq_price,
+            };
+            if reached {
+                self.liquidate(&product, &position);
+                liquidated = true;
+            }
+        }
+        if liquidated {
+            self.emit_account();
+        }
+    }
+
+    /// Close the position at the bankruptcy price, the whole margin is lost
+    fn liquidate(&mut self, product: &Product, position: &PaperPosition) {
+        let now = now_ms();
+        let side = position.side();
+        let quantity = position.quantity.unsigned_abs();
+        let price = bankruptcy_price(
+            product,
+            side,
+            position.entry_price,
+            quantity,
+            position.margin,
+        );
+        info!(
+            "Paper position {} {:?} {} is liquidated at {}",
+            product.symbol, side, quantity, price
+        );
+        let rpnl = -position.margin;
+        self.cash += rpnl;
+        if let Some(state) = self.positions.get_mut(&product.symbol) {
+            *state = PaperPosition {
+                rpnl: state.rpnl + rpnl,
+                timestamp: now,
+                ..PaperPosition::default()
+            };
+        }
+        let api_price = product.api_price(price);
+        self.fills.push(FillDetails {
+            order: OrderDetails {
+                ext_order_id: String::new(),
+                filled: quantity as f64,
+                leverage: position.leverage,
+                margin_type: MarginType::Isolated,
+                order_id: 0,
+                order_type: OrderType::Market,
+                price: api_price,
+                quantity,
+                settlement_type: SettlementType::Instant,
+                side: side.inverse(),
+                symbol: product.symbol.clone(),
+                timestamp: now,
+                uid: PAPER_UID,
+            },
+            remaining: 0,
+            partial: false,
+            is_maker: false,
+            is_liquidation: true,
+            is_selftrade: false,
+        });
+        self.emit(KolliderTaggedMsg::Trade {
+            fees: 0.0,
+            is_liquidation: true,
+            is_maker: false,
+            leverage: position.leverage as f64,
+            margin_type: MarginType::Isolated,
+            order_id: 0,
+            price,
+            quantity,
+            rpnl,
+            settlement_type: SettlementType::Instant,
+            side: side.inverse(),
+            symbol: product.symbol.clone(),
+            timestamp: now,
+        });
+    }
+
+    fn ws_positions(&self) -> HashMap<Symbol, Position> {
+        self.positions
+            .iter()
+            .filter(|(_, position)| position.quantity != 0)
+            .filter_map(|(symbol, state)| {
+                let product = self.products.get(symbol)?;
+                let side = state.side();
+                let quantity = state.quantity.unsigned_abs();
+                let mark = self.mark_price(product).unwrap_or(state.entry_price);
+                let entry_value = position_value(product, state.entry_price, quantity);
+                let open_order_ids = self
+                    .resting_orders()
+                    .filter(|order| &order.symbol == symbol)
+                    .map(|order| order.order_id)
+                    .collect();
+                let position = Position {
+                    adl_score: 0.0,
+                    bankruptcy_price: bankruptcy_price(
+                        product,
+                        side,
+                        state.entry_price,
+                        quantity,
+                        state.margin,
+                    ),
+                    entry_price: state.entry_price,
+                    entry_time: state.entry_time,
+                    entry_value,
+                    funding: state.funding,
+                    is_liquidating: false,
+                    leverage: state.leverage as f64,
+                    liq_price: liquidation_price(
+                        product,
+                        side,
+                        state.entry_price,
+                        quantity,
+                        state.margin,
+                    ),
+                    mark_value: position_value(product, mark, quantity),
+                    open_order_ids,
+                    position_id: format!("paper-{}", symbol),
+                    quantity: quantity as f64,
+                    real_leverage: if state.margin > 0.0 {
+                        entry_value / state.margin
+                    } else {
+                        0.0
+                    },
+                    rpnl: state.rpnl,
+                    side: Some(side),
+                    symbol: symbol.clone(),
+                    timestamp: state.timestamp,
+                    uid: PAPER_UID,
+                    upnl: pnl(product, side, state.entry_price, mark, quantity),
+                };
+                Some((symbol.clone(), position))
+            })
+            .collect()
+    }
+}
+
+fn done(order: &OrderDetails, reason: &str, timestamp: u64) -> KolliderTaggedMsg {
+    KolliderTaggedMsg::Done {
+        orde_type: order.order_type,
+        order_id: order.order_id,
+        reason: reason.to_owned(),
+        symbol: order.symbol.clone(),
+        timestamp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::websocket::data::{IndexValue, OrderBookLevel2, UpdateType};
+
+    const SYMBOL: &str = "BTCUSD.PERP";
+
+    fn product() -> Product {
+        Product {
+            symbol: SYMBOL.to_owned(),
+            contract_size: 1.0,
+            max_leverage: 100.0,
+            base_margin: 0.005,
+            maintenance_margin: 0.004,
+            is_inverse_priced: true,
+            price_dp: 1.0,
+            underlying_symbol: ".BTCUSD".to_owned(),
+            last_price: 500000.0,
+            tick_size: 0.5,
+            risk_limit: 100000000.0,
+        }
+    }
+
+    fn client(balance: u64) -> PaperTradingClient {
+        let products = HashMap::from([(SYMBOL.to_owned(), product())]);
+        PaperTradingClient::new(products, balance)
+    }
+
+    fn book(
+        update_type: UpdateType,
+        seq_number: u64,
+        asks: &[(u64, u64)],
+        bids: &[(u64, u64)],
+    ) -> KolliderMsg {
+        let levels = |levels: &[(u64, u64)]| {
+            levels
+                .iter()
+                .map(|(price, quantity)| (price.to_string(), *quantity))
+                .collect()
+        };
+        KolliderMsg::Tagged(KolliderTaggedMsg::OrderBookLevel2(OrderBookLevel2 {
+            asks: levels(asks),
+            bids: levels(bids),
+            seq_number,
+            symbol: SYMBOL.to_owned(),
+            update_type,
+        }))
+    }
+
+    fn order(side: OrderSide, order_type: OrderType, price: u64, quantity: u64) -> OrderBody {
+        OrderBody {
+            leverage: 10,
+            margin_type: MarginType::Isolated,
+            order_type,
+            price,
+            quantity,
+            settlement_type: SettlementType::Delayed,
+            side,
+            symbol: SYMBOL.to_owned(),
+        }
+    }
+
+    fn drain(events: &mut UnboundedReceiver<KolliderMsg>) -> Vec<KolliderTaggedMsg> {
+        let mut messages = vec![];
+        while let Ok(KolliderMsg::Tagged(msg)) = events.try_recv() {
+            messages.push(msg);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_market_and_resting_orders() {
+        let paper = client(1_000_000);
+        let mut events = paper.events();
+        paper.on_message(&book(
+            UpdateType::Snapshot,
+            1,
+            &[(500000, 10), (500100, 10)],
+            &[(499900, 10)],
+        ));
+
+        paper
+            .create_order(&order(OrderSide::Bid, OrderType::Market, 0, 15))
+            .await
+            .unwrap();
+        let tags: Vec<&str> = drain(&mut events).iter().map(|m| m.type_tag()).collect();
+        assert_eq!(
+            tags,
+            vec![
+                "received",
+                "open",
+                "fill",
+                "trade",
+                "fill",
+                "trade",
+                "done",
+                "positions",
+                "balances"
+            ]
+        );
+        let positions = paper.positions().await.unwrap();
+        assert_eq!(positions[SYMBOL].quantity, 15.0);
+        assert_eq!(positions[SYMBOL].side, OrderSide::Bid);
+        let entry = positions[SYMBOL].entry_price;
+        assert!(entry > 50000.0 && entry < 50010.0);
+
+        let resting = paper
+            .create_order(&order(OrderSide::Ask, OrderType::Limit, 501000, 15))
+            .await
+            .unwrap();
+        assert_eq!(paper.open_orders().await.unwrap()[SYMBOL].len(), 1);
+        paper.on_message(&book(UpdateType::Delta, 2, &[], &[(501500, 20)]));
+        let messages = drain(&mut events);
+        assert!(messages.iter().any(|m| matches!(
+            m,
+            KolliderTaggedMsg::Fill { order_id, is_maker: true, price: 501000, .. }
+                if *order_id == resting.order_id
+        )));
+        assert!(paper.positions().await.unwrap().is_empty());
+        let balances = paper.balances().await.unwrap();
+        assert!(balances.cash.sat > 1_000_000.0);
+        assert_eq!(
+            paper.cancel_order(SYMBOL, resting.order_id).await,
+            Err(PaperError::OrderNotFound(
+                resting.order_id,
+                SYMBOL.to_owned()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repeated_ticker() {
+        let paper = client(1_000_000);
+        let resting = paper
+            .create_order(&order(OrderSide::Bid, OrderType::Limit, 499000, 10))
+            .await
+            .unwrap();
+        let ticker = |last_price, last_quantity| {
+            KolliderMsg::Tagged(KolliderTaggedMsg::Ticker(Ticker {
+                best_ask: 49900.0,
+                best_bid: 49800.0,
+                last_price,
+                last_quantity,
+                last_side: OrderSide::Ask,
+                symbol: SYMBOL.to_owned(),
+            }))
+        };
+        let filled = |paper: &PaperTradingClient| {
+            let orders = paper.state().orders.clone();
+            orders[&resting.order_id].filled
+        };
+
+        paper.on_message(&ticker(49800.0, 3));
+        assert_eq!(filled(&paper), 3.0);
+        // The same trade is reported again
+        paper.on_message(&ticker(49800.0, 3));
+        assert_eq!(filled(&paper), 3.0);
+        paper.on_message(&ticker(49800.0, 2));
+        assert_eq!(filled(&paper), 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_margin_funding_and_liquidation() {
+        let paper = client(10_000);
+        paper.on_message(&book(UpdateType::Snapshot, 1, &[(500000, 100)], &[]));
+        let rejected = paper
+            .create_order(&order(OrderSide::Bid, OrderType::Market, 0, 100))
+            .await;
+        assert_eq!(
+            rejected,
+            Err(PaperError::Rejected(OrderReject::NotEnoughAvailableBalance))
+        );
+
+        // 5 contracts at 50000 are worth 10000 sats, 10x leverage locks 1000 sats
+        paper
+            .create_order(&order(OrderSide::Bid, OrderType::Market, 0, 5))
+            .await
+            .unwrap();
+        let balances = paper.balances().await.unwrap();
+        assert!((balances.isolated_margin[SYMBOL] - 1000.0).abs() < 1e-6);
+
+        paper.apply_funding(SYMBOL, 0.01);
+        let balances = paper.balances().await.unwrap();
+        assert!((balances.cash.sat - 8900.0).abs() < 1e-6);
+
+        let liq_price = paper.positions().await.unwrap()[SYMBOL].liq_price;
+        assert!(liq_price > 45000.0 && liq_price < 50000.0);
+        let mut events = paper.events();
+        paper.on_message(&KolliderMsg::Tagged(KolliderTaggedMsg::IndexValues(
+            IndexValue {
+                denom: "USD".to_owned(),
+                symbol: ".BTCUSD".to_owned(),
+                value: liq_price - 1.0,
+            },
+        )));
+        assert!(drain(&mut events).iter().any(|m| matches!(
+            m,
+            KolliderTaggedMsg::Trade {
+                is_liquidation: true,
+                ..
+            }
+        )));
+        assert!(paper.positions().await.unwrap().is_empty());
+        let balances = paper.balances().await.unwrap();
+        // The margin is lost, but it was not available already
+        assert!((balances.cash.sat - 8900.0).abs() < 1e-6);
+        assert!(balances.isolated_margin.is_empty());
+    }
+}
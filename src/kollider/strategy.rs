@@ -0,0 +1,776 @@
+//! Runtime for trading bots. A bot implements `Strategy` hooks and `StrategyRuntime` does the
+//! plumbing: connects the WebSocket, authenticates, subscribes to market data, keeps local
+//! order books, routes messages to the hooks and fires the timer. Orders are placed through a
+//! `TradingApi` backend, usually the REST `KolliderClient`, so they can be cancelled on
+//! shutdown even when the socket is lost. Only orders placed by the strategy are cancelled,
+//! see `CancelOnStop`.
+//!
+//! ```no_run
+//! # use kollider_api::kollider::backend::Result;
+//! # use kollider_api::kollider::client::{KolliderAuth, KolliderClient};
+//! # use kollider_api::kollider::strategy::*;
+//! # use kollider_api::kollider::websocket::data::{ChannelName, IndexValue};
+//! # use async_trait::async_trait;
+//! # use std::time::Duration;
+//! struct PrintIndex;
+//!
+//! #[async_trait]
+//! impl Strategy for PrintIndex {
+//!     async fn on_index(&mut self, _ctx: &mut StrategyContext, index: &IndexValue) -> Result<()> {
+//!         println!("{} = {}", index.symbol, index.value);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # async fn run(auth: KolliderAuth) -> std::result::Result<(), RuntimeError> {
+//! let mut client = KolliderClient::mainnet();
+//! client.set_auth(auth.clone());
+//! let options = RuntimeOptions {
+//!     subscriptions: vec![(ChannelName::IndexValues, ".BTCUSD".to_owned())],
+//!     timer: Some(Duration::from_secs(10)),
+//!     ..RuntimeOptions::default()
+//! };
+//! StrategyRuntime::new(client, options)
+//!     .auth(auth)
+//!     .run(&mut PrintIndex, async {
+//!         tokio::signal::ctrl_c().await.ok();
+//!     })
+//!     .await
+//! # }
+//! ```
+use crate::kollider::api::{Order, OrderBody, OrderCreated, Position, Symbol};
+use crate::kollider::backend::{self, TradingApi, TradingError};
+use crate::kollider::client::signer::Signer;
+use crate::kollider::websocket::book::LocalOrderBook;
+use crate::kollider::websocket::client::{
+    kollider_connect, kollider_websocket_over, WebsocketOptions,
+};
+use crate::kollider::websocket::data::{
+    make_signed_auth_async, AuthError, ChannelName, IndexValue, KolliderMsg, KolliderTaggedMsg,
+    SubscribeTag,
+};
+use crate::kollider::websocket::error::Error as WebsocketError;
+use async_trait::async_trait;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::{future, pin_mut, Future, StreamExt};
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+
+const AUTH_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum RuntimeError {
+    #[error("WebSocket failed: {0}")]
+    Websocket(#[from] WebsocketError),
+    #[error("Failed to make auth message: {0}")]
+    Auth(#[from] AuthError),
+    #[error("Authentification is rejected: {0}")]
+    AuthRejected(String),
+    #[error("No response to authentification in {0:?}")]
+    AuthTimeout(Duration),
+    #[error("{0}")]
+    Trading(#[from] TradingError),
+    #[error("WebSocket session is closed")]
+    Disconnected,
+}
+
+/// Alias for a `Result` with the error type `RuntimeError`.
+pub type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Hooks of a trading bot. Errors of the hooks are logged and do not stop the bot, except for
+/// `on_start`.
+#[async_trait]
+pub trait Strategy: Send {
+    /// Called once after authentification and subscription
+    async fn on_start(&mut self, _ctx: &mut StrategyContext) -> backend::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every `index_values` message
+    async fn on_index(
+        &mut self,
+        _ctx: &mut StrategyContext,
+        _index: &IndexValue,
+    ) -> backend::Result<()> {
+        Ok(())
+    }
+
+    /// Called after a level 1 or level 2 update is applied to the local book of the symbol
+    async fn on_book(
+        &mut self,
+        _ctx: &mut StrategyContext,
+        _book: &LocalOrderBook,
+    ) -> backend::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every `trade` message, an execution of own order or a liquidation
+    async fn on_fill(
+        &mut self,
+        _ctx: &mut StrategyContext,
+        _trade: &KolliderTaggedMsg,
+    ) -> backend::Result<()> {
+        Ok(())
+    }
+
+    /// Called for `received`, `open`, `fill`, `done`, `order_rejection` and `order_not_found`
+    /// messages about own orders
+    async fn on_order_update(
+        &mut self,
+        _ctx: &mut StrategyContext,
+        _update: &KolliderTaggedMsg,
+    ) -> backend::Result<()> {
+        Ok(())
+    }
+
+    /// Called every `RuntimeOptions::timer`
+    async fn on_timer(&mut self, _ctx: &mut StrategyContext) -> backend::Result<()> {
+        Ok(())
+    }
+
+    /// Called once before own open orders are cancelled on shutdown
+    async fn on_stop(&mut self, _ctx: &mut StrategyContext) -> backend::Result<()> {
+        Ok(())
+    }
+}
+
+/// Orders placed by the strategy
+#[derive(Debug, Default)]
+struct OwnOrders {
+    /// Symbols of the orders by order id
+    orders: HashMap<u64, Symbol>,
+    /// `ext_order_id` of orders sent to the socket that are not received yet
+    sent: HashSet<String>,
+}
+
+/// Backend of the hooks that remembers orders created through it
+struct TrackingApi {
+    inner: Arc<dyn TradingApi>,
+    own: Mutex<OwnOrders>,
+}
+
+impl TrackingApi {
+    fn own(&self) -> MutexGuard<'_, OwnOrders> {
+        self.own.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl TradingApi for TrackingApi {
+    async fn create_order(&self, body: &OrderBody) -> backend::Result<OrderCreated> {
+        let created = self.inner.create_order(body).await?;
+        self.own()
+            .orders
+            .insert(created.order_id, created.symbol.clone());
+        Ok(created)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> backend::Result<()> {
+        self.inner.cancel_order(symbol, order_id).await?;
+        self.own().orders.remove(&order_id);
+        Ok(())
+    }
+
+    async fn open_orders(&self) -> backend::Result<HashMap<Symbol, Vec<Order>>> {
+        self.inner.open_orders().await
+    }
+
+    async fn positions(&self) -> backend::Result<HashMap<Symbol, Position>> {
+        self.inner.positions().await
+    }
+}
+
+/// Access of the hooks to the trading backend and the WebSocket session
+pub struct StrategyContext {
+    trading: TrackingApi,
+    outgoing: UnboundedSender<KolliderMsg>,
+    stopping: bool,
+}
+
+impl StrategyContext {
+    pub(crate) fn new(
+        trading: Arc<dyn TradingApi>,
+        outgoing: UnboundedSender<KolliderMsg>,
+    ) -> Self {
+        StrategyContext {
+            trading: TrackingApi {
+                inner: trading,
+                own: Mutex::new(OwnOrders::default()),
+            },
+            outgoing,
+            stopping: false,
+        }
+    }
+
+    /// Backend that places orders. Orders created through it are cancelled on shutdown.
+    pub fn trading(&self) -> &dyn TradingApi {
+        &self.trading
+    }
+
+    /// Send a raw message to the WebSocket session. Orders sent this way are cancelled on
+    /// shutdown once the session reports them as received.
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, msg: KolliderMsg) -> Result<()> {
+        if let KolliderMsg::Order { ext_order_id, .. } = &msg {
+            self.trading.own().sent.insert(ext_order_id.clone());
+        }
+        self.outgoing
+            .unbounded_send(msg)
+            .map_err(|_| RuntimeError::Disconnected)
+    }
+
+    /// Symbols of the orders placed by the strategy that are not known to be done, by order id
+    pub fn own_orders(&self) -> HashMap<u64, Symbol> {
+        self.trading.own().orders.clone()
+    }
+
+    /// Follow own orders by the messages of the session
+    fn track(&self, msg: &KolliderTaggedMsg) {
+        let mut own = self.trading.own();
+        match msg {
+            KolliderTaggedMsg::Received {
+                order_id,
+                symbol,
+                ext_order_id,
+                ..
+            }
+            | KolliderTaggedMsg::Open {
+                order_id,
+                symbol,
+                ext_order_id,
+                ..
+            } if own.sent.remove(ext_order_id) => {
+                own.orders.insert(*order_id, symbol.clone());
+            }
+            KolliderTaggedMsg::Done { order_id, .. } => {
+                own.orders.remove(order_id);
+            }
+            _ => (),
+        }
+    }
+
+    /// Finish the runtime after the current hook returns
+    pub fn stop(&mut self) {
+        self.stopping = true;
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping
+    }
+}
+
+/// Orders that are cancelled on shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOnStop {
+    /// Leave all orders open
+    Nothing,
+    /// Orders placed through `StrategyContext`
+    OwnOrders,
+    /// Every open order of the account, including ones of other bots and manual ones
+    AllOrders,
+}
+
+/// Settings of the strategy runtime
+#[derive(Debug, Clone)]
+pub struct RuntimeOptions {
+    pub websocket: WebsocketOptions,
+    /// Market data channels that are subscribed before `Strategy::on_start`
+    pub subscriptions: Vec<(ChannelName, Symbol)>,
+    /// Period of `Strategy::on_timer`, `None` disables the timer
+    pub timer: Option<Duration>,
+    /// Orders to cancel on shutdown
+    pub cancel_on_stop: CancelOnStop,
+}
+
+impl Default for RuntimeOptions {
+    fn default() -> Self {
+        RuntimeOptions {
+            websocket: WebsocketOptions::default(),
+            subscriptions: vec![],
+            timer: None,
+            cancel_on_stop: CancelOnStop::OwnOrders,
+        }
+    }
+}
+
+/// Drives a `Strategy` from a WebSocket session
+pub struct StrategyRuntime {
+    trading: Arc<dyn TradingApi>,
+    signer: Option<Arc<dyn Signer>>,
+    options: RuntimeOptions,
+}
+
+impl StrategyRuntime {
+    pub fn new<T: TradingApi + 'static>(trading: T, options: RuntimeOptions) -> Self {
+        StrategyRuntime {
+            trading: Arc::new(trading),
+            signer: None,
+            options,
+        }
+    }
+
+    /// Authentificate the WebSocket session to receive updates of own orders
+    pub fn auth<S: Signer + 'static>(mut self, signer: S) -> Self {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Run the strategy until `shutdown` resolves, the strategy calls `StrategyContext::stop`
+    /// or the socket is closed. Then `Strategy::on_stop` is called and open orders are
+    /// cancelled according to `RuntimeOptions::cancel_on_stop`. `shutdown` is also watched
+    /// while waiting for authentification and during `Strategy::on_start`.
+    pub async fn run<S, F>(&self, strategy: &mut S, shutdown: F) -> Result<()>
+    where
+        S: Strategy + ?Sized,
+        F: Future<Output = ()>,
+    {
+        let (outgoing, outgoing_rx) = unbounded();
+        let (incoming_tx, incoming) = unbounded();
+        // Auth is signed after the handshake that measured the server clock
+        let stream = kollider_connect(&self.options.websocket).await?;
+        let worker = tokio::spawn(kollider_websocket_over(
+            self.options.websocket.clone(),
+            stream,
+            outgoing_rx,
+            incoming_tx,
+        ));
+        match self.drive(strategy, outgoing, incoming, shutdown).await {
+            Err(RuntimeError::Disconnected) => match worker.await {
+                Ok(Err(e)) => Err(e.into()),
+                _ => Err(RuntimeError::Disconnected),
+            },
+            res => res,
+        }
+    }
+
+    async fn drive<S, F>(
+        &self,
+        strategy: &mut S,
+        outgoing: UnboundedSender<KolliderMsg>,
+        mut incoming: UnboundedReceiver<KolliderMsg>,
+        shutdown: F,
+    ) -> Result<()>
+    where
+        S: Strategy + ?Sized,
+        F: Future<Output = ()>,
+    {
+        let mut ctx = StrategyContext::new(self.trading.clone(), outgoing);
+        pin_mut!(shutdown);
+        if let Some(signer) = &self.signer {
+            ctx.send(make_signed_auth_async(signer.as_ref()).await?)?;
+            let auth = tokio::time::timeout(AUTH_TIMEOUT, wait_auth(&mut incoming));
+            tokio::select! {
+                _ = &mut shutdown => {
+                    info!("Strategy runtime is shut down before authentification");
+                    return Ok(());
+                }
+                res = auth => res.map_err(|_| RuntimeError::AuthTimeout(AUTH_TIMEOUT))??,
+            }
+        }
+        for (channel, symbol) in &self.options.subscriptions {
+            ctx.send(KolliderMsg::Subscribe {
+                _type: SubscribeTag::Tag,
+                symbols: vec![symbol.clone()],
+                channels: vec![*channel],
+            })?;
+        }
+
+        let started = tokio::select! {
+            _ = &mut shutdown => None,
+            res = strategy.on_start(&mut ctx) => Some(res),
+        };
+        let res = match started {
+            None => {
+                info!("Strategy runtime is shutting down");
+                Ok(())
+            }
+            Some(Err(e)) => Err(e.into()),
+            Some(Ok(())) => {
+                self.event_loop(strategy, &mut ctx, &mut incoming, shutdown)
+                    .await
+            }
+        };
+        log_hook("on_stop", strategy.on_stop(&mut ctx).await);
+        let cancelled = match self.options.cancel_on_stop {
+            CancelOnStop::Nothing => Ok(0),
+            CancelOnStop::OwnOrders => cancel_own_orders(&ctx.trading).await,
+            CancelOnStop::AllOrders => cancel_open_orders(self.trading.as_ref()).await,
+        };
+        if let Err(e) = cancelled {
+            error!("Failed to cancel open orders on shutdown: {}", e);
+            res?;
+            return Err(e.into());
+        }
+        res
+    }
+
+    async fn event_loop<S, F>(
+        &self,
+        strategy: &mut S,
+        ctx: &mut StrategyContext,
+        incoming: &mut UnboundedReceiver<KolliderMsg>,
+        mut shutdown: Pin<&mut F>,
+    ) -> Result<()>
+    where
+        S: Strategy + ?Sized,
+        F: Future<Output = ()>,
+    {
+        let mut books = HashMap::new();
+        let mut timer = self
+            .options
+            .timer
+            .map(|period| tokio::time::interval_at(Instant::now() + period, period));
+        while !ctx.stopping {
+            let tick = async {
+                match &mut timer {
+                    Some(timer) => {
+                        timer.tick().await;
+                    }
+                    None => future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = &mut shutdown => {
+                    info!("Strategy runtime is shutting down");
+                    break;
+                }
+                _ = tick => log_hook("on_timer", strategy.on_timer(ctx).await),
+                msg = incoming.next() => match msg {
+                    Some(KolliderMsg::Tagged(msg)) => dispatch(strategy, ctx, &mut books, msg).await,
+                    Some(_) => (),
+                    None => return Err(RuntimeError::Disconnected),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn wait_auth(incoming: &mut UnboundedReceiver<KolliderMsg>) -> Result<()> {
+    while let Some(msg) = incoming.next().await {
+        match msg {
+            KolliderMsg::Tagged(KolliderTaggedMsg::Authenticate { message }) => {
+                return if message == "success" {
+                    Ok(())
+                } else {
+                    Err(RuntimeError::AuthRejected(message))
+                };
+            }
+            KolliderMsg::Tagged(KolliderTaggedMsg::Error(message)) => {
+                return Err(RuntimeError::AuthRejected(message))
+            }
+            _ => (),
+        }
+    }
+    Err(RuntimeError::Disconnected)
+}
+
+async fn dispatch<S: Strategy + ?Sized>(
+    strategy: &mut S,
+    ctx: &mut StrategyContext,
+    books: &mut HashMap<Symbol, LocalOrderBook>,
+    msg: KolliderTaggedMsg,
+) {
+    ctx.track(&msg);
+    match &msg {
+        KolliderTaggedMsg::IndexValues(index) => {
+            log_hook("on_index", strategy.on_index(ctx, index).await)
+        }
+        KolliderTaggedMsg::OrderBookLevel1(update) => {
+            let book = books
+                .entry(update.symbol.clone())
+                .or_insert_with(|| LocalOrderBook::new(&update.symbol));
+            match book.apply_level1(update) {
+                Ok(true) => log_hook("on_book", strategy.on_book(ctx, book).await),
+                Ok(false) => (),
+                Err(e) => warn!("Failed to apply book update: {}", e),
+            }
+        }
+        KolliderTaggedMsg::OrderBookLevel2(update) => {
+            let book = books
+                .entry(update.symbol.clone())
+                .or_insert_with(|| LocalOrderBook::new(&update.symbol));
+            match book.apply_level2(update) {
+                Ok(true) => log_hook("on_book", strategy.on_book(ctx, book).await),
+                Ok(false) => (),
+                Err(e) => warn!("Failed to apply book update: {}", e),
+            }
+        }
+        KolliderTaggedMsg::Trade { .. } => log_hook("on_fill", strategy.on_fill(ctx, &msg).await),
+        KolliderTaggedMsg::Received { .. }
+        | KolliderTaggedMsg::Open { .. }
+        | KolliderTaggedMsg::Fill { .. }
+        | KolliderTaggedMsg::Done { .. }
+        | KolliderTaggedMsg::OrderRejection { .. }
+        | KolliderTaggedMsg::OrderNotFound { .. } => {
+            log_hook("on_order_update", strategy.on_order_update(ctx, &msg).await)
+        }
+        KolliderTaggedMsg::Error(message) => warn!("WebSocket error: {}", message),
+        _ => trace!("Strategy runtime skips {}", msg.type_tag()),
+    }
+}
+
+pub(crate) fn log_hook(hook: &str, res: backend::Result<()>) {
+    if let Err(e) = res {
+        error!("Strategy hook {} failed: {}", hook, e);
+    }
+}
+
+/// Cancel every open order of the account. All orders are tried, the last error is returned.
+pub async fn cancel_open_orders(trading: &dyn TradingApi) -> backend::Result<usize> {
+    cancel_orders(trading, |_| true).await
+}
+
+/// Cancel open orders that the strategy placed
+async fn cancel_own_orders(trading: &TrackingApi) -> backend::Result<usize> {
+    let own = trading.own().orders.clone();
+    if own.is_empty() {
+        return Ok(0);
+    }
+    cancel_orders(trading, |order_id| own.contains_key(&order_id)).await
+}
+
+/// Cancel open orders with ids that pass the filter
+async fn cancel_orders<F: Fn(u64) -> bool>(
+    trading: &dyn TradingApi,
+    filter: F,
+) -> backend::Result<usize> {
+    let mut cancelled = 0;
+    let mut failure = None;
+    for (symbol, orders) in trading.open_orders().await? {
+        for order in orders.into_iter().filter(|order| filter(order.order_id)) {
+            match trading.cancel_order(&symbol, order.order_id).await {
+                Ok(()) => cancelled += 1,
+                Err(e) => {
+                    warn!("Failed to cancel order {}: {}", order.order_id, e);
+                    failure = Some(e);
+                }
+            }
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(cancelled),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::{MarginType, OrderBody, OrderSide, OrderType, SettlementType};
+    use crate::kollider::websocket::data::{OrderBookLevel2, UpdateType};
+
+    const SYMBOL: &str = "BTCUSD.PERP";
+
+    fn limit(side: OrderSide, price: u64, quantity: u64) -> OrderBody {
+        OrderBody {
+            leverage: 1,
+            margin_type: MarginType::Isolated,
+            order_type: OrderType::Limit,
+            price,
+            quantity,
+            settlement_type: SettlementType::Delayed,
+            side,
+            symbol: SYMBOL.to_owned(),
+        }
+    }
+
+    /// Rests a bid on start, stops on the first index value and records called hooks
+    #[derive(Default)]
+    struct Recorder {
+        hooks: Vec<&'static str>,
+    }
+
+    #[async_trait]
+    impl Strategy for Recorder {
+        async fn on_start(&mut self, ctx: &mut StrategyContext) -> backend::Result<()> {
+            self.hooks.push("start");
+            ctx.trading()
+                .create_order(&limit(OrderSide::Bid, 490000, 3))
+                .await?;
+            Ok(())
+        }
+
+        async fn on_index(
+            &mut self,
+            ctx: &mut StrategyContext,
+            _index: &IndexValue,
+        ) -> backend::Result<()> {
+            self.hooks.push("index");
+            ctx.stop();
+            Ok(())
+        }
+
+        async fn on_book(
+            &mut self,
+            _ctx: &mut StrategyContext,
+            book: &LocalOrderBook,
+        ) -> backend::Result<()> {
+            assert_eq!(book.best_ask(), Some((500000, 100)));
+            self.hooks.push("book");
+            Ok(())
+        }
+
+        async fn on_stop(&mut self, _ctx: &mut StrategyContext) -> backend::Result<()> {
+            self.hooks.push("stop");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_and_cancel_on_stop() {
+        use crate::kollider::api::Product;
+        use crate::kollider::paper::PaperTradingClient;
+
+        let product = Product {
+            symbol: SYMBOL.to_owned(),
+            contract_size: 1.0,
+            max_leverage: 100.0,
+            base_margin: 0.005,
+            maintenance_margin: 0.004,
+            is_inverse_priced: true,
+            price_dp: 1.0,
+            underlying_symbol: ".BTCUSD".to_owned(),
+            last_price: 500000.0,
+            tick_size: 0.5,
+            risk_limit: 100000000.0,
+        };
+        let paper = PaperTradingClient::new(HashMap::from([(SYMBOL.to_owned(), product)]), 100_000);
+        let options = RuntimeOptions {
+            subscriptions: vec![(ChannelName::OrderBookLevel2, SYMBOL.to_owned())],
+            ..RuntimeOptions::default()
+        };
+        let runtime = StrategyRuntime::new(paper.clone(), options);
+        // Order of someone else on the account
+        let manual = paper
+            .create_order(&limit(OrderSide::Ask, 510000, 1))
+            .await
+            .unwrap();
+
+        let (outgoing, mut outgoing_rx) = unbounded();
+        let (incoming_tx, incoming) = unbounded();
+        let snapshot = OrderBookLevel2 {
+            asks: HashMap::from([("500000".to_owned(), 100)]),
+            bids: HashMap::new(),
+            seq_number: 1,
+            symbol: SYMBOL.to_owned(),
+            update_type: UpdateType::Snapshot,
+        };
+        let index = IndexValue {
+            denom: "USD".to_owned(),
+            symbol: ".BTCUSD".to_owned(),
+            value: 50000.0,
+        };
+        for msg in [
+            KolliderTaggedMsg::OrderBookLevel2(snapshot),
+            KolliderTaggedMsg::IndexValues(index),
+        ] {
+            incoming_tx
+                .unbounded_send(KolliderMsg::Tagged(msg))
+                .unwrap();
+        }
+
+        let mut strategy = Recorder::default();
+        runtime
+            .drive(&mut strategy, outgoing, incoming, future::pending())
+            .await
+            .unwrap();
+        assert_eq!(strategy.hooks, vec!["start", "book", "index", "stop"]);
+        assert!(matches!(
+            outgoing_rx.try_recv(),
+            Ok(KolliderMsg::Subscribe { .. })
+        ));
+        let open = paper.open_orders().await.unwrap();
+        assert_eq!(open[SYMBOL].len(), 1);
+        assert_eq!(open[SYMBOL][0].order_id, manual.order_id);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_during_auth() {
+        use crate::kollider::client::KolliderAuth;
+        use crate::kollider::paper::PaperTradingClient;
+
+        let paper = PaperTradingClient::new(HashMap::new(), 100_000);
+        let auth = KolliderAuth::new("key", "c2VjcmV0", "pass").unwrap();
+        let runtime = StrategyRuntime::new(paper, RuntimeOptions::default()).auth(auth);
+        let (outgoing, _outgoing_rx) = unbounded();
+        let (_incoming_tx, incoming) = unbounded();
+        let mut strategy = Recorder::default();
+        let drive = runtime.drive(&mut strategy, outgoing, incoming, future::ready(()));
+        tokio::time::timeout(Duration::from_secs(5), drive)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(strategy.hooks.is_empty());
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_runtime_with_mock_server() {
+        use crate::kollider::mock::MockServer;
+
+        /// Takes liquidity on start and stops after the trade
+        struct Taker {
+            fills: usize,
+            updates: usize,
+        }
+
+        #[async_trait]
+        impl Strategy for Taker {
+            async fn on_start(&mut self, ctx: &mut StrategyContext) -> backend::Result<()> {
+                ctx.trading()
+                    .create_order(&limit(OrderSide::Bid, 500000, 5))
+                    .await?;
+                Ok(())
+            }
+
+            async fn on_fill(
+                &mut self,
+                ctx: &mut StrategyContext,
+                _trade: &KolliderTaggedMsg,
+            ) -> backend::Result<()> {
+                self.fills += 1;
+                ctx.stop();
+                Ok(())
+            }
+
+            async fn on_order_update(
+                &mut self,
+                _ctx: &mut StrategyContext,
+                _update: &KolliderTaggedMsg,
+            ) -> backend::Result<()> {
+                self.updates += 1;
+                Ok(())
+            }
+        }
+
+        let server = MockServer::start().await.unwrap();
+        server
+            .add_liquidity(SYMBOL, OrderSide::Ask, 500000, 100)
+            .unwrap();
+        let auth = server.add_account(100_000);
+        let mut client = server.client();
+        client.set_auth(auth.clone());
+        let options = RuntimeOptions {
+            websocket: server.ws_options(),
+            ..RuntimeOptions::default()
+        };
+        let mut strategy = Taker {
+            fills: 0,
+            updates: 0,
+        };
+        let runtime = StrategyRuntime::new(client, options).auth(auth);
+        let run = runtime.run(&mut strategy, future::pending());
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(strategy.fills, 1);
+        assert!(strategy.updates > 0);
+    }
+}
@@ -0,0 +1,426 @@
+use super::data::{OrderBookLevel1, OrderBookLevel2, OrderBookLevel3, PriceLevel, UpdateType};
+use crate::kollider::api::{OrderDetails, OrderSide, Symbol};
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum BookError {
+    #[error("Book for {0} got delta update before snapshot")]
+    NoSnapshot(Symbol),
+    #[error("Book for {expected} got update for other symbol {got}")]
+    WrongSymbol { expected: Symbol, got: Symbol },
+    #[error("Failed to parse price level '{0}'")]
+    InvalidPrice(String),
+}
+
+/// Local copy of the aggregated (Level 2) order book maintained from `level2state`
+/// or `level1state` messages. Prices are in the integer units of the API.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LocalOrderBook {
+    pub symbol: Symbol,
+    pub seq_number: u64,
+    pub asks: BTreeMap<u64, u64>,
+    pub bids: BTreeMap<u64, u64>,
+    initialized: bool,
+}
+
+impl LocalOrderBook {
+    pub fn new(symbol: &str) -> Self {
+        LocalOrderBook {
+            symbol: symbol.to_owned(),
+            seq_number: 0,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            initialized: false,
+        }
+    }
+
+    /// Whether the book got its first snapshot
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Apply snapshot or delta. Returns `false` if the update is older than the book and was skipped.
+    pub fn apply_level2(&mut self, update: &OrderBookLevel2) -> Result<bool, BookError> {
+        self.apply_levels(
+            &update.symbol,
+            update.seq_number,
+            update.update_type,
+            &update.asks,
+            &update.bids,
+            false,
+        )
+    }
+
+    /// Apply snapshot or delta of the top of the book. A level 1 update carries the whole top
+    /// of each side it has, so the previous levels of that side are replaced.
+    pub fn apply_level1(&mut self, update: &OrderBookLevel1) -> Result<bool, BookError> {
+        self.apply_levels(
+            &update.symbol,
+            update.seq_number,
+            update.update_type,
+            &update.asks,
+            &update.bids,
+            true,
+        )
+    }
+
+    fn apply_levels(
+        &mut self,
+        symbol: &str,
+        seq_number: u64,
+        update_type: UpdateType,
+        asks: &HashMap<String, u64>,
+        bids: &HashMap<String, u64>,
+        replace_sides: bool,
+    ) -> Result<bool, BookError> {
+        check_update(&self.symbol, self.initialized, symbol, update_type)?;
+        if self.initialized && seq_number <= self.seq_number && update_type == UpdateType::Delta {
+            return Ok(false);
+        }
+        if update_type == UpdateType::Snapshot {
+            self.asks.clear();
+            self.bids.clear();
+            self.initialized = true;
+        }
+        if replace_sides && !asks.is_empty() {
+            self.asks.clear();
+        }
+        if replace_sides && !bids.is_empty() {
+            self.bids.clear();
+        }
+        apply_side(&mut self.asks, asks)?;
+        apply_side(&mut self.bids, bids)?;
+        self.seq_number = seq_number;
+        Ok(true)
+    }
+
+    /// Lowest ask price and quantity
+    pub fn best_ask(&self) -> Option<(u64, u64)> {
+        self.asks.iter().next().map(|(p, q)| (*p, *q))
+    }
+
+    /// Highest bid price and quantity
+    pub fn best_bid(&self) -> Option<(u64, u64)> {
+        self.bids.iter().next_back().map(|(p, q)| (*p, *q))
+    }
+
+    /// Difference between best ask and best bid
+    pub fn spread(&self) -> Option<u64> {
+        Some(self.best_ask()?.0.saturating_sub(self.best_bid()?.0))
+    }
+
+    /// Price levels of the side sorted from the best price to the worst one
+    pub fn levels(&self, side: OrderSide) -> Vec<(u64, u64)> {
+        match side {
+            OrderSide::Ask => self.asks.iter().map(|(p, q)| (*p, *q)).collect(),
+            OrderSide::Bid => self.bids.iter().rev().map(|(p, q)| (*p, *q)).collect(),
+        }
+    }
+}
+
+/// Local copy of the per order (Level 3) book maintained from `level3state` messages
+#[derive(Debug, PartialEq, Clone)]
+pub struct LocalOrderBookL3 {
+    pub symbol: Symbol,
+    pub seq_number: u64,
+    pub asks: BTreeMap<u64, Vec<OrderDetails>>,
+    pub bids: BTreeMap<u64, Vec<OrderDetails>>,
+    initialized: bool,
+}
+
+impl LocalOrderBookL3 {
+    pub fn new(symbol: &str) -> Self {
+        LocalOrderBookL3 {
+            symbol: symbol.to_owned(),
+            seq_number: 0,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            initialized: false,
+        }
+    }
+
+    /// Whether the book got its first snapshot
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Apply snapshot or delta. Returns `false` if the update is older than the book and was skipped.
+    pub fn apply_level3(&mut self, update: &OrderBookLevel3) -> Result<bool, BookError> {
+        check_update(
+            &self.symbol,
+            self.initialized,
+            &update.symbol,
+            update.update_type,
+        )?;
+        if self.initialized
+            && update.seq_number <= self.seq_number
+            && update.update_type == UpdateType::Delta
+        {
+            return Ok(false);
+        }
+        if update.update_type == UpdateType::Snapshot {
+            self.asks.clear();
+            self.bids.clear();
+            self.initialized = true;
+        }
+        apply_orders(&mut self.asks, &update.asks);
+        apply_orders(&mut self.bids, &update.bids);
+        self.seq_number = update.seq_number;
+        Ok(true)
+    }
+
+    /// Find resting order by its ID
+    pub fn order(&self, order_id: u64) -> Option<&OrderDetails> {
+        self.asks
+            .values()
+            .chain(self.bids.values())
+            .flatten()
+            .find(|o| o.order_id == order_id)
+    }
+
+    /// Lowest ask price and orders at it
+    pub fn best_ask(&self) -> Option<(u64, &[OrderDetails])> {
+        self.asks.iter().next().map(|(p, os)| (*p, os.as_slice()))
+    }
+
+    /// Highest bid price and orders at it
+    pub fn best_bid(&self) -> Option<(u64, &[OrderDetails])> {
+        self.bids
+            .iter()
+            .next_back()
+            .map(|(p, os)| (*p, os.as_slice()))
+    }
+
+    /// Aggregate the book into Level 2 book. Level quantity is the sum of unfilled quantities of orders.
+    pub fn to_level2(&self) -> LocalOrderBook {
+        let aggregate = |side: &BTreeMap<u64, Vec<OrderDetails>>| {
+            side.iter()
+                .map(|(p, os)| {
+                    let qty = os
+                        .iter()
+                        .map(|o| o.quantity.saturating_sub(o.filled as u64))
+                        .sum();
+                    (*p, qty)
+                })
+                .filter(|(_, qty)| *qty > 0)
+                .collect()
+        };
+        LocalOrderBook {
+            symbol: self.symbol.clone(),
+            seq_number: self.seq_number,
+            asks: aggregate(&self.asks),
+            bids: aggregate(&self.bids),
+            initialized: self.initialized,
+        }
+    }
+}
+
+fn check_update(
+    book_symbol: &str,
+    initialized: bool,
+    symbol: &str,
+    update_type: UpdateType,
+) -> Result<(), BookError> {
+    if book_symbol != symbol {
+        return Err(BookError::WrongSymbol {
+            expected: book_symbol.to_owned(),
+            got: symbol.to_owned(),
+        });
+    }
+    if !initialized && update_type == UpdateType::Delta {
+        return Err(BookError::NoSnapshot(book_symbol.to_owned()));
+    }
+    Ok(())
+}
+
+fn apply_side(
+    side: &mut BTreeMap<u64, u64>,
+    levels: &HashMap<String, u64>,
+) -> Result<(), BookError> {
+    for (price_str, qty) in levels {
+        let price = price_str
+            .parse::<u64>()
+            .map_err(|_| BookError::InvalidPrice(price_str.clone()))?;
+        if *qty == 0 {
+            side.remove(&price);
+        } else {
+            side.insert(price, *qty);
+        }
+    }
+    Ok(())
+}
+
+fn apply_orders(side: &mut BTreeMap<u64, Vec<OrderDetails>>, levels: &[PriceLevel]) {
+    for level in levels {
+        if level.orders.is_empty() {
+            side.remove(&level.price);
+        } else {
+            side.insert(level.price, level.orders.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::api::{MarginType, OrderType, SettlementType};
+
+    fn level2(
+        update_type: UpdateType,
+        seq_number: u64,
+        asks: HashMap<String, u64>,
+        bids: HashMap<String, u64>,
+    ) -> OrderBookLevel2 {
+        OrderBookLevel2 {
+            asks,
+            bids,
+            seq_number,
+            symbol: "BTCUSD.PERP".to_owned(),
+            update_type,
+        }
+    }
+
+    fn order(order_id: u64, side: OrderSide, price: u64, quantity: u64) -> OrderDetails {
+        OrderDetails {
+            ext_order_id: "07e10e56-bd45-4e3c-9981-688e6af7fc69".to_owned(),
+            filled: 0.0,
+            leverage: 100,
+            margin_type: MarginType::Isolated,
+            order_id,
+            order_type: OrderType::Limit,
+            price,
+            quantity,
+            settlement_type: SettlementType::Delayed,
+            side,
+            symbol: "BTCUSD.PERP".to_owned(),
+            timestamp: 0,
+            uid: 1,
+        }
+    }
+
+    #[test]
+    fn test_level2_snapshot_and_delta() {
+        let mut book = LocalOrderBook::new("BTCUSD.PERP");
+        let snapshot = level2(
+            UpdateType::Snapshot,
+            10,
+            hashmap! { "486950".to_owned() => 10, "487195".to_owned() => 20 },
+            hashmap! { "486840".to_owned() => 5, "486595".to_owned() => 7 },
+        );
+        assert_eq!(book.apply_level2(&snapshot), Ok(true));
+        assert_eq!(book.best_ask(), Some((486950, 10)));
+        assert_eq!(book.best_bid(), Some((486840, 5)));
+        assert_eq!(book.spread(), Some(110));
+
+        let delta = level2(
+            UpdateType::Delta,
+            11,
+            hashmap! { "486950".to_owned() => 0 },
+            hashmap! { "486900".to_owned() => 1 },
+        );
+        assert_eq!(book.apply_level2(&delta), Ok(true));
+        assert_eq!(book.best_ask(), Some((487195, 20)));
+        assert_eq!(
+            book.levels(OrderSide::Bid),
+            vec![(486900, 1), (486840, 5), (486595, 7)]
+        );
+
+        let stale = level2(
+            UpdateType::Delta,
+            11,
+            hashmap! {},
+            hashmap! { "486900".to_owned() => 0 },
+        );
+        assert_eq!(book.apply_level2(&stale), Ok(false));
+        assert_eq!(book.best_bid(), Some((486900, 1)));
+    }
+
+    #[test]
+    fn test_level2_delta_before_snapshot() {
+        let mut book = LocalOrderBook::new("BTCUSD.PERP");
+        let delta = level2(UpdateType::Delta, 1, hashmap! {}, hashmap! {});
+        assert_eq!(
+            book.apply_level2(&delta),
+            Err(BookError::NoSnapshot("BTCUSD.PERP".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_level1_replaces_top() {
+        let mut book = LocalOrderBook::new("BTCUSD.PERP");
+        let level1 = |update_type, seq_number, asks, bids| OrderBookLevel1 {
+            asks,
+            bids,
+            seq_number,
+            symbol: "BTCUSD.PERP".to_owned(),
+            update_type,
+        };
+        let snapshot = level1(
+            UpdateType::Snapshot,
+            1,
+            hashmap! { "486950".to_owned() => 10 },
+            hashmap! { "486840".to_owned() => 5 },
+        );
+        assert_eq!(book.apply_level1(&snapshot), Ok(true));
+
+        let delta = level1(
+            UpdateType::Delta,
+            2,
+            hashmap! {},
+            hashmap! { "486700".to_owned() => 3 },
+        );
+        assert_eq!(book.apply_level1(&delta), Ok(true));
+        assert_eq!(book.levels(OrderSide::Bid), vec![(486700, 3)]);
+        assert_eq!(book.best_ask(), Some((486950, 10)));
+
+        let delta = level1(
+            UpdateType::Delta,
+            3,
+            hashmap! { "487000".to_owned() => 1 },
+            hashmap! { "486960".to_owned() => 2 },
+        );
+        assert_eq!(book.apply_level1(&delta), Ok(true));
+        assert_eq!(book.levels(OrderSide::Ask), vec![(487000, 1)]);
+        assert_eq!(book.levels(OrderSide::Bid), vec![(486960, 2)]);
+    }
+
+    #[test]
+    fn test_level3_book() {
+        let mut book = LocalOrderBookL3::new("BTCUSD.PERP");
+        let snapshot = OrderBookLevel3 {
+            asks: vec![PriceLevel::from((
+                486950,
+                vec![
+                    order(1, OrderSide::Ask, 486950, 10),
+                    order(2, OrderSide::Ask, 486950, 5),
+                ],
+            ))],
+            bids: vec![PriceLevel::from((
+                486840,
+                vec![order(3, OrderSide::Bid, 486840, 7)],
+            ))],
+            seq_number: 1,
+            symbol: "BTCUSD.PERP".to_owned(),
+            update_type: UpdateType::Snapshot,
+        };
+        assert_eq!(book.apply_level3(&snapshot), Ok(true));
+        assert_eq!(book.order(2).map(|o| o.quantity), Some(5));
+        assert_eq!(book.to_level2().best_ask(), Some((486950, 15)));
+
+        let delta = OrderBookLevel3 {
+            asks: vec![PriceLevel::from((
+                486950,
+                vec![order(2, OrderSide::Ask, 486950, 5)],
+            ))],
+            bids: vec![PriceLevel::from((486840, vec![]))],
+            seq_number: 2,
+            symbol: "BTCUSD.PERP".to_owned(),
+            update_type: UpdateType::Delta,
+        };
+        assert_eq!(book.apply_level3(&delta), Ok(true));
+        assert_eq!(book.order(1), None);
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.to_level2().best_ask(), Some((486950, 5)));
+    }
+}
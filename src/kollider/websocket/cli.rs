@@ -1,46 +1,724 @@
 use super::data::*;
-use futures_channel::mpsc::UnboundedSender;
-use std::str::FromStr;
-
-use shellfish::{Command, Shell};
+use super::subscription::{SubscriptionHandle, SubscriptionKey, SubscriptionRegistry};
+use crate::kollider::api::{MarginType, OrderSide, OrderType, SettlementType, Symbol};
+use crate::kollider::client::signer::Signer;
+use futures::{future, StreamExt};
+use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use rustyline::completion::{extract_word, Completer};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
 
-// Our helper method which will read data from stdin and send it along the
-// sender provided.
-pub async fn websocket_stdin_controller(tx: UnboundedSender<KolliderMsg>) {
-    // Define a shell
-    let mut shell = Shell::new_async(tx, "$ ");
-
-    // Add some commands
-    shell.commands.insert(
-        "subscribe",
-        Command::new(
-            "Subscribe to channel for symbol. Usage: subscribe <symbol> <channel>".to_owned(),
-            subscribe,
-        ),
-    );
+/// Settings of the interactive websocket shell
+#[derive(Clone)]
+pub struct ShellOptions {
+    pub prompt: String,
+    /// Symbols for tab completion, e.x. fetched from `/market/products`
+    pub symbols: Vec<Symbol>,
+    /// Symbol for commands where it is omitted
+    pub default_symbol: Symbol,
+    /// Credentials for the `auth` command, e.x. from the profile or the keystore
+    pub auth: Option<Arc<dyn Signer>>,
+    /// Where to load and save command history
+    pub history_file: Option<PathBuf>,
+}
+
+impl Default for ShellOptions {
+    fn default() -> Self {
+        ShellOptions {
+            prompt: "$ ".to_owned(),
+            symbols: vec![],
+            default_symbol: "BTCUSD.PERP".to_owned(),
+            auth: None,
+            history_file: None,
+        }
+    }
+}
+
+/// Which incoming messages are printed by the shell. Messages are matched by their type tag.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MessageFilter {
+    hidden: HashSet<String>,
+    hide_all: bool,
+}
+
+impl MessageFilter {
+    pub fn show(&mut self, kind: &str) {
+        if kind == "all" {
+            self.hidden.clear();
+            self.hide_all = false;
+        } else if self.hide_all {
+            self.hidden = MESSAGE_KINDS.iter().map(|k| k.to_string()).collect();
+            self.hide_all = false;
+            self.hidden.remove(kind);
+        } else {
+            self.hidden.remove(kind);
+        }
+    }
+
+    pub fn hide(&mut self, kind: &str) {
+        if kind == "all" {
+            self.hidden.clear();
+            self.hide_all = true;
+        } else {
+            self.hidden.insert(kind.to_owned());
+        }
+    }
+
+    pub fn is_shown(&self, kind: &str) -> bool {
+        !self.hide_all && !self.hidden.contains(kind)
+    }
+}
+
+/// Type tags of incoming messages that can be used in filters
+pub const MESSAGE_KINDS: &[&str] = &[
+    "index_values",
+    "level1state",
+    "level2state",
+    "level3state",
+    "ticker",
+    "matches",
+    "success",
+    "error",
+    "authenticate",
+    "received",
+    "balances",
+    "open",
+    "user_advanced_orders",
+    "open_orders",
+    "tradable_products",
+    "positions",
+    "withdrawal_limit_info",
+    "done",
+    "order_not_found",
+    "fill",
+    "trade",
+    "settlement_request",
+    "change_leverage_info",
+    "change_leverage_success",
+    "order_rejection",
+    "unknown",
+];
+
+/// Kind of the message that is used for filtering
+pub fn message_kind(msg: &KolliderMsg) -> &'static str {
+    match msg {
+        KolliderMsg::Tagged(tagged) => tagged.type_tag(),
+        _ => "unknown",
+    }
+}
 
-    // Run the shell
-    shell.run_async().await.unwrap();
+/// Human friendly single line representation of incoming message
+pub fn format_message(msg: &KolliderMsg) -> String {
+    let tagged = match msg {
+        KolliderMsg::Tagged(tagged) => tagged,
+        KolliderMsg::Unknown { type_tag, raw } => {
+            return format!("[unknown {}] {}", type_tag.as_deref().unwrap_or("-"), raw)
+        }
+        _ => {
+            return format!(
+                "[outgoing] {}",
+                serde_json::to_string(msg).unwrap_or_default()
+            )
+        }
+    };
+    let kind = tagged.type_tag();
+    match tagged {
+        KolliderTaggedMsg::IndexValues(v) => {
+            format!("[{}] {} {} {}", kind, v.symbol, v.value, v.denom)
+        }
+        KolliderTaggedMsg::Ticker(t) => format!(
+            "[{}] {} bid {} ask {} last {:?} {} @ {}",
+            kind, t.symbol, t.best_bid, t.best_ask, t.last_side, t.last_quantity, t.last_price
+        ),
+        KolliderTaggedMsg::Matches(m) => format!(
+            "[{}] {} {:?} {} @ {}",
+            kind, m.symbol, m.side, m.quantity, m.price
+        ),
+        KolliderTaggedMsg::OrderBookLevel1(b) => format!(
+            "[{}] {} {:?} #{} asks {:?} bids {:?}",
+            kind, b.symbol, b.update_type, b.seq_number, b.asks, b.bids
+        ),
+        KolliderTaggedMsg::OrderBookLevel2(b) => format!(
+            "[{}] {} {:?} #{} asks {} levels, bids {} levels",
+            kind,
+            b.symbol,
+            b.update_type,
+            b.seq_number,
+            b.asks.len(),
+            b.bids.len()
+        ),
+        KolliderTaggedMsg::OrderBookLevel3(b) => format!(
+            "[{}] {} {:?} #{} asks {} levels, bids {} levels",
+            kind,
+            b.symbol,
+            b.update_type,
+            b.seq_number,
+            b.asks.len(),
+            b.bids.len()
+        ),
+        KolliderTaggedMsg::Success(m) | KolliderTaggedMsg::Error(m) => {
+            format!("[{}] {}", kind, m)
+        }
+        KolliderTaggedMsg::Authenticate { message } => format!("[{}] {}", kind, message),
+        KolliderTaggedMsg::Open {
+            order_id,
+            price,
+            quantity,
+            symbol,
+            side,
+            ..
+        } => format!(
+            "[{}] order {} {} {:?} {} @ {}",
+            kind, order_id, symbol, side, quantity, price
+        ),
+        KolliderTaggedMsg::Fill {
+            order_id,
+            price,
+            quantity,
+            symbol,
+            side,
+            partial,
+            ..
+        } => format!(
+            "[{}] order {} {} {:?} {} @ {}{}",
+            kind,
+            order_id,
+            symbol,
+            side,
+            quantity,
+            price,
+            if *partial { " (partial)" } else { "" }
+        ),
+        KolliderTaggedMsg::Done {
+            order_id,
+            reason,
+            symbol,
+            ..
+        } => format!("[{}] order {} {} {}", kind, order_id, symbol, reason),
+        KolliderTaggedMsg::OrderRejection {
+            order_id, reason, ..
+        } => format!("[{}] order {} {:?}", kind, order_id, reason),
+        _ => {
+            let data = serde_json::to_value(tagged)
+                .ok()
+                .and_then(|v| v.get("data").cloned())
+                .unwrap_or_default();
+            format!("[{}] {}", kind, data)
+        }
+    }
 }
 
-fn subscribe(
-    tx: &mut UnboundedSender<KolliderMsg>,
-    args: Vec<String>,
+/// Launch interactive shell that sends commands to `msg_outcoming` and prints messages from `msg_incoming`
+pub async fn websocket_stdin_controller(
+    options: ShellOptions,
+    msg_outcoming: UnboundedSender<KolliderMsg>,
+    msg_incoming: UnboundedReceiver<KolliderMsg>,
 ) -> Result<(), Box<dyn Error>> {
+    let registry = SubscriptionRegistry::new(msg_outcoming.clone());
+    let filter = Arc::new(Mutex::new(MessageFilter::default()));
+
+    let printer = {
+        let registry = registry.clone();
+        let filter = filter.clone();
+        tokio::spawn(msg_incoming.for_each(move |msg| {
+            registry.handle_message(&msg);
+            if filter.lock().unwrap().is_shown(message_kind(&msg)) {
+                println!("{}", format_message(&msg));
+            }
+            future::ready(())
+        }))
+    };
+
+    let mut state = ShellState {
+        sender: msg_outcoming,
+        registry,
+        handles: HashMap::new(),
+        filter,
+        auth: options.auth.clone(),
+        default_symbol: options.default_symbol.clone(),
+    };
+
+    let mut editor = Editor::<ShellHelper>::new();
+    editor.set_helper(Some(ShellHelper::new(&options.symbols)));
+    if let Some(path) = &options.history_file {
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let prompt = options.prompt.clone();
+        let (returned_editor, readline) = tokio::task::spawn_blocking(move || {
+            let res = editor.readline(&prompt);
+            (editor, res)
+        })
+        .await?;
+        editor = returned_editor;
+        match readline {
+            Ok(line) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if keep_in_history(&line) {
+                    editor.add_history_entry(line.as_str());
+                }
+                match run_command(&mut state, &line) {
+                    Ok(true) => break,
+                    Ok(false) => (),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    if let Some(path) = &options.history_file {
+        editor.save_history(path)?;
+    }
+    printer.abort();
+    Ok(())
+}
+
+struct ShellState {
+    sender: UnboundedSender<KolliderMsg>,
+    registry: SubscriptionRegistry,
+    handles: HashMap<SubscriptionKey, SubscriptionHandle>,
+    filter: Arc<Mutex<MessageFilter>>,
+    auth: Option<Arc<dyn Signer>>,
+    default_symbol: Symbol,
+}
+
+type CommandResult = Result<(), Box<dyn Error>>;
+
+struct ShellCommand {
+    name: &'static str,
+    usage: &'static str,
+    run: fn(&mut ShellState, &[String]) -> CommandResult,
+}
+
+const COMMANDS: &[ShellCommand] = &[
+    ShellCommand {
+        name: "subscribe",
+        usage: "subscribe <symbol> <channel>... - subscribe to channels for the symbol",
+        run: subscribe,
+    },
+    ShellCommand {
+        name: "unsubscribe",
+        usage: "unsubscribe <symbol> <channel>... - unsubscribe from channels for the symbol",
+        run: unsubscribe,
+    },
+    ShellCommand {
+        name: "subscriptions",
+        usage: "subscriptions - list subscriptions and their state",
+        run: subscriptions,
+    },
+    ShellCommand {
+        name: "auth",
+        usage: "auth - authentificate the session with configured credentials",
+        run: auth,
+    },
+    ShellCommand {
+        name: "order",
+        usage:
+            "order <bid|ask> <quantity> <price> [symbol] [leverage] [limit|market] - place order",
+        run: order,
+    },
+    ShellCommand {
+        name: "cancel",
+        usage: "cancel <order_id> [symbol] - cancel order",
+        run: cancel,
+    },
+    ShellCommand {
+        name: "positions",
+        usage: "positions - fetch positions",
+        run: positions,
+    },
+    ShellCommand {
+        name: "balances",
+        usage: "balances - fetch balances",
+        run: balances,
+    },
+    ShellCommand {
+        name: "orders",
+        usage: "orders - fetch open orders",
+        run: open_orders,
+    },
+    ShellCommand {
+        name: "leverage",
+        usage: "leverage <leverage> [symbol] - change leverage of position",
+        run: leverage,
+    },
+    ShellCommand {
+        name: "ticker",
+        usage: "ticker [symbol] - request ticker",
+        run: ticker,
+    },
+    ShellCommand {
+        name: "products",
+        usage: "products - request tradable products",
+        run: products,
+    },
+    ShellCommand {
+        name: "show",
+        usage: "show <message type|all>... - print incoming messages of the types",
+        run: show,
+    },
+    ShellCommand {
+        name: "hide",
+        usage: "hide <message type|all>... - don't print incoming messages of the types",
+        run: hide,
+    },
+    ShellCommand {
+        name: "help",
+        usage: "help - print this help",
+        run: help,
+    },
+    ShellCommand {
+        name: "quit",
+        usage: "quit - exit the shell",
+        run: |_, _| Ok(()),
+    },
+];
+
+/// Run single line of input. Returns `true` when the shell should exit.
+fn run_command(state: &mut ShellState, line: &str) -> Result<bool, Box<dyn Error>> {
+    let args: Vec<String> = line.split_whitespace().map(|s| s.to_owned()).collect();
+    let name = match args.first() {
+        Some(name) => name.as_str(),
+        None => return Ok(false),
+    };
+    if name == "quit" || name == "exit" {
+        return Ok(true);
+    }
+    let command = COMMANDS
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| Box::new(UnknownCommand(name.to_owned())))?;
+    (command.run)(state, &args)?;
+    Ok(false)
+}
+
+/// Lines that could carry credentials, e.x. `auth` with arguments, are not saved to the history
+fn keep_in_history(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    !(words.next() == Some("auth") && words.next().is_some())
+}
+
+fn help(_: &mut ShellState, _: &[String]) -> CommandResult {
+    for command in COMMANDS {
+        println!("  {}", command.usage);
+    }
+    Ok(())
+}
+
+fn arg<'a>(args: &'a [String], i: usize, usage: &'static str) -> Result<&'a str, Box<dyn Error>> {
+    args.get(i)
+        .map(|s| s.as_str())
+        .ok_or_else(|| Box::new(MissingArgument(usage)) as Box<dyn Error>)
+}
+
+fn symbol_arg(state: &ShellState, args: &[String], i: usize) -> Symbol {
+    args.get(i)
+        .cloned()
+        .unwrap_or_else(|| state.default_symbol.clone())
+}
+
+fn channels_arg(args: &[String]) -> Result<Vec<ChannelName>, Box<dyn Error>> {
+    if args.len() < 3 {
+        return Err(Box::new(MissingChannel));
+    }
+    let mut channels = vec![];
+    for name in &args[2..] {
+        channels.push(ChannelName::from_str(name)?);
+    }
+    Ok(channels)
+}
+
+fn subscribe(state: &mut ShellState, args: &[String]) -> CommandResult {
     let symbol = args.get(1).ok_or_else(|| Box::new(MissingSybmol))?;
-    let channel = ChannelName::from_str(args.get(2).ok_or_else(|| Box::new(MissingChannel))?)?;
+    for channel in channels_arg(args)? {
+        let key = (channel, symbol.clone());
+        if !state.handles.contains_key(&key) {
+            let handle = state.registry.subscribe(channel, symbol)?;
+            state.handles.insert(key, handle);
+        }
+    }
+    Ok(())
+}
 
-    let msg = KolliderMsg::Subscribe {
-        _type: SubscribeTag::Tag,
-        symbols: vec![symbol.clone()],
-        channels: vec![channel],
+fn unsubscribe(state: &mut ShellState, args: &[String]) -> CommandResult {
+    let symbol = args.get(1).ok_or_else(|| Box::new(MissingSybmol))?;
+    for channel in channels_arg(args)? {
+        if state.handles.remove(&(channel, symbol.clone())).is_none() {
+            println!("Not subscribed to {:?} for {}", channel, symbol);
+        }
+    }
+    Ok(())
+}
+
+fn subscriptions(state: &mut ShellState, _: &[String]) -> CommandResult {
+    for ((channel, symbol), sub_state) in state.registry.subscriptions() {
+        println!("  {} {:?}: {:?}", symbol, channel, sub_state);
+    }
+    Ok(())
+}
+
+fn auth(state: &mut ShellState, args: &[String]) -> CommandResult {
+    if args.len() > 1 {
+        return Err(Box::new(InlineCredentials));
+    }
+    let auth = state
+        .auth
+        .as_ref()
+        .ok_or_else(|| Box::new(MissingCredentials) as Box<dyn Error>)?;
+    let msg = make_signed_auth(auth.as_ref())?;
+    state.sender.unbounded_send(msg)?;
+    Ok(())
+}
+
+fn order(state: &mut ShellState, args: &[String]) -> CommandResult {
+    let usage = "order <bid|ask> <quantity> <price> [symbol] [leverage] [limit|market]";
+    let side = OrderSide::from_str(arg(args, 1, usage)?)?;
+    let quantity = arg(args, 2, usage)?.parse()?;
+    let price = arg(args, 3, usage)?.parse()?;
+    let symbol = symbol_arg(state, args, 4);
+    let leverage = match args.get(5) {
+        Some(v) => v.parse()?,
+        None => 100,
+    };
+    let order_type = match args.get(6) {
+        Some(v) => OrderType::from_str(v)?,
+        None => OrderType::Limit,
     };
-    tx.unbounded_send(msg)?;
+    let ext_order_id = Uuid::new_v4().to_string();
+    state.sender.unbounded_send(KolliderMsg::Order {
+        _type: OrderTag::Tag,
+        price,
+        quantity,
+        symbol,
+        leverage,
+        side,
+        margin_type: MarginType::Isolated,
+        order_type,
+        settlement_type: SettlementType::Delayed,
+        ext_order_id: ext_order_id.clone(),
+    })?;
+    println!("Sent order {}", ext_order_id);
     Ok(())
 }
 
+fn cancel(state: &mut ShellState, args: &[String]) -> CommandResult {
+    let order_id = arg(args, 1, "cancel <order_id> [symbol]")?.parse()?;
+    let symbol = symbol_arg(state, args, 2);
+    state.sender.unbounded_send(KolliderMsg::CancelOrder {
+        _type: CancelOrderTag::Tag,
+        order_id,
+        symbol,
+        settlement_type: SettlementType::Delayed,
+    })?;
+    Ok(())
+}
+
+fn positions(state: &mut ShellState, _: &[String]) -> CommandResult {
+    state.sender.unbounded_send(KolliderMsg::FetchPositions {
+        _type: FetchPositionsTag::Tag,
+    })?;
+    Ok(())
+}
+
+fn balances(state: &mut ShellState, _: &[String]) -> CommandResult {
+    state.sender.unbounded_send(KolliderMsg::FetchBalances {
+        _type: FetchBalancesTag::Tag,
+    })?;
+    Ok(())
+}
+
+fn open_orders(state: &mut ShellState, _: &[String]) -> CommandResult {
+    state.sender.unbounded_send(KolliderMsg::FetchOpenOrders {
+        _type: FetchOpenOrdersTag::Tag,
+    })?;
+    Ok(())
+}
+
+fn leverage(state: &mut ShellState, args: &[String]) -> CommandResult {
+    let leverage = arg(args, 1, "leverage <leverage> [symbol]")?.parse()?;
+    let symbol = symbol_arg(state, args, 2);
+    state.sender.unbounded_send(KolliderMsg::ChangeLeverage {
+        _type: ChangeLeverageTag::Tag,
+        symbol,
+        leverage,
+    })?;
+    Ok(())
+}
+
+fn ticker(state: &mut ShellState, args: &[String]) -> CommandResult {
+    let symbol = symbol_arg(state, args, 1);
+    state.sender.unbounded_send(KolliderMsg::GetTicker {
+        _type: GetTickerTag::Tag,
+        symbol,
+    })?;
+    Ok(())
+}
+
+fn products(state: &mut ShellState, _: &[String]) -> CommandResult {
+    state.sender.unbounded_send(KolliderMsg::TradableProducts {
+        _type: TradableProductsTag::Tag,
+    })?;
+    Ok(())
+}
+
+fn show(state: &mut ShellState, args: &[String]) -> CommandResult {
+    arg(args, 1, "show <message type|all>...")?;
+    let mut filter = state.filter.lock().unwrap();
+    for kind in &args[1..] {
+        filter.show(kind);
+    }
+    Ok(())
+}
+
+fn hide(state: &mut ShellState, args: &[String]) -> CommandResult {
+    arg(args, 1, "hide <message type|all>...")?;
+    let mut filter = state.filter.lock().unwrap();
+    for kind in &args[1..] {
+        filter.hide(kind);
+    }
+    Ok(())
+}
+
+/// Tab completion of command names, channels, symbols and message types
+struct ShellHelper {
+    commands: Vec<String>,
+    arguments: Vec<String>,
+}
+
+impl ShellHelper {
+    fn new(symbols: &[Symbol]) -> Self {
+        let mut commands: Vec<String> = COMMANDS.iter().map(|c| c.name.to_owned()).collect();
+        commands.push("exit".to_owned());
+        let mut arguments: Vec<String> = [
+            "index_values",
+            "orderbook_level1",
+            "orderbook_level2",
+            "orderbook_level3",
+            "matches",
+            "bid",
+            "ask",
+            "limit",
+            "market",
+            "all",
+        ]
+        .iter()
+        .chain(MESSAGE_KINDS.iter())
+        .map(|s| s.to_string())
+        .chain(symbols.iter().cloned())
+        .collect();
+        arguments.sort();
+        arguments.dedup();
+        ShellHelper {
+            commands,
+            arguments,
+        }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let (start, word) = extract_word(line, pos, None, b" \t");
+        let pool = if line[..start].trim().is_empty() {
+            &self.commands
+        } else {
+            &self.arguments
+        };
+        let candidates = pool
+            .iter()
+            .filter(|c| c.starts_with(word))
+            .cloned()
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+#[derive(Debug)]
+struct UnknownCommand(String);
+
+impl fmt::Display for UnknownCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown command '{}', type 'help' to list commands",
+            self.0
+        )
+    }
+}
+
+impl Error for UnknownCommand {}
+
+#[derive(Debug)]
+struct MissingArgument(&'static str);
+
+impl fmt::Display for MissingArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not enough arguments. Usage: {}", self.0)
+    }
+}
+
+impl Error for MissingArgument {}
+
+#[derive(Debug)]
+struct InlineCredentials;
+
+impl fmt::Display for InlineCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Credentials are not accepted in the shell, configure them in the profile, the keystore or the environment"
+        )
+    }
+}
+
+impl Error for InlineCredentials {}
+
+#[derive(Debug)]
+struct MissingCredentials;
+
+impl fmt::Display for MissingCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No credentials configured, set them in the profile, the keystore or the environment"
+        )
+    }
+}
+
+impl Error for MissingCredentials {}
+
 #[derive(Debug)]
 struct MissingSybmol;
 
@@ -62,3 +740,44 @@ impl fmt::Display for MissingChannel {
 }
 
 impl Error for MissingChannel {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_filter() {
+        let mut filter = MessageFilter::default();
+        assert!(filter.is_shown("index_values"));
+
+        filter.hide("index_values");
+        assert!(!filter.is_shown("index_values"));
+        assert!(filter.is_shown("ticker"));
+
+        filter.hide("all");
+        filter.show("fill");
+        assert!(filter.is_shown("fill"));
+        assert!(!filter.is_shown("ticker"));
+
+        filter.show("all");
+        assert!(filter.is_shown("index_values"));
+    }
+
+    #[test]
+    fn test_keep_in_history() {
+        assert!(keep_in_history("subscribe ticker BTCUSD.PERP"));
+        assert!(keep_in_history("auth"));
+        assert!(!keep_in_history(" auth key secret password"));
+    }
+
+    #[test]
+    fn test_format_index_value() {
+        let msg = KolliderMsg::Tagged(KolliderTaggedMsg::IndexValues(IndexValue {
+            denom: "USD".to_owned(),
+            symbol: ".BTCUSD".to_owned(),
+            value: 41900.5,
+        }));
+
+        assert_eq!(format_message(&msg), "[index_values] .BTCUSD 41900.5 USD");
+    }
+}
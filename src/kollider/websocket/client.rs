@@ -1,32 +1,119 @@
-use super::data::KolliderMsg;
-use super::error::Result;
+use super::data::{DecodeMode, KolliderMsg};
+use super::error::{Error, Result};
+use super::recorder::Recorder;
+use crate::kollider::client::clock::global_clock;
+#[cfg(feature = "journal")]
+use crate::kollider::journal::Journal;
+use chrono::Utc;
 use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
-use futures::{future, pin_mut, StreamExt, TryStreamExt};
+use futures::{future, pin_mut, SinkExt, StreamExt, TryStreamExt};
 use log::*;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+};
 
 pub const KOLLIDER_WEBSOCKET: &str = "wss://api.kollider.xyz/v1/ws/";
+pub const KOLLIDER_TESTNET_WEBSOCKET: &str = "wss://test.api.kollider.xyz/v1/ws/";
 
+/// Settings of the websocket worker
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebsocketOptions {
+    pub url: String,
+    /// What to do with incoming messages that we cannot decode
+    pub decode_mode: DecodeMode,
+    /// Record orders, fills and balances passing through the socket
+    #[cfg(feature = "journal")]
+    pub journal: Option<Journal>,
+    /// Write every received frame to a file for later replay
+    pub recorder: Option<Recorder>,
+}
+
+impl Default for WebsocketOptions {
+    fn default() -> Self {
+        WebsocketOptions {
+            url: KOLLIDER_WEBSOCKET.to_owned(),
+            decode_mode: DecodeMode::default(),
+            #[cfg(feature = "journal")]
+            journal: None,
+            recorder: None,
+        }
+    }
+}
+
+impl WebsocketOptions {
+    /// Options of the testnet socket, `KOLLIDER_TESTNET_WEBSOCKET`
+    pub fn testnet() -> Self {
+        WebsocketOptions {
+            url: KOLLIDER_TESTNET_WEBSOCKET.to_owned(),
+            ..WebsocketOptions::default()
+        }
+    }
+}
+
+/// Run websocket worker with default options
 pub async fn kollider_websocket(
     msg_outcoming: UnboundedReceiver<KolliderMsg>,
     msg_incoming: UnboundedSender<KolliderMsg>,
 ) -> Result<()> {
-    let url = url::Url::parse(KOLLIDER_WEBSOCKET)?;
+    kollider_websocket_with(WebsocketOptions::default(), msg_outcoming, msg_incoming).await
+}
+
+/// Socket opened by `kollider_connect`
+pub type KolliderStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
-    let (ws_stream, _) = connect_async(url).await?;
+/// Run websocket worker. In strict decoding mode the worker exits with error on
+/// first message that cannot be decoded.
+pub async fn kollider_websocket_with(
+    options: WebsocketOptions,
+    msg_outcoming: UnboundedReceiver<KolliderMsg>,
+    msg_incoming: UnboundedSender<KolliderMsg>,
+) -> Result<()> {
+    let ws_stream = kollider_connect(&options).await?;
+    kollider_websocket_over(options, ws_stream, msg_outcoming, msg_incoming).await
+}
+
+/// Open the socket. `Date` header of the handshake response is fed to the global server
+/// clock, so auth messages signed after the call carry the server time.
+pub async fn kollider_connect(options: &WebsocketOptions) -> Result<KolliderStream> {
+    let url = url::Url::parse(&options.url)?;
+
+    let sent = Utc::now();
+    let (ws_stream, response) = connect_async(url).await?;
     debug!("WebSocket handshake has been successfully completed");
+    global_clock().observe_date_header(sent, Utc::now(), response.headers());
+    Ok(ws_stream)
+}
 
+/// Run websocket worker over the socket opened by `kollider_connect`
+pub async fn kollider_websocket_over(
+    options: WebsocketOptions,
+    ws_stream: KolliderStream,
+    msg_outcoming: UnboundedReceiver<KolliderMsg>,
+    msg_incoming: UnboundedSender<KolliderMsg>,
+) -> Result<()> {
     let (write, read) = ws_stream.split();
 
+    #[allow(clippy::result_large_err)]
     let stdin_to_ws = msg_outcoming
         .map(|msg| {
+            // Only incoming messages are wrapped into it, it has no wire format
+            if let KolliderMsg::Unknown { type_tag, .. } = msg {
+                return Err(Error::UnknownOutgoing(type_tag));
+            }
+            #[cfg(feature = "journal")]
+            if let Some(journal) = &options.journal {
+                if let Err(e) = journal.record_outgoing(&msg) {
+                    warn!("Failed to journal outgoing message: {}", e);
+                }
+            }
             let msg_str = serde_json::to_string(&msg).unwrap();
             debug!("Sending WS message: {}", msg_str);
             Ok(Message::text(msg_str))
         })
-        .forward(write);
+        .forward(write.sink_map_err(Error::from));
     let ws_to_stdout = {
-        read.try_for_each(|message| async {
+        read.map_err(Error::from).try_for_each(|message| async {
             match message {
                 Message::Ping(data) => trace!("Ping {:?}", data),
                 Message::Pong(data) => trace!("Pong {:?}", data),
@@ -35,18 +122,12 @@ pub async fn kollider_websocket(
                 }
                 _ => {
                     let data = message.into_text()?;
-                    match serde_json::from_str(&data) {
-                        Err(e) => {
-                            error!(
-                                "Failed to decode WS message with error {}, body: {}",
-                                e, data
-                            );
-                        }
-                        Ok(msg) => {
-                            debug!("Incoming WS message: {:?}", msg);
-                            msg_incoming.unbounded_send(msg).unwrap();
+                    if let Some(recorder) = &options.recorder {
+                        if let Err(e) = recorder.record(&data) {
+                            warn!("Failed to record WS frame: {}", e);
                         }
                     }
+                    dispatch_frame(&options, data, &msg_incoming)?;
                 }
             }
             Ok(())
@@ -54,7 +135,56 @@ pub async fn kollider_websocket(
     };
 
     pin_mut!(stdin_to_ws, ws_to_stdout);
-    future::select(stdin_to_ws, ws_to_stdout).await;
+    let res = match future::select(stdin_to_ws, ws_to_stdout).await {
+        future::Either::Right((Err(Error::ConsumerClosed), _)) => {
+            debug!("Consumer of incoming messages is gone");
+            Ok(())
+        }
+        future::Either::Right((Err(e), _)) => Err(e),
+        future::Either::Left((Err(e @ Error::UnknownOutgoing(_)), _)) => Err(e),
+        _ => Ok(()),
+    };
     debug!("Websocket worker exited");
+    res
+}
+
+/// Decode a raw text frame and pass it to the consumer. Shared by the live worker and the
+/// replayer, so both see exactly the same messages. Fails with `Error::ConsumerClosed` when
+/// the receiver is dropped, callers stop on it without reporting.
+#[allow(clippy::result_large_err)]
+pub(crate) fn dispatch_frame(
+    options: &WebsocketOptions,
+    data: String,
+    msg_incoming: &UnboundedSender<KolliderMsg>,
+) -> Result<()> {
+    match KolliderMsg::decode(&data, options.decode_mode) {
+        Err(e) => {
+            error!(
+                "Failed to decode WS message with error {}, body: {}",
+                e, data
+            );
+            if options.decode_mode == DecodeMode::Strict {
+                return Err(Error::Decode(e, data));
+            }
+        }
+        Ok(msg @ KolliderMsg::Unknown { .. }) => {
+            warn!("Unknown WS message: {}", data);
+            msg_incoming
+                .unbounded_send(msg)
+                .map_err(|_| Error::ConsumerClosed)?;
+        }
+        Ok(msg) => {
+            debug!("Incoming WS message: {:?}", msg);
+            #[cfg(feature = "journal")]
+            if let Some(journal) = &options.journal {
+                if let Err(e) = journal.record_incoming(&msg) {
+                    warn!("Failed to journal incoming message: {}", e);
+                }
+            }
+            msg_incoming
+                .unbounded_send(msg)
+                .map_err(|_| Error::ConsumerClosed)?;
+        }
+    }
     Ok(())
 }
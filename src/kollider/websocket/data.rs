@@ -1,12 +1,15 @@
-use crate::kollider::api::{MarginType, OrderSide, OrderType, SettlementType, Symbol};
-use chrono::prelude::*;
-use hmac::{Hmac, Mac};
+use crate::kollider::api::trading::model;
+use crate::kollider::api::{
+    MarginType, OrderDetails, OrderSide, OrderType, Product, SettlementType, Symbol, Ticker,
+};
+use crate::kollider::client::clock::global_clock;
+use crate::kollider::client::env::KolliderAuth;
+use crate::kollider::client::signer::{ws_auth_payload, SignError, Signer};
 use log::*;
 use serde::{Deserialize, Serialize};
 use serde_aux::field_attributes::{
     deserialize_number_from_string, deserialize_option_number_from_string,
 };
-use sha2::Sha256;
 use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
@@ -89,7 +92,50 @@ pub enum KolliderMsg {
         #[serde(rename = "type")]
         _type: TradableProductsTag,
     },
+    ChangeLeverage {
+        #[serde(rename = "type")]
+        _type: ChangeLeverageTag,
+        symbol: Symbol,
+        leverage: u64,
+    },
     Tagged(KolliderTaggedMsg),
+    /// Incoming message that doesn't match any known format. Produced only in
+    /// lenient decoding mode, see `DecodeMode`. The worker fails when it is sent.
+    #[serde(skip_deserializing)]
+    Unknown {
+        /// Value of the "type" field of the message if there is any
+        type_tag: Option<String>,
+        raw: serde_json::Value,
+    },
+}
+
+impl KolliderMsg {
+    /// Decode incoming WS message. In lenient mode messages that we don't know
+    /// are wrapped into `KolliderMsg::Unknown` instead of failing.
+    pub fn decode(data: &str, mode: DecodeMode) -> Result<Self, serde_json::Error> {
+        match serde_json::from_str(data) {
+            Ok(msg) => Ok(msg),
+            Err(e) if mode == DecodeMode::Strict => Err(e),
+            Err(_) => {
+                let raw: serde_json::Value = serde_json::from_str(data)?;
+                let type_tag = raw
+                    .get("type")
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_owned());
+                Ok(KolliderMsg::Unknown { type_tag, raw })
+            }
+        }
+    }
+}
+
+/// How to treat incoming WS messages that cannot be decoded into `KolliderMsg`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub enum DecodeMode {
+    /// Unknown message is an error
+    Strict,
+    /// Unknown message is passed further as `KolliderMsg::Unknown`
+    #[default]
+    Lenient,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
@@ -154,6 +200,12 @@ pub enum TradableProductsTag {
 }
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
+pub enum ChangeLeverageTag {
+    #[serde(rename = "change_leverage")]
+    Tag,
+}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[cfg_attr(feature = "openapi", derive(Schema))]
 pub enum ErrorTag {
     #[serde(rename = "error")]
     Tag,
@@ -173,8 +225,16 @@ pub enum KolliderTaggedMsg {
     Error(String),
     #[serde(rename = "success")]
     Success(String),
+    #[serde(rename = "level1state")]
+    OrderBookLevel1(OrderBookLevel1),
     #[serde(rename = "level2state")]
     OrderBookLevel2(OrderBookLevel2),
+    #[serde(rename = "level3state")]
+    OrderBookLevel3(OrderBookLevel3),
+    #[serde(rename = "ticker")]
+    Ticker(Ticker),
+    #[serde(rename = "matches")]
+    Matches(TradeMatch),
     #[serde(rename = "authenticate")]
     Authenticate { message: String },
     #[serde(rename = "received")]
@@ -220,6 +280,8 @@ pub enum KolliderTaggedMsg {
     OpenOrders {
         open_orders: HashMap<Symbol, Vec<OpenOrder>>,
     },
+    #[serde(rename = "tradable_products")]
+    TradableProducts { symbols: HashMap<Symbol, Product> },
     #[serde(rename = "positions")]
     Positions {
         positions: HashMap<Symbol, Position>,
@@ -301,16 +363,49 @@ pub enum KolliderTaggedMsg {
         ext_order_id: String,
         order_id: u64,
         reason: OrderReject,
+    },
+}
+
+impl KolliderTaggedMsg {
+    /// Value of the "type" field of the message
+    pub fn type_tag(&self) -> &'static str {
+        match self {
+            KolliderTaggedMsg::IndexValues(_) => "index_values",
+            KolliderTaggedMsg::Error(_) => "error",
+            KolliderTaggedMsg::Success(_) => "success",
+            KolliderTaggedMsg::OrderBookLevel1(_) => "level1state",
+            KolliderTaggedMsg::OrderBookLevel2(_) => "level2state",
+            KolliderTaggedMsg::OrderBookLevel3(_) => "level3state",
+            KolliderTaggedMsg::Ticker(_) => "ticker",
+            KolliderTaggedMsg::Matches(_) => "matches",
+            KolliderTaggedMsg::Authenticate { .. } => "authenticate",
+            KolliderTaggedMsg::Received { .. } => "received",
+            KolliderTaggedMsg::Balances { .. } => "balances",
+            KolliderTaggedMsg::Open { .. } => "open",
+            KolliderTaggedMsg::AdvancedOrders { .. } => "user_advanced_orders",
+            KolliderTaggedMsg::OpenOrders { .. } => "open_orders",
+            KolliderTaggedMsg::TradableProducts { .. } => "tradable_products",
+            KolliderTaggedMsg::Positions { .. } => "positions",
+            KolliderTaggedMsg::WithdrawalLimitInfo { .. } => "withdrawal_limit_info",
+            KolliderTaggedMsg::Done { .. } => "done",
+            KolliderTaggedMsg::OrderNotFound { .. } => "order_not_found",
+            KolliderTaggedMsg::Fill { .. } => "fill",
+            KolliderTaggedMsg::Trade { .. } => "trade",
+            KolliderTaggedMsg::SettlementRequest { .. } => "settlement_request",
+            KolliderTaggedMsg::ChangeLeverageInfo { .. } => "change_leverage_info",
+            KolliderTaggedMsg::ChangeLeverageSuccess { .. } => "change_leverage_success",
+            KolliderTaggedMsg::OrderRejection { .. } => "order_rejection",
+        }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct BalancesCash {
-    #[serde(rename="KKP", deserialize_with = "deserialize_number_from_string")]
+    #[serde(rename = "KKP", deserialize_with = "deserialize_number_from_string")]
     pub kkp: f64,
-    #[serde(rename="SAT", deserialize_with = "deserialize_number_from_string")]
-    pub sat: f64, 
+    #[serde(rename = "SAT", deserialize_with = "deserialize_number_from_string")]
+    pub sat: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -333,6 +428,26 @@ pub struct OpenOrder {
     pub uid: u64,
 }
 
+impl From<OpenOrder> for model::Order {
+    fn from(order: OpenOrder) -> Self {
+        model::Order {
+            uid: order.uid,
+            order_id: order.order_id,
+            ext_order_id: order.ext_order_id,
+            symbol: order.symbol,
+            side: order.side,
+            order_type: order.order_type,
+            margin_type: order.margin_type,
+            settlement_type: order.settlement_type,
+            price: order.price,
+            quantity: order.quantity,
+            filled: order.filled,
+            leverage: order.leverage,
+            timestamp: order.timestamp,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub struct Position {
@@ -370,6 +485,33 @@ pub struct Position {
     pub upnl: f64,
 }
 
+impl From<Position> for model::Position {
+    fn from(position: Position) -> Self {
+        model::Position {
+            uid: position.uid,
+            symbol: position.symbol,
+            side: position.side,
+            quantity: position.quantity.round() as u64,
+            entry_price: position.entry_price,
+            leverage: position.leverage,
+            liq_price: position.liq_price,
+            upnl: position.upnl,
+            open_order_ids: position.open_order_ids,
+            timestamp: position.timestamp,
+            position_id: Some(position.position_id),
+            entry_time: position.entry_time,
+            entry_value: Some(position.entry_value),
+            mark_value: Some(position.mark_value),
+            bankruptcy_price: Some(position.bankruptcy_price),
+            rpnl: Some(position.rpnl),
+            funding: Some(position.funding),
+            real_leverage: Some(position.real_leverage),
+            adl_score: Some(position.adl_score),
+            is_liquidating: Some(position.is_liquidating),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 #[cfg_attr(feature = "openapi", derive(Schema))]
 pub enum OrderReject {
@@ -408,6 +550,20 @@ impl fmt::Display for UnknownChannelName {
     }
 }
 
+impl ChannelName {
+    /// Name of the channel in the API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelName::IndexValues => "index_values",
+            ChannelName::OrderBookLevel1 => "orderbook_level1",
+            ChannelName::OrderBookLevel2 => "orderbook_level2",
+            ChannelName::OrderBookLevel3 => "orderbook_level3",
+            ChannelName::Ticker => "ticker",
+            ChannelName::Matches => "matches",
+        }
+    }
+}
+
 impl FromStr for ChannelName {
     type Err = UnknownChannelName;
 
@@ -452,14 +608,71 @@ pub struct OrderBookLevel2 {
     pub update_type: UpdateType,
 }
 
-type HmacSha256 = Hmac<Sha256>;
+/// Best bid and ask from the `orderbook_level1` channel. Same layout as level 2,
+/// but each side contains at most one price.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "openapi", derive(Schema))]
+pub struct OrderBookLevel1 {
+    pub asks: HashMap<String, u64>,
+    pub bids: HashMap<String, u64>,
+    pub seq_number: u64,
+    pub symbol: Symbol,
+    pub update_type: UpdateType,
+}
+
+/// Per order book from the `orderbook_level3` channel. In delta updates each
+/// price level replaces the whole list of orders at the price, empty list removes
+/// the level.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "openapi", derive(Schema))]
+pub struct OrderBookLevel3 {
+    pub asks: Vec<PriceLevel>,
+    pub bids: Vec<PriceLevel>,
+    pub seq_number: u64,
+    pub symbol: Symbol,
+    pub update_type: UpdateType,
+}
+
+/// Orders at the price in a level 3 book, `[price, [orders...]]` on the wire
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(from = "(u64, Vec<OrderDetails>)", into = "(u64, Vec<OrderDetails>)")]
+#[cfg_attr(feature = "openapi", derive(Schema))]
+pub struct PriceLevel {
+    pub price: u64,
+    pub orders: Vec<OrderDetails>,
+}
+
+impl From<(u64, Vec<OrderDetails>)> for PriceLevel {
+    fn from((price, orders): (u64, Vec<OrderDetails>)) -> Self {
+        PriceLevel { price, orders }
+    }
+}
+
+impl From<PriceLevel> for (u64, Vec<OrderDetails>) {
+    fn from(level: PriceLevel) -> Self {
+        (level.price, level.orders)
+    }
+}
+
+/// Public trade from the `matches` channel
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "openapi", derive(Schema))]
+pub struct TradeMatch {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub price: f64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub quantity: u64,
+    pub side: OrderSide,
+    pub symbol: Symbol,
+    pub timestamp: u64,
+}
 
 #[derive(Error, Debug)]
 pub enum AuthError {
     #[error("Failed to parse API secret from base64: {0}")]
     ApiSecretDecode(#[from] base64::DecodeError),
-    #[error("Invalid length of API secret: {0}")]
-    ApiSecretLength(#[from] crypto_common::InvalidLength),
+    #[error("Failed to sign auth message: {0}")]
+    Sign(#[from] SignError),
 }
 
 /// Make user auth message for WebSocket
@@ -468,23 +681,39 @@ pub fn make_user_auth(
     api_key: &str,
     passphrase: &str,
 ) -> Result<KolliderMsg, AuthError> {
-    let mut mac = HmacSha256::new_from_slice(&base64::decode(api_secret)?)?;
-    let mut payload = vec![];
-    let timestamp = format!("{}", Utc::now().timestamp());
-    payload.extend(timestamp.as_bytes());
-    payload.extend("authentication".bytes());
-    trace!("HMAC payload: {}", std::str::from_utf8(&payload).unwrap());
-    mac.update(&payload);
-    let signature = base64::encode(mac.finalize().into_bytes());
-    trace!("Signagure {}", signature);
+    let auth = KolliderAuth::new(api_key, api_secret, passphrase)?;
+    make_signed_auth(&auth)
+}
+
+/// Make user auth message for WebSocket with signature made by the signer. Timestamp is
+/// taken from the global server clock.
+pub fn make_signed_auth(signer: &dyn Signer) -> Result<KolliderMsg, AuthError> {
+    make_signed_auth_at(signer, global_clock().timestamp())
+}
+
+/// Make user auth message for WebSocket signed for the given timestamp in seconds
+pub fn make_signed_auth_at(signer: &dyn Signer, timestamp: i64) -> Result<KolliderMsg, AuthError> {
+    let timestamp = format!("{}", timestamp);
+    let signature = signer.sign(&ws_auth_payload(&timestamp))?;
+    Ok(user_auth(signer, timestamp, signature))
+}
+
+/// Same as `make_signed_auth`, but the signature is made with `Signer::sign_async`
+pub async fn make_signed_auth_async(signer: &dyn Signer) -> Result<KolliderMsg, AuthError> {
+    let timestamp = format!("{}", global_clock().timestamp());
+    let signature = signer.sign_async(&ws_auth_payload(&timestamp)).await?;
+    Ok(user_auth(signer, timestamp, signature))
+}
 
-    Ok(KolliderMsg::UserAuth {
+fn user_auth(signer: &dyn Signer, timestamp: String, signature: String) -> KolliderMsg {
+    trace!("Signagure {}", signature);
+    KolliderMsg::UserAuth {
         _type: AuthenticateTag::Tag,
-        token: api_key.to_owned(),
-        passphrase: passphrase.to_owned(),
+        token: signer.api_key().to_owned(),
+        passphrase: signer.passphrase().to_owned(),
         signature,
         timestamp,
-    })
+    }
 }
 
 #[cfg(test)]
@@ -549,6 +778,63 @@ mod tests {
                 },
             }
         );
+
+        let KolliderTaggedMsg::OpenOrders { mut open_orders } = v else {
+            panic!("Expected open orders message");
+        };
+        let order: model::Order = open_orders.remove("BTCUSD.PERP").unwrap().remove(0).into();
+        assert_eq!(order.order_id, 9951519);
+        assert_eq!(order.remaining(), 1);
+    }
+
+    #[test]
+    fn test_positions_msg() {
+        let data = r#"
+        {
+            "data": {
+                "positions": {
+                    "BTCUSD.PERP": {
+                        "adl_score": "0.0",
+                        "bankruptcy_price": "0.0",
+                        "entry_price": "47340.5",
+                        "entry_time": 1640000000000,
+                        "entry_value": "2112",
+                        "funding": "0",
+                        "is_liquidating": false,
+                        "leverage": "1.00",
+                        "liq_price": "0.0",
+                        "mark_value": "2110",
+                        "open_order_ids": [9951519],
+                        "position_id": "7051-BTCUSD.PERP",
+                        "quantity": "1",
+                        "real_leverage": "1.0",
+                        "rpnl": "0",
+                        "side": "Bid",
+                        "symbol": "BTCUSD.PERP",
+                        "timestamp": 1640000000000,
+                        "uid": 7051,
+                        "upnl": "-2"
+                    }
+                }
+            },
+            "seq": 648,
+            "type": "positions"
+        }
+        "#;
+
+        let v: KolliderTaggedMsg = serde_json::from_str(data).unwrap();
+        let position: model::Position = match v {
+            KolliderTaggedMsg::Positions { mut positions } => {
+                positions.remove("BTCUSD.PERP").unwrap().into()
+            }
+            _ => panic!("Expected positions message, got {:?}", v),
+        };
+        assert_eq!(position.side, Some(OrderSide::Bid));
+        assert_eq!(position.quantity, 1);
+        assert_eq!(position.upnl, -2.0);
+        assert_eq!(position.open_order_ids, vec![9951519]);
+        assert_eq!(position.mark_value, Some(2110.0));
+        assert_eq!(position.position_id.as_deref(), Some("7051-BTCUSD.PERP"));
     }
 
     #[test]
@@ -749,4 +1035,272 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_level1_msg() {
+        let data = r#"
+        {
+            "data": {
+                "asks": {
+                    "486950": 1016
+                },
+                "bids": {
+                    "486840": 1016
+                },
+                "seq_number": 8411464,
+                "symbol": "BTCUSD.PERP",
+                "update_type": "snapshot"
+            },
+            "seq": 2,
+            "type": "level1state"
+        }
+        "#;
+
+        let v: KolliderTaggedMsg = serde_json::from_str(data).unwrap();
+
+        assert_eq!(
+            v,
+            KolliderTaggedMsg::OrderBookLevel1(OrderBookLevel1 {
+                asks: hashmap! { "486950".to_owned() => 1016 },
+                bids: hashmap! { "486840".to_owned() => 1016 },
+                seq_number: 8411464,
+                symbol: "BTCUSD.PERP".to_owned(),
+                update_type: UpdateType::Snapshot,
+            })
+        );
+    }
+
+    #[test]
+    fn test_level3_msg() {
+        let data = r#"
+        {
+            "data": {
+                "asks": [
+                    [
+                        486950,
+                        [
+                            {
+                                "advanced_order_type": null,
+                                "ext_order_id": "07e10e56-bd45-4e3c-9981-688e6af7fc69",
+                                "filled": 0,
+                                "leverage": 100,
+                                "margin_type": "Isolated",
+                                "order_id": 9317213,
+                                "order_type": "Limit",
+                                "price": 486950,
+                                "quantity": 1016,
+                                "settlement_type": "Delayed",
+                                "side": "Ask",
+                                "symbol": "BTCUSD.PERP",
+                                "timestamp": 0,
+                                "trigger_price_type": null,
+                                "uid": 1
+                            }
+                        ]
+                    ]
+                ],
+                "bids": [
+                    [486840, []]
+                ],
+                "seq_number": 8411405,
+                "symbol": "BTCUSD.PERP",
+                "update_type": "delta"
+            },
+            "seq": 3,
+            "type": "level3state"
+        }
+        "#;
+
+        let v: KolliderTaggedMsg = serde_json::from_str(data).unwrap();
+
+        assert_eq!(
+            v,
+            KolliderTaggedMsg::OrderBookLevel3(OrderBookLevel3 {
+                asks: vec![PriceLevel::from((
+                    486950,
+                    vec![OrderDetails {
+                        ext_order_id: "07e10e56-bd45-4e3c-9981-688e6af7fc69".to_owned(),
+                        filled: 0.0,
+                        leverage: 100,
+                        margin_type: MarginType::Isolated,
+                        order_id: 9317213,
+                        order_type: OrderType::Limit,
+                        price: 486950,
+                        quantity: 1016,
+                        settlement_type: SettlementType::Delayed,
+                        side: OrderSide::Ask,
+                        symbol: "BTCUSD.PERP".to_owned(),
+                        timestamp: 0,
+                        uid: 1,
+                    }]
+                ))],
+                bids: vec![PriceLevel::from((486840, vec![]))],
+                seq_number: 8411405,
+                symbol: "BTCUSD.PERP".to_owned(),
+                update_type: UpdateType::Delta,
+            })
+        );
+    }
+
+    #[test]
+    fn test_ticker_msg() {
+        let data = r#"
+        {
+            "data": {
+                "best_ask": "47949.5",
+                "best_bid": "47938.5",
+                "last_price": "46490.0",
+                "last_quantity": 100,
+                "last_side": "Bid",
+                "symbol": "BTCUSD.PERP"
+            },
+            "seq": 4,
+            "type": "ticker"
+        }
+        "#;
+
+        let v: KolliderTaggedMsg = serde_json::from_str(data).unwrap();
+
+        assert_eq!(
+            v,
+            KolliderTaggedMsg::Ticker(Ticker {
+                best_ask: 47949.5,
+                best_bid: 47938.5,
+                last_price: 46490.0,
+                last_quantity: 100,
+                last_side: OrderSide::Bid,
+                symbol: "BTCUSD.PERP".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_matches_msg() {
+        let data = r#"
+        {
+            "data": {
+                "price": "41900.5",
+                "quantity": "3",
+                "side": "Ask",
+                "symbol": "BTCUSD.PERP",
+                "timestamp": 1642633795546
+            },
+            "seq": 5,
+            "type": "matches"
+        }
+        "#;
+
+        let v: KolliderTaggedMsg = serde_json::from_str(data).unwrap();
+
+        assert_eq!(
+            v,
+            KolliderTaggedMsg::Matches(TradeMatch {
+                price: 41900.5,
+                quantity: 3,
+                side: OrderSide::Ask,
+                symbol: "BTCUSD.PERP".to_owned(),
+                timestamp: 1642633795546,
+            })
+        );
+    }
+
+    #[test]
+    fn test_tradable_products_msg() {
+        let data = r#"
+        {
+            "data": {
+                "symbols": {
+                    "BTCUSD.PERP": {
+                        "symbol": "BTCUSD.PERP",
+                        "contract_size": "1",
+                        "max_leverage": "100.00",
+                        "base_margin": "0.00500",
+                        "maintenance_margin": "0.00400",
+                        "is_inverse_priced": true,
+                        "price_dp": "1",
+                        "underlying_symbol": ".XTBUSD",
+                        "last_price": "130713",
+                        "tick_size": "0.5",
+                        "risk_limit": "100000000"
+                    }
+                }
+            },
+            "seq": 6,
+            "type": "tradable_products"
+        }
+        "#;
+
+        let v: KolliderTaggedMsg = serde_json::from_str(data).unwrap();
+
+        assert_eq!(
+            v,
+            KolliderTaggedMsg::TradableProducts {
+                symbols: hashmap! {
+                    "BTCUSD.PERP".to_owned() => Product {
+                        symbol: "BTCUSD.PERP".to_owned(),
+                        contract_size: 1.0,
+                        max_leverage: 100.0,
+                        base_margin: 0.005,
+                        maintenance_margin: 0.004,
+                        is_inverse_priced: true,
+                        price_dp: 1.0,
+                        underlying_symbol: ".XTBUSD".to_owned(),
+                        last_price: 130713.0,
+                        tick_size: 0.5,
+                        risk_limit: 100000000.0,
+                    }
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_msg_lenient() {
+        let data = r#"
+        {
+            "data": {
+                "symbol": "BTCUSD.PERP"
+            },
+            "seq": 42,
+            "type": "brand_new_message"
+        }
+        "#;
+
+        let v = KolliderMsg::decode(data, DecodeMode::Lenient).unwrap();
+
+        assert_eq!(
+            v,
+            KolliderMsg::Unknown {
+                type_tag: Some("brand_new_message".to_owned()),
+                raw: serde_json::json!({
+                    "data": {
+                        "symbol": "BTCUSD.PERP"
+                    },
+                    "seq": 42,
+                    "type": "brand_new_message"
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_msg_strict() {
+        let data = r#"{"data": {}, "seq": 42, "type": "brand_new_message"}"#;
+
+        assert!(KolliderMsg::decode(data, DecodeMode::Strict).is_err());
+    }
+
+    #[test]
+    fn test_known_msg_lenient() {
+        let data = r#"{"data": {"symbol": "BTCUSD.PERP"}, "seq": 12523, "type": "change_leverage_success"}"#;
+
+        let v = KolliderMsg::decode(data, DecodeMode::Lenient).unwrap();
+
+        assert_eq!(
+            v,
+            KolliderMsg::Tagged(KolliderTaggedMsg::ChangeLeverageSuccess {
+                symbol: "BTCUSD.PERP".to_owned(),
+            })
+        );
+    }
 }
@@ -6,6 +6,16 @@ pub enum Error {
     UrlDecode(#[from] url::ParseError),
     #[error("Websocket operation error: {0}")]
     SocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("Failed to decode WS message with error {0}, body: {1}")]
+    Decode(serde_json::Error, String),
+    #[error("Failed to access recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed frame at line {0} of recording: {1}")]
+    Recording(usize, serde_json::Error),
+    #[error("Consumer of incoming messages is gone")]
+    ConsumerClosed,
+    #[error("Unknown message with type {0:?} cannot be sent")]
+    UnknownOutgoing(Option<String>),
 }
 
 /// Alias for a `Result` with the error type `self::Error`.
@@ -1,9 +1,16 @@
+pub mod book;
 pub mod cli;
 pub mod client;
 pub mod data;
 pub mod error;
 pub mod oneshot;
+pub mod recorder;
+pub mod session;
+pub mod subscription;
 
+pub use book::*;
 pub use cli::*;
 pub use client::*;
 pub use data::*;
+pub use recorder::*;
+pub use subscription::*;
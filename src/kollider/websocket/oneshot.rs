@@ -1,18 +1,21 @@
-use super::client::kollider_websocket;
+use super::client::{kollider_connect, kollider_websocket_over, KolliderStream, WebsocketOptions};
 use super::data::{
-    make_user_auth, AuthError, BalancesCash, CancelOrderTag, FetchBalancesTag, FetchPositionsTag,
-    KolliderMsg, KolliderTaggedMsg, OrderReject, OrderTag, Position,
+    make_signed_auth_async, AuthError, BalancesCash, CancelOrderTag, FetchBalancesTag,
+    FetchOpenOrdersTag, FetchPositionsTag, GetTickerTag, KolliderMsg, KolliderTaggedMsg, OpenOrder,
+    OrderReject, OrderTag, Position, TradableProductsTag,
 };
-use crate::kollider::api::{OrderBody, OrderCreated, SettlementType, Symbol};
-use crate::kollider::client::env::KolliderAuth;
+use super::error::Error as WebsocketError;
+use crate::kollider::api::{OrderBody, OrderCreated, Product, SettlementType, Symbol, Ticker};
+use crate::kollider::client::signer::Signer;
 use futures::future::Future;
 use futures::StreamExt;
 use futures_channel::mpsc::{TrySendError, UnboundedSender};
+use serde::Serialize;
 use std::collections::HashMap;
 use thiserror::Error;
 use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct Balances {
     pub cash: BalancesCash,
     pub cross_margin: f64,
@@ -21,32 +24,104 @@ pub struct Balances {
 }
 use std::time::Duration;
 
+/// Limit on opening the socket and on waiting for the reply
+const ONESHOT_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Failed to make auth data: {0}")]
     Auth(#[from] AuthError),
+    #[error("Failed to open WebSocket: {0}")]
+    Connect(Box<WebsocketError>),
     #[error("Failed to communicate via channel: {0}")]
-    Channel(#[from] TrySendError<KolliderMsg>),
+    Channel(Box<TrySendError<KolliderMsg>>),
     #[error("There is no response from the server")]
     NoResponse,
     #[error("Order {0} rejected, reason: {1:#?}")]
     OrderError(u64, OrderReject),
     #[error("Cannot cancel order {0}, reason: {1}")]
     CancelError(u64, String),
+    #[error("Request requires authentification, but no credentials are set")]
+    AuthRequired,
+    #[error("Request rejected: {0}")]
+    Rejected(String),
+}
+
+impl From<WebsocketError> for Error {
+    fn from(e: WebsocketError) -> Self {
+        Error::Connect(Box::new(e))
+    }
+}
+
+impl From<TrySendError<KolliderMsg>> for Error {
+    fn from(e: TrySendError<KolliderMsg>) -> Self {
+        Error::Channel(Box::new(e))
+    }
 }
 
 /// Helper to create oneshot sync requests via websocket. Open socket, request, wait for response, close.
-pub async fn oneshot_ws_request<F, Fut, T>(auth: &KolliderAuth, body: F) -> Result<T, Error>
+pub async fn oneshot_ws_request<F, Fut, T>(auth: &dyn Signer, body: F) -> Result<T, Error>
+where
+    F: FnOnce(UnboundedSender<KolliderMsg>, KolliderMsg) -> Fut + Clone,
+    Fut: Future<Output = Result<Option<T>, Error>>,
+{
+    oneshot_ws_request_with(&WebsocketOptions::default(), auth, body).await
+}
+
+/// Same as `oneshot_ws_request`, but the socket is opened with the given options
+pub async fn oneshot_ws_request_with<F, Fut, T>(
+    options: &WebsocketOptions,
+    auth: &dyn Signer,
+    body: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(UnboundedSender<KolliderMsg>, KolliderMsg) -> Fut + Clone,
+    Fut: Future<Output = Result<Option<T>, Error>>,
+{
+    let stream = connect(options).await?;
+    // Signed after the handshake that measured the server clock
+    let auth_msg = make_signed_auth_async(auth).await?;
+    oneshot_ws_send(options, stream, auth_msg, body).await
+}
+
+/// Helper to create oneshot sync requests that don't require authentification.
+pub async fn oneshot_public<F, Fut, T>(request: KolliderMsg, body: F) -> Result<T, Error>
+where
+    F: FnOnce(KolliderMsg) -> Fut + Clone,
+    Fut: Future<Output = Result<Option<T>, Error>>,
+{
+    let options = WebsocketOptions::default();
+    let stream = connect(&options).await?;
+    oneshot_ws_send(&options, stream, request, |_, message| body(message)).await
+}
+
+async fn connect(options: &WebsocketOptions) -> Result<KolliderStream, Error> {
+    match tokio::time::timeout(ONESHOT_TIMEOUT, kollider_connect(options)).await {
+        Ok(stream) => Ok(stream?),
+        Err(_) => Err(Error::NoResponse),
+    }
+}
+
+/// Send the first message to the socket and feed incoming messages to the body until it returns value.
+async fn oneshot_ws_send<F, Fut, T>(
+    options: &WebsocketOptions,
+    stream: KolliderStream,
+    first_msg: KolliderMsg,
+    body: F,
+) -> Result<T, Error>
 where
     F: FnOnce(UnboundedSender<KolliderMsg>, KolliderMsg) -> Fut + Clone,
     Fut: Future<Output = Result<Option<T>, Error>>,
 {
     let (stdin_tx, stdin_rx) = futures_channel::mpsc::unbounded();
     let (msg_sender, mut msg_receiver) = futures_channel::mpsc::unbounded();
-    let secret_str = base64::encode(&auth.api_secret);
-    let auth_msg = make_user_auth(&secret_str, &auth.api_key, &auth.password)?;
-    stdin_tx.unbounded_send(auth_msg)?;
-    tokio::spawn(kollider_websocket(stdin_rx, msg_sender));
+    stdin_tx.unbounded_send(first_msg)?;
+    tokio::spawn(kollider_websocket_over(
+        options.clone(),
+        stream,
+        stdin_rx,
+        msg_sender,
+    ));
 
     let listen_fut = async move {
         loop {
@@ -62,7 +137,7 @@ where
         }
     };
 
-    let res = tokio::time::timeout(Duration::from_secs(60), listen_fut).await;
+    let res = tokio::time::timeout(ONESHOT_TIMEOUT, listen_fut).await;
     match res {
         Err(_) => Err(Error::NoResponse),
         Ok(e) => e,
@@ -71,7 +146,21 @@ where
 
 /// Open websocket and request positions as synchronous request
 pub async fn oneshot_authed<F, Fut, T>(
-    auth: &KolliderAuth,
+    auth: &dyn Signer,
+    on_auth: KolliderMsg,
+    body: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(KolliderMsg) -> Fut + Clone,
+    Fut: Future<Output = Result<Option<T>, Error>>,
+{
+    oneshot_authed_with(&WebsocketOptions::default(), auth, on_auth, body).await
+}
+
+/// Same as `oneshot_authed`, but the socket is opened with the given options
+pub async fn oneshot_authed_with<F, Fut, T>(
+    options: &WebsocketOptions,
+    auth: &dyn Signer,
     on_auth: KolliderMsg,
     body: F,
 ) -> Result<T, Error>
@@ -79,7 +168,7 @@ where
     F: FnOnce(KolliderMsg) -> Fut + Clone,
     Fut: Future<Output = Result<Option<T>, Error>>,
 {
-    oneshot_ws_request(auth, |stdin_tx, message| async move {
+    oneshot_ws_request_with(options, auth, |stdin_tx, message| async move {
         match message {
             KolliderMsg::Tagged(KolliderTaggedMsg::Authenticate { message })
                 if message == "success" =>
@@ -94,8 +183,17 @@ where
 }
 
 /// Open websocket and request balances as synchronous request
-pub async fn fetch_balances(auth: &KolliderAuth) -> Result<Balances, Error> {
-    oneshot_authed(
+pub async fn fetch_balances(auth: &dyn Signer) -> Result<Balances, Error> {
+    fetch_balances_with(&WebsocketOptions::default(), auth).await
+}
+
+/// Same as `fetch_balances`, but the socket is opened with the given options
+pub async fn fetch_balances_with(
+    options: &WebsocketOptions,
+    auth: &dyn Signer,
+) -> Result<Balances, Error> {
+    oneshot_authed_with(
+        options,
         auth,
         KolliderMsg::FetchBalances {
             _type: FetchBalancesTag::Tag,
@@ -121,8 +219,17 @@ pub async fn fetch_balances(auth: &KolliderAuth) -> Result<Balances, Error> {
 }
 
 /// Open websocket and request positions as synchronous request
-pub async fn fetch_positions(auth: &KolliderAuth) -> Result<HashMap<Symbol, Position>, Error> {
-    oneshot_authed(
+pub async fn fetch_positions(auth: &dyn Signer) -> Result<HashMap<Symbol, Position>, Error> {
+    fetch_positions_with(&WebsocketOptions::default(), auth).await
+}
+
+/// Same as `fetch_positions`, but the socket is opened with the given options
+pub async fn fetch_positions_with(
+    options: &WebsocketOptions,
+    auth: &dyn Signer,
+) -> Result<HashMap<Symbol, Position>, Error> {
+    oneshot_authed_with(
+        options,
         auth,
         KolliderMsg::FetchPositions {
             _type: FetchPositionsTag::Tag,
@@ -139,13 +246,94 @@ pub async fn fetch_positions(auth: &KolliderAuth) -> Result<HashMap<Symbol, Posi
     .await
 }
 
+/// Open websocket and request open orders as synchronous request
+pub async fn fetch_open_orders(
+    auth: &dyn Signer,
+) -> Result<HashMap<Symbol, Vec<OpenOrder>>, Error> {
+    fetch_open_orders_with(&WebsocketOptions::default(), auth).await
+}
+
+/// Same as `fetch_open_orders`, but the socket is opened with the given options
+pub async fn fetch_open_orders_with(
+    options: &WebsocketOptions,
+    auth: &dyn Signer,
+) -> Result<HashMap<Symbol, Vec<OpenOrder>>, Error> {
+    oneshot_authed_with(
+        options,
+        auth,
+        KolliderMsg::FetchOpenOrders {
+            _type: FetchOpenOrdersTag::Tag,
+        },
+        |message| async move {
+            match message {
+                KolliderMsg::Tagged(KolliderTaggedMsg::OpenOrders { open_orders }) => {
+                    Ok(Some(open_orders))
+                }
+                _ => Ok(None),
+            }
+        },
+    )
+    .await
+}
+
+/// Open websocket and request ticker of the symbol as synchronous request
+pub async fn get_ticker(symbol: &str) -> Result<Ticker, Error> {
+    let ticker_symbol = symbol.to_owned();
+    oneshot_public(
+        KolliderMsg::GetTicker {
+            _type: GetTickerTag::Tag,
+            symbol: symbol.to_owned(),
+        },
+        |message| async move {
+            match message {
+                KolliderMsg::Tagged(KolliderTaggedMsg::Ticker(ticker))
+                    if ticker.symbol == ticker_symbol =>
+                {
+                    Ok(Some(ticker))
+                }
+                _ => Ok(None),
+            }
+        },
+    )
+    .await
+}
+
+/// Open websocket and request tradable products as synchronous request
+pub async fn tradable_products() -> Result<HashMap<Symbol, Product>, Error> {
+    oneshot_public(
+        KolliderMsg::TradableProducts {
+            _type: TradableProductsTag::Tag,
+        },
+        |message| async move {
+            match message {
+                KolliderMsg::Tagged(KolliderTaggedMsg::TradableProducts { symbols }) => {
+                    Ok(Some(symbols))
+                }
+                _ => Ok(None),
+            }
+        },
+    )
+    .await
+}
+
 /// Open websocket and request positions as synchronous request
 pub async fn cancel_order(
-    auth: &KolliderAuth,
+    auth: &dyn Signer,
     cancel_order_id: u64,
     symbol: &str,
 ) -> Result<(), Error> {
-    oneshot_authed(
+    cancel_order_with(&WebsocketOptions::default(), auth, cancel_order_id, symbol).await
+}
+
+/// Same as `cancel_order`, but the socket is opened with the given options
+pub async fn cancel_order_with(
+    options: &WebsocketOptions,
+    auth: &dyn Signer,
+    cancel_order_id: u64,
+    symbol: &str,
+) -> Result<(), Error> {
+    oneshot_authed_with(
+        options,
         auth,
         KolliderMsg::CancelOrder {
             _type: CancelOrderTag::Tag,
@@ -169,8 +357,18 @@ pub async fn cancel_order(
 }
 
 /// Open websocket and open order as synchronous request
-pub async fn open_order(auth: &KolliderAuth, body: &OrderBody) -> Result<OrderCreated, Error> {
-    oneshot_authed(
+pub async fn open_order(auth: &dyn Signer, body: &OrderBody) -> Result<OrderCreated, Error> {
+    open_order_with(&WebsocketOptions::default(), auth, body).await
+}
+
+/// Same as `open_order`, but the socket is opened with the given options
+pub async fn open_order_with(
+    options: &WebsocketOptions,
+    auth: &dyn Signer,
+    body: &OrderBody,
+) -> Result<OrderCreated, Error> {
+    oneshot_authed_with(
+        options,
         auth,
         KolliderMsg::Order {
             _type: OrderTag::Tag,
@@ -0,0 +1,209 @@
+//! Recording of raw websocket frames to JSONL files and their replay through the same decoding
+//! path as the live worker, so order book and strategy code can be tested offline.
+use super::client::{dispatch_frame, WebsocketOptions};
+use super::data::KolliderMsg;
+use super::error::{Error, Result};
+use chrono::Utc;
+use futures::channel::mpsc::UnboundedSender;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Single line of a recording
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RecordedFrame {
+    /// Time of receiving in milliseconds since Unix epoch
+    pub timestamp: i64,
+    /// Text of the frame as it came from the server
+    pub frame: String,
+}
+
+/// Appends received frames to a JSONL file. Clones write to the same file.
+#[derive(Clone)]
+pub struct Recorder {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl PartialEq for Recorder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.file, &other.file)
+    }
+}
+
+impl Recorder {
+    /// Open the file for appending, creating it if needed
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            path: path.to_owned(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the frame with the current time
+    pub fn record(&self, frame: &str) -> std::io::Result<()> {
+        self.record_frame(&RecordedFrame {
+            timestamp: Utc::now().timestamp_millis(),
+            frame: frame.to_owned(),
+        })
+    }
+
+    pub fn record_frame(&self, frame: &RecordedFrame) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(frame)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+    }
+}
+
+/// Pace of replaying a recording
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the original delays between frames
+    Original,
+    /// Divide the original delays by the factor
+    Accelerated(f64),
+    /// Feed frames as fast as the consumer takes them
+    Instant,
+}
+
+impl ReplaySpeed {
+    fn factor(self) -> Option<f64> {
+        match self {
+            ReplaySpeed::Original => Some(1.0),
+            ReplaySpeed::Accelerated(factor) if factor > 0.0 => Some(factor),
+            ReplaySpeed::Accelerated(_) | ReplaySpeed::Instant => None,
+        }
+    }
+}
+
+/// Read all frames of a recording
+#[allow(clippy::result_large_err)]
+pub fn read_recording(path: &Path) -> Result<Vec<RecordedFrame>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut frames = vec![];
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame = serde_json::from_str(&line).map_err(|e| Error::Recording(index + 1, e))?;
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// Feed recorded frames to the consumer as if they came from the server. Decoding mode and
+/// journal are taken from `options`, the URL and recorder are ignored. Replay stops early
+/// without error when the consumer drops the receiver.
+pub async fn replay_websocket(
+    path: &Path,
+    speed: ReplaySpeed,
+    options: &WebsocketOptions,
+    msg_incoming: UnboundedSender<KolliderMsg>,
+) -> Result<()> {
+    let frames = read_recording(path)?;
+    debug!("Replaying {} frames from {}", frames.len(), path.display());
+    let started = Instant::now();
+    let first = frames.first().map(|frame| frame.timestamp);
+    for frame in frames {
+        if let (Some(factor), Some(first)) = (speed.factor(), first) {
+            let offset = (frame.timestamp - first).max(0) as f64 / factor;
+            tokio::time::sleep_until(started + Duration::from_secs_f64(offset / 1000.0)).await;
+        }
+        match dispatch_frame(options, frame.frame, &msg_incoming) {
+            Err(Error::ConsumerClosed) => {
+                debug!("Replay of {} stopped by the consumer", path.display());
+                return Ok(());
+            }
+            res => res?,
+        }
+    }
+    debug!("Replay of {} finished", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kollider::websocket::data::DecodeMode;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let path =
+            std::env::temp_dir().join(format!("kollider-record-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let recorder = Recorder::create(&path).unwrap();
+        let frames = [
+            r#"{"data": {"symbol": "BTCUSD.PERP"}, "seq": 1, "type": "change_leverage_success"}"#,
+            r#"{"data": {}, "seq": 2, "type": "brand_new_message"}"#,
+            r#"{"data": {"symbol": "BTCUSD.PERP"}, "seq": 3, "type": "change_leverage_success"}"#,
+        ];
+        for (i, frame) in frames.iter().enumerate() {
+            recorder
+                .record_frame(&RecordedFrame {
+                    timestamp: 1640000000000 + 100 * i as i64,
+                    frame: frame.to_string(),
+                })
+                .unwrap();
+        }
+        assert_eq!(read_recording(&path).unwrap().len(), 3);
+
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let started = std::time::Instant::now();
+        replay_websocket(
+            &path,
+            ReplaySpeed::Accelerated(10.0),
+            &WebsocketOptions::default(),
+            tx,
+        )
+        .await
+        .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        let msgs: Vec<KolliderMsg> = rx.collect().await;
+        assert_eq!(msgs.len(), 3);
+        assert!(matches!(msgs[1], KolliderMsg::Unknown { .. }));
+
+        let (tx, _rx) = futures::channel::mpsc::unbounded();
+        let options = WebsocketOptions {
+            decode_mode: DecodeMode::Strict,
+            ..WebsocketOptions::default()
+        };
+        match replay_websocket(&path, ReplaySpeed::Instant, &options, tx).await {
+            Err(Error::Decode(..)) => (),
+            res => panic!("Expected decode error, got {:?}", res),
+        }
+
+        // The consumer is gone before the end of the recording
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        drop(rx);
+        replay_websocket(
+            &path,
+            ReplaySpeed::Instant,
+            &WebsocketOptions::default(),
+            tx,
+        )
+        .await
+        .unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
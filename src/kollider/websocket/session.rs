@@ -0,0 +1,331 @@
+//! Long living authentificated WebSocket session. Unlike `oneshot`, the socket is opened and
+//! authentificated once and shared by all requests. Replies are matched to requests by
+//! `ext_order_id` for orders, by `order_id` for cancels and by type for fetches, so requests
+//! may be in flight concurrently.
+use super::client::{kollider_connect, kollider_websocket_over, WebsocketOptions};
+use super::data::{
+    make_signed_auth_async, CancelOrderTag, FetchOpenOrdersTag, FetchPositionsTag, KolliderMsg,
+    KolliderTaggedMsg, OpenOrder, OrderTag, Position,
+};
+use super::oneshot::Error;
+use crate::kollider::api::{OrderBody, OrderCreated, SettlementType, Symbol};
+use crate::kollider::client::signer::Signer;
+use futures::channel::oneshot;
+use futures::StreamExt;
+use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Limit on opening the socket and on waiting for a reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Reply<T> = oneshot::Sender<Result<T, Error>>;
+
+/// Requests waiting for replies
+#[derive(Default)]
+struct Pending {
+    orders: HashMap<String, Reply<OrderCreated>>,
+    cancels: HashMap<u64, Reply<()>>,
+    open_orders: VecDeque<Reply<HashMap<Symbol, Vec<OpenOrder>>>>,
+    positions: VecDeque<Reply<HashMap<Symbol, Position>>>,
+}
+
+impl Pending {
+    fn len(&self) -> usize {
+        self.orders.len() + self.cancels.len() + self.open_orders.len() + self.positions.len()
+    }
+
+    /// `error` replies don't name the request, so they are credited only when a single
+    /// request is in flight
+    fn fail_single(&mut self, msg: String) {
+        if self.len() != 1 {
+            warn!("Ignoring error that matches no single request: {}", msg);
+            return;
+        }
+        if let Some(id) = self.orders.keys().next().cloned() {
+            let reply = self.orders.remove(&id).expect("Key is taken from the map");
+            let _ = reply.send(Err(Error::Rejected(msg)));
+        } else if let Some(&order_id) = self.cancels.keys().next() {
+            let reply = self
+                .cancels
+                .remove(&order_id)
+                .expect("Key is taken from the map");
+            let _ = reply.send(Err(Error::CancelError(order_id, msg)));
+        } else if let Some(reply) = self.open_orders.pop_front() {
+            let _ = reply.send(Err(Error::Rejected(msg)));
+        } else if let Some(reply) = self.positions.pop_front() {
+            let _ = reply.send(Err(Error::Rejected(msg)));
+        }
+    }
+}
+
+/// Authentificated socket for trading requests, closed when dropped
+pub struct WebsocketSession {
+    sender: UnboundedSender<KolliderMsg>,
+    pending: Arc<Mutex<Pending>>,
+    dispatcher: JoinHandle<()>,
+}
+
+impl WebsocketSession {
+    /// Open the socket and authentificate, fails if the server rejects the credentials
+    pub async fn connect(options: &WebsocketOptions, auth: &dyn Signer) -> Result<Self, Error> {
+        let stream = match tokio::time::timeout(REPLY_TIMEOUT, kollider_connect(options)).await {
+            Ok(stream) => stream?,
+            Err(_) => return Err(Error::NoResponse),
+        };
+        let (sender, msg_outcoming) = futures_channel::mpsc::unbounded();
+        let (msg_sender, mut msg_incoming) = futures_channel::mpsc::unbounded();
+        // Signed after the handshake that measured the server clock
+        sender.unbounded_send(make_signed_auth_async(auth).await?)?;
+        tokio::spawn(kollider_websocket_over(
+            options.clone(),
+            stream,
+            msg_outcoming,
+            msg_sender,
+        ));
+
+        let authed = async {
+            while let Some(msg) = msg_incoming.next().await {
+                match msg {
+                    KolliderMsg::Tagged(KolliderTaggedMsg::Authenticate { message })
+                        if message == "success" =>
+                    {
+                        return Ok(());
+                    }
+                    KolliderMsg::Tagged(KolliderTaggedMsg::Authenticate { message })
+                    | KolliderMsg::Tagged(KolliderTaggedMsg::Error(message)) => {
+                        return Err(Error::Rejected(message));
+                    }
+                    _ => (),
+                }
+            }
+            Err(Error::NoResponse)
+        };
+        match tokio::time::timeout(REPLY_TIMEOUT, authed).await {
+            Ok(res) => res?,
+            Err(_) => return Err(Error::NoResponse),
+        }
+
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let dispatcher = tokio::spawn(dispatch(msg_incoming, pending.clone()));
+        Ok(WebsocketSession {
+            sender,
+            pending,
+            dispatcher,
+        })
+    }
+
+    /// Send the order and wait until it is open or rejected
+    pub async fn create_order(&self, body: &OrderBody) -> Result<OrderCreated, Error> {
+        let ext_order_id = Uuid::new_v4().to_string();
+        let msg = KolliderMsg::Order {
+            _type: OrderTag::Tag,
+            price: body.price,
+            quantity: body.quantity,
+            symbol: body.symbol.clone(),
+            leverage: body.leverage,
+            side: body.side,
+            margin_type: body.margin_type,
+            order_type: body.order_type,
+            settlement_type: body.settlement_type,
+            ext_order_id: ext_order_id.clone(),
+        };
+        self.request(msg, |pending, reply| {
+            pending.orders.insert(ext_order_id, reply);
+        })
+        .await
+    }
+
+    /// Cancel the order and wait until it is done
+    pub async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<(), Error> {
+        let msg = KolliderMsg::CancelOrder {
+            _type: CancelOrderTag::Tag,
+            order_id,
+            symbol: symbol.to_owned(),
+            settlement_type: SettlementType::Delayed,
+        };
+        self.request(msg, |pending, reply| {
+            pending.cancels.insert(order_id, reply);
+        })
+        .await
+    }
+
+    pub async fn open_orders(&self) -> Result<HashMap<Symbol, Vec<OpenOrder>>, Error> {
+        let msg = KolliderMsg::FetchOpenOrders {
+            _type: FetchOpenOrdersTag::Tag,
+        };
+        self.request(msg, |pending, reply| pending.open_orders.push_back(reply))
+            .await
+    }
+
+    pub async fn positions(&self) -> Result<HashMap<Symbol, Position>, Error> {
+        let msg = KolliderMsg::FetchPositions {
+            _type: FetchPositionsTag::Tag,
+        };
+        self.request(msg, |pending, reply| pending.positions.push_back(reply))
+            .await
+    }
+
+    /// Register the reply before sending, so it is not missed
+    async fn request<T>(
+        &self,
+        msg: KolliderMsg,
+        register: impl FnOnce(&mut Pending, Reply<T>),
+    ) -> Result<T, Error> {
+        let (reply, receiver) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            register(&mut pending, reply);
+            self.sender.unbounded_send(msg)?;
+        }
+        match tokio::time::timeout(REPLY_TIMEOUT, receiver).await {
+            Ok(Ok(res)) => res,
+            // Timed out or the socket is closed
+            _ => Err(Error::NoResponse),
+        }
+    }
+}
+
+impl Drop for WebsocketSession {
+    fn drop(&mut self) {
+        self.dispatcher.abort();
+    }
+}
+
+/// Route replies to waiting requests. Requests that are still waiting when the socket is
+/// closed get `Error::NoResponse`.
+async fn dispatch(mut msg_incoming: UnboundedReceiver<KolliderMsg>, pending: Arc<Mutex<Pending>>) {
+    while let Some(msg) = msg_incoming.next().await {
+        let mut pending = pending.lock().unwrap();
+        let tagged = match msg {
+            KolliderMsg::Tagged(tagged) => tagged,
+            _ => continue,
+        };
+        match tagged {
+            KolliderTaggedMsg::Open {
+                order_id,
+                price,
+                quantity,
+                symbol,
+                leverage,
+                order_type,
+                ext_order_id,
+                timestamp,
+                ..
+            } => {
+                if let Some(reply) = pending.orders.remove(&ext_order_id) {
+                    let _ = reply.send(Ok(OrderCreated {
+                        timestamp,
+                        order_id,
+                        ext_order_id,
+                        uid: order_id,
+                        symbol,
+                        quantity,
+                        order_type,
+                        price,
+                        leverage,
+                    }));
+                }
+            }
+            KolliderTaggedMsg::OrderRejection {
+                ext_order_id,
+                order_id,
+                reason,
+            } => {
+                if let Some(reply) = pending.orders.remove(&ext_order_id) {
+                    let _ = reply.send(Err(Error::OrderError(order_id, reason)));
+                }
+            }
+            KolliderTaggedMsg::Done {
+                order_id, reason, ..
+            } if reason == "Cancel" => {
+                if let Some(reply) = pending.cancels.remove(&order_id) {
+                    let _ = reply.send(Ok(()));
+                }
+            }
+            KolliderTaggedMsg::OrderNotFound { order_id, .. } => {
+                if let Some(reply) = pending.cancels.remove(&order_id) {
+                    let _ = reply.send(Err(Error::CancelError(
+                        order_id,
+                        "order not found".to_owned(),
+                    )));
+                }
+            }
+            KolliderTaggedMsg::OpenOrders { open_orders } => {
+                if let Some(reply) = pending.open_orders.pop_front() {
+                    let _ = reply.send(Ok(open_orders));
+                }
+            }
+            KolliderTaggedMsg::Positions { positions } => {
+                if let Some(reply) = pending.positions.pop_front() {
+                    let _ = reply.send(Ok(positions));
+                }
+            }
+            KolliderTaggedMsg::Error(msg) => pending.fail_single(msg),
+            _ => (),
+        }
+    }
+    debug!("WebSocket session is closed");
+    *pending.lock().unwrap() = Pending::default();
+}
+
+#[cfg(all(test, feature = "mock-server"))]
+mod tests {
+    use super::*;
+    use crate::kollider::api::{MarginType, OrderSide, OrderType};
+    use crate::kollider::mock::{Fault, FaultRule, MockServer};
+    use crate::kollider::websocket::data::OrderReject;
+
+    fn bid(price: u64) -> OrderBody {
+        OrderBody {
+            leverage: 1,
+            margin_type: MarginType::Isolated,
+            order_type: OrderType::Limit,
+            price,
+            quantity: 1,
+            settlement_type: SettlementType::Delayed,
+            side: OrderSide::Bid,
+            symbol: "BTCUSD.PERP".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests() {
+        let server = MockServer::start().await.unwrap();
+        let session = WebsocketSession::connect(&server.ws_options(), &server.add_account(100_000))
+            .await
+            .unwrap();
+
+        // The first order is rejected, the reply goes to it and not to the second one
+        server.inject(
+            FaultRule::ws(
+                "order",
+                Fault::RejectOrder(OrderReject::NotEnoughAvailableBalance),
+            )
+            .times(1),
+        );
+        let (first, second) = (bid(490000), bid(480000));
+        let (rejected, opened) =
+            futures::join!(session.create_order(&first), session.create_order(&second));
+        assert!(matches!(rejected, Err(Error::OrderError(..))));
+        let opened = opened.unwrap();
+        assert_eq!(opened.price, 480000);
+
+        let (cancelled, missing) = futures::join!(
+            session.cancel_order("BTCUSD.PERP", opened.order_id),
+            session.cancel_order("BTCUSD.PERP", opened.order_id + 1)
+        );
+        cancelled.unwrap();
+        assert!(matches!(missing, Err(Error::CancelError(..))));
+        assert!(session
+            .open_orders()
+            .await
+            .unwrap()
+            .values()
+            .all(Vec::is_empty));
+    }
+}
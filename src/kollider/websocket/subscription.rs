@@ -0,0 +1,403 @@
+use super::data::{ChannelName, KolliderMsg, KolliderTaggedMsg, SubscribeTag, UnsubscribeTag};
+use crate::kollider::api::Symbol;
+use futures::channel::oneshot;
+use futures_channel::mpsc::{TrySendError, UnboundedSender};
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SubscriptionError {
+    #[error("Failed to communicate via channel: {0}")]
+    Channel(Box<TrySendError<KolliderMsg>>),
+    #[error("Subscription to {0:?} for {1} rejected: {2}")]
+    Rejected(ChannelName, Symbol, String),
+    #[error("Subscription registry was dropped before the server replied")]
+    NoResponse,
+}
+
+/// Current state of subscription to a channel for a symbol
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SubscriptionState {
+    /// Subscribe request is sent, waiting for the reply
+    Pending,
+    /// Server confirmed the subscription
+    Active,
+    /// Server rejected the subscription with the given message
+    Failed(String),
+}
+
+/// Key of subscription in the registry
+pub type SubscriptionKey = (ChannelName, Symbol);
+
+/// Tracks active channel subscriptions of a websocket session. Subscribing twice to the
+/// same channel and symbol sends only one request to the server. Each consumer holds a
+/// `SubscriptionHandle` and the registry sends `Unsubscribe` when the last handle is dropped.
+///
+/// Server replies with `success` or `error` messages that carry only a text. A reply that
+/// names the channel and the symbol of a request is matched to it. Other replies are assumed
+/// to come in the same order as requests, but only while a subscribe request is in flight, so
+/// errors of orders and authentification are not taken for subscription replies. Feed all
+/// incoming messages to `SubscriptionRegistry::handle_message` to track the replies.
+#[derive(Clone)]
+pub struct SubscriptionRegistry {
+    sender: UnboundedSender<KolliderMsg>,
+    state: Arc<Mutex<RegistryState>>,
+}
+
+#[derive(Default)]
+struct RegistryState {
+    entries: HashMap<SubscriptionKey, Entry>,
+    awaiting: VecDeque<Request>,
+}
+
+struct Entry {
+    consumers: usize,
+    state: SubscriptionState,
+    waiters: Vec<oneshot::Sender<Result<(), String>>>,
+}
+
+enum Request {
+    Subscribe(SubscriptionKey),
+    Unsubscribe(SubscriptionKey),
+}
+
+impl Request {
+    /// Whether the reply text names the channel and the symbol of the request
+    fn is_named_in(&self, text: &str) -> bool {
+        let (Request::Subscribe((channel, symbol)) | Request::Unsubscribe((channel, symbol))) =
+            self;
+        text.contains(channel.as_str()) && text.contains(symbol.as_str())
+    }
+}
+
+impl SubscriptionRegistry {
+    /// Create registry that sends requests to the given websocket sink
+    pub fn new(sender: UnboundedSender<KolliderMsg>) -> Self {
+        SubscriptionRegistry {
+            sender,
+            state: Arc::new(Mutex::new(RegistryState::default())),
+        }
+    }
+
+    /// Subscribe to the channel for the symbol. Request is sent only for the first consumer.
+    pub fn subscribe(
+        &self,
+        channel: ChannelName,
+        symbol: &str,
+    ) -> Result<SubscriptionHandle, SubscriptionError> {
+        let key = (channel, symbol.to_owned());
+        let mut state = self.state.lock().unwrap();
+        match state.entries.get_mut(&key) {
+            Some(entry) => {
+                if let SubscriptionState::Failed(_) = entry.state {
+                    self.send_subscribe(&key)?;
+                    entry.state = SubscriptionState::Pending;
+                    entry.consumers += 1;
+                    state.awaiting.push_back(Request::Subscribe(key.clone()));
+                } else {
+                    entry.consumers += 1;
+                }
+            }
+            None => {
+                self.send_subscribe(&key)?;
+                state.entries.insert(
+                    key.clone(),
+                    Entry {
+                        consumers: 1,
+                        state: SubscriptionState::Pending,
+                        waiters: vec![],
+                    },
+                );
+                state.awaiting.push_back(Request::Subscribe(key.clone()));
+            }
+        }
+        Ok(SubscriptionHandle {
+            registry: self.clone(),
+            key,
+        })
+    }
+
+    /// Subscribe and wait until the server confirms or rejects the subscription
+    pub async fn subscribe_confirmed(
+        &self,
+        channel: ChannelName,
+        symbol: &str,
+    ) -> Result<SubscriptionHandle, SubscriptionError> {
+        let handle = self.subscribe(channel, symbol)?;
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            let entry = state
+                .entries
+                .get_mut(&handle.key)
+                .expect("Entry exists while handle is alive");
+            match &entry.state {
+                SubscriptionState::Active => return Ok(handle),
+                SubscriptionState::Failed(msg) => {
+                    return Err(SubscriptionError::Rejected(
+                        channel,
+                        symbol.to_owned(),
+                        msg.clone(),
+                    ))
+                }
+                SubscriptionState::Pending => {
+                    let (tx, rx) = oneshot::channel();
+                    entry.waiters.push(tx);
+                    rx
+                }
+            }
+        };
+        match receiver.await {
+            Ok(Ok(())) => Ok(handle),
+            Ok(Err(msg)) => Err(SubscriptionError::Rejected(channel, symbol.to_owned(), msg)),
+            Err(_) => Err(SubscriptionError::NoResponse),
+        }
+    }
+
+    /// Track `success` and `error` replies. Returns `true` if the message was a reply to
+    /// subscription request of the registry. Errors that don't name the channel and the symbol
+    /// are left for others, they could be replies to orders or authentification.
+    pub fn handle_message(&self, msg: &KolliderMsg) -> bool {
+        let (reply, text) = match msg {
+            KolliderMsg::Tagged(KolliderTaggedMsg::Success(text)) => (Ok(()), text),
+            KolliderMsg::Tagged(KolliderTaggedMsg::Error(text)) => (Err(text.clone()), text),
+            _ => return false,
+        };
+        let mut state = self.state.lock().unwrap();
+        let index = match state.awaiting.iter().position(|r| r.is_named_in(text)) {
+            Some(index) => index,
+            // Orders and authentification are not confirmed with `success`
+            None if reply.is_ok() && !state.awaiting.is_empty() => 0,
+            None => return false,
+        };
+        match state.awaiting.remove(index) {
+            Some(Request::Subscribe(key)) => {
+                if let Some(entry) = state.entries.get_mut(&key) {
+                    entry.state = match &reply {
+                        Ok(()) => SubscriptionState::Active,
+                        Err(msg) => {
+                            warn!("Subscription to {:?} for {} failed: {}", key.0, key.1, msg);
+                            SubscriptionState::Failed(msg.clone())
+                        }
+                    };
+                    for waiter in entry.waiters.drain(..) {
+                        let _ = waiter.send(reply.clone());
+                    }
+                }
+                true
+            }
+            Some(Request::Unsubscribe(key)) => {
+                if let Err(msg) = reply {
+                    warn!("Unsubscribe from {:?} for {} failed: {}", key.0, key.1, msg);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// State of subscription for the channel and symbol if there are any consumers of it
+    pub fn state(&self, channel: ChannelName, symbol: &str) -> Option<SubscriptionState> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(&(channel, symbol.to_owned()))
+            .map(|e| e.state.clone())
+    }
+
+    /// Number of consumers of the subscription
+    pub fn consumers(&self, channel: ChannelName, symbol: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(&(channel, symbol.to_owned()))
+            .map_or(0, |e| e.consumers)
+    }
+
+    /// All subscriptions that have at least one consumer
+    pub fn subscriptions(&self) -> Vec<(SubscriptionKey, SubscriptionState)> {
+        let state = self.state.lock().unwrap();
+        let mut res: Vec<_> = state
+            .entries
+            .iter()
+            .map(|(k, e)| (k.clone(), e.state.clone()))
+            .collect();
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        res
+    }
+
+    fn release(&self, key: &SubscriptionKey) {
+        let mut state = self.state.lock().unwrap();
+        let (last, failed) = match state.entries.get_mut(key) {
+            Some(entry) => {
+                entry.consumers -= 1;
+                let failed = matches!(entry.state, SubscriptionState::Failed(_));
+                (entry.consumers == 0, failed)
+            }
+            None => (false, false),
+        };
+        if last {
+            state.entries.remove(key);
+        }
+        if last && !failed {
+            let msg = KolliderMsg::Unsubscribe {
+                _type: UnsubscribeTag::Tag,
+                symbols: vec![key.1.clone()],
+                channels: vec![key.0],
+            };
+            match self.sender.unbounded_send(msg) {
+                Ok(()) => state.awaiting.push_back(Request::Unsubscribe(key.clone())),
+                Err(e) => debug!("Cannot unsubscribe from {:?} for {}: {}", key.0, key.1, e),
+            }
+        }
+    }
+
+    fn send_subscribe(&self, key: &SubscriptionKey) -> Result<(), SubscriptionError> {
+        self.sender
+            .unbounded_send(KolliderMsg::Subscribe {
+                _type: SubscribeTag::Tag,
+                symbols: vec![key.1.clone()],
+                channels: vec![key.0],
+            })
+            .map_err(|e| SubscriptionError::Channel(Box::new(e)))
+    }
+}
+
+/// Consumer of subscription. Dropping the last handle unsubscribes from the channel.
+pub struct SubscriptionHandle {
+    registry: SubscriptionRegistry,
+    key: SubscriptionKey,
+}
+
+impl SubscriptionHandle {
+    pub fn channel(&self) -> ChannelName {
+        self.key.0
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        &self.key.1
+    }
+
+    /// Current state of the subscription
+    pub fn state(&self) -> Option<SubscriptionState> {
+        self.registry.state(self.key.0, &self.key.1)
+    }
+}
+
+impl Drop for SubscriptionHandle {
+    fn drop(&mut self) {
+        self.registry.release(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn success() -> KolliderMsg {
+        KolliderMsg::Tagged(KolliderTaggedMsg::Success("subscribed".to_owned()))
+    }
+
+    #[test]
+    fn test_subscribe_dedup_and_unsubscribe() {
+        let (tx, mut rx) = futures_channel::mpsc::unbounded();
+        let registry = SubscriptionRegistry::new(tx);
+
+        let h1 = registry
+            .subscribe(ChannelName::IndexValues, ".BTCUSD")
+            .unwrap();
+        let h2 = registry
+            .subscribe(ChannelName::IndexValues, ".BTCUSD")
+            .unwrap();
+        assert_eq!(
+            rx.try_recv().ok(),
+            Some(KolliderMsg::Subscribe {
+                _type: SubscribeTag::Tag,
+                symbols: vec![".BTCUSD".to_owned()],
+                channels: vec![ChannelName::IndexValues],
+            })
+        );
+        assert!(rx.try_recv().is_err());
+        assert_eq!(registry.consumers(ChannelName::IndexValues, ".BTCUSD"), 2);
+        assert_eq!(h1.state(), Some(SubscriptionState::Pending));
+
+        assert!(registry.handle_message(&success()));
+        assert_eq!(h2.state(), Some(SubscriptionState::Active));
+
+        drop(h1);
+        assert!(rx.try_recv().is_err());
+        drop(h2);
+        assert_eq!(
+            rx.try_recv().ok(),
+            Some(KolliderMsg::Unsubscribe {
+                _type: UnsubscribeTag::Tag,
+                symbols: vec![".BTCUSD".to_owned()],
+                channels: vec![ChannelName::IndexValues],
+            })
+        );
+        assert_eq!(registry.state(ChannelName::IndexValues, ".BTCUSD"), None);
+        assert!(registry.handle_message(&success()));
+        assert!(!registry.handle_message(&success()));
+    }
+
+    #[test]
+    fn test_subscribe_rejected() {
+        let (tx, mut rx) = futures_channel::mpsc::unbounded();
+        let registry = SubscriptionRegistry::new(tx);
+
+        let res = futures::executor::block_on(async {
+            let registry2 = registry.clone();
+            let fut = registry.subscribe_confirmed(ChannelName::Ticker, "BTCUSD.PERP");
+            let reply = async move {
+                rx.next().await;
+                registry2.handle_message(&KolliderMsg::Tagged(KolliderTaggedMsg::Error(
+                    "Unknown symbol BTCUSD.PERP for ticker".to_owned(),
+                )));
+            };
+            futures::join!(fut, reply).0
+        });
+        match res {
+            Err(SubscriptionError::Rejected(ChannelName::Ticker, symbol, msg)) => {
+                assert_eq!(symbol, "BTCUSD.PERP");
+                assert_eq!(msg, "Unknown symbol BTCUSD.PERP for ticker");
+            }
+            _ => panic!("Expected rejection"),
+        }
+        assert_eq!(registry.state(ChannelName::Ticker, "BTCUSD.PERP"), None);
+    }
+
+    #[test]
+    fn test_replies_of_other_requests() {
+        let (tx, mut rx) = futures_channel::mpsc::unbounded();
+        let registry = SubscriptionRegistry::new(tx);
+        let error = |text: &str| KolliderMsg::Tagged(KolliderTaggedMsg::Error(text.to_owned()));
+
+        let ticker = registry
+            .subscribe(ChannelName::Ticker, "BTCUSD.PERP")
+            .unwrap();
+        let index = registry
+            .subscribe(ChannelName::IndexValues, ".BTCUSD")
+            .unwrap();
+        // Unnamed errors are not credited to subscriptions in flight
+        assert!(!registry.handle_message(&error("Order rejected")));
+        assert_eq!(index.state(), Some(SubscriptionState::Pending));
+        assert!(registry.handle_message(&error("Unknown channel index_values for .BTCUSD")));
+        assert!(matches!(index.state(), Some(SubscriptionState::Failed(_))));
+        assert_eq!(ticker.state(), Some(SubscriptionState::Pending));
+        assert!(registry.handle_message(&success()));
+        assert_eq!(ticker.state(), Some(SubscriptionState::Active));
+
+        // Nothing is in flight, so the error belongs to some order
+        assert!(!registry.handle_message(&error("Not enough available balance")));
+        assert_eq!(ticker.state(), Some(SubscriptionState::Active));
+
+        // Failed resubscription does not leak a consumer
+        rx.close();
+        assert!(registry
+            .subscribe(ChannelName::IndexValues, ".BTCUSD")
+            .is_err());
+        assert_eq!(registry.consumers(ChannelName::IndexValues, ".BTCUSD"), 1);
+    }
+}
@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+/// How the CLI prints responses
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Aligned human friendly table
+    Table,
+    /// Whole response as pretty printed JSON
+    Json,
+    /// One JSON object per line for each row
+    Jsonl,
+    /// Comma separated values with header, nested fields are flattened with dots
+    Csv,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct UnknownOutputFormat(String);
+
+impl Error for UnknownOutputFormat {}
+
+impl fmt::Display for UnknownOutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Given output format '{}' is unknown, valid are: table, json, jsonl, csv",
+            self.0
+        )
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = UnknownOutputFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(UnknownOutputFormat(s.to_owned())),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Print response. Arrays are printed as one row per element.
+    pub fn print<T: Serialize>(&self, value: &T) -> Result<(), Box<dyn Error>> {
+        self.print_with_rows(value, value)
+    }
+
+    /// Print `full` response in JSON format and `rows` in the row based formats. Useful
+    /// when the response has nested structure that doesn't fit into table.
+    pub fn print_with_rows<T: Serialize, R: Serialize>(
+        &self,
+        full: &T,
+        rows: &R,
+    ) -> Result<(), Box<dyn Error>> {
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        match self {
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, full)?;
+                writeln!(out)?;
+            }
+            OutputFormat::Jsonl => {
+                for row in into_rows(serde_json::to_value(rows)?) {
+                    serde_json::to_writer(&mut out, &row)?;
+                    writeln!(out)?;
+                }
+            }
+            OutputFormat::Csv => {
+                let rows: Vec<Map<String, Value>> = into_rows(serde_json::to_value(rows)?)
+                    .into_iter()
+                    .map(flatten)
+                    .collect();
+                let header = columns(&rows);
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(&header)?;
+                for row in &rows {
+                    writer.write_record(header.iter().map(|c| cell(row.get(c))))?;
+                }
+                writer.flush()?;
+            }
+            OutputFormat::Table => {
+                let rows: Vec<Map<String, Value>> = into_rows(serde_json::to_value(rows)?)
+                    .into_iter()
+                    .map(flatten)
+                    .collect();
+                write_table(&mut out, &rows)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print single message of a stream, e.x. incoming websocket message
+    pub fn print_stream_item<T: Serialize>(
+        &self,
+        value: &T,
+        human: impl FnOnce(&T) -> String,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            OutputFormat::Table => println!("{}", human(value)),
+            _ => println!("{}", serde_json::to_string(value)?),
+        }
+        Ok(())
+    }
+}
+
+fn into_rows(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(items) => items,
+        Value::Null => vec![],
+        other => vec![other],
+    }
+}
+
+/// Flatten nested objects into single level with dotted keys
+fn flatten(value: Value) -> Map<String, Value> {
+    let mut res = Map::new();
+    match value {
+        Value::Object(obj) => flatten_into("", obj, &mut res),
+        other => {
+            res.insert("value".to_owned(), other);
+        }
+    }
+    res
+}
+
+fn flatten_into(prefix: &str, obj: Map<String, Value>, res: &mut Map<String, Value>) {
+    for (k, v) in obj {
+        let key = if prefix.is_empty() {
+            k
+        } else {
+            format!("{}.{}", prefix, k)
+        };
+        match v {
+            Value::Object(inner) if !inner.is_empty() => flatten_into(&key, inner, res),
+            other => {
+                res.insert(key, other);
+            }
+        }
+    }
+}
+
+/// Union of keys of all rows in order of appearance
+fn columns(rows: &[Map<String, Value>]) -> Vec<String> {
+    let mut res: Vec<String> = vec![];
+    for row in rows {
+        for k in row.keys() {
+            if !res.contains(k) {
+                res.push(k.clone());
+            }
+        }
+    }
+    res
+}
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "".to_owned(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn write_table<W: Write>(out: &mut W, rows: &[Map<String, Value>]) -> std::io::Result<()> {
+    if rows.len() == 1 {
+        // Single object is printed vertically as key-value pairs
+        let row = &rows[0];
+        let width = row.keys().map(|k| k.len()).max().unwrap_or(0);
+        for (k, v) in row {
+            writeln!(out, "{:width$}  {}", k, cell(Some(v)), width = width)?;
+        }
+        return Ok(());
+    }
+    let header = columns(rows);
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| header.iter().map(|c| cell(row.get(c))).collect())
+        .collect();
+    let widths: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            cells
+                .iter()
+                .map(|r| r[i].len())
+                .chain(std::iter::once(h.len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let write_line = |out: &mut W, line: &[String]| -> std::io::Result<()> {
+        let parts: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:w$}", c, w = *w))
+            .collect();
+        writeln!(out, "{}", parts.join("  ").trim_end())
+    };
+    write_line(out, &header)?;
+    for row in &cells {
+        write_line(out, row)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_output_format_parse() {
+        assert_eq!("JSONL".parse::<OutputFormat>(), Ok(OutputFormat::Jsonl));
+        assert_eq!(
+            "xml".parse::<OutputFormat>(),
+            Err(UnknownOutputFormat("xml".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_flatten_and_table() {
+        let rows: Vec<Map<String, Value>> = into_rows(json!([
+            {"symbol": "BTCUSD.PERP", "margin": {"isolated": 10}, "ids": [1, 2]},
+            {"symbol": "ETHUSD.PERP", "margin": {"isolated": 200}, "ids": null},
+        ]))
+        .into_iter()
+        .map(flatten)
+        .collect();
+        assert_eq!(columns(&rows), vec!["ids", "margin.isolated", "symbol"]);
+
+        let mut out = vec![];
+        write_table(&mut out, &rows).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ids    margin.isolated  symbol\n\
+             [1,2]  10               BTCUSD.PERP\n\
+             \x20      200              ETHUSD.PERP\n"
+        );
+    }
+}